@@ -0,0 +1,38 @@
+#!/usr/bin/env python3
+"""A sample model for the `command` client: it echoes the last user message.
+
+aichat writes one JSON object to stdin:
+    {"model": "...", "messages": [...], "temperature": null, "top_p": null, "stream": false}
+
+Without `stream`, print one JSON object: {"text": "...", "usage": {"input_tokens": 1, "output_tokens": 2}}
+With `stream`, print text deltas line by line, each encoded as a JSON string.
+Exit with a non-zero status and a message on stderr to report an error.
+"""
+
+import json
+import sys
+import time
+
+
+def main():
+    data = json.load(sys.stdin)
+    users = [m for m in data["messages"] if m["role"] == "user"]
+    if not users:
+        print("no user message", file=sys.stderr)
+        sys.exit(1)
+    content = users[-1]["content"]
+    if isinstance(content, list):
+        content = " ".join(p["text"] for p in content if p["type"] == "text")
+    text = f"[{data['model']}] {content}"
+
+    if data["stream"]:
+        for word in text.split(" "):
+            print(json.dumps(word + " "), flush=True)
+            time.sleep(0.05)
+    else:
+        usage = {"input_tokens": len(content.split()), "output_tokens": len(text.split())}
+        print(json.dumps({"text": text, "usage": usage}))
+
+
+if __name__ == "__main__":
+    main()
@@ -19,7 +19,7 @@ _aichat() {
 
     case "${cmd}" in
         aichat)
-            opts="-m -r -s -e -c -f -H -S -w -h -V --model --role --session --save-session --serve --execute --code --file --no-highlight --no-stream --wrap --light-theme --dry-run --info --list-models --list-roles --list-sessions --help --version"
+            opts="-m -r -s -e -c -f -H -S -w -v -h -V --model --role --session --save-session --serve --execute --code --file --no-highlight --no-stream --wrap --tag --stdin-type --light-theme --dry-run --verbose --replay --count-tokens --embed --info --list-models --live --list-roles --list-sessions --help --version"
             if [[ ${cur} == -* || ${COMP_CWORD} -eq 1 ]] ; then
                 COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
                 return 0
@@ -72,6 +72,18 @@ _aichat() {
                     fi
                     return 0
                     ;;
+                --stdin-type)
+                    COMPREPLY=($(compgen -W "auto text image" -- "${cur}"))
+                    return 0
+                    ;;
+                --replay)
+                    COMPREPLY=($(compgen -W "" -- "${cur}"))
+                    return 0
+                    ;;
+                --tag)
+                    COMPREPLY=($(compgen -W "" -- "${cur}"))
+                    return 0
+                    ;;
                 -w|--wrap)
                     COMPREPLY=($(compgen -f "${cur}"))
                     return 0
@@ -3,6 +3,8 @@ complete -c aichat -s r -l role -x -a "(aichat --list-roles)" -d 'Select a role'
 complete -c aichat -s s -l session -x  -a"(aichat --list-sessions)" -d 'Start or join a session' -r
 complete -c aichat -s f -l file -d 'Include files with the message' -r -F
 complete -c aichat -s w -l wrap -d 'Control text wrapping (no, auto, <max-width>)'
+complete -c aichat -l tag -d 'Attach a tag to requests for header templates' -r
+complete -c aichat -l stdin-type -d 'Specify how to interpret stdin (auto, text, image)' -r
 complete -c aichat -l save-session -d 'Forces the session to be saved'
 complete -c aichat -l serve -d 'Serve all LLMs as OpenAI-compatible API'
 complete -c aichat -s e -l execute -d 'Execute commands in natural language'
@@ -11,8 +13,13 @@ complete -c aichat -s H -l no-highlight -d 'Turn off syntax highlighting'
 complete -c aichat -s S -l no-stream -d 'Turns off stream mode'
 complete -c aichat -l light-theme -d 'Use light theme'
 complete -c aichat -l dry-run -d 'Display the message without sending it'
+complete -c aichat -s v -l verbose -d 'Print the text removed by output filters and the generation stats of local models to stderr'
+complete -c aichat -l replay -d 'Replay a logged request against the current model' -r
+complete -c aichat -l count-tokens -d 'Count the tokens of the input for the current model'
+complete -c aichat -l embed -d 'Embed each line of the input with the current model, printing the vectors as JSON'
 complete -c aichat -l info -d 'Display information'
 complete -c aichat -l list-models -d 'List all available models'
+complete -c aichat -l live -d 'Probe each client when listing models, showing reachability and latency'
 complete -c aichat -l list-roles -d 'List all available roles'
 complete -c aichat -l list-sessions -d 'List all available sessions'
 complete -c aichat -s h -l help -d 'Print help'
@@ -30,6 +30,8 @@ Register-ArgumentCompleter -Native -CommandName 'aichat' -ScriptBlock {
             [CompletionResult]::new('--file', '--file', [CompletionResultType]::ParameterName, 'Include files with the message')
             [CompletionResult]::new('-w', '-w', [CompletionResultType]::ParameterName, 'Control text wrapping (no, auto, <max-width>)')
             [CompletionResult]::new('--wrap', '--wrap', [CompletionResultType]::ParameterName, 'Control text wrapping (no, auto, <max-width>)')
+            [CompletionResult]::new('--tag', '--tag', [CompletionResultType]::ParameterName, 'Attach a tag to requests for header templates')
+            [CompletionResult]::new('--stdin-type', '--stdin-type', [CompletionResultType]::ParameterName, 'Specify how to interpret stdin (auto, text, image)')
             [CompletionResult]::new('--save-session', '--save-session', [CompletionResultType]::ParameterName, 'Forces the session to be saved')
             [CompletionResult]::new('--serve', '--serve', [CompletionResultType]::ParameterName, 'Serve all LLMs as OpenAI-compatible API')
             [CompletionResult]::new('-e', '-e', [CompletionResultType]::ParameterName, 'Execute commands in natural language')
@@ -42,8 +44,14 @@ Register-ArgumentCompleter -Native -CommandName 'aichat' -ScriptBlock {
             [CompletionResult]::new('--no-stream', '--no-stream', [CompletionResultType]::ParameterName, 'Turns off stream mode')
             [CompletionResult]::new('--light-theme', '--light-theme', [CompletionResultType]::ParameterName, 'Use light theme')
             [CompletionResult]::new('--dry-run', '--dry-run', [CompletionResultType]::ParameterName, 'Display the message without sending it')
+            [CompletionResult]::new('-v', '-v', [CompletionResultType]::ParameterName, 'Print the text removed by output filters and the generation stats of local models to stderr')
+            [CompletionResult]::new('--verbose', '--verbose', [CompletionResultType]::ParameterName, 'Print the text removed by output filters and the generation stats of local models to stderr')
+            [CompletionResult]::new('--replay', '--replay', [CompletionResultType]::ParameterName, 'Replay a logged request against the current model')
+            [CompletionResult]::new('--count-tokens', '--count-tokens', [CompletionResultType]::ParameterName, 'Count the tokens of the input for the current model')
+            [CompletionResult]::new('--embed', '--embed', [CompletionResultType]::ParameterName, 'Embed each line of the input with the current model, printing the vectors as JSON')
             [CompletionResult]::new('--info', '--info', [CompletionResultType]::ParameterName, 'Display information')
             [CompletionResult]::new('--list-models', '--list-models', [CompletionResultType]::ParameterName, 'List all available models')
+            [CompletionResult]::new('--live', '--live', [CompletionResultType]::ParameterName, 'Probe each client when listing models, showing reachability and latency')
             [CompletionResult]::new('--list-roles', '--list-roles', [CompletionResultType]::ParameterName, 'List all available roles')
             [CompletionResult]::new('--list-sessions', '--list-sessions', [CompletionResultType]::ParameterName, 'List all available sessions')
             [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
@@ -0,0 +1,395 @@
+use crate::cli::Cli;
+use crate::client::{
+    ensure_model_capabilities, init_client, list_models, list_models_live, send_stream,
+    sync_deepinfra_models, sync_ollama_models, sync_openrouter_models, sync_vertexai_models,
+    Message, ModelCapabilities, SendData,
+};
+use crate::config::{
+    bytes_to_data_url, check_budget, detect_image_mime, Config, GlobalConfig, Input, WorkingMode,
+    CODE_ROLE, EXPLAIN_ROLE, SHELL_ROLE,
+};
+use crate::render::{render_error, MarkdownRender};
+use crate::repl::Repl;
+use crate::utils::{
+    cl100k_base_singleton, create_abort_signal, extract_block, run_command, run_spinner,
+    CODE_BLOCK_RE,
+};
+use crate::{replay, serve};
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Parser;
+use inquire::{Select, Text};
+use is_terminal::IsTerminal;
+use parking_lot::RwLock;
+use std::io::{stderr, stdin, stdout, Read};
+use std::process;
+use std::sync::Arc;
+use tokio::sync::oneshot;
+
+/// Run the command line with the arguments of the process, the `aichat` binary.
+pub async fn start() -> Result<()> {
+    crate::telemetry::setup_telemetry()?;
+    let ret = run().await;
+    crate::webhook::wait_pending().await;
+    crate::telemetry::shutdown_telemetry();
+    ret
+}
+
+async fn run() -> Result<()> {
+    let cli = Cli::parse();
+    let text = cli.text();
+    let file = &cli.file;
+    let no_input = text.is_none() && file.is_empty();
+    let working_mode = if cli.serve.is_some() {
+        WorkingMode::Serve
+    } else if no_input {
+        WorkingMode::Repl
+    } else {
+        WorkingMode::Command
+    };
+    crate::logger::setup_logger(working_mode)?;
+    let config = Arc::new(RwLock::new(Config::init(working_mode)?));
+    if let Some(addr) = cli.serve {
+        return serve::run(config, addr).await;
+    }
+    if cli.list_roles {
+        config
+            .read()
+            .roles
+            .iter()
+            .for_each(|v| println!("{}", v.name));
+        return Ok(());
+    }
+    if cli.list_sessions {
+        let sessions = config.read().list_sessions().join("\n");
+        println!("{sessions}");
+        return Ok(());
+    }
+    // Only the paths that list or pick models need fresh listings
+    if let Err(err) = sync_openrouter_models(&config).await {
+        debug!("Failed to sync openrouter models: {err}");
+    }
+    if let Err(err) = sync_deepinfra_models(&config).await {
+        debug!("Failed to sync deepinfra models: {err}");
+    }
+    if let Err(err) = sync_vertexai_models(&config).await {
+        debug!("Failed to sync vertexai models: {err}");
+    }
+    if let Err(err) = sync_ollama_models(&config).await {
+        debug!("Failed to sync ollama models: {err}");
+    }
+    if cli.list_models {
+        if cli.live {
+            let models = list_models_live(&config).await;
+            let width = models
+                .iter()
+                .map(|(v, _)| v.id().len())
+                .max()
+                .unwrap_or_default();
+            for (model, reachability) in models {
+                println!("{:width$}  {reachability}", model.id());
+            }
+        } else {
+            for model in list_models(&config.read()) {
+                println!("{}", model.id());
+            }
+        }
+        return Ok(());
+    }
+    if let Some(wrap) = &cli.wrap {
+        config.write().set_wrap(wrap)?;
+    }
+    if cli.light_theme {
+        config.write().light_theme = true;
+    }
+    if cli.dry_run {
+        config.write().dry_run = true;
+    }
+    if cli.verbose {
+        config.write().verbose = true;
+    }
+    if let Some(name) = &cli.role {
+        config.write().set_role(name)?;
+    } else if cli.execute {
+        config.write().set_role(SHELL_ROLE)?;
+    } else if cli.code {
+        config.write().set_role(CODE_ROLE)?;
+    }
+    if let Some(session) = &cli.session {
+        config
+            .write()
+            .start_session(session.as_ref().map(|v| v.as_str()))?;
+    }
+    if let Some(model) = &cli.model {
+        config.write().set_model(model)?;
+    }
+    if cli.save_session {
+        config.write().set_save_session(Some(true));
+    }
+    if cli.no_highlight {
+        config.write().highlight = false;
+    }
+    if let Some(tag) = &cli.tag {
+        config.write().tag = Some(tag.clone());
+    }
+    if cli.info {
+        let info = config.read().info()?;
+        println!("{}", info);
+        return Ok(());
+    }
+    if let Some(path) = &cli.replay {
+        return replay::run(&config, path).await;
+    }
+    let (text, stdin_image) = aggregate_text(text, cli.stdin_type.as_deref())?;
+    if cli.count_tokens {
+        if text.is_none() && file.is_empty() && stdin_image.is_none() {
+            bail!("No input");
+        }
+        let input = create_input(&config, text, file, stdin_image)?;
+        return count_tokens(&config, input).await;
+    }
+    if cli.embed {
+        let Some(text) = text else {
+            bail!("No input");
+        };
+        return embed(&config, &text).await;
+    }
+    if cli.execute {
+        if no_input {
+            bail!("No input");
+        }
+        let input = create_input(&config, text, file, stdin_image)?;
+        execute(&config, input).await?;
+        return Ok(());
+    }
+    config.write().apply_prelude()?;
+    if let Err(err) = match no_input {
+        false => {
+            let input = create_input(&config, text, file, stdin_image)?;
+            start_directive(&config, input, cli.no_stream, cli.code).await
+        }
+        true => start_interactive(&config).await,
+    } {
+        let highlight = stderr().is_terminal() && config.read().highlight;
+        render_error(err, highlight)
+    }
+    Ok(())
+}
+
+async fn start_directive(
+    config: &GlobalConfig,
+    input: Input,
+    no_stream: bool,
+    code_mode: bool,
+) -> Result<()> {
+    let mut client = init_client(config)?;
+    ensure_model_capabilities(client.as_mut(), input.required_capabilities())?;
+    config.read().maybe_print_send_tokens(&input);
+    check_budget(config, client.model(), &input)?;
+    let is_terminal_stdout = stdout().is_terminal();
+    let extract_code = !is_terminal_stdout && code_mode;
+    let output = if no_stream || extract_code {
+        let output = client.send_message(input.clone()).await?;
+        let output = if extract_code && output.trim_start().starts_with("```") {
+            extract_block(&output)
+        } else {
+            output.clone()
+        };
+        if is_terminal_stdout {
+            let render_options = config.read().get_render_options()?;
+            let mut markdown_render = MarkdownRender::init(render_options)?;
+            println!("{}", markdown_render.render(&output).trim());
+        } else {
+            println!("{}", output);
+        }
+        output
+    } else {
+        let abort = create_abort_signal();
+        send_stream(&input, client.as_ref(), config, abort).await?
+    };
+    // Save the message/session
+    config.write().save_message(input, &output)?;
+    config.write().end_session()?;
+    Ok(())
+}
+
+async fn start_interactive(config: &GlobalConfig) -> Result<()> {
+    cl100k_base_singleton();
+    let mut repl: Repl = Repl::init(config)?;
+    repl.run().await
+}
+
+#[async_recursion::async_recursion]
+async fn execute(config: &GlobalConfig, mut input: Input) -> Result<()> {
+    let client = init_client(config)?;
+    config.read().maybe_print_send_tokens(&input);
+    check_budget(config, client.model(), &input)?;
+    let (spinner_tx, spinner_rx) = oneshot::channel();
+    tokio::spawn(run_spinner(" Generating", spinner_rx));
+    let ret = client.send_message(input.clone()).await;
+    let _ = spinner_tx.send(());
+    let mut eval_str = ret?;
+    if let Ok(true) = CODE_BLOCK_RE.is_match(&eval_str) {
+        eval_str = extract_block(&eval_str);
+    }
+    config.write().save_message(input.clone(), &eval_str)?;
+    config.read().maybe_copy(&eval_str);
+    let render_options = config.read().get_render_options()?;
+    let mut markdown_render = MarkdownRender::init(render_options)?;
+    if config.read().dry_run {
+        println!("{}", markdown_render.render(&eval_str).trim());
+        return Ok(());
+    }
+    if stdout().is_terminal() {
+        let mut explain = false;
+        loop {
+            let answer = Select::new(
+                markdown_render.render(&eval_str).trim(),
+                vec!["✅ Execute", "🤔 Revise", "📙 Explain", "❌ Cancel"],
+            )
+            .prompt()?;
+
+            match answer {
+                "✅ Execute" => {
+                    let code = run_command(&eval_str)?;
+                    if code != 0 {
+                        crate::telemetry::shutdown_telemetry();
+                        process::exit(code);
+                    }
+                }
+                "🤔 Revise" => {
+                    let revision = Text::new("Enter your revision:").prompt()?;
+                    let text = input.text();
+                    let text =
+                        format!("[INST] {text} [/INST]\n{eval_str}\n[INST] {revision} [/INST]\n");
+                    input.set_text(text);
+                    return execute(config, input).await;
+                }
+                "📙 Explain" => {
+                    if !explain {
+                        config.write().set_role(EXPLAIN_ROLE)?;
+                    }
+                    let input = Input::from_str(&eval_str, config.read().input_context());
+                    let abort = create_abort_signal();
+                    send_stream(&input, client.as_ref(), config, abort).await?;
+                    explain = true;
+                    continue;
+                }
+                _ => {}
+            }
+            break;
+        }
+    } else {
+        println!("{}", eval_str);
+    }
+    Ok(())
+}
+
+async fn embed(config: &GlobalConfig, text: &str) -> Result<()> {
+    let mut client = init_client(config)?;
+    ensure_model_capabilities(client.as_mut(), ModelCapabilities::Embedding)?;
+    let texts = text
+        .lines()
+        .filter(|v| !v.trim().is_empty())
+        .map(|v| v.to_string())
+        .collect();
+    let output = client.embeddings(texts).await?;
+    println!("{}", serde_json::to_string(&output.vectors)?);
+    Ok(())
+}
+
+/// Print how many tokens the input takes and how many of the model's limit remain.
+async fn count_tokens(config: &GlobalConfig, input: Input) -> Result<()> {
+    let client = init_client(config)?;
+    let model = client.model().clone();
+    let messages = config.read().build_messages(&input)?;
+    let input_tokens = model.total_tokens(&[Message::new(&input)]);
+    let session_tokens = config.read().session.as_ref().map(|v| v.tokens());
+    let data = SendData {
+        messages: messages.clone(),
+        temperature: None,
+        top_p: None,
+        functions: None,
+        stream: false,
+    };
+    let (total_tokens, method) = match client.count_tokens(data).await? {
+        Some(tokens) => (tokens, "provider API"),
+        None => (model.total_tokens(&messages), model.tokenizer()),
+    };
+    let remaining = model
+        .max_input_tokens
+        .map(|v| v as isize - total_tokens as isize);
+    let items = [
+        ("model", model.id()),
+        ("method", method.to_string()),
+        ("input_tokens", input_tokens.to_string()),
+        ("session_tokens", format_option(session_tokens)),
+        ("total_tokens", total_tokens.to_string()),
+        ("max_input_tokens", format_option(model.max_input_tokens)),
+        ("remaining_tokens", format_option(remaining)),
+    ];
+    for (name, value) in items {
+        println!("{name:<20}{value}");
+    }
+    Ok(())
+}
+
+/// Render an optional value, `-` when absent.
+fn format_option<T: std::fmt::Display>(value: Option<T>) -> String {
+    value.map_or_else(|| "-".into(), |v| v.to_string())
+}
+
+/// Merge stdin into the text, or return it as an image data url if it is binary image data
+fn aggregate_text(
+    text: Option<String>,
+    stdin_type: Option<&str>,
+) -> Result<(Option<String>, Option<String>)> {
+    let stdin_type = stdin_type.unwrap_or("auto");
+    if !["auto", "text", "image"].contains(&stdin_type) {
+        bail!("Invalid stdin type '{stdin_type}', possible values: auto, text, image");
+    }
+    if stdin().is_terminal() {
+        return Ok((text, None));
+    }
+    let mut stdin_data = vec![];
+    stdin().read_to_end(&mut stdin_data)?;
+    let image_mime = match stdin_type {
+        "text" => None,
+        "image" => Some(detect_image_mime(&stdin_data).ok_or_else(|| {
+            anyhow!("Unrecognized image data on stdin, supported formats: png, jpeg, webp, gif")
+        })?),
+        _ => detect_image_mime(&stdin_data),
+    };
+    if let Some(mime_type) = image_mime {
+        return Ok((text, Some(bytes_to_data_url(mime_type, &stdin_data))));
+    }
+    let stdin_text =
+        String::from_utf8(stdin_data).with_context(|| "The stdin is not valid UTF-8 text")?;
+    let text = if let Some(text) = text {
+        Some(format!("{text}\n{stdin_text}"))
+    } else {
+        Some(stdin_text)
+    };
+    Ok((text, None))
+}
+
+fn create_input(
+    config: &GlobalConfig,
+    text: Option<String>,
+    file: &[String],
+    stdin_image: Option<String>,
+) -> Result<Input> {
+    let input_context = config.read().input_context();
+    let mut input = if file.is_empty() {
+        Input::from_str(&text.unwrap_or_default(), input_context)
+    } else {
+        Input::new(&text.unwrap_or_default(), file.to_vec(), input_context)?
+    };
+    if let Some(data_url) = stdin_image {
+        input.attach_media(data_url, "<stdin>");
+    }
+    if input.is_empty() {
+        bail!("No input");
+    }
+    Ok(input)
+}
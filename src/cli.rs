@@ -36,18 +36,39 @@ pub struct Cli {
     /// Control text wrapping (no, auto, <max-width>)
     #[clap(short = 'w', long)]
     pub wrap: Option<String>,
+    /// Attach a tag to requests for header templates
+    #[clap(long)]
+    pub tag: Option<String>,
+    /// Specify how to interpret stdin (auto, text, image)
+    #[clap(long, value_name = "TYPE")]
+    pub stdin_type: Option<String>,
     /// Use light theme
     #[clap(long)]
     pub light_theme: bool,
     /// Display the message without sending it
     #[clap(long)]
     pub dry_run: bool,
+    /// Print the text removed by output filters and the generation stats of local models to stderr
+    #[clap(short = 'v', long)]
+    pub verbose: bool,
+    /// Replay a logged request against the current model
+    #[clap(long, value_name = "FILE")]
+    pub replay: Option<String>,
+    /// Count the tokens of the input for the current model
+    #[clap(long)]
+    pub count_tokens: bool,
+    /// Embed each line of the input with the current model, printing the vectors as JSON
+    #[clap(long)]
+    pub embed: bool,
     /// Display information
     #[clap(long)]
     pub info: bool,
     /// List all available models
     #[clap(long)]
     pub list_models: bool,
+    /// Probe each client when listing models, showing reachability and latency
+    #[clap(long, requires = "list_models")]
+    pub live: bool,
     /// List all available roles
     #[clap(long)]
     pub list_roles: bool,
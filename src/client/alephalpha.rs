@@ -0,0 +1,273 @@
+use super::{
+    event_source, extract_sytem_message, message::*, note_response, send_request, AlephAlphaClient,
+    Client, CompletionOutput, ExtraConfig, Model, ModelConfig, PromptType, ReplyHandler, SendData,
+};
+
+use crate::utils::PromptKind;
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use reqwest_eventsource::{Error as EventSourceError, Event};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+const API_BASE: &str = "https://api.aleph-alpha.com";
+
+const MODELS: [(&str, usize, &str); 3] = [
+    // https://docs.aleph-alpha.com/docs/introduction/model-card/
+    ("luminous-base-control", 2048, "text"),
+    ("luminous-extended-control", 2048, "text"),
+    ("luminous-supreme-control", 2048, "text"),
+];
+
+const DEFAULT_MAX_TOKENS: isize = 1024;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlephAlphaConfig {
+    pub name: Option<String>,
+    pub api_key: Option<String>,
+    pub api_base: Option<String>,
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+#[async_trait]
+impl Client for AlephAlphaClient {
+    client_common_fns!();
+
+    async fn send_message_inner(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+    ) -> Result<CompletionOutput> {
+        let builder = self.request_builder(client, data)?;
+        send_message(builder).await
+    }
+
+    async fn send_message_streaming_inner(
+        &self,
+        client: &ReqwestClient,
+        handler: &mut ReplyHandler,
+        data: SendData,
+    ) -> Result<()> {
+        let builder = self.request_builder(client, data)?;
+        send_message_streaming(builder, handler).await
+    }
+}
+
+impl AlephAlphaClient {
+    list_models_fn!(AlephAlphaConfig, &MODELS);
+    config_get_fn!(api_key, get_api_key);
+    config_get_fn!(api_base, get_api_base);
+
+    pub const PROMPTS: [PromptType<'static>; 1] =
+        [("api_key", "API Key:", true, PromptKind::String)];
+
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let api_key = self.get_api_key()?;
+        let api_base = self.get_api_base().unwrap_or_else(|_| API_BASE.to_string());
+
+        let body = build_body(data, &self.model)?;
+
+        let url = format!("{}/complete", api_base.trim_end_matches('/'));
+
+        debug!("AlephAlpha Request: {url} {body}");
+
+        let builder = client.post(url).bearer_auth(api_key).json(&body);
+
+        Ok(builder)
+    }
+}
+
+async fn send_message(builder: RequestBuilder) -> Result<CompletionOutput> {
+    let res = send_request(builder).await?;
+    let status = res.status();
+    let data: Value = res.json().await?;
+    if status != 200 {
+        catch_error(&data, status.as_u16())?;
+    }
+
+    let text = data["completions"][0]["completion"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+
+    Ok(CompletionOutput {
+        text: text.trim_start().to_string(),
+        input_tokens: data["num_tokens_prompt_total"].as_u64(),
+        output_tokens: data["num_tokens_generated"].as_u64(),
+        tool_calls: vec![],
+        request_id: None,
+        stop_sequence: None,
+        stop_reason: None,
+        stats: None,
+        cache_usage: None,
+    })
+}
+
+async fn send_message_streaming(builder: RequestBuilder, handler: &mut ReplyHandler) -> Result<()> {
+    let mut es = event_source(builder)?;
+    while let Some(event) = es.next().await {
+        match event {
+            Ok(Event::Open) => {}
+            Ok(Event::Message(message)) => {
+                let data: Value = serde_json::from_str(&message.data)?;
+                match data["type"].as_str() {
+                    Some("stream_chunk") => {
+                        if let Some(text) = data["completion"].as_str() {
+                            match handler.get_buffer().is_empty() {
+                                true => handler.text(text.trim_start())?,
+                                false => handler.text(text)?,
+                            }
+                        }
+                    }
+                    Some("completion_summary") => {
+                        handler.set_usage(
+                            data["num_tokens_prompt_total"].as_u64(),
+                            data["num_tokens_generated"].as_u64(),
+                        );
+                    }
+                    _ => {}
+                }
+            }
+            Err(err) => {
+                match err {
+                    EventSourceError::StreamEnded => {}
+                    EventSourceError::InvalidStatusCode(status, res) => {
+                        note_response(&res);
+                        let text = res.text().await?;
+                        let data: Value = match text.parse() {
+                            Ok(data) => data,
+                            Err(_) => {
+                                bail!("Invalid respoinse, status: {status}, text: {text}");
+                            }
+                        };
+                        catch_error(&data, status.as_u16())?;
+                    }
+                    EventSourceError::InvalidContentType(_, res) => {
+                        let text = res.text().await?;
+                        bail!("The API server should return data as 'text/event-stream', but it isn't. Check the client config. {text}");
+                    }
+                    _ => {
+                        bail!("{}", err);
+                    }
+                }
+                es.close();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn build_body(data: SendData, model: &Model) -> Result<Value> {
+    let SendData {
+        mut messages,
+        temperature,
+        top_p,
+        functions: _,
+        stream,
+    } = data;
+
+    let system_message = extract_sytem_message(&mut messages);
+    let prompt = build_prompt(system_message, &messages)?;
+
+    let mut body = json!({
+        "model": &model.name,
+        "prompt": prompt,
+        "maximum_tokens": model.max_output_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+        "stop_sequences": ["###"],
+    });
+
+    if let Some(temperature) = temperature {
+        body["temperature"] = temperature.into();
+    }
+    if let Some(top_p) = top_p {
+        body["top_p"] = top_p.into();
+    }
+    if stream {
+        body["stream"] = true.into();
+    }
+
+    Ok(body)
+}
+
+/// The instruction format of the control models.
+fn build_prompt(system_message: Option<String>, messages: &[Message]) -> Result<String> {
+    let mut prompt = String::new();
+    if let Some(system_message) = system_message {
+        prompt.push_str(&format!("### Instruction:\n{system_message}\n\n"));
+    }
+    for message in messages {
+        if let MessageContent::Array(list) = &message.content {
+            if list
+                .iter()
+                .any(|v| matches!(v, MessageContentPart::ImageUrl { .. }))
+            {
+                bail!("The model does not support images");
+            }
+        }
+        let text = message.content.to_text();
+        match message.role {
+            MessageRole::Assistant => prompt.push_str(&format!("### Response:\n{text}\n\n")),
+            _ => prompt.push_str(&format!("### Input:\n{text}\n\n")),
+        }
+    }
+    prompt.push_str("### Response:\n");
+    Ok(prompt)
+}
+
+fn catch_error(data: &Value, status: u16) -> Result<()> {
+    debug!("Invalid response, status: {status}, data: {data}");
+    if let Some(error) = data["error"].as_str() {
+        match data["code"].as_str() {
+            Some(code) => bail!("{error} (code: {code})"),
+            None => bail!("{error}"),
+        }
+    }
+    bail!("Invalid response, status: {status}, data: {data}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::fixture;
+
+    #[test]
+    fn test_build_prompt() -> Result<()> {
+        let messages = vec![
+            Message::user("Hi"),
+            Message::assistant("Hello"),
+            Message::user("Bye"),
+        ];
+        assert_eq!(
+            build_prompt(Some("Be brief".into()), &messages)?,
+            "### Instruction:\nBe brief\n\n### Input:\nHi\n\n### Response:\nHello\n\n### Input:\nBye\n\n### Response:\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let client = AlephAlphaClient::for_test("api_key: xxx")?;
+        let http = ReqwestClient::new();
+        let request = |stream| client.request_builder(&http, fixture::send_data(stream));
+
+        let (mut handler, _rx) = fixture::handler();
+        send_message_streaming(
+            fixture::replay("alephalpha/stream", request(true)?).await?,
+            &mut handler,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "Hello! How can I help you today?");
+        assert_eq!(handler.get_usage(), (Some(12), Some(9)));
+
+        let err = send_message(fixture::replay("alephalpha/auth_failure", request(false)?).await?)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Unauthorized (code: UNAUTHENTICATED)");
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+use super::openai::openai_build_body;
+use super::{AzureAIClient, ExtraConfig, Model, ModelConfig, PromptType, SendData};
+
+use crate::utils::PromptKind;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AzureAIConfig {
+    pub name: Option<String>,
+    pub api_base: Option<String>,
+    pub api_key: Option<String>,
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+openai_compatible_client!(AzureAIClient);
+
+impl AzureAIClient {
+    list_models_fn!(AzureAIConfig);
+    config_get_fn!(api_base, get_api_base);
+    config_get_fn!(api_key, get_api_key);
+
+    pub const PROMPTS: [PromptType<'static>; 4] = [
+        ("api_base", "API Base:", true, PromptKind::String),
+        ("api_key", "API Key:", true, PromptKind::String),
+        ("models[].name", "Model Name:", true, PromptKind::String),
+        (
+            "models[].max_input_tokens",
+            "Max Input Tokens:",
+            false,
+            PromptKind::Integer,
+        ),
+    ];
+
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let api_base = self.get_api_base()?;
+        let api_key = self.get_api_key()?;
+
+        let mut body = openai_build_body(data, &self.model);
+        self.model.merge_extra_fields(&mut body);
+
+        let url = format!("{}/chat/completions", api_base.trim_end_matches('/'));
+
+        debug!("AzureAI Request: {url} {body}");
+
+        let builder = client.post(url).header("api-key", api_key).json(&body);
+
+        Ok(builder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{fixture, openai::openai_send_message};
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let client = AzureAIClient::for_test(
+            r#"
+api_key: xxx
+api_base: https://example.com/v1
+models:
+  - name: Meta-Llama-3-70B-Instruct
+"#,
+        )?;
+        let http = ReqwestClient::new();
+        let request = |stream| client.request_builder(&http, fixture::send_data(stream));
+
+        let err =
+            openai_send_message(fixture::replay("azure-ai/auth_failure", request(false)?).await?)
+                .await
+                .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Access denied due to invalid subscription key or wrong API endpoint. (code: Unauthorized)"
+        );
+        Ok(())
+    }
+}
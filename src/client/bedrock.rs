@@ -0,0 +1,671 @@
+use super::{
+    extract_sytem_message, message::*, send_request, BedrockClient, Client, CompletionOutput,
+    ExtraConfig, Model, ModelConfig, PromptType, ReplyHandler, SendData,
+};
+
+use crate::utils::{hex_encode, hmac_sha256, sha256sum, PromptKind};
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use reqwest::{header::CONTENT_TYPE, Client as ReqwestClient, RequestBuilder, Response, Url};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{collections::HashMap, env};
+
+const SERVICE: &str = "bedrock";
+
+const MODELS: [(&str, usize, &str); 7] = [
+    // https://docs.aws.amazon.com/bedrock/latest/userguide/model-ids.html
+    (
+        "anthropic.claude-3-opus-20240229-v1:0",
+        200000,
+        "text,vision",
+    ),
+    (
+        "anthropic.claude-3-sonnet-20240229-v1:0",
+        200000,
+        "text,vision",
+    ),
+    (
+        "anthropic.claude-3-haiku-20240307-v1:0",
+        200000,
+        "text,vision",
+    ),
+    ("meta.llama3-8b-instruct-v1:0", 8192, "text"),
+    ("meta.llama3-70b-instruct-v1:0", 8192, "text"),
+    ("mistral.mistral-large-2402-v1:0", 32000, "text"),
+    ("mistral.mixtral-8x7b-instruct-v0:1", 32000, "text"),
+];
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct BedrockConfig {
+    pub name: Option<String>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub session_token: Option<String>,
+    pub region: Option<String>,
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+#[derive(Debug, Clone)]
+struct Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+#[async_trait]
+impl Client for BedrockClient {
+    client_common_fns!();
+
+    async fn send_message_inner(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+    ) -> Result<CompletionOutput> {
+        let builder = self.request_builder(client, data)?;
+        send_message(builder).await
+    }
+
+    async fn send_message_streaming_inner(
+        &self,
+        client: &ReqwestClient,
+        handler: &mut ReplyHandler,
+        data: SendData,
+    ) -> Result<()> {
+        let builder = self.request_builder(client, data)?;
+        send_message_streaming(builder, handler).await
+    }
+}
+
+impl BedrockClient {
+    list_models_fn!(BedrockConfig, &MODELS);
+    config_get_fn!(access_key_id, get_access_key_id);
+    config_get_fn!(secret_access_key, get_secret_access_key);
+    config_get_fn!(session_token, get_session_token);
+    config_get_fn!(region, get_region);
+
+    pub const PROMPTS: [PromptType<'static>; 3] = [
+        (
+            "access_key_id",
+            "AWS Access Key ID:",
+            true,
+            PromptKind::String,
+        ),
+        (
+            "secret_access_key",
+            "AWS Secret Access Key:",
+            true,
+            PromptKind::String,
+        ),
+        ("region", "AWS Region:", true, PromptKind::String),
+    ];
+
+    /// Credentials from the config, then `BEDROCK_*` and the standard `AWS_*` env vars.
+    fn credentials(&self) -> Result<Credentials> {
+        let access_key_id = self
+            .get_access_key_id()
+            .or_else(|err| env::var("AWS_ACCESS_KEY_ID").map_err(|_| err))?;
+        let secret_access_key = self
+            .get_secret_access_key()
+            .or_else(|err| env::var("AWS_SECRET_ACCESS_KEY").map_err(|_| err))?;
+        let session_token = self
+            .get_session_token()
+            .ok()
+            .or_else(|| env::var("AWS_SESSION_TOKEN").ok());
+        Ok(Credentials {
+            access_key_id,
+            secret_access_key,
+            session_token,
+        })
+    }
+
+    fn region(&self) -> Result<String> {
+        self.get_region().or_else(|err| {
+            env::var("AWS_REGION")
+                .or_else(|_| env::var("AWS_DEFAULT_REGION"))
+                .map_err(|_| err)
+        })
+    }
+
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let credentials = self.credentials()?;
+        let region = self.region()?;
+
+        let action = if data.stream {
+            "converse-stream"
+        } else {
+            "converse"
+        };
+        let body = build_body(data, &self.model)?;
+
+        let url: Url = format!(
+            "https://bedrock-runtime.{region}.amazonaws.com/model/{}/{action}",
+            uri_encode(&self.model.name)
+        )
+        .parse()?;
+
+        debug!("Bedrock Request: {url} {body}");
+
+        let body = body.to_string();
+        let headers = sign_request(
+            &credentials,
+            &region,
+            SERVICE,
+            "POST",
+            &url,
+            &body,
+            Utc::now(),
+        );
+
+        let mut builder = client
+            .post(url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body);
+        for (key, value) in headers {
+            builder = builder.header(key, value);
+        }
+
+        Ok(builder)
+    }
+}
+
+async fn send_message(builder: RequestBuilder) -> Result<CompletionOutput> {
+    let res = send_request(builder).await?;
+    let status = res.status();
+    let error_type = error_type(&res);
+    let data: Value = res.json().await?;
+    if status != 200 {
+        catch_error(&data, status.as_u16(), error_type.as_deref())?;
+    }
+
+    let text = data["output"]["message"]["content"][0]["text"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+
+    Ok(CompletionOutput {
+        text: text.to_string(),
+        input_tokens: data["usage"]["inputTokens"].as_u64(),
+        output_tokens: data["usage"]["outputTokens"].as_u64(),
+        tool_calls: vec![],
+        request_id: None,
+        stop_sequence: None,
+        stop_reason: None,
+        stats: None,
+        cache_usage: None,
+    })
+}
+
+async fn send_message_streaming(builder: RequestBuilder, handler: &mut ReplyHandler) -> Result<()> {
+    let res = send_request(builder).await?;
+    let status = res.status();
+    if status != 200 {
+        let error_type = error_type(&res);
+        let data: Value = res.json().await?;
+        catch_error(&data, status.as_u16(), error_type.as_deref())?;
+    } else {
+        let mut decoder = EventStreamDecoder::default();
+        let mut stream = res.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            for message in decoder.decode(&chunk?)? {
+                handle_event(&message, handler)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn handle_event(message: &EventStreamMessage, handler: &mut ReplyHandler) -> Result<()> {
+    let data: Value = serde_json::from_slice(&message.payload)?;
+    match message.header(":message-type") {
+        Some("event") => match message.header(":event-type") {
+            Some("contentBlockDelta") => {
+                if let Some(text) = data["delta"]["text"].as_str() {
+                    handler.text(text)?;
+                }
+            }
+            Some("metadata") => {
+                let usage = &data["usage"];
+                handler.set_usage(
+                    usage["inputTokens"].as_u64(),
+                    usage["outputTokens"].as_u64(),
+                );
+            }
+            _ => {}
+        },
+        Some("exception") => catch_error(&data, 200, message.header(":exception-type"))?,
+        _ => catch_error(&data, 200, message.header(":error-code"))?,
+    }
+    Ok(())
+}
+
+fn build_body(data: SendData, model: &Model) -> Result<Value> {
+    let SendData {
+        mut messages,
+        temperature,
+        top_p,
+        functions: _,
+        stream: _,
+    } = data;
+
+    let system_message = extract_sytem_message(&mut messages);
+
+    let mut network_image_urls = vec![];
+    let messages: Vec<Value> = messages
+        .into_iter()
+        .map(|message| {
+            let role = message.role;
+            let content = match message.content {
+                MessageContent::Text(text) | MessageContent::ToolResults((_, text)) => {
+                    vec![json!({ "text": text })]
+                }
+                MessageContent::Array(list) => list
+                    .into_iter()
+                    .map(|item| match item {
+                        MessageContentPart::Text { text } => json!({ "text": text }),
+                        MessageContentPart::ImageUrl {
+                            image_url: ImageUrl { url },
+                        } => {
+                            if let Some((mime_type, data)) = url
+                                .strip_prefix("data:")
+                                .and_then(|v| v.split_once(";base64,"))
+                            {
+                                let format = mime_type.trim_start_matches("image/");
+                                json!({
+                                    "image": {
+                                        "format": format,
+                                        "source": { "bytes": data },
+                                    }
+                                })
+                            } else {
+                                network_image_urls.push(url.clone());
+                                json!({ "url": url })
+                            }
+                        }
+                    })
+                    .collect(),
+            };
+            json!({ "role": role, "content": content })
+        })
+        .collect();
+
+    if !network_image_urls.is_empty() {
+        bail!(
+            "The model does not support network images: {:?}",
+            network_image_urls
+        );
+    }
+
+    let mut body = json!({ "messages": messages });
+
+    if let Some(system) = system_message {
+        body["system"] = json!([{ "text": system }]);
+    }
+
+    let mut inference_config = json!({});
+    if let Some(max_tokens) = model.max_output_tokens {
+        inference_config["maxTokens"] = max_tokens.into();
+    }
+    if let Some(temperature) = temperature {
+        inference_config["temperature"] = temperature.into();
+    }
+    if let Some(top_p) = top_p {
+        inference_config["topP"] = top_p.into();
+    }
+    if inference_config.as_object().map(|v| !v.is_empty()) == Some(true) {
+        body["inferenceConfig"] = inference_config;
+    }
+
+    Ok(body)
+}
+
+fn error_type(res: &Response) -> Option<String> {
+    res.headers()
+        .get("x-amzn-ErrorType")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(':').next().unwrap_or(v).to_string())
+}
+
+fn catch_error(data: &Value, status: u16, error_type: Option<&str>) -> Result<()> {
+    debug!("Invalid response, status: {status}, data: {data}");
+    let error_type = error_type.or(match status {
+        403 => Some("AccessDeniedException"),
+        429 => Some("ThrottlingException"),
+        _ => None,
+    });
+    let message = data["message"]
+        .as_str()
+        .or_else(|| data["Message"].as_str());
+    match (error_type, message) {
+        (Some(type_), Some(message)) if type_.eq_ignore_ascii_case("AccessDeniedException") => {
+            bail!("Access denied: {message}. Check the credentials and whether the model is enabled for the region in the Bedrock console")
+        }
+        (Some(type_), Some(message)) if type_.eq_ignore_ascii_case("ThrottlingException") => {
+            bail!("Too many requests: {message}. Wait a moment and try again")
+        }
+        (Some(type_), Some(message)) => bail!("{message} (type: {type_})"),
+        (None, Some(message)) => bail!("{message}"),
+        _ => bail!("Invalid response, status: {status}, data: {data}"),
+    }
+}
+
+/// Sign a request with AWS Signature Version 4, returning the headers to add.
+fn sign_request(
+    credentials: &Credentials,
+    region: &str,
+    service: &str,
+    method: &str,
+    url: &Url,
+    body: &str,
+    now: DateTime<Utc>,
+) -> Vec<(&'static str, String)> {
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let host = url.host_str().unwrap_or_default();
+
+    let mut headers = vec![("host", host.to_string()), ("x-amz-date", amz_date.clone())];
+    if let Some(token) = &credentials.session_token {
+        headers.push(("x-amz-security-token", token.clone()));
+    }
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(key, value)| format!("{key}:{}\n", value.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(key, _)| *key)
+        .collect::<Vec<_>>()
+        .join(";");
+
+    // Every service but S3 encodes the already encoded path segments once more.
+    let canonical_uri = url
+        .path()
+        .split('/')
+        .map(uri_encode)
+        .collect::<Vec<_>>()
+        .join("/");
+    let canonical_request = format!(
+        "{method}\n{canonical_uri}\n{}\n{canonical_headers}\n{signed_headers}\n{}",
+        url.query().unwrap_or_default(),
+        sha256sum(body)
+    );
+
+    let scope = format!("{date}/{region}/{service}/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        sha256sum(&canonical_request)
+    );
+
+    let key = format!("AWS4{}", credentials.secret_access_key);
+    let key = hmac_sha256(key.as_bytes(), &date);
+    let key = hmac_sha256(&key, region);
+    let key = hmac_sha256(&key, service);
+    let key = hmac_sha256(&key, "aws4_request");
+    let signature = hex_encode(&hmac_sha256(&key, &string_to_sign));
+
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+        credentials.access_key_id
+    );
+
+    let mut headers: Vec<(&'static str, String)> = headers
+        .into_iter()
+        .filter(|(key, _)| *key != "host")
+        .collect();
+    headers.push(("authorization", authorization));
+    headers
+}
+
+fn uri_encode(input: &str) -> String {
+    let mut output = String::new();
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                output.push(byte as char)
+            }
+            _ => output.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    output
+}
+
+/// A message of the `application/vnd.amazon.eventstream` encoding.
+///
+/// Only string headers are kept, as those are the ones that identify the events.
+#[derive(Debug, Default, PartialEq)]
+struct EventStreamMessage {
+    headers: HashMap<String, String>,
+    payload: Vec<u8>,
+}
+
+impl EventStreamMessage {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(|v| v.as_str())
+    }
+}
+
+/// Decode the binary frames of ConverseStream as they arrive, chunk by chunk.
+///
+/// Each frame is a 12-byte prelude (total length, headers length and their CRC32), the headers,
+/// the payload and the CRC32 of everything before it.
+#[derive(Debug, Default)]
+struct EventStreamDecoder {
+    buffer: Vec<u8>,
+}
+
+impl EventStreamDecoder {
+    fn decode(&mut self, chunk: &[u8]) -> Result<Vec<EventStreamMessage>> {
+        self.buffer.extend_from_slice(chunk);
+        let mut messages = vec![];
+        while self.buffer.len() >= 12 {
+            let total_len = read_u32(&self.buffer[0..4]) as usize;
+            let headers_len = read_u32(&self.buffer[4..8]) as usize;
+            if crc32(&self.buffer[0..8]) != read_u32(&self.buffer[8..12]) {
+                bail!("Invalid event stream, prelude checksum mismatch");
+            }
+            if total_len < 16 + headers_len {
+                bail!("Invalid event stream, bad frame length {total_len}");
+            }
+            if self.buffer.len() < total_len {
+                break;
+            }
+            let frame: Vec<u8> = self.buffer.drain(..total_len).collect();
+            if crc32(&frame[..total_len - 4]) != read_u32(&frame[total_len - 4..]) {
+                bail!("Invalid event stream, message checksum mismatch");
+            }
+            messages.push(EventStreamMessage {
+                headers: parse_headers(&frame[12..12 + headers_len])?,
+                payload: frame[12 + headers_len..total_len - 4].to_vec(),
+            });
+        }
+        Ok(messages)
+    }
+}
+
+fn parse_headers(mut data: &[u8]) -> Result<HashMap<String, String>> {
+    fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+        if data.len() < len {
+            bail!("Invalid event stream, truncated headers");
+        }
+        let (head, tail) = data.split_at(len);
+        *data = tail;
+        Ok(head)
+    }
+    let mut headers = HashMap::new();
+    while !data.is_empty() {
+        let name_len = take(&mut data, 1)?[0] as usize;
+        let name = String::from_utf8_lossy(take(&mut data, name_len)?).to_string();
+        let value_len = match take(&mut data, 1)?[0] {
+            0 | 1 => 0,
+            2 => 1,
+            3 => 2,
+            4 => 4,
+            5 | 8 => 8,
+            9 => 16,
+            6 => {
+                let len = u16::from_be_bytes([take(&mut data, 1)?[0], take(&mut data, 1)?[0]]);
+                take(&mut data, len as usize)?;
+                continue;
+            }
+            7 => {
+                let len = u16::from_be_bytes([take(&mut data, 1)?[0], take(&mut data, 1)?[0]]);
+                let value = take(&mut data, len as usize)?;
+                headers.insert(name, String::from_utf8_lossy(value).to_string());
+                continue;
+            }
+            type_ => bail!("Invalid event stream, unknown header type {type_}"),
+        };
+        take(&mut data, value_len)?;
+    }
+    Ok(headers)
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::fixture;
+
+    fn encode(headers: &[(&str, &str)], payload: &str) -> Vec<u8> {
+        let mut header_bytes = vec![];
+        for (name, value) in headers {
+            header_bytes.push(name.len() as u8);
+            header_bytes.extend_from_slice(name.as_bytes());
+            header_bytes.push(7);
+            header_bytes.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            header_bytes.extend_from_slice(value.as_bytes());
+        }
+        let total_len = 16 + header_bytes.len() + payload.len();
+        let mut frame = vec![];
+        frame.extend_from_slice(&(total_len as u32).to_be_bytes());
+        frame.extend_from_slice(&(header_bytes.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&crc32(&frame).to_be_bytes());
+        frame.extend_from_slice(&header_bytes);
+        frame.extend_from_slice(payload.as_bytes());
+        frame.extend_from_slice(&crc32(&frame).to_be_bytes());
+        frame
+    }
+
+    fn event(event_type: &str, payload: &str) -> Vec<u8> {
+        encode(
+            &[
+                (":event-type", event_type),
+                (":content-type", "application/json"),
+                (":message-type", "event"),
+            ],
+            payload,
+        )
+    }
+
+    #[test]
+    fn test_sign_request() -> Result<()> {
+        // The `get-vanilla` case of the AWS SigV4 test suite
+        let credentials = Credentials {
+            access_key_id: "AKIDEXAMPLE".into(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".into(),
+            session_token: None,
+        };
+        let now = "2015-08-30T12:36:00Z".parse()?;
+        let url = "https://example.amazonaws.com/".parse()?;
+        let headers = sign_request(&credentials, "us-east-1", "service", "GET", &url, "", now);
+        assert_eq!(
+            headers,
+            [
+                ("x-amz-date", "20150830T123600Z".to_string()),
+                ("authorization", "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=host;x-amz-date, Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31".to_string()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_event_stream_decoder() -> Result<()> {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let mut data = event("contentBlockDelta", r#"{"delta":{"text":"Hello"}}"#);
+        data.extend(event("contentBlockDelta", r#"{"delta":{"text":"!"}}"#));
+        data.extend(event(
+            "metadata",
+            r#"{"usage":{"inputTokens":8,"outputTokens":2}}"#,
+        ));
+        let mut decoder = EventStreamDecoder::default();
+        let mut messages = vec![];
+        for chunk in data.chunks(7) {
+            messages.extend(decoder.decode(chunk)?);
+        }
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].header(":event-type"), Some("contentBlockDelta"));
+
+        let (mut handler, _rx) = fixture::handler();
+        for message in &messages {
+            handle_event(message, &mut handler)?;
+        }
+        assert_eq!(handler.get_buffer(), "Hello!");
+        assert_eq!(handler.get_usage(), (Some(8), Some(2)));
+
+        let exception = encode(
+            &[
+                (":exception-type", "throttlingException"),
+                (":content-type", "application/json"),
+                (":message-type", "exception"),
+            ],
+            r#"{"message":"Too many tokens, please wait before trying again."}"#,
+        );
+        let messages = EventStreamDecoder::default().decode(&exception)?;
+        let err = handle_event(&messages[0], &mut handler).unwrap_err();
+        assert!(err.to_string().starts_with("Too many requests:"));
+
+        let mut corrupted = event("contentBlockDelta", r#"{"delta":{"text":"Hello"}}"#);
+        let len = corrupted.len();
+        corrupted[len - 6] ^= 0xff;
+        assert!(EventStreamDecoder::default().decode(&corrupted).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let client = BedrockClient::for_test(
+            "{ access_key_id: xxx, secret_access_key: xxx, region: us-east-1 }",
+        )?;
+        let http = ReqwestClient::new();
+        let request = |stream| client.request_builder(&http, fixture::send_data(stream));
+
+        let output =
+            send_message(fixture::replay("bedrock/success", request(false)?).await?).await?;
+        assert_eq!(output.text, "Hello! How can I help you today?");
+        assert_eq!(
+            (output.input_tokens, output.output_tokens),
+            (Some(8), Some(12))
+        );
+
+        let err = send_message(fixture::replay("bedrock/access_denied", request(false)?).await?)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with(
+            "Access denied: You don't have access to the model with the specified model ID."
+        ));
+        Ok(())
+    }
+}
@@ -1,19 +1,57 @@
 use super::{
-    extract_sytem_message, ClaudeClient, Client, ExtraConfig, ImageUrl, MessageContent,
-    MessageContentPart, Model, ModelConfig, PromptType, ReplyHandler, SendData,
+    event_source, extract_sytem_message, note_response, probe_data, probe_listing,
+    send_request, CacheUsage, ClaudeClient, Client, CompletionOutput, ExtraConfig, ImageUrl, MessageContent,
+    MessageContentPart, MessageRole, Model, ModelConfig, PromptType, ReplyHandler, SendData,
+    ToolCall, ToolResult,
 };
 
-use crate::utils::PromptKind;
+use crate::utils::{count_tokens, sha256sum, PromptKind};
 
 use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
 use futures_util::StreamExt;
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType, ImageReader};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
 use reqwest::{Client as ReqwestClient, RequestBuilder};
-use reqwest_eventsource::{Error as EventSourceError, Event, RequestBuilderExt};
+use reqwest_eventsource::{Error as EventSourceError, Event};
 use serde::Deserialize;
 use serde_json::{json, Value};
+use std::{
+    collections::VecDeque,
+    io::Cursor,
+    time::Duration,
+};
+use tokio::time::sleep;
+
+const API_BASE: &str = "https://api.anthropic.com";
+
+const PROMPT_CACHING_BETA: &str = "prompt-caching-2024-07-31";
+const MESSAGE_BATCHES_BETA: &str = "message-batches-2024-09-24";
+const TOKEN_COUNTING_BETA: &str = "token-counting-2024-11-01";
+
+// https://docs.anthropic.com/en/docs/build-with-claude/vision
+const MAX_IMAGES: usize = 20;
+const MAX_IMAGE_BYTES: usize = 5 * 1024 * 1024;
+const MAX_IMAGE_DIMENSION: u32 = 8000;
+
+const USER_PLACEHOLDER: &str = "...";
 
-const API_BASE: &str = "https://api.anthropic.com/v1/messages";
+const MAX_STOP_SEQUENCES: usize = 8191;
+
+const BATCH_POLL_INTERVAL_MIN: Duration = Duration::from_secs(1);
+const BATCH_POLL_INTERVAL_MAX: Duration = Duration::from_secs(60);
+
+/// The fields of a messages request the count_tokens API accepts.
+const COUNT_TOKENS_FIELDS: [&str; 5] = ["model", "system", "messages", "tools", "tool_choice"];
+/// The number of recent counts kept, enough for the requests of a session and their retries.
+const INPUT_TOKENS_CACHE_SIZE: usize = 32;
+
+lazy_static! {
+    /// The input tokens of the recent requests counted by the API, by the hash of the body.
+    static ref INPUT_TOKENS: Mutex<VecDeque<(String, usize)>> = Mutex::new(VecDeque::new());
+}
 
 const MODELS: [(&str, usize, &str); 3] = [
     // https://docs.anthropic.com/claude/docs/models-overview
@@ -22,10 +60,16 @@ const MODELS: [(&str, usize, &str); 3] = [
     ("claude-3-haiku-20240307", 200000, "text,vision"),
 ];
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Default)]
 pub struct ClaudeConfig {
     pub name: Option<String>,
     pub api_key: Option<String>,
+    pub api_base: Option<String>,
+    pub prompt_cache: Option<bool>,
+    pub stop_sequences: Option<Vec<String>>,
+    pub top_k: Option<u64>,
+    pub resize_images: Option<bool>,
+    pub check_input_tokens: Option<bool>,
     #[serde(default)]
     pub models: Vec<ModelConfig>,
     pub extra: Option<ExtraConfig>,
@@ -35,7 +79,12 @@ pub struct ClaudeConfig {
 impl Client for ClaudeClient {
     client_common_fns!();
 
-    async fn send_message_inner(&self, client: &ReqwestClient, data: SendData) -> Result<String> {
+    async fn send_message_inner(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+    ) -> Result<CompletionOutput> {
+        self.check_input_tokens(client, &data).await?;
         let builder = self.request_builder(client, data)?;
         send_message(builder).await
     }
@@ -46,71 +95,311 @@ impl Client for ClaudeClient {
         handler: &mut ReplyHandler,
         data: SendData,
     ) -> Result<()> {
+        self.check_input_tokens(client, &data).await?;
         let builder = self.request_builder(client, data)?;
         send_message_streaming(builder, handler).await
     }
+
+    async fn count_tokens_inner(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+    ) -> Result<Option<usize>> {
+        if !self.config.check_input_tokens.unwrap_or_default() {
+            return Ok(None);
+        }
+        self.count_input_tokens(client, data).await.map(Some)
+    }
+
+    async fn send_batch_inner(
+        &self,
+        client: &ReqwestClient,
+        list: Vec<SendData>,
+    ) -> Result<Vec<Result<CompletionOutput>>> {
+        let count = list.len();
+        let (builder, betas) = self.batch_request_builder(client, list)?;
+        let batch = send_json(builder).await?;
+        let id = batch["id"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Invalid response data: {batch}"))?;
+        // Follow the api base rather than `results_url`, which always points to Anthropic
+        let batch_url = format!("{}/batches/{id}", self.messages_url());
+        wait_batch(batch.clone(), || {
+            self.add_headers(client.get(&batch_url), &betas)
+        })
+        .await?;
+        let builder = self.add_headers(client.get(format!("{batch_url}/results")), &betas);
+        let text = download_batch_results(builder).await?;
+        parse_batch_results(&text, count)
+    }
+
+    /// Probe the free models listing, the api base may not have it.
+    async fn health_check_inner(&self, client: &ReqwestClient) -> Result<()> {
+        let builder = self.add_headers(client.get(self.models_url()), &[]);
+        if !probe_listing(builder).await? {
+            self.send_message_inner(client, probe_data()).await?;
+        }
+        Ok(())
+    }
 }
 
 impl ClaudeClient {
     list_models_fn!(ClaudeConfig, &MODELS);
     config_get_fn!(api_key, get_api_key);
+    config_get_fn!(api_base, get_api_base);
 
-    pub const PROMPTS: [PromptType<'static>; 1] =
-        [("api_key", "API Key:", false, PromptKind::String)];
+    pub const PROMPTS: [PromptType<'static>; 2] = [
+        ("api_key", "API Key:", false, PromptKind::String),
+        ("api_base", "API Base:", false, PromptKind::String),
+    ];
 
-    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
-        let api_key = self.get_api_key().ok();
+    /// Build the request creating the batch, returning it with the betas it needs.
+    fn batch_request_builder(
+        &self,
+        client: &ReqwestClient,
+        list: Vec<SendData>,
+    ) -> Result<(RequestBuilder, Vec<&'static str>)> {
+        let count = list.len();
+        let mut betas = vec![MESSAGE_BATCHES_BETA];
+        let mut requests = vec![];
+        for (index, data) in list.into_iter().enumerate() {
+            let (body, prompt_cache) = self.prepare_body(SendData {
+                stream: false,
+                ..data
+            })?;
+            if prompt_cache && !betas.contains(&PROMPT_CACHING_BETA) {
+                betas.push(PROMPT_CACHING_BETA);
+            }
+            requests.push(json!({ "custom_id": format!("request-{index}"), "params": body }));
+        }
+
+        let url = format!("{}/batches", self.messages_url());
+
+        debug!("Claude Batch Request: {url} {count} requests");
+
+        let builder = self
+            .add_headers(client.post(&url), &betas)
+            .json(&json!({ "requests": requests }));
+        Ok((builder, betas))
+    }
+
+    /// Count the input with the count_tokens API, reusing the count of an identical request.
+    async fn count_input_tokens(&self, client: &ReqwestClient, data: SendData) -> Result<usize> {
+        let (body, prompt_cache) = self.prepare_body(data)?;
+        let body: Value = body
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(k, _)| COUNT_TOKENS_FIELDS.contains(&k.as_str()))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<serde_json::Map<_, _>>()
+            .into();
+        let key = sha256sum(&body.to_string());
+        if let Some((_, tokens)) = INPUT_TOKENS.lock().iter().find(|(v, _)| *v == key) {
+            return Ok(*tokens);
+        }
+
+        let url = format!("{}/count_tokens", self.messages_url());
+
+        debug!("Claude Request: {url} {body}");
+
+        let mut betas = vec![TOKEN_COUNTING_BETA];
+        if prompt_cache {
+            betas.push(PROMPT_CACHING_BETA);
+        }
+        let builder = self.add_headers(client.post(&url), &betas).json(&body);
+        let tokens = fetch_input_tokens(builder).await?;
+        let mut cache = INPUT_TOKENS.lock();
+        if cache.len() == INPUT_TOKENS_CACHE_SIZE {
+            cache.pop_front();
+        }
+        cache.push_back((key, tokens));
+        Ok(tokens)
+    }
+
+    /// Fail fast if the input exceeds the context window, by the count of the count_tokens API.
+    async fn check_input_tokens(&self, client: &ReqwestClient, data: &SendData) -> Result<()> {
+        let Some(max_input_tokens) = self
+            .model
+            .max_input_tokens
+            .filter(|_| self.config.check_input_tokens.unwrap_or_default())
+        else {
+            return Ok(());
+        };
+        let tokens = self.count_input_tokens(client, data.clone()).await?;
+        if tokens > max_input_tokens {
+            bail!("The input is {tokens} tokens but the model allows {max_input_tokens}");
+        }
+        Ok(())
+    }
 
-        let body = build_body(data, &self.model)?;
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let (body, prompt_cache) = self.prepare_body(data)?;
 
-        let url = API_BASE;
+        let url = self.messages_url();
 
         debug!("Claude Request: {url} {body}");
 
-        let mut builder = client.post(url).json(&body);
+        let betas = if prompt_cache {
+            vec![PROMPT_CACHING_BETA]
+        } else {
+            vec![]
+        };
+        let builder = self.add_headers(client.post(&url), &betas).json(&body);
+
+        Ok(builder)
+    }
+
+    fn messages_url(&self) -> String {
+        let api_base = self.get_api_base().unwrap_or_else(|_| API_BASE.to_string());
+        format!("{}/v1/messages", api_base.trim_end_matches('/'))
+    }
+
+    fn models_url(&self) -> String {
+        let api_base = self.get_api_base().unwrap_or_else(|_| API_BASE.to_string());
+        format!("{}/v1/models", api_base.trim_end_matches('/'))
+    }
+
+    /// Build the body, returning whether the prompt cache is used.
+    ///
+    /// The `stop_sequences` and `top_k` of the model's `extra_fields` replace the client's.
+    fn prepare_body(&self, data: SendData) -> Result<(Value, bool)> {
+        let mut body = build_body(data, &self.model)?;
+        if let Some(stop_sequences) = &self.config.stop_sequences {
+            body["stop_sequences"] = stop_sequences.clone().into();
+        }
+        if let Some(top_k) = self.config.top_k {
+            body["top_k"] = top_k.into();
+        }
+        self.model.merge_extra_fields(&mut body);
+        check_params(&body)?;
+        check_images(&mut body, self.config.resize_images.unwrap_or_default())?;
+        let prompt_cache = self.config.prompt_cache.unwrap_or_default()
+            && add_prompt_cache(&mut body, &self.model);
+        Ok((body, prompt_cache))
+    }
+
+    fn add_headers(&self, mut builder: RequestBuilder, betas: &[&str]) -> RequestBuilder {
         builder = builder.header("anthropic-version", "2023-06-01");
-        if let Some(api_key) = api_key {
+        if !betas.is_empty() {
+            builder = builder.header("anthropic-beta", betas.join(","));
+        }
+        if let Ok(api_key) = self.get_api_key() {
             builder = builder.header("x-api-key", api_key)
         }
+        builder
+    }
+}
 
-        Ok(builder)
+pub(crate) async fn send_message(builder: RequestBuilder) -> Result<CompletionOutput> {
+    let res = send_request(builder).await?;
+    let status = res.status();
+    let data: Value = res.json().await?;
+    if status != 200 {
+        catch_error(&data, status.as_u16())?;
     }
+
+    extract_output(&data)
+}
+
+async fn fetch_input_tokens(builder: RequestBuilder) -> Result<usize> {
+    let data = send_json(builder).await?;
+    data["input_tokens"]
+        .as_u64()
+        .map(|v| v as usize)
+        .ok_or_else(|| anyhow!("Invalid response data: {data}"))
 }
 
-async fn send_message(builder: RequestBuilder) -> Result<String> {
-    let res = builder.send().await?;
+async fn send_json(builder: RequestBuilder) -> Result<Value> {
+    let res = send_request(builder).await?;
     let status = res.status();
     let data: Value = res.json().await?;
     if status != 200 {
         catch_error(&data, status.as_u16())?;
     }
+    Ok(data)
+}
+
+/// Poll the batch with backoff until its processing has ended.
+async fn wait_batch(mut batch: Value, get_batch: impl Fn() -> RequestBuilder) -> Result<Value> {
+    let mut interval = BATCH_POLL_INTERVAL_MIN;
+    loop {
+        match batch["processing_status"].as_str() {
+            Some("ended") => return Ok(batch),
+            Some(_) => {}
+            None => bail!("Invalid response data: {batch}"),
+        }
+        sleep(interval).await;
+        interval = (interval * 2).min(BATCH_POLL_INTERVAL_MAX);
+        batch = send_json(get_batch()).await?;
+    }
+}
 
-    let output = data["content"][0]["text"]
-        .as_str()
-        .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+async fn download_batch_results(builder: RequestBuilder) -> Result<String> {
+    let res = send_request(builder).await?;
+    let status = res.status();
+    let text = res.text().await?;
+    if status != 200 {
+        let data: Value = serde_json::from_str(&text).unwrap_or_default();
+        catch_error(&data, status.as_u16())?;
+    }
+    Ok(text)
+}
 
-    Ok(output.to_string())
+/// Match the lines of a results file back to the requests by their `custom_id`.
+fn parse_batch_results(text: &str, count: usize) -> Result<Vec<Result<CompletionOutput>>> {
+    let mut outputs: Vec<Option<Result<CompletionOutput>>> = (0..count).map(|_| None).collect();
+    for line in text.lines().filter(|v| !v.trim().is_empty()) {
+        let data: Value = serde_json::from_str(line)?;
+        let index = data["custom_id"]
+            .as_str()
+            .and_then(|v| v.strip_prefix("request-"))
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|v| *v < count)
+            .ok_or_else(|| anyhow!("Invalid batch result: {line}"))?;
+        let result = &data["result"];
+        let output = match result["type"].as_str() {
+            Some("succeeded") => extract_output(&result["message"]),
+            // Always an error, with the same shape as an error response
+            Some("errored") => {
+                catch_error(&result["error"], 200).map(|_| CompletionOutput::default())
+            }
+            Some(typ) => Err(anyhow!("The request was {typ}")),
+            None => Err(anyhow!("Invalid batch result: {line}")),
+        };
+        outputs[index] = Some(output);
+    }
+    Ok(outputs
+        .into_iter()
+        .map(|v| v.unwrap_or_else(|| Err(anyhow!("The batch has no result for the request"))))
+        .collect())
 }
 
-async fn send_message_streaming(builder: RequestBuilder, handler: &mut ReplyHandler) -> Result<()> {
-    let mut es = builder.eventsource()?;
+pub(crate) async fn send_message_streaming(
+    builder: RequestBuilder,
+    handler: &mut ReplyHandler,
+) -> Result<()> {
+    let mut es = event_source(builder)?;
+    let mut tool_use = None;
     while let Some(event) = es.next().await {
         match event {
             Ok(Event::Open) => {}
             Ok(Event::Message(message)) => {
                 let data: Value = serde_json::from_str(&message.data)?;
-                if let Some(typ) = data["type"].as_str() {
-                    if typ == "content_block_delta" {
-                        if let Some(text) = data["delta"]["text"].as_str() {
-                            handler.text(text)?;
-                        }
-                    }
+                if handle_event(&data, handler, &mut tool_use)? {
+                    es.close();
+                    break;
                 }
             }
             Err(err) => {
                 match err {
-                    EventSourceError::StreamEnded => {}
+                    EventSourceError::StreamEnded => {
+                        bail!(
+                            "The stream ended before `message_stop`, the output may be incomplete"
+                        )
+                    }
                     EventSourceError::InvalidStatusCode(status, res) => {
+                        note_response(&res);
                         let text = res.text().await?;
                         let data: Value = match text.parse() {
                             Ok(data) => data,
@@ -136,23 +425,192 @@ async fn send_message_streaming(builder: RequestBuilder, handler: &mut ReplyHand
     Ok(())
 }
 
-fn build_body(data: SendData, model: &Model) -> Result<Value> {
+pub(crate) fn extract_output(data: &Value) -> Result<CompletionOutput> {
+    let blocks = data["content"]
+        .as_array()
+        .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+    let mut text = String::new();
+    let mut tool_calls = vec![];
+    for block in blocks {
+        match block["type"].as_str() {
+            Some("text") => text.push_str(block["text"].as_str().unwrap_or_default()),
+            Some("tool_use") => {
+                let (Some(id), Some(name)) = (block["id"].as_str(), block["name"].as_str()) else {
+                    bail!("Invalid response data: {data}");
+                };
+                tool_calls.push(ToolCall::new(name, block["input"].clone(), Some(id.into())));
+            }
+            _ => {}
+        }
+    }
+
+    let stop_reason = data["stop_reason"].as_str();
+    let (input_tokens, output_tokens, cache_usage) = extract_usage(&data["usage"]);
+    Ok(CompletionOutput {
+        text,
+        tool_calls,
+        input_tokens,
+        output_tokens,
+        request_id: None,
+        stop_sequence: extract_stop_sequence(data),
+        stop_reason: stop_reason.map(|v| v.to_string()),
+        stats: None,
+        cache_usage,
+    })
+}
+
+/// A `tool_use` block being streamed, its input arrives as pieces of JSON.
+#[derive(Debug)]
+pub(crate) struct PendingToolUse {
+    id: String,
+    name: String,
+    input: String,
+}
+
+/// Handle one event of a Messages stream, returning whether the message has stopped.
+pub(crate) fn handle_event(
+    data: &Value,
+    handler: &mut ReplyHandler,
+    tool_use: &mut Option<PendingToolUse>,
+) -> Result<bool> {
+    match data["type"].as_str() {
+        Some("message_stop") => return Ok(true),
+        Some("error") => catch_error(data, 200)?,
+        Some("message_start") => {
+            let (input_tokens, output_tokens, cache_usage) =
+                extract_usage(&data["message"]["usage"]);
+            handler.set_usage(input_tokens, output_tokens);
+            if let Some(cache_usage) = cache_usage {
+                handler.set_cache_usage(cache_usage);
+            }
+        }
+        Some("message_delta") => {
+            handler.set_usage(None, data["usage"]["output_tokens"].as_u64());
+            if let Some(stop_sequence) = extract_stop_sequence(&data["delta"]) {
+                handler.set_stop_sequence(&stop_sequence);
+            }
+            if let Some(stop_reason) = data["delta"]["stop_reason"].as_str() {
+                handler.set_stop_reason(stop_reason);
+            }
+        }
+        Some("content_block_start") => {
+            let block = &data["content_block"];
+            if let ("tool_use", Some(id), Some(name)) = (
+                block["type"].as_str().unwrap_or_default(),
+                block["id"].as_str(),
+                block["name"].as_str(),
+            ) {
+                *tool_use = Some(PendingToolUse {
+                    id: id.into(),
+                    name: name.into(),
+                    input: String::new(),
+                });
+            }
+        }
+        Some("content_block_delta") => {
+            if let Some(text) = data["delta"]["text"].as_str() {
+                handler.text(text)?;
+            } else if let (Some(partial_json), Some(tool_use)) =
+                (data["delta"]["partial_json"].as_str(), tool_use.as_mut())
+            {
+                tool_use.input.push_str(partial_json);
+            }
+        }
+        Some("content_block_stop") => {
+            if let Some(PendingToolUse { id, name, input }) = tool_use.take() {
+                // A tool without parameters streams no input at all
+                let input = match input.trim() {
+                    "" => json!({}),
+                    input => serde_json::from_str(input)
+                        .map_err(|_| anyhow!("Invalid input of the tool '{name}': {input}"))?,
+                };
+                handler.tool_call(ToolCall::new(&name, input, Some(id)));
+            }
+        }
+        _ => {}
+    }
+    Ok(false)
+}
+
+fn extract_stop_sequence(data: &Value) -> Option<String> {
+    match (data["stop_reason"].as_str(), data["stop_sequence"].as_str()) {
+        (Some("stop_sequence"), Some(stop_sequence)) => {
+            debug!("Claude stopped at the stop sequence {stop_sequence:?}");
+            Some(stop_sequence.to_string())
+        }
+        _ => None,
+    }
+}
+
+/// Input tokens include the ones written to and read from the prompt cache.
+fn extract_usage(usage: &Value) -> (Option<u64>, Option<u64>, Option<CacheUsage>) {
+    let cache_usage = CacheUsage {
+        creation_input_tokens: usage["cache_creation_input_tokens"]
+            .as_u64()
+            .unwrap_or_default(),
+        read_input_tokens: usage["cache_read_input_tokens"]
+            .as_u64()
+            .unwrap_or_default(),
+    };
+    let input_tokens = usage["input_tokens"]
+        .as_u64()
+        .map(|v| v + cache_usage.creation_input_tokens + cache_usage.read_input_tokens);
+    let cache_usage = (cache_usage != CacheUsage::default()).then_some(cache_usage);
+    (input_tokens, usage["output_tokens"].as_u64(), cache_usage)
+}
+
+pub(crate) fn build_body(data: SendData, model: &Model) -> Result<Value> {
     let SendData {
         mut messages,
         temperature,
         top_p,
+        functions,
         stream,
     } = data;
 
     let system_message = extract_sytem_message(&mut messages);
 
     let mut network_image_urls = vec![];
+    let mut tool_use_count = 0;
     let messages: Vec<Value> = messages
         .into_iter()
-        .map(|message| {
+        .flat_map(|message| {
             let role = message.role;
             let content = match message.content {
                 MessageContent::Text(text) => vec![json!({"type": "text", "text": text})],
+                MessageContent::ToolResults((results, text)) => {
+                    let mut tool_uses = vec![];
+                    if !text.is_empty() {
+                        tool_uses.push(json!({"type": "text", "text": text}));
+                    }
+                    let mut tool_results = vec![];
+                    for ToolResult { call, output } in results {
+                        // Calls made by other clients have no id, but each use needs its result
+                        let id = call.id.unwrap_or_else(|| {
+                            tool_use_count += 1;
+                            format!("toolu_{tool_use_count}")
+                        });
+                        let content = match output {
+                            Value::String(output) => output,
+                            output => output.to_string(),
+                        };
+                        tool_uses.push(json!({
+                            "type": "tool_use",
+                            "id": id,
+                            "name": call.name,
+                            "input": call.arguments,
+                        }));
+                        tool_results.push(json!({
+                            "type": "tool_result",
+                            "tool_use_id": id,
+                            "content": content,
+                        }));
+                    }
+                    return vec![
+                        json!({ "role": MessageRole::Assistant, "content": tool_uses }),
+                        json!({ "role": MessageRole::User, "content": tool_results }),
+                    ];
+                }
                 MessageContent::Array(list) => list
                     .into_iter()
                     .map(|item| match item {
@@ -180,7 +638,7 @@ fn build_body(data: SendData, model: &Model) -> Result<Value> {
                     })
                     .collect(),
             };
-            json!({ "role": role, "content": content })
+            vec![json!({ "role": role, "content": content })]
         })
         .collect();
 
@@ -191,6 +649,8 @@ fn build_body(data: SendData, model: &Model) -> Result<Value> {
         );
     }
 
+    let messages = normalize_messages(messages);
+
     let max_tokens = model.max_output_tokens.unwrap_or(4096);
 
     let mut body = json!({
@@ -203,8 +663,26 @@ fn build_body(data: SendData, model: &Model) -> Result<Value> {
         body["system"] = system.into();
     }
 
+    if let Some(functions) = functions.filter(|v| !v.is_empty()) {
+        let tools: Vec<Value> = functions
+            .into_iter()
+            .map(|v| {
+                json!({
+                    "name": v.name,
+                    "description": v.description,
+                    "input_schema": v.parameters,
+                })
+            })
+            .collect();
+        body["tools"] = tools.into();
+    }
+
     if let Some(v) = temperature {
-        body["temperature"] = v.into();
+        // Other providers go up to 2, Claude only to 1
+        if !(0.0..=1.0).contains(&v) {
+            warn!("Claude accepts a temperature from 0 to 1, clamped {v}");
+        }
+        body["temperature"] = v.clamp(0.0, 1.0).into();
     }
     if let Some(v) = top_p {
         body["top_p"] = v.into();
@@ -215,7 +693,190 @@ fn build_body(data: SendData, model: &Model) -> Result<Value> {
     Ok(body)
 }
 
-fn catch_error(data: &Value, status: u16) -> Result<()> {
+/// Check the parameters from the config, which the API would reject.
+fn check_params(body: &Value) -> Result<()> {
+    if let Some(value) = body.get("top_k") {
+        if value.as_u64().is_none_or(|v| v == 0) {
+            bail!("Invalid `top_k` {value}, expected a positive integer");
+        }
+    }
+    let Some(value) = body.get("stop_sequences") else {
+        return Ok(());
+    };
+    let Some(list) = value.as_array() else {
+        bail!("Invalid `stop_sequences` {value}, expected a list of strings");
+    };
+    if list.len() > MAX_STOP_SEQUENCES {
+        bail!(
+            "Too many `stop_sequences`, {} given but at most {MAX_STOP_SEQUENCES} are allowed",
+            list.len()
+        );
+    }
+    for item in list {
+        if item.as_str().is_none_or(|v| v.trim().is_empty()) {
+            bail!("Invalid stop sequence {item}, expected a string with non-whitespace characters");
+        }
+    }
+    Ok(())
+}
+
+/// Check the images against the limits of the API, or downscale the oversized ones if `resize`.
+fn check_images(body: &mut Value, resize: bool) -> Result<()> {
+    let mut index = 0;
+    for message in body["messages"].as_array_mut().into_iter().flatten() {
+        for block in message["content"].as_array_mut().into_iter().flatten() {
+            if block["type"] != "image" {
+                continue;
+            }
+            index += 1;
+            if index > MAX_IMAGES {
+                bail!("Too many images, Claude accepts at most {MAX_IMAGES} in a request");
+            }
+            let source = &mut block["source"];
+            let data = STANDARD
+                .decode(source["data"].as_str().unwrap_or_default())
+                .map_err(|_| anyhow!("Image #{index} is not valid base64"))?;
+            let (width, height) = ImageReader::new(Cursor::new(&data))
+                .with_guessed_format()?
+                .into_dimensions()
+                .map_err(|err| anyhow!("Image #{index} can't be read, {err}"))?;
+            let problem = if width.max(height) > MAX_IMAGE_DIMENSION {
+                format!(
+                    "is {width}x{height} pixels, over the limit of {MAX_IMAGE_DIMENSION} pixels"
+                )
+            } else if data.len() > MAX_IMAGE_BYTES {
+                format!(
+                    "is {} bytes, over the limit of {MAX_IMAGE_BYTES} bytes",
+                    data.len()
+                )
+            } else {
+                continue;
+            };
+            if !resize {
+                bail!("Image #{index} {problem}, set `resize_images: true` to downscale it");
+            }
+            debug!("Image #{index} {problem}, downscaling it");
+            let data = downscale_image(&data, width.max(height))?;
+            *source = json!({
+                "type": "base64",
+                "media_type": "image/jpeg",
+                "data": STANDARD.encode(data),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Re-encode the image as JPEG, shrinking it until it fits both limits.
+fn downscale_image(data: &[u8], dimension: u32) -> Result<Vec<u8>> {
+    let image = image::load_from_memory(data)?;
+    let mut target = dimension.min(MAX_IMAGE_DIMENSION);
+    loop {
+        let resized = image.resize(target, target, FilterType::Triangle).to_rgb8();
+        let mut output = vec![];
+        JpegEncoder::new_with_quality(&mut output, 85).encode_image(&resized)?;
+        if output.len() <= MAX_IMAGE_BYTES || target <= 1 {
+            return Ok(output);
+        }
+        target = target * 3 / 4;
+    }
+}
+
+/// Make the messages start with a user turn and alternate, as the API requires.
+///
+/// A trailing assistant message prefills the reply, so it is kept as is.
+fn normalize_messages(mut list: Vec<Value>) -> Vec<Value> {
+    let placeholder = || {
+        json!({
+            "role": "user",
+            "content": [{ "type": "text", "text": USER_PLACEHOLDER }]
+        })
+    };
+    let prefill = match list.last() {
+        Some(v) if v["role"] == "assistant" => list.pop(),
+        _ => None,
+    };
+    let mut output: Vec<Value> = vec![];
+    let mut repairs = vec![];
+    for mut message in list {
+        if output.is_empty() && message["role"] == "assistant" {
+            output.push(placeholder());
+            repairs.push("inserted a user message before a leading assistant message");
+        }
+        if let Some(last) = output.last_mut() {
+            if last["role"] == message["role"] {
+                if let (Some(blocks), Some(more)) = (
+                    last["content"].as_array_mut(),
+                    message["content"].as_array_mut(),
+                ) {
+                    blocks.append(more);
+                    repairs.push("merged adjacent messages of the same role");
+                    continue;
+                }
+            }
+        }
+        output.push(message);
+    }
+    if let Some(prefill) = prefill {
+        if output.last().is_none_or(|v| v["role"] == "assistant") {
+            output.push(placeholder());
+            repairs.push("inserted a user message before the prefill");
+        }
+        output.push(prefill);
+    }
+    if !repairs.is_empty() {
+        debug!("Claude messages repaired: {}", repairs.join(", "));
+    }
+    output
+}
+
+/// Mark the system prompt and the first user message as cacheable.
+///
+/// Returns false, leaving the body untouched, when the prefix is shorter than
+/// the minimum the model can cache.
+fn add_prompt_cache(body: &mut Value, model: &Model) -> bool {
+    let min_tokens = if model.name.contains("haiku") {
+        2048
+    } else {
+        1024
+    };
+    let first_user_index = body["messages"]
+        .as_array()
+        .and_then(|list| list.iter().position(|v| v["role"] == "user"));
+    let mut tokens = body["system"]
+        .as_str()
+        .map(count_tokens)
+        .unwrap_or_default();
+    if let Some(index) = first_user_index {
+        if let Some(list) = body["messages"][index]["content"].as_array() {
+            tokens += list
+                .iter()
+                .filter_map(|v| v["text"].as_str())
+                .map(count_tokens)
+                .sum::<usize>();
+        }
+    }
+    if tokens < min_tokens {
+        return false;
+    }
+
+    let cache_control = json!({ "type": "ephemeral" });
+    if let Some(system) = body["system"].as_str() {
+        body["system"] =
+            json!([{ "type": "text", "text": system, "cache_control": cache_control }]);
+    }
+    if let Some(index) = first_user_index {
+        if let Some(block) = body["messages"][index]["content"]
+            .as_array_mut()
+            .and_then(|list| list.last_mut())
+        {
+            block["cache_control"] = cache_control;
+        }
+    }
+    true
+}
+
+pub(crate) fn catch_error(data: &Value, status: u16) -> Result<()> {
     debug!("Invalid response, status: {status}, data: {data}");
     if let Some(error) = data["error"].as_object() {
         if let (Some(type_), Some(message)) = (error["type"].as_str(), error["message"].as_str()) {
@@ -224,3 +885,700 @@ fn catch_error(data: &Value, status: u16) -> Result<()> {
     }
     bail!("Invalid response, status: {status}, data: {data}");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{fixture, ClientConfig, FunctionDeclaration, Message};
+    use crate::config::{Config, Input};
+
+    use parking_lot::RwLock;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_messages_url() {
+        let build = |api_base: Option<&str>| {
+            let config = ClaudeConfig {
+                api_base: api_base.map(|v| v.to_string()),
+                ..Default::default()
+            };
+            ClaudeClient {
+                global_config: Arc::new(RwLock::new(Config::default())),
+                model: ClaudeClient::list_models(&config).remove(0),
+                config,
+            }
+        };
+        assert_eq!(
+            build(None).messages_url(),
+            "https://api.anthropic.com/v1/messages"
+        );
+        assert_eq!(
+            build(Some(
+                "https://gateway.ai.cloudflare.com/v1/xxx/gw/anthropic/"
+            ))
+            .messages_url(),
+            "https://gateway.ai.cloudflare.com/v1/xxx/gw/anthropic/v1/messages"
+        );
+    }
+
+    #[test]
+    fn test_add_prompt_cache() -> Result<()> {
+        let model = ClaudeClient::list_models(&ClaudeConfig::default()).remove(0);
+        let data = |system: &str| SendData {
+            messages: vec![
+                Message::system(system),
+                Message::user("Hi"),
+                Message::assistant("Hello"),
+                Message::user("Bye"),
+            ],
+            temperature: None,
+            top_p: None,
+            functions: None,
+            stream: false,
+        };
+
+        let mut body = build_body(data("Be brief"), &model)?;
+        let expect = body.clone();
+        assert!(!add_prompt_cache(&mut body, &model));
+        assert_eq!(body, expect);
+
+        let system = "Be brief. ".repeat(400);
+        let mut body = build_body(data(&system), &model)?;
+        assert!(add_prompt_cache(&mut body, &model));
+        assert_eq!(
+            body["system"],
+            json!([{ "type": "text", "text": system, "cache_control": { "type": "ephemeral" } }])
+        );
+        assert_eq!(
+            body["messages"][0]["content"],
+            json!([{ "type": "text", "text": "Hi", "cache_control": { "type": "ephemeral" } }])
+        );
+        assert_eq!(
+            body["messages"][2]["content"],
+            json!([{ "type": "text", "text": "Bye" }])
+        );
+
+        let haiku = ClaudeClient::list_models(&ClaudeConfig::default()).remove(2);
+        let mut body = build_body(data(&system), &haiku)?;
+        assert!(!add_prompt_cache(&mut body, &haiku));
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_body_with_tools() -> Result<()> {
+        let model = ClaudeClient::list_models(&ClaudeConfig::default()).remove(2);
+        let call = ToolCall::new(
+            "get_weather",
+            json!({ "location": "Boston" }),
+            Some("toolu_01A09q90qw90lq917835lq9".into()),
+        );
+        let data = SendData {
+            messages: vec![
+                Message::user("What's the weather in Boston?"),
+                Message {
+                    role: MessageRole::Assistant,
+                    content: MessageContent::ToolResults((
+                        vec![ToolResult {
+                            call,
+                            output: json!({ "temperature": 22 }),
+                        }],
+                        "Let me check the weather.".into(),
+                    )),
+                    pinned: false,
+                },
+            ],
+            temperature: None,
+            top_p: None,
+            functions: Some(vec![FunctionDeclaration {
+                name: "get_weather".into(),
+                description: "Get the current weather of a location".into(),
+                parameters: json!({ "type": "object" }),
+            }]),
+            stream: false,
+        };
+        let body = build_body(data, &model)?;
+        assert_eq!(
+            body["messages"][1],
+            json!({
+                "role": "assistant",
+                "content": [
+                    { "type": "text", "text": "Let me check the weather." },
+                    {
+                        "type": "tool_use",
+                        "id": "toolu_01A09q90qw90lq917835lq9",
+                        "name": "get_weather",
+                        "input": { "location": "Boston" },
+                    },
+                ]
+            })
+        );
+        assert_eq!(
+            body["messages"][2],
+            json!({
+                "role": "user",
+                "content": [{
+                    "type": "tool_result",
+                    "tool_use_id": "toolu_01A09q90qw90lq917835lq9",
+                    "content": "{\"temperature\":22}",
+                }]
+            })
+        );
+        assert_eq!(
+            body["tools"],
+            json!([{
+                "name": "get_weather",
+                "description": "Get the current weather of a location",
+                "input_schema": { "type": "object" },
+            }])
+        );
+        Ok(())
+    }
+
+    /// A haiku client, the model the fixtures were recorded with.
+    fn haiku(config: &str) -> Result<ClaudeClient> {
+        let mut client = ClaudeClient::for_test(config)?;
+        client.model = ClaudeClient::list_models(&client.config).remove(2);
+        Ok(client)
+    }
+
+    fn ask(messages: Vec<Message>, stream: bool) -> SendData {
+        SendData {
+            messages,
+            ..fixture::send_data(stream)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_use_fixtures() -> Result<()> {
+        let client = haiku("api_key: xxx")?;
+        let http = ReqwestClient::new();
+        let request = |stream| {
+            let data = SendData {
+                functions: Some(vec![FunctionDeclaration {
+                    name: "get_weather".into(),
+                    description: "Get the current weather of a location".into(),
+                    parameters: json!({
+                        "type": "object",
+                        "properties": { "location": { "type": "string" } },
+                        "required": ["location"],
+                    }),
+                }]),
+                ..ask(vec![Message::user("What's the weather in Boston?")], stream)
+            };
+            client.request_builder(&http, data)
+        };
+
+        let output =
+            send_message(fixture::replay("claude/tool_use", request(false)?).await?).await?;
+        assert_eq!(output.text, "Let me check the weather.");
+        assert_eq!(
+            output.tool_calls,
+            vec![ToolCall::new(
+                "get_weather",
+                json!({ "location": "Boston" }),
+                Some("toolu_01A09q90qw90lq917835lq9".into())
+            )]
+        );
+
+        let (mut handler, _rx) = fixture::handler();
+        send_message_streaming(
+            fixture::replay("claude/tool_use_stream", request(true)?).await?,
+            &mut handler,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "Let me check the weather.");
+        assert_eq!(
+            handler.get_tool_calls(),
+            [ToolCall::new(
+                "get_weather",
+                json!({ "location": "Boston" }),
+                Some("toolu_01T1x1fJ34qAmk2tNTrN7Up6".into())
+            )]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let client = haiku("api_key: xxx")?;
+        let http = ReqwestClient::new();
+        let request = |stream| client.request_builder(&http, fixture::send_data(stream));
+
+        let output =
+            send_message(fixture::replay("claude/success", request(false)?).await?).await?;
+        assert_eq!(output.text, "Hello! How can I help you today?");
+        assert_eq!(
+            (output.input_tokens, output.output_tokens),
+            (Some(8), Some(12))
+        );
+
+        assert_eq!(output.stop_sequence, None);
+
+        let stop_client = haiku("{ api_key: xxx, stop_sequences: ['###'] }")?;
+        let data = ask(
+            vec![Message::user("Describe aichat as JSON, then write ###")],
+            false,
+        );
+        let builder = stop_client.request_builder(&http, data)?;
+        let output = send_message(fixture::replay("claude/stop_sequence", builder).await?).await?;
+        assert_eq!(output.text, "```json\n{\"name\": \"aichat\"}\n```\n");
+        assert_eq!(output.stop_sequence.as_deref(), Some("###"));
+
+        // Only the continuation of the prefill comes back
+        let data = ask(
+            vec![
+                Message::user("Describe aichat as JSON"),
+                Message::assistant("{"),
+            ],
+            false,
+        );
+        let builder = client.request_builder(&http, data)?;
+        let output = send_message(fixture::replay("claude/prefill", builder).await?).await?;
+        assert_eq!(output.text, "\"name\": \"aichat\"}");
+
+        let cache_client = haiku("{ api_key: xxx, prompt_cache: true }")?;
+        let system = format!(
+            "You are a helpful assistant. Answer with the reference manual below in mind.\n{}",
+            "aichat ".repeat(2048)
+        );
+        let data = ask(
+            vec![Message::system(&system), Message::user("Hello")],
+            false,
+        );
+        let builder = cache_client.request_builder(&http, data)?;
+        let output = send_message(fixture::replay("claude/prompt_cache", builder).await?).await?;
+        assert_eq!(
+            (output.input_tokens, output.output_tokens),
+            (Some(2061), Some(12))
+        );
+        assert_eq!(
+            output.cache_usage,
+            Some(CacheUsage {
+                creation_input_tokens: 2053,
+                read_input_tokens: 0
+            })
+        );
+
+        let (mut handler, _rx) = fixture::handler();
+        send_message_streaming(
+            fixture::replay("claude/stream", request(true)?).await?,
+            &mut handler,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "Hello! How can I help you today?");
+        assert_eq!(handler.get_usage(), (Some(8), Some(12)));
+        assert_eq!(handler.get_stop_reason(), Some("end_turn"));
+
+        let (mut handler, _rx) = fixture::handler();
+        let mut story_client = haiku("api_key: xxx")?;
+        story_client.model.max_output_tokens = Some(8);
+        let builder = story_client.request_builder(
+            &http,
+            ask(vec![Message::user("Tell me a long story")], true),
+        )?;
+        send_message_streaming(
+            fixture::replay("claude/max_tokens", builder).await?,
+            &mut handler,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "Once upon a time, there was");
+        assert_eq!(handler.get_usage(), (Some(14), Some(8)));
+        assert_eq!(handler.get_stop_reason(), Some("max_tokens"));
+
+        let err = send_message(fixture::replay("claude/auth_failure", request(false)?).await?)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid x-api-key (type: authentication_error)"
+        );
+
+        let (mut handler, _rx) = fixture::handler();
+        let err = send_message_streaming(
+            fixture::replay("claude/rate_limit", request(true)?).await?,
+            &mut handler,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Number of request tokens has exceeded your per-minute rate limit (type: rate_limit_error)"
+        );
+
+        let (mut handler, _rx) = fixture::handler();
+        let err = send_message_streaming(
+            fixture::replay("claude/stream_error", request(true)?).await?,
+            &mut handler,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(handler.get_buffer(), "Hello!");
+        assert_eq!(err.to_string(), "Overloaded (type: overloaded_error)");
+
+        let (mut handler, _rx) = fixture::handler();
+        let err = send_message_streaming(
+            fixture::replay("claude/stream_eof", request(true)?).await?,
+            &mut handler,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(handler.get_buffer(), "Hello! How can I help");
+        assert_eq!(
+            err.to_string(),
+            "The stream ended before `message_stop`, the output may be incomplete"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_body_clamps_temperature() -> Result<()> {
+        let model = ClaudeClient::list_models(&ClaudeConfig::default()).remove(2);
+        let mut data = fixture::send_data(false);
+        data.temperature = Some(1.5);
+        assert_eq!(build_body(data.clone(), &model)?["temperature"], 1.0);
+        data.temperature = Some(0.7);
+        assert_eq!(build_body(data, &model)?["temperature"], 0.7);
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalize_messages() {
+        let text = |role: &str, text: &str| json!({ "role": role, "content": [{ "type": "text", "text": text }] });
+        let image = json!({
+            "type": "image",
+            "source": { "type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo=" }
+        });
+
+        let list = vec![
+            text("user", "Hi"),
+            text("assistant", "Hello"),
+            text("user", "Bye"),
+        ];
+        assert_eq!(normalize_messages(list.clone()), list);
+
+        assert_eq!(
+            normalize_messages(vec![text("assistant", "Context"), text("user", "Hi")]),
+            vec![
+                text("user", USER_PLACEHOLDER),
+                text("assistant", "Context"),
+                text("user", "Hi"),
+            ]
+        );
+
+        assert_eq!(
+            normalize_messages(vec![
+                text("user", "Hi"),
+                text("assistant", "Hel"),
+                text("assistant", "Hello"),
+                json!({ "role": "user", "content": [image.clone()] }),
+                text("user", "What is it?"),
+            ]),
+            vec![
+                text("user", "Hi"),
+                json!({
+                    "role": "assistant",
+                    "content": [
+                        { "type": "text", "text": "Hel" },
+                        { "type": "text", "text": "Hello" },
+                    ]
+                }),
+                json!({
+                    "role": "user",
+                    "content": [image, { "type": "text", "text": "What is it?" }]
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_prefill() -> Result<()> {
+        let model = ClaudeClient::list_models(&ClaudeConfig::default()).remove(2);
+        let prefill: Message =
+            serde_json::from_value(json!({ "role": "assistant", "content": "{" }))?;
+        let data = SendData {
+            messages: vec![Message::user("Describe aichat as JSON"), prefill.clone()],
+            temperature: None,
+            top_p: None,
+            functions: None,
+            stream: false,
+        };
+        let body = build_body(data, &model)?;
+        assert_eq!(
+            body["messages"][1],
+            json!({ "role": "assistant", "content": [{ "type": "text", "text": "{" }] })
+        );
+
+        // Not merged into an aborted reply before it
+        let data = SendData {
+            messages: vec![
+                Message::user("Describe aichat as JSON"),
+                Message::assistant("Sure"),
+                prefill,
+            ],
+            temperature: None,
+            top_p: None,
+            functions: None,
+            stream: false,
+        };
+        let body = build_body(data, &model)?;
+        assert_eq!(body["messages"][2]["content"][0]["text"], USER_PLACEHOLDER);
+        assert_eq!(body["messages"][3]["content"][0]["text"], "{");
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_images() -> Result<()> {
+        let png = |width: u32, height: u32| -> Result<Value> {
+            let mut data = vec![];
+            image::RgbImage::new(width, height)
+                .write_to(&mut Cursor::new(&mut data), image::ImageFormat::Png)?;
+            Ok(json!({
+                "type": "image",
+                "source": { "type": "base64", "media_type": "image/png", "data": STANDARD.encode(data) }
+            }))
+        };
+        let body =
+            |images: Vec<Value>| json!({ "messages": [{ "role": "user", "content": images }] });
+
+        let mut value = body(vec![png(800, 600)?, png(8000, 10)?]);
+        let expect = value.clone();
+        check_images(&mut value, false)?;
+        assert_eq!(value, expect);
+
+        let mut value = body(vec![png(10, 10)?, png(8001, 10)?]);
+        assert_eq!(
+            check_images(&mut value, false).unwrap_err().to_string(),
+            "Image #2 is 8001x10 pixels, over the limit of 8000 pixels, set `resize_images: true` to downscale it"
+        );
+        check_images(&mut value, true)?;
+        let source = &value["messages"][0]["content"][1]["source"];
+        assert_eq!(source["media_type"], "image/jpeg");
+        let data = STANDARD.decode(source["data"].as_str().unwrap())?;
+        let image = image::load_from_memory(&data)?;
+        assert_eq!((image.width(), image.height()), (8000, 10));
+
+        let mut value = body((0..=MAX_IMAGES).map(|_| png(1, 1)).collect::<Result<_>>()?);
+        assert_eq!(
+            check_images(&mut value, true).unwrap_err().to_string(),
+            "Too many images, Claude accepts at most 20 in a request"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_params() {
+        assert!(check_params(&json!({})).is_ok());
+        assert!(check_params(&json!({ "stop_sequences": ["###", "\nUser:"] })).is_ok());
+        assert!(check_params(&json!({ "stop_sequences": "###" })).is_err());
+        assert!(check_params(&json!({ "stop_sequences": [" \n"] })).is_err());
+        let list = vec!["###"; MAX_STOP_SEQUENCES + 1];
+        assert_eq!(
+            check_params(&json!({ "stop_sequences": list }))
+                .unwrap_err()
+                .to_string(),
+            "Too many `stop_sequences`, 8192 given but at most 8191 are allowed"
+        );
+        assert!(check_params(&json!({ "top_k": 40 })).is_ok());
+        assert!(check_params(&json!({ "top_k": 0 })).is_err());
+        assert!(check_params(&json!({ "top_k": -1 })).is_err());
+        assert!(check_params(&json!({ "top_k": 0.5 })).is_err());
+    }
+
+    #[test]
+    fn test_stop_sequence_event() -> Result<()> {
+        let (mut handler, _rx) = fixture::handler();
+        let data = json!({
+            "type": "message_delta",
+            "delta": { "stop_reason": "stop_sequence", "stop_sequence": "###" },
+            "usage": { "output_tokens": 12 },
+        });
+        assert!(!handle_event(&data, &mut handler, &mut None)?);
+        assert_eq!(handler.get_stop_sequence(), Some("###"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_overloaded() -> Result<()> {
+        let build = |api_base: String, max_retries| {
+            let config = ClaudeConfig {
+                api_base: Some(api_base),
+                extra: Some(ExtraConfig {
+                    max_retries: Some(max_retries),
+                    max_retry_delay: Some(0),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            ClaudeClient {
+                global_config: Arc::new(RwLock::new(Config::default())),
+                model: ClaudeClient::list_models(&config).remove(2),
+                config,
+            }
+        };
+
+        let client = build(
+            fixture::serve(&["claude/overloaded", "claude/success"]).await?,
+            1,
+        );
+        let output = client.send_data(fixture::send_data(false)).await?;
+        assert_eq!(output.text, "Hello! How can I help you today?");
+
+        let client = build(
+            fixture::serve(&["claude/overloaded", "claude/stream"]).await?,
+            1,
+        );
+        let (mut handler, _rx) = fixture::handler();
+        client
+            .send_data_streaming(&mut handler, fixture::send_data(true))
+            .await?;
+        assert_eq!(handler.get_buffer(), "Hello! How can I help you today?");
+
+        let client = build(fixture::serve(&["claude/overloaded"]).await?, 1);
+        let err = client
+            .send_data(fixture::send_data(false))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.chain().nth(1).map(|v| v.to_string()).as_deref(),
+            Some("Gave up after 2 attempts")
+        );
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Overloaded (type: overloaded_error)"
+        );
+
+        let client = build(fixture::serve(&["claude/overloaded"]).await?, 1);
+        let (mut handler, _rx) = fixture::handler();
+        let err = client
+            .send_data_streaming(&mut handler, fixture::send_data(true))
+            .await
+            .unwrap_err();
+        assert!(
+            err.chain().any(|v| v.to_string() == "Gave up after 2 attempts"),
+            "{err:?}"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_check_input_tokens() -> Result<()> {
+        let build = |api_base: String, max_input_tokens: usize| {
+            let config = ClaudeConfig {
+                api_base: Some(api_base),
+                check_input_tokens: Some(true),
+                ..Default::default()
+            };
+            let mut model = ClaudeClient::list_models(&config).remove(2);
+            model.max_input_tokens = Some(max_input_tokens);
+            ClaudeClient {
+                global_config: Arc::new(RwLock::new(Config::default())),
+                model,
+                config,
+            }
+        };
+        let http = ReqwestClient::new();
+
+        let client = build(
+            fixture::serve(&["claude/count_tokens", "claude/success"]).await?,
+            200000,
+        );
+        let tokens = client
+            .count_tokens_inner(&http, fixture::send_data(false))
+            .await?;
+        assert_eq!(tokens, Some(8));
+        let output = client
+            .send_message_inner(&http, fixture::send_data(false))
+            .await?;
+        assert_eq!(output.text, "Hello! How can I help you today?");
+
+        // The count of the same input is cached, so nothing is sent
+        let client = build(fixture::serve(&["claude/success"]).await?, 5);
+        let err = client
+            .send_message_inner(&http, fixture::send_data(false))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The input is 8 tokens but the model allows 5"
+        );
+
+        // The local estimate doesn't reject the input before the API counts it
+        let mut config = Config {
+            model: client.model.clone(),
+            clients: vec![ClientConfig::ClaudeConfig(client.config.clone())],
+            ..Default::default()
+        };
+        config.model.max_input_tokens = Some(1);
+        let input = Input::from_str("Hello", Default::default());
+        assert!(config.prepare_send_data(&input, false).is_ok());
+        config.clients = vec![ClientConfig::ClaudeConfig(ClaudeConfig::default())];
+        assert!(config.prepare_send_data(&input, false).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batch_fixtures() -> Result<()> {
+        let client = haiku("api_key: xxx")?;
+        let http = ReqwestClient::new();
+        let (builder, betas) = client.batch_request_builder(
+            &http,
+            vec![
+                fixture::send_data(false),
+                ask(vec![Message::user("")], false),
+                fixture::send_data(false),
+            ],
+        )?;
+        let batch = send_json(fixture::replay("claude/batch_create", builder).await?).await?;
+        assert_eq!(batch["processing_status"], "in_progress");
+
+        let url = fixture::serve(&["claude/batch_status"]).await?;
+        let http = ReqwestClient::new();
+        let batch = wait_batch(batch, || {
+            http.get(format!(
+                "{url}/v1/messages/batches/msgbatch_013Zva2CMHLNnXjNJJKqJ2EF"
+            ))
+        })
+        .await?;
+        assert_eq!(batch["request_counts"]["succeeded"], 1);
+
+        let url = format!(
+            "{}/batches/msgbatch_013Zva2CMHLNnXjNJJKqJ2EF/results",
+            client.messages_url()
+        );
+        let builder = client.add_headers(http.get(url), &betas);
+        let text =
+            download_batch_results(fixture::replay("claude/batch_results", builder).await?).await?;
+        let outputs = parse_batch_results(&text, 3)?;
+        assert_eq!(
+            outputs[0].as_ref().unwrap().text,
+            "Hello! How can I help you today?"
+        );
+        assert_eq!(
+            outputs[1].as_ref().unwrap_err().to_string(),
+            "messages: text content blocks must be non-empty (type: invalid_request_error)"
+        );
+        assert_eq!(
+            outputs[2].as_ref().unwrap_err().to_string(),
+            "The request was expired"
+        );
+        assert!(parse_batch_results(&text, 2).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn record_fixtures() -> Result<()> {
+        if !fixture::recording() {
+            return Ok(());
+        }
+        let mut client = ClaudeClient::for_recording()?;
+        let http = client.build_client()?;
+        let builder = client.request_builder(&http, fixture::send_data(false))?;
+        fixture::record("claude/success", builder).await?;
+        let builder = client.request_builder(&http, fixture::send_data(true))?;
+        fixture::record("claude/stream", builder).await?;
+        client.config.api_key = Some("invalid".into());
+        let builder = client.request_builder(&http, fixture::send_data(false))?;
+        fixture::record("claude/auth_failure", builder).await?;
+        Ok(())
+    }
+}
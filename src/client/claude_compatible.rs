@@ -0,0 +1,177 @@
+use super::claude::{build_body, send_message, send_message_streaming};
+use super::{
+    ClaudeCompatibleClient, Client, CompletionOutput, ExtraConfig, Model, ModelConfig, PromptType,
+    ReplyHandler, SendData,
+};
+
+use crate::utils::PromptKind;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+const DEFAULT_AUTH_HEADER: &str = "x-api-key";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClaudeCompatibleConfig {
+    pub name: Option<String>,
+    pub api_base: String,
+    pub api_key: Option<String>,
+    pub auth_header: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+#[async_trait]
+impl Client for ClaudeCompatibleClient {
+    client_common_fns!();
+
+    async fn send_message_inner(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+    ) -> Result<CompletionOutput> {
+        let builder = self.request_builder(client, data)?;
+        send_message(builder).await
+    }
+
+    async fn send_message_streaming_inner(
+        &self,
+        client: &ReqwestClient,
+        handler: &mut ReplyHandler,
+        data: SendData,
+    ) -> Result<()> {
+        let builder = self.request_builder(client, data)?;
+        send_message_streaming(builder, handler).await
+    }
+}
+
+impl ClaudeCompatibleClient {
+    list_models_fn!(ClaudeCompatibleConfig);
+    config_get_fn!(api_key, get_api_key);
+
+    pub const PROMPTS: [PromptType<'static>; 5] = [
+        ("name", "Platform Name:", true, PromptKind::String),
+        ("api_base", "API Base:", true, PromptKind::String),
+        ("api_key", "API Key:", false, PromptKind::String),
+        ("models[].name", "Model Name:", true, PromptKind::String),
+        (
+            "models[].max_input_tokens",
+            "Max Input Tokens:",
+            false,
+            PromptKind::Integer,
+        ),
+    ];
+
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let api_key = self.get_api_key().ok();
+
+        let body = build_body(data, &self.model)?;
+
+        let url = format!("{}/messages", self.config.api_base.trim_end_matches('/'));
+
+        debug!("ClaudeCompatible Request: {url} {body}");
+
+        let mut builder = client.post(url).json(&body);
+        builder = builder.header("anthropic-version", "2023-06-01");
+        if let Some(api_key) = api_key {
+            let auth_header = self
+                .config
+                .auth_header
+                .as_deref()
+                .unwrap_or(DEFAULT_AUTH_HEADER);
+            if auth_header.eq_ignore_ascii_case("authorization") {
+                builder = builder.bearer_auth(api_key);
+            } else {
+                builder = builder.header(auth_header, api_key);
+            }
+        }
+        for (key, value) in &self.config.headers {
+            builder = builder.header(key, value);
+        }
+
+        Ok(builder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{fixture, list_models, ClientConfig};
+    use crate::config::Config;
+
+    use parking_lot::RwLock;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_request_builder() -> Result<()> {
+        let clients: Vec<ClientConfig> = serde_yaml::from_str(
+            r#"
+- type: claude
+  api_key: sk-ant-xxx
+- type: claude-compatible
+  name: litellm
+  api_base: http://localhost:4000/v1/
+  api_key: sk-1234
+  auth_header: Authorization
+  models:
+    - name: claude-3-haiku-20240307
+- type: claude-compatible
+  name: gateway
+  api_base: https://llm.example.com/anthropic
+  api_key: xxx
+  headers:
+    X-Team: ml
+  models:
+    - name: claude-3-opus-20240229
+"#,
+        )?;
+        let config = Config {
+            clients: clients.clone(),
+            ..Default::default()
+        };
+        let ids: Vec<String> = list_models(&config).iter().map(|v| v.id()).collect();
+        assert!(ids.contains(&"claude:claude-3-haiku-20240307".to_string()));
+        assert!(ids.contains(&"litellm:claude-3-haiku-20240307".to_string()));
+        assert!(ids.contains(&"gateway:claude-3-opus-20240229".to_string()));
+
+        let global_config = Arc::new(RwLock::new(config));
+        let http = ReqwestClient::new();
+        let build = |config: &ClaudeCompatibleConfig| {
+            let client = ClaudeCompatibleClient {
+                global_config: global_config.clone(),
+                config: config.clone(),
+                model: ClaudeCompatibleClient::list_models(config).remove(0),
+            };
+            client
+                .request_builder(&http, fixture::send_data(false))?
+                .build()
+                .map_err(anyhow::Error::from)
+        };
+        let configs: Vec<&ClaudeCompatibleConfig> = clients
+            .iter()
+            .filter_map(|v| match v {
+                ClientConfig::ClaudeCompatibleConfig(c) => Some(c),
+                _ => None,
+            })
+            .collect();
+
+        let request = build(configs[0])?;
+        assert_eq!(request.url().as_str(), "http://localhost:4000/v1/messages");
+        assert_eq!(request.headers()["authorization"], "Bearer sk-1234");
+        assert!(request.headers().get("x-api-key").is_none());
+
+        let request = build(configs[1])?;
+        assert_eq!(
+            request.url().as_str(),
+            "https://llm.example.com/anthropic/messages"
+        );
+        assert_eq!(request.headers()["x-api-key"], "xxx");
+        assert_eq!(request.headers()["x-team"], "ml");
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use super::{
-    extract_sytem_message, json_stream, message::*, Client, CohereClient, ExtraConfig, Model,
-    ModelConfig, PromptType, ReplyHandler, SendData,
+    extract_sytem_message, json_stream, message::*, probe_listing, send_request, Client,
+    CohereClient, CompletionOutput, ExtraConfig, Model, ModelConfig, PromptType, ReplyHandler,
+    SendData,
 };
 
 use crate::utils::PromptKind;
@@ -12,6 +13,7 @@ use serde::Deserialize;
 use serde_json::{json, Value};
 
 const API_URL: &str = "https://api.cohere.ai/v1/chat";
+const MODELS_API_URL: &str = "https://api.cohere.ai/v1/models";
 
 const MODELS: [(&str, usize, &str); 2] = [
     // https://docs.cohere.com/docs/command-r
@@ -32,7 +34,11 @@ pub struct CohereConfig {
 impl Client for CohereClient {
     client_common_fns!();
 
-    async fn send_message_inner(&self, client: &ReqwestClient, data: SendData) -> Result<String> {
+    async fn send_message_inner(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+    ) -> Result<CompletionOutput> {
         let builder = self.request_builder(client, data)?;
         send_message(builder).await
     }
@@ -46,6 +52,16 @@ impl Client for CohereClient {
         let builder = self.request_builder(client, data)?;
         send_message_streaming(builder, handler).await
     }
+
+    /// Probe the free models listing.
+    async fn health_check_inner(&self, client: &ReqwestClient) -> Result<()> {
+        let mut builder = client.get(MODELS_API_URL);
+        if let Ok(api_key) = self.get_api_key() {
+            builder = builder.bearer_auth(api_key);
+        }
+        probe_listing(builder).await?;
+        Ok(())
+    }
 }
 
 impl CohereClient {
@@ -73,22 +89,32 @@ impl CohereClient {
     }
 }
 
-pub(crate) async fn send_message(builder: RequestBuilder) -> Result<String> {
-    let res = builder.send().await?;
+pub(crate) async fn send_message(builder: RequestBuilder) -> Result<CompletionOutput> {
+    let res = send_request(builder).await?;
     let status = res.status();
     let data: Value = res.json().await?;
     if status != 200 {
         catch_error(&data, status.as_u16())?;
     }
-    let output = extract_text(&data)?;
-    Ok(output.to_string())
+    let text = extract_text(&data)?;
+    Ok(CompletionOutput {
+        text: text.to_string(),
+        input_tokens: data["meta"]["billed_units"]["input_tokens"].as_u64(),
+        output_tokens: data["meta"]["billed_units"]["output_tokens"].as_u64(),
+        tool_calls: vec![],
+        request_id: None,
+        stop_sequence: None,
+        stop_reason: None,
+        stats: None,
+        cache_usage: None,
+    })
 }
 
 pub(crate) async fn send_message_streaming(
     builder: RequestBuilder,
     handler: &mut ReplyHandler,
 ) -> Result<()> {
-    let res = builder.send().await?;
+    let res = send_request(builder).await?;
     let status = res.status();
     if status != 200 {
         let data: Value = res.json().await?;
@@ -96,8 +122,23 @@ pub(crate) async fn send_message_streaming(
     } else {
         let handle = |value: &str| -> Result<()> {
             let value: Value = serde_json::from_str(value)?;
-            if let Some("text-generation") = value["event_type"].as_str() {
-                handler.text(extract_text(&value)?)?;
+            match value["event_type"].as_str() {
+                Some("text-generation") => handler.text(extract_text(&value)?)?,
+                Some("stream-end") => {
+                    let usage = &value["response"]["meta"]["billed_units"];
+                    handler.set_usage(
+                        usage["input_tokens"].as_u64(),
+                        usage["output_tokens"].as_u64(),
+                    );
+                    // ERROR, ERROR_TOXIC or ERROR_LIMIT
+                    if let Some(reason) = value["finish_reason"]
+                        .as_str()
+                        .filter(|v| v.starts_with("ERROR"))
+                    {
+                        bail!("The stream ended with {reason}");
+                    }
+                }
+                _ => {}
             }
             Ok(())
         };
@@ -111,6 +152,7 @@ fn build_body(data: SendData, model: &Model) -> Result<Value> {
         mut messages,
         temperature,
         top_p,
+        functions: _,
         stream,
     } = data;
 
@@ -125,7 +167,7 @@ fn build_body(data: SendData, model: &Model) -> Result<Value> {
                 _ => "CHATBOT",
             };
             match message.content {
-                MessageContent::Text(text) => json!({
+                MessageContent::Text(text) | MessageContent::ToolResults((_, text)) => json!({
                     "role": role,
                     "message": text,
                 }),
@@ -202,3 +244,82 @@ fn extract_text(data: &Value) -> Result<&str> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::fixture;
+
+    #[test]
+    fn test_build_body() -> Result<()> {
+        let model = CohereClient::list_models(&CohereConfig::default()).remove(1);
+        let data = SendData {
+            messages: vec![
+                Message::system("Be brief"),
+                Message::user("Hi"),
+                Message::assistant("Hello"),
+                Message::user("Bye"),
+            ],
+            temperature: None,
+            top_p: Some(0.9),
+            functions: None,
+            stream: true,
+        };
+        let body = build_body(data, &model)?;
+        assert_eq!(
+            body,
+            json!({
+                "model": "command-r-plus",
+                "message": "Bye",
+                "preamble": "Be brief",
+                "chat_history": [
+                    { "role": "USER", "message": "Hi" },
+                    { "role": "CHATBOT", "message": "Hello" },
+                ],
+                "p": 0.9,
+                "stream": true,
+            })
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let client = CohereClient::for_test("api_key: xxx")?;
+        let http = ReqwestClient::new();
+        let request = |stream| client.request_builder(&http, fixture::send_data(stream));
+
+        let output =
+            send_message(fixture::replay("cohere/success", request(false)?).await?).await?;
+        assert_eq!(output.text, "Hello! How can I help you today?");
+        assert_eq!(
+            (output.input_tokens, output.output_tokens),
+            (Some(1), Some(9))
+        );
+
+        let (mut handler, _rx) = fixture::handler();
+        send_message_streaming(
+            fixture::replay("cohere/stream", request(true)?).await?,
+            &mut handler,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "Hello! How can I help you today?");
+        assert_eq!(handler.get_usage(), (Some(1), Some(9)));
+
+        let err = send_message(fixture::replay("cohere/auth_failure", request(false)?).await?)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "invalid api token");
+
+        let (mut handler, _rx) = fixture::handler();
+        let err = send_message_streaming(
+            fixture::replay("cohere/stream_error", request(true)?).await?,
+            &mut handler,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(handler.get_buffer(), "Hello!");
+        assert_eq!(err.to_string(), "The stream ended with ERROR_TOXIC");
+        Ok(())
+    }
+}
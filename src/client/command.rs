@@ -0,0 +1,228 @@
+//! Run an external program as a model.
+//!
+//! For each request aichat spawns the command, writes one JSON object to its stdin and closes it:
+//!
+//! ```json
+//! {"model":"echo","messages":[{"role":"user","content":"hi"}],"temperature":null,"top_p":null,"stream":false}
+//! ```
+//!
+//! Without `stream`, the program prints a single JSON object to stdout:
+//! `{"text":"...","usage":{"input_tokens":1,"output_tokens":2}}` (`usage` is optional).
+//!
+//! With `stream`, every stdout line is a text delta. A line holding a JSON string (`"Hel"`) is
+//! decoded, so deltas can carry newlines; any other line is used verbatim with its newline.
+//!
+//! A non-zero exit status fails the request with the captured stderr.
+//! See `scripts/command-client.py` for a sample.
+
+use super::{
+    Client, CommandClient, CompletionOutput, ExtraConfig, Model, ModelConfig, PromptType,
+    ReplyHandler, SendData,
+};
+
+use crate::utils::PromptKind;
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use reqwest::Client as ReqwestClient;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{future::Future, process::Stdio, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    process::{Child, Command},
+    time::timeout,
+};
+
+const DEFAULT_TIMEOUT: u64 = 300;
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct CommandConfig {
+    pub name: Option<String>,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub timeout: Option<u64>,
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+#[async_trait]
+impl Client for CommandClient {
+    client_common_fns!();
+
+    async fn send_message_inner(
+        &self,
+        _client: &ReqwestClient,
+        data: SendData,
+    ) -> Result<CompletionOutput> {
+        let input = self.build_input(data);
+        let mut child = self.spawn()?;
+        let stdout = child.stdout.take().unwrap();
+        let output = self
+            .wait(child, input, async move {
+                let mut output = String::new();
+                BufReader::new(stdout).read_to_string(&mut output).await?;
+                Ok(output)
+            })
+            .await?;
+        parse_output(&output)
+    }
+
+    async fn send_message_streaming_inner(
+        &self,
+        _client: &ReqwestClient,
+        handler: &mut ReplyHandler,
+        data: SendData,
+    ) -> Result<()> {
+        let input = self.build_input(data);
+        let mut child = self.spawn()?;
+        let stdout = child.stdout.take().unwrap();
+        self.wait(child, input, async move {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Some(line) = lines.next_line().await? {
+                match serde_json::from_str::<Value>(&line) {
+                    Ok(Value::String(text)) => handler.text(&text)?,
+                    _ => handler.text(&format!("{line}\n"))?,
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+}
+
+impl CommandClient {
+    list_models_fn!(CommandConfig);
+
+    pub const PROMPTS: [PromptType<'static>; 3] = [
+        ("name", "Platform Name:", true, PromptKind::String),
+        ("command", "Command:", true, PromptKind::String),
+        ("models[].name", "Model Name:", true, PromptKind::String),
+    ];
+
+    fn build_input(&self, data: SendData) -> String {
+        let SendData {
+            messages,
+            temperature,
+            top_p,
+            functions: _,
+            stream,
+        } = data;
+        let input = json!({
+            "model": &self.model.name,
+            "messages": messages,
+            "temperature": temperature,
+            "top_p": top_p,
+            "stream": stream,
+        });
+
+        debug!("Command Request: {} {input}", self.config.command);
+
+        input.to_string()
+    }
+
+    fn spawn(&self) -> Result<Child> {
+        Command::new(&self.config.command)
+            .args(&self.config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to run '{}'", self.config.command))
+    }
+
+    /// Write `input` to stdin while driving `read_stdout` to the end, and check the exit status,
+    /// all within the timeout.
+    async fn wait<T>(
+        &self,
+        mut child: Child,
+        input: String,
+        read_stdout: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let mut stdin = child.stdin.take().unwrap();
+        let write_stdin = async move {
+            // A program may exit without reading its input; its exit status tells what went wrong.
+            let _ = stdin.write_all(input.as_bytes()).await;
+            drop(stdin);
+        };
+        let mut stderr = child.stderr.take().unwrap();
+        let read_stderr = async move {
+            let mut output = String::new();
+            let _ = stderr.read_to_string(&mut output).await;
+            output
+        };
+        let seconds = self.config.timeout.unwrap_or(DEFAULT_TIMEOUT);
+        let run = async {
+            let (_, output, stderr) = tokio::join!(write_stdin, read_stdout, read_stderr);
+            let status = child.wait().await?;
+            if !status.success() {
+                let stderr = stderr.trim();
+                if stderr.is_empty() {
+                    bail!("'{}' {status}", self.config.command);
+                }
+                bail!("'{}' {status}: {stderr}", self.config.command);
+            }
+            output
+        };
+        timeout(Duration::from_secs(seconds), run)
+            .await
+            .map_err(|_| anyhow!("'{}' timed out after {seconds}s", self.config.command))?
+    }
+}
+
+fn parse_output(output: &str) -> Result<CompletionOutput> {
+    let data: Value = serde_json::from_str(output.trim())
+        .with_context(|| format!("Invalid command output: {output}"))?;
+    let text = data["text"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Invalid command output: {output}"))?;
+    Ok(CompletionOutput {
+        text: text.to_string(),
+        input_tokens: data["usage"]["input_tokens"].as_u64(),
+        output_tokens: data["usage"]["output_tokens"].as_u64(),
+        tool_calls: vec![],
+        request_id: None,
+        stop_sequence: None,
+        stop_reason: None,
+        stats: None,
+        cache_usage: None,
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::client::{fixture, Message};
+    use crate::config::Config;
+
+    use parking_lot::RwLock;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_large_input() -> Result<()> {
+        // Echoes the input, so it fills the stdout pipe before it has read all of it
+        let config = CommandConfig {
+            command: "cat".into(),
+            timeout: Some(10),
+            ..Default::default()
+        };
+        let client = CommandClient {
+            global_config: Arc::new(RwLock::new(Config::default())),
+            model: Model::new(CommandClient::name(&config), "cat"),
+            config,
+        };
+        let text = "x".repeat(1024 * 1024);
+        let data = SendData {
+            messages: vec![Message::user(&text)],
+            ..fixture::send_data(true)
+        };
+        let (mut handler, _rx) = fixture::handler();
+        client
+            .send_message_streaming_inner(&ReqwestClient::new(), &mut handler, data)
+            .await?;
+        assert!(handler.get_buffer().contains(&text));
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+use super::openai::openai_build_body;
+use super::{
+    custom_headers, read_cache, refresh_cache, send_request, with_headers, Client, ClientConfig, DeepInfraClient, ExtraConfig,
+    Model, ModelConfig, PromptType, SendData,
+};
+
+use crate::config::GlobalConfig;
+use crate::utils::PromptKind;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use serde::Deserialize;
+use serde_json::Value;
+use std::time::Duration;
+
+const API_BASE: &str = "https://api.deepinfra.com/v1/openai";
+
+const MODELS_CACHE_FILE: &str = "deepinfra_models.json";
+const MODELS_CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const MODELS_FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+const MODELS: [(&str, usize, &str); 3] = [
+    // https://deepinfra.com/models/text-generation
+    ("meta-llama/Meta-Llama-3-70B-Instruct", 8192, "text"),
+    ("meta-llama/Meta-Llama-3-8B-Instruct", 8192, "text"),
+    ("mistralai/Mixtral-8x22B-Instruct-v0.1", 65536, "text"),
+];
+
+lazy_static! {
+    static ref REMOTE_MODELS: Mutex<Option<Value>> = Mutex::new(None);
+}
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct DeepInfraConfig {
+    pub name: Option<String>,
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+openai_compatible_client!(DeepInfraClient);
+
+impl DeepInfraClient {
+    config_get_fn!(api_key, get_api_key);
+
+    pub const PROMPTS: [PromptType<'static>; 1] =
+        [("api_key", "API Key:", true, PromptKind::String)];
+
+    /// The declared models, with a missing `max_input_tokens` taken from the cached model metadata.
+    pub fn list_models(local_config: &DeepInfraConfig) -> Vec<Model> {
+        let client_name = Self::name(local_config);
+        if local_config.models.is_empty() {
+            return Model::from_static(client_name, &MODELS);
+        }
+        let models = Model::from_config(client_name, &local_config.models);
+        if models.iter().all(|v| v.max_input_tokens.is_some()) {
+            return models;
+        }
+        let mut remote_models = REMOTE_MODELS.lock();
+        if remote_models.is_none() {
+            *remote_models = read_cache(MODELS_CACHE_FILE);
+        }
+        let Some(data) = remote_models.as_ref() else {
+            return models;
+        };
+        models
+            .into_iter()
+            .map(|model| match model.max_input_tokens {
+                Some(_) => model,
+                None => {
+                    let max_input_tokens = context_length(data, &model.name);
+                    model.set_max_input_tokens(max_input_tokens)
+                }
+            })
+            .collect()
+    }
+
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let api_key = self.get_api_key()?;
+
+        let mut body = openai_build_body(data, &self.model);
+        self.model.merge_extra_fields(&mut body);
+
+        let url = format!("{API_BASE}/chat/completions");
+
+        debug!("DeepInfra Request: {url} {body}");
+
+        let builder = client.post(url).bearer_auth(api_key).json(&body);
+
+        Ok(builder)
+    }
+}
+
+/// Refresh the cached model metadata once a day, if a declared deepinfra model omits `max_input_tokens`.
+pub async fn sync_deepinfra_models(config: &GlobalConfig) -> Result<()> {
+    let Some(local_config) = config.read().clients.iter().find_map(|v| match v {
+        ClientConfig::DeepInfraConfig(c)
+            if c.models.iter().any(|v| v.max_input_tokens.is_none()) =>
+        {
+            Some(c.clone())
+        }
+        _ => None,
+    }) else {
+        return Ok(());
+    };
+    let headers = custom_headers(&local_config.extra)?;
+    let fetch = async {
+        let model = Model::new(DeepInfraClient::name(&local_config), "");
+        let client = DeepInfraClient {
+            global_config: config.clone(),
+            config: local_config,
+            model,
+        };
+        let mut builder = client
+            .build_client()?
+            .get(format!("{API_BASE}/models"))
+            .timeout(MODELS_FETCH_TIMEOUT);
+        if let Ok(api_key) = client.get_api_key() {
+            builder = builder.bearer_auth(api_key);
+        }
+        let res = send_request(builder).await?;
+        let status = res.status();
+        let data: Value = res.json().await?;
+        if status != 200 || !data["data"].is_array() {
+            bail!("Invalid response, status: {status}, data: {data}");
+        }
+        Ok(data)
+    };
+    let fetch = with_headers(headers, fetch);
+    if let Some(data) = refresh_cache(MODELS_CACHE_FILE, MODELS_CACHE_TTL, fetch).await? {
+        *REMOTE_MODELS.lock() = Some(data);
+    }
+    Ok(())
+}
+
+fn context_length(data: &Value, name: &str) -> Option<usize> {
+    data["data"]
+        .as_array()?
+        .iter()
+        .find(|v| v["id"].as_str() == Some(name))?["metadata"]["context_length"]
+        .as_u64()
+        .map(|v| v as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{
+        fixture,
+        openai::{openai_send_message, openai_send_message_streaming},
+    };
+    use serde_json::json;
+
+    #[test]
+    fn test_context_length() {
+        let data = json!({
+            "object": "list",
+            "data": [
+                {
+                    "id": "meta-llama/Meta-Llama-3-70B-Instruct",
+                    "object": "model",
+                    "metadata": { "context_length": 8192 },
+                },
+                { "id": "BAAI/bge-large-en-v1.5", "object": "model", "metadata": null },
+            ]
+        });
+        assert_eq!(
+            context_length(&data, "meta-llama/Meta-Llama-3-70B-Instruct"),
+            Some(8192)
+        );
+        assert_eq!(context_length(&data, "BAAI/bge-large-en-v1.5"), None);
+        assert_eq!(context_length(&data, "missing"), None);
+    }
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let client = DeepInfraClient::for_test("api_key: xxx")?;
+        let http = ReqwestClient::new();
+        let request = |stream| client.request_builder(&http, fixture::send_data(stream));
+
+        let (mut handler, _rx) = fixture::handler();
+        openai_send_message_streaming(
+            fixture::replay("deepinfra/stream", request(true)?).await?,
+            &mut handler,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "Hello! How can I help you today?");
+        assert_eq!(handler.get_usage(), (Some(12), Some(10)));
+
+        let err =
+            openai_send_message(fixture::replay("deepinfra/auth_failure", request(false)?).await?)
+                .await
+                .unwrap_err();
+        assert_eq!(err.to_string(), "Authentication required");
+
+        let client = DeepInfraClient::for_test(
+            r#"
+api_key: xxx
+models:
+  - name: meta-llama/Llama-4
+    max_input_tokens: 8192
+"#,
+        )?;
+        let builder = client.request_builder(&http, fixture::send_data(false))?;
+        let err = openai_send_message(fixture::replay("deepinfra/model_not_found", builder).await?)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The model `meta-llama/Llama-4` does not exist"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use super::openai::openai_build_body;
+use super::{DeepSeekClient, ExtraConfig, Model, ModelConfig, PromptType, SendData};
+
+use crate::utils::PromptKind;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use serde::Deserialize;
+
+const API_URL: &str = "https://api.deepseek.com/chat/completions";
+
+const MAX_OUTPUT_TOKENS: isize = 4096;
+
+const MODELS: [(&str, usize, &str); 2] = [
+    // https://platform.deepseek.com/api-docs/pricing
+    ("deepseek-chat", 32768, "text"),
+    ("deepseek-coder", 32768, "text"),
+];
+
+// USD per 1M tokens
+const PRICES: (f64, f64) = (0.14, 0.28);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeepSeekConfig {
+    pub name: Option<String>,
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+openai_compatible_client!(DeepSeekClient);
+
+impl DeepSeekClient {
+    config_get_fn!(api_key, get_api_key);
+
+    pub const PROMPTS: [PromptType<'static>; 1] =
+        [("api_key", "API Key:", true, PromptKind::String)];
+
+    pub fn list_models(local_config: &DeepSeekConfig) -> Vec<Model> {
+        let client_name = Self::name(local_config);
+        if local_config.models.is_empty() {
+            Model::from_static(client_name, &MODELS)
+                .into_iter()
+                .map(|v| {
+                    v.set_max_output_tokens(Some(MAX_OUTPUT_TOKENS))
+                        .set_input_price(Some(PRICES.0))
+                        .set_output_price(Some(PRICES.1))
+                })
+                .collect()
+        } else {
+            Model::from_config(client_name, &local_config.models)
+        }
+    }
+
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let api_key = self.get_api_key()?;
+
+        let body = openai_build_body(data, &self.model);
+
+        let url = API_URL;
+
+        debug!("DeepSeek Request: {url} {body}");
+
+        let builder = client.post(url).bearer_auth(api_key).json(&body);
+
+        Ok(builder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{fixture, openai::openai_send_message};
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let client = DeepSeekClient::for_test("api_key: xxx")?;
+        let http = ReqwestClient::new();
+        let request = |stream| client.request_builder(&http, fixture::send_data(stream));
+
+        let err =
+            openai_send_message(fixture::replay("deepseek/auth_failure", request(false)?).await?)
+                .await
+                .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Authentication Fails (no such user) (type: authentication_error, code: invalid_request_error)"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use super::openai::openai_build_body;
+use super::{DoubaoClient, ExtraConfig, Model, ModelConfig, PromptType, SendData};
+
+use crate::utils::PromptKind;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use serde::Deserialize;
+
+const API_BASE: &str = "https://ark.cn-beijing.volces.com/api/v3";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DoubaoConfig {
+    pub name: Option<String>,
+    pub api_key: Option<String>,
+    pub api_base: Option<String>,
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+openai_compatible_client!(DoubaoClient);
+
+impl DoubaoClient {
+    list_models_fn!(DoubaoConfig);
+    config_get_fn!(api_key, get_api_key);
+    config_get_fn!(api_base, get_api_base);
+
+    pub const PROMPTS: [PromptType<'static>; 4] = [
+        ("api_key", "API Key:", true, PromptKind::String),
+        ("models[].name", "Model Name:", true, PromptKind::String),
+        (
+            "models[].real_name",
+            "Endpoint ID:",
+            true,
+            PromptKind::String,
+        ),
+        (
+            "models[].max_input_tokens",
+            "Max Input Tokens:",
+            false,
+            PromptKind::Integer,
+        ),
+    ];
+
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let api_key = self.get_api_key()?;
+        let api_base = self.get_api_base().unwrap_or_else(|_| API_BASE.to_string());
+
+        let mut body = openai_build_body(data, &self.model);
+        // Ark addresses models by their endpoint id
+        body["model"] = self.model.real_name().into();
+        self.model.merge_extra_fields(&mut body);
+
+        let url = format!("{}/chat/completions", api_base.trim_end_matches('/'));
+
+        debug!("Doubao Request: {url} {body}");
+
+        let builder = client.post(url).bearer_auth(api_key).json(&body);
+
+        Ok(builder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{
+        fixture,
+        openai::{openai_send_message, openai_send_message_streaming},
+    };
+    use serde_json::Value;
+
+    const CONFIG: &str = r#"
+api_key: xxx
+api_base: https://ark.cn-shanghai.volces.com/api/v3/
+models:
+  - name: doubao-pro-32k
+    real_name: ep-20240601123456-abcde
+    max_input_tokens: 32768
+"#;
+
+    #[test]
+    fn test_request_builder() -> Result<()> {
+        let client = DoubaoClient::for_test(CONFIG)?;
+        assert_eq!(client.model.id(), "doubao:doubao-pro-32k");
+        let request = client
+            .request_builder(&ReqwestClient::new(), fixture::send_data(false))?
+            .build()?;
+        assert_eq!(
+            request.url().as_str(),
+            "https://ark.cn-shanghai.volces.com/api/v3/chat/completions"
+        );
+        let body: Value =
+            serde_json::from_slice(request.body().and_then(|v| v.as_bytes()).unwrap())?;
+        assert_eq!(body["model"], "ep-20240601123456-abcde");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let client = DoubaoClient::for_test(CONFIG)?;
+        let http = ReqwestClient::new();
+        let request = |stream| client.request_builder(&http, fixture::send_data(stream));
+
+        let (mut handler, _rx) = fixture::handler();
+        openai_send_message_streaming(
+            fixture::replay("doubao/stream", request(true)?).await?,
+            &mut handler,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "Hello! How can I help you today?");
+
+        let err =
+            openai_send_message(fixture::replay("doubao/auth_failure", request(false)?).await?)
+                .await
+                .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the API key or AK/SK in the request is missing or invalid (type: Unauthorized, code: AuthenticationError)"
+        );
+        Ok(())
+    }
+}
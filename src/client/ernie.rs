@@ -1,22 +1,27 @@
 use super::{
-    patch_system_message, Client, ErnieClient, ExtraConfig, Model, ModelConfig, PromptType,
-    ReplyHandler, SendData,
+    event_source, extract_sytem_message, message::*, patch_system_message, send_request,
+    send_token_request, Client, CompletionOutput, EmbeddingsOutput, ErnieClient, ExtraConfig, Model, ModelCapabilities,
+    ModelConfig, PromptType, ReplyHandler, SendData,
 };
 
-use crate::utils::PromptKind;
+use crate::config::Config;
+use crate::utils::{count_tokens, sha256sum, PromptKind, SseParser};
 
 use anyhow::{anyhow, bail, Context, Result};
 use async_trait::async_trait;
+use chrono::Utc;
 use futures_util::StreamExt;
 use lazy_static::lazy_static;
+use parking_lot::Mutex;
 use reqwest::{Client as ReqwestClient, RequestBuilder};
-use reqwest_eventsource::{Error as EventSourceError, Event, RequestBuilderExt};
+use reqwest_eventsource::{Error as EventSourceError, Event};
 use serde::Deserialize;
-use serde_json::{json, Value};
-use std::{env, sync::Mutex};
+use serde_json::{json, Map, Value};
+use std::{collections::HashMap, env, fmt, fs};
 
-const API_BASE: &str = "https://aip.baidubce.com/rpc/2.0/ai_custom/v1";
-const ACCESS_TOKEN_URL: &str = "https://aip.baidubce.com/oauth/2.0/token";
+const API_BASE: &str = "https://aip.baidubce.com";
+const CHAT_PATH: &str = "/rpc/2.0/ai_custom/v1";
+const ACCESS_TOKEN_PATH: &str = "/oauth/2.0/token";
 
 const MODELS: [(&str, &str, usize, isize); 7] = [
     // https://cloud.baidu.com/doc/WENXINWORKSHOP/s/clntwmv7t
@@ -64,8 +69,34 @@ const MODELS: [(&str, &str, usize, isize); 7] = [
     ),
 ];
 
+const EMBEDDING_MODELS: [(&str, &str, usize, usize); 3] = [
+    // https://cloud.baidu.com/doc/WENXINWORKSHOP/s/alj562vvu
+    // name, endpoint, max tokens of each input, max inputs of each request
+    (
+        "embedding-v1",
+        "/wenxinworkshop/embeddings/embedding-v1",
+        384,
+        16,
+    ),
+    (
+        "bge-large-zh",
+        "/wenxinworkshop/embeddings/bge_large_zh",
+        512,
+        16,
+    ),
+    ("tao-8k", "/wenxinworkshop/embeddings/tao_8k", 8192, 1),
+];
+
+// Models whose chat API rejects the top-level `system` field
+const NO_SYSTEM_MODELS: [&str; 1] = ["ernie-tiny-8k"];
+
+const ACCESS_TOKENS_FILE: &str = "ernie_tokens.json";
+/// Refresh an access token this many seconds before it expires, they last 30 days.
+const TOKEN_REFRESH_MARGIN: i64 = 24 * 60 * 60;
+
 lazy_static! {
-    static ref ACCESS_TOKEN: Mutex<Option<String>> = Mutex::new(None);
+    /// The access token and its expiry, by client name and api_key hash, loaded from the file once.
+    static ref ACCESS_TOKENS: Mutex<Option<HashMap<String, (String, i64)>>> = Mutex::new(None);
 }
 
 #[derive(Debug, Clone, Deserialize, Default)]
@@ -73,6 +104,14 @@ pub struct ErnieConfig {
     pub name: Option<String>,
     pub api_key: Option<String>,
     pub secret_key: Option<String>,
+    pub api_base: Option<String>,
+    /// Parameters such as `penalty_score` or `disable_search` for all the models, after the ones
+    /// of the model.
+    pub extra_fields: Option<Map<String, Value>>,
+    /// Turn off the web search of the model, a model can override it in its `extra_fields`.
+    pub disable_search: Option<bool>,
+    /// List the web sources of the answer after it, a model can override it in its `extra_fields`.
+    pub enable_citation: Option<bool>,
     #[serde(default)]
     pub models: Vec<ModelConfig>,
     pub extra: Option<ExtraConfig>,
@@ -82,10 +121,24 @@ pub struct ErnieConfig {
 impl Client for ErnieClient {
     client_common_fns!();
 
-    async fn send_message_inner(&self, client: &ReqwestClient, data: SendData) -> Result<String> {
-        self.prepare_access_token().await?;
-        let builder = self.request_builder(client, data)?;
-        send_message(builder).await
+    async fn send_message_inner(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+    ) -> Result<CompletionOutput> {
+        let access_token = self.prepare_access_token().await?;
+        let builder = self.request_builder(client, data.clone(), &access_token)?;
+        let ret = send_message(builder).await;
+        if !self.check_access_token(&ret) {
+            return ret;
+        }
+        // Only once, in case the credentials themselves are wrong
+        debug!("Ernie access token rejected, retrying with a fresh one");
+        let access_token = self.prepare_access_token().await?;
+        let builder = self.request_builder(client, data, &access_token)?;
+        let ret = send_message(builder).await;
+        self.check_access_token(&ret);
+        ret
     }
 
     async fn send_message_streaming_inner(
@@ -94,9 +147,56 @@ impl Client for ErnieClient {
         handler: &mut ReplyHandler,
         data: SendData,
     ) -> Result<()> {
-        self.prepare_access_token().await?;
-        let builder = self.request_builder(client, data)?;
-        send_message_streaming(builder, handler).await
+        let access_token = self.prepare_access_token().await?;
+        let builder = self.request_builder(client, data.clone(), &access_token)?;
+        let ret = send_message_streaming(builder, handler).await;
+        if !self.check_access_token(&ret) || handler.has_started() {
+            return ret;
+        }
+        debug!("Ernie access token rejected, retrying with a fresh one");
+        let access_token = self.prepare_access_token().await?;
+        let builder = self.request_builder(client, data, &access_token)?;
+        let ret = send_message_streaming(builder, handler).await;
+        self.check_access_token(&ret);
+        ret
+    }
+
+    async fn embeddings_inner(
+        &self,
+        client: &ReqwestClient,
+        texts: Vec<String>,
+    ) -> Result<EmbeddingsOutput> {
+        let (_, endpoint, max_tokens, batch_size) = EMBEDDING_MODELS
+            .iter()
+            .find(|(v, _, _, _)| *v == self.model.name)
+            .ok_or_else(|| anyhow!("Miss Model '{}'", self.model.id()))?;
+        if let Some((i, tokens)) = texts
+            .iter()
+            .map(|v| count_tokens(v))
+            .enumerate()
+            .find(|(_, tokens)| tokens > max_tokens)
+        {
+            bail!(
+                "The input {} is about {tokens} tokens but the model allows {max_tokens}",
+                i + 1
+            );
+        }
+        let access_token = self.prepare_access_token().await?;
+        let mut output = EmbeddingsOutput::default();
+        for chunk in texts.chunks(*batch_size) {
+            let builder = self.embeddings_request_builder(client, endpoint, chunk, &access_token);
+            let ret = embeddings(builder).await;
+            self.check_access_token(&ret);
+            let EmbeddingsOutput {
+                vectors,
+                input_tokens,
+            } = ret?;
+            output.vectors.extend(vectors);
+            if let Some(tokens) = input_tokens {
+                output.input_tokens = Some(output.input_tokens.unwrap_or_default() + tokens);
+            }
+        }
+        Ok(output)
     }
 }
 
@@ -109,6 +209,14 @@ impl ErnieClient {
     pub fn list_models(local_config: &ErnieConfig) -> Vec<Model> {
         let client_name = Self::name(local_config);
         if local_config.models.is_empty() {
+            let embedding_models =
+                EMBEDDING_MODELS
+                    .into_iter()
+                    .map(|(name, _, max_input_tokens, _)| {
+                        Model::new(client_name, name)
+                            .set_capabilities(ModelCapabilities::Embedding)
+                            .set_max_input_tokens(Some(max_input_tokens))
+                    });
             MODELS
                 .into_iter()
                 .map(|(name, _, max_input_tokens, max_output_tokens)| {
@@ -116,28 +224,46 @@ impl ErnieClient {
                         .set_max_input_tokens(Some(max_input_tokens))
                         .set_max_output_tokens(Some(max_output_tokens))
                 }) // ERNIE tokenizer is different from cl100k_base
+                .chain(embedding_models)
                 .collect()
         } else {
             Model::from_config(client_name, &local_config.models)
         }
     }
 
-    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
-        let body = build_body(data, &self.model);
-
-        let model = &self.model.name;
-        let (_, chat_endpoint, _, _) = MODELS
-            .iter()
-            .find(|(v, _, _, _)| v == model)
-            .ok_or_else(|| anyhow!("Miss Model '{}'", self.model.id()))?;
+    fn request_builder(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+        access_token: &str,
+    ) -> Result<RequestBuilder> {
+        let mut body = build_body(data, &self.model);
+        self.model.merge_extra_fields(&mut body);
+        if let Some(body) = body.as_object_mut() {
+            for (key, value) in self.config.extra_fields.iter().flatten() {
+                if !body.contains_key(key) {
+                    body.insert(key.clone(), value.clone());
+                }
+            }
+            let options = [
+                ("disable_search", self.config.disable_search),
+                ("enable_citation", self.config.enable_citation),
+            ];
+            for (key, value) in options {
+                if let (false, Some(value)) = (body.contains_key(key), value) {
+                    body.insert(key.into(), value.into());
+                }
+            }
+            if !supports_search(&self.model) {
+                body.retain(|key, _| !matches!(key.as_str(), "disable_search" | "enable_citation"));
+            }
+        }
+        check_params(&body)?;
 
-        let access_token = ACCESS_TOKEN
-            .lock()
-            .unwrap()
-            .clone()
-            .ok_or_else(|| anyhow!("Failed to load access token"))?;
+        let chat_endpoint = chat_endpoint(&self.model)?;
 
-        let url = format!("{API_BASE}{chat_endpoint}?access_token={access_token}");
+        let api_base = self.api_base();
+        let url = format!("{api_base}{CHAT_PATH}{chat_endpoint}?access_token={access_token}");
 
         debug!("Ernie Request: {url} {body}");
 
@@ -146,51 +272,283 @@ impl ErnieClient {
         Ok(builder)
     }
 
-    async fn prepare_access_token(&self) -> Result<()> {
-        if ACCESS_TOKEN.lock().unwrap().is_none() {
-            let env_prefix = Self::name(&self.config).to_uppercase();
-            let api_key = self.config.api_key.clone();
-            let api_key = api_key
-                .or_else(|| env::var(format!("{env_prefix}_API_KEY")).ok())
-                .ok_or_else(|| anyhow!("Miss api_key"))?;
-
-            let secret_key = self.config.secret_key.clone();
-            let secret_key = secret_key
-                .or_else(|| env::var(format!("{env_prefix}_SECRET_KEY")).ok())
-                .ok_or_else(|| anyhow!("Miss secret_key"))?;
-
-            let client = self.build_client()?;
-            let token = fetch_access_token(&client, &api_key, &secret_key)
-                .await
-                .with_context(|| "Failed to fetch access token")?;
-            *ACCESS_TOKEN.lock().unwrap() = Some(token);
+    fn embeddings_request_builder(
+        &self,
+        client: &ReqwestClient,
+        endpoint: &str,
+        texts: &[String],
+        access_token: &str,
+    ) -> RequestBuilder {
+        let api_base = self.api_base();
+        let url = format!("{api_base}{CHAT_PATH}{endpoint}?access_token={access_token}");
+        let body = json!({ "input": texts });
+
+        debug!("Ernie Request: {url} {body}");
+
+        client.post(url).json(&body)
+    }
+
+    fn token_request_builder(&self, client: &ReqwestClient) -> Result<RequestBuilder> {
+        let (api_key, secret_key) = self.credentials()?;
+        let api_base = self.api_base();
+        let url = format!("{api_base}{ACCESS_TOKEN_PATH}?grant_type=client_credentials&client_id={api_key}&client_secret={secret_key}");
+        Ok(client.get(url))
+    }
+
+    /// The host of both the chat and the token APIs.
+    fn api_base(&self) -> String {
+        let api_base = self.config.api_base.as_deref().unwrap_or(API_BASE);
+        api_base.trim_end_matches('/').to_string()
+    }
+
+    fn credentials(&self) -> Result<(String, String)> {
+        let env_prefix = Self::name(&self.config).to_uppercase();
+        let api_key = self.config.api_key.clone();
+        let api_key = api_key
+            .or_else(|| env::var(format!("{env_prefix}_API_KEY")).ok())
+            .ok_or_else(|| anyhow!("Miss api_key"))?;
+
+        let secret_key = self.config.secret_key.clone();
+        let secret_key = secret_key
+            .or_else(|| env::var(format!("{env_prefix}_SECRET_KEY")).ok())
+            .ok_or_else(|| anyhow!("Miss secret_key"))?;
+        Ok((api_key, secret_key))
+    }
+
+    fn access_token_key(&self) -> Result<String> {
+        let (api_key, _) = self.credentials()?;
+        Ok(format!(
+            "{}:{}",
+            Self::name(&self.config),
+            sha256sum(&api_key)
+        ))
+    }
+
+    /// The stored access token of this client, or a fresh one once it is about to expire.
+    async fn prepare_access_token(&self) -> Result<String> {
+        let key = self.access_token_key()?;
+        let now = Utc::now().timestamp();
+        {
+            let mut tokens = ACCESS_TOKENS.lock();
+            let tokens = tokens.get_or_insert_with(load_access_tokens);
+            if let Some((token, expires_at)) = tokens.get(&key) {
+                if now + TOKEN_REFRESH_MARGIN < *expires_at {
+                    return Ok(token.clone());
+                }
+            }
+        }
+
+        let builder = self.token_request_builder(&self.build_client()?)?;
+        let (token, expires_in) = fetch_access_token(builder)
+            .await
+            .with_context(|| "Failed to fetch access token")?;
+
+        let mut tokens = ACCESS_TOKENS.lock();
+        let tokens = tokens.get_or_insert_with(HashMap::new);
+        tokens.retain(|_, (_, expires_at)| now < *expires_at);
+        tokens.insert(key, (token.clone(), now + expires_in));
+        save_access_tokens(tokens);
+        Ok(token)
+    }
+
+    /// Drop the stored access token if the request was rejected for it, returning whether it was.
+    fn check_access_token<T>(&self, ret: &Result<T>) -> bool {
+        if !ret.as_ref().is_err_and(|v| v.is::<InvalidAccessToken>()) {
+            return false;
+        }
+        if let (Ok(key), Some(tokens)) = (self.access_token_key(), ACCESS_TOKENS.lock().as_mut()) {
+            tokens.remove(&key);
+            save_access_tokens(tokens);
+        }
+        true
+    }
+}
+
+/// Repair the alternation Ernie requires, an odd number of messages from user to user: merge
+/// adjacent messages of the same role and drop assistant messages at either end.
+fn normalize_messages(list: Vec<Value>) -> Vec<Value> {
+    let mut output: Vec<Value> = vec![];
+    let mut repairs = vec![];
+    for message in list {
+        let role = message["role"].as_str().unwrap_or_default();
+        if output.is_empty() && role == "assistant" {
+            repairs.push("dropped a leading assistant message");
+            continue;
+        }
+        if let Some(last) = output.last_mut() {
+            let mergeable = |v: &Value| v["content"].is_string() && v["function_call"].is_null();
+            if last["role"] == role && role != "function" && mergeable(last) && mergeable(&message)
+            {
+                let content = format!(
+                    "{}\n\n{}",
+                    last["content"].as_str().unwrap_or_default(),
+                    message["content"].as_str().unwrap_or_default()
+                );
+                last["content"] = content.into();
+                repairs.push("merged adjacent messages of the same role");
+                continue;
+            }
+        }
+        output.push(message);
+    }
+    if output.last().is_some_and(|v| v["role"] == "assistant") {
+        output.pop();
+        repairs.push("dropped a trailing assistant message");
+    }
+    if !repairs.is_empty() {
+        debug!("Ernie messages repaired: {}", repairs.join(", "));
+    }
+    output
+}
+
+/// The `function_call` the model returns instead of text, its arguments are a JSON string.
+fn extract_function_call(data: &Value) -> Option<ToolCall> {
+    let call = &data["function_call"];
+    let name = call["name"].as_str()?;
+    let arguments = match call["arguments"].as_str() {
+        Some(arguments) => serde_json::from_str(arguments).unwrap_or_else(|_| arguments.into()),
+        None => call["arguments"].clone(),
+    };
+    Some(ToolCall::new(name, arguments, None))
+}
+
+/// Check the ranges of the parameters the API documents, it only tells an error_code otherwise.
+fn check_params(body: &Value) -> Result<()> {
+    if let Some(value) = body.get("penalty_score") {
+        if !value.as_f64().is_some_and(|v| (1.0..=2.0).contains(&v)) {
+            bail!("Invalid `penalty_score` {value}, expected a number from 1.0 to 2.0");
+        }
+    }
+    if let Some(value) = body.get("max_output_tokens") {
+        if value.as_i64().is_none_or(|v| v < 2) {
+            bail!("Invalid `max_output_tokens` {value}, expected an integer of at least 2");
+        }
+    }
+    if let Some(value) = body.get("stop") {
+        let valid = value.as_array().is_some_and(|list| {
+            list.len() <= 4
+                && list
+                    .iter()
+                    .all(|v| v.as_str().is_some_and(|v| v.chars().count() <= 20))
+        });
+        if !valid {
+            bail!("Invalid `stop` {value}, expected up to 4 strings of at most 20 characters");
+        }
+    }
+    if let Some(value) = body.get("disable_search") {
+        if !value.is_boolean() {
+            bail!("Invalid `disable_search` {value}, expected a boolean");
+        }
+    }
+    Ok(())
+}
+
+/// Only ERNIE 3.5 and 4.0 take `disable_search` and `enable_citation`, the others reject them.
+fn supports_search(model: &Model) -> bool {
+    ["ernie-3.5", "ernie-4.0"]
+        .iter()
+        .any(|v| model.name.starts_with(v))
+}
+
+/// The chat endpoint of the model, `real_name` of a declared one can be a path or the last segment
+/// of `/wenxinworkshop/chat/...`.
+fn chat_endpoint(model: &Model) -> Result<String> {
+    if let Some(real_name) = &model.real_name {
+        return match real_name.starts_with('/') {
+            true => Ok(real_name.clone()),
+            false => Ok(format!("/wenxinworkshop/chat/{real_name}")),
+        };
+    }
+    match MODELS.iter().find(|(v, _, _, _)| *v == model.name) {
+        Some((_, chat_endpoint, _, _)) => Ok(chat_endpoint.to_string()),
+        None => bail!(
+            "Miss Model '{}', set its endpoint with `real_name`",
+            model.id()
+        ),
+    }
+}
+
+/// The stored access tokens, or none if the file is missing or unreadable.
+fn load_access_tokens() -> HashMap<String, (String, i64)> {
+    Config::local_path(ACCESS_TOKENS_FILE)
+        .ok()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+/// Store the access tokens for the next invocations, it is fine to fetch them again on failure.
+fn save_access_tokens(tokens: &HashMap<String, (String, i64)>) {
+    let ret = Config::local_path(ACCESS_TOKENS_FILE).and_then(|path| {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
         }
+        fs::write(&path, serde_json::to_string(tokens)?)?;
         Ok(())
+    });
+    if let Err(err) = ret {
+        debug!("Failed to save the Ernie access tokens: {err}");
     }
 }
 
-async fn send_message(builder: RequestBuilder) -> Result<String> {
-    let data: Value = builder.send().await?.json().await?;
+async fn embeddings(builder: RequestBuilder) -> Result<EmbeddingsOutput> {
+    let data: Value = send_request(builder).await?.json().await?;
     catch_error(&data)?;
 
-    let output = data["result"]
-        .as_str()
+    let list = data["data"]
+        .as_array()
+        .ok_or_else(|| anyhow!("Unexpected response {data}"))?;
+    let vectors = list
+        .iter()
+        .map(|v| {
+            v["embedding"]
+                .as_array()
+                .and_then(|list| list.iter().map(|v| v.as_f64().map(|v| v as f32)).collect())
+        })
+        .collect::<Option<Vec<Vec<f32>>>>()
         .ok_or_else(|| anyhow!("Unexpected response {data}"))?;
 
-    Ok(output.to_string())
+    Ok(EmbeddingsOutput {
+        vectors,
+        input_tokens: data["usage"]["prompt_tokens"].as_u64(),
+    })
+}
+
+async fn send_message(builder: RequestBuilder) -> Result<CompletionOutput> {
+    let data: Value = send_request(builder).await?.json().await?;
+    catch_error(&data)?;
+    check_result(&data)?;
+
+    let mut output = match extract_function_call(&data) {
+        Some(call) => {
+            let mut output = CompletionOutput::new(data["result"].as_str().unwrap_or_default());
+            output.tool_calls.push(call);
+            output
+        }
+        None => {
+            let text = data["result"]
+                .as_str()
+                .ok_or_else(|| anyhow!("Unexpected response {data}"))?;
+            CompletionOutput::new(text)
+        }
+    };
+    let mut citations = vec![];
+    extract_citations(&data, &mut citations);
+    output.text.push_str(&format_citations(&citations));
+    output.input_tokens = data["usage"]["prompt_tokens"].as_u64();
+    output.output_tokens = data["usage"]["completion_tokens"].as_u64();
+    output.stop_reason = data["finish_reason"].as_str().map(|v| v.to_string());
+    Ok(output)
 }
 
 async fn send_message_streaming(builder: RequestBuilder, handler: &mut ReplyHandler) -> Result<()> {
-    let mut es = builder.eventsource()?;
+    // The sources are listed once the stream ends
+    let mut citations = vec![];
+    let mut es = event_source(builder)?;
     while let Some(event) = es.next().await {
         match event {
             Ok(Event::Open) => {}
-            Ok(Event::Message(message)) => {
-                let data: Value = serde_json::from_str(&message.data)?;
-                if let Some(text) = data["result"].as_str() {
-                    handler.text(text)?;
-                }
-            }
+            Ok(Event::Message(message)) => handle_event(&message.data, handler, &mut citations)?,
             Err(err) => {
                 match err {
                     EventSourceError::InvalidContentType(header_value, res) => {
@@ -203,14 +561,15 @@ async fn send_message_streaming(builder: RequestBuilder, handler: &mut ReplyHand
                             bail!("Request failed");
                         } else {
                             let text = res.text().await?;
-                            if let Some(text) = text.strip_prefix("data: ") {
-                                let data: Value = serde_json::from_str(text)?;
-                                if let Some(text) = data["result"].as_str() {
-                                    handler.text(text)?;
-                                }
-                            } else {
+                            let mut parser = SseParser::new();
+                            let mut events = parser.push(text.as_bytes())?;
+                            events.extend(parser.finish()?);
+                            if events.is_empty() {
                                 bail!("Invalid response data: {text}")
                             }
+                            for event in events {
+                                handle_event(&event.data, handler, &mut citations)?;
+                            }
                         }
                     }
                     EventSourceError::StreamEnded => {}
@@ -223,6 +582,74 @@ async fn send_message_streaming(builder: RequestBuilder, handler: &mut ReplyHand
         }
     }
 
+    if !citations.is_empty() {
+        handler.text(&format_citations(&citations))?;
+    }
+    Ok(())
+}
+
+/// Handle one SSE frame, the last one carries the usage and the finish_reason.
+fn handle_event(
+    data: &str,
+    handler: &mut ReplyHandler,
+    citations: &mut Vec<(String, String)>,
+) -> Result<()> {
+    let data: Value = serde_json::from_str(data)?;
+    catch_error(&data)?;
+    check_result(&data)?;
+    extract_citations(&data, citations);
+    if let Some(text) = data["result"].as_str() {
+        handler.text(text)?;
+    }
+    if let Some(call) = extract_function_call(&data) {
+        handler.tool_call(call);
+    }
+    let usage = &data["usage"];
+    if usage.is_object() {
+        handler.set_usage(
+            usage["prompt_tokens"].as_u64(),
+            usage["completion_tokens"].as_u64(),
+        );
+    }
+    if let Some(finish_reason) = data["finish_reason"].as_str().filter(|v| !v.is_empty()) {
+        handler.set_stop_reason(finish_reason);
+    }
+    Ok(())
+}
+
+/// Collect the title and url of each new source in `search_info` of an `enable_citation` request.
+fn extract_citations(data: &Value, citations: &mut Vec<(String, String)>) {
+    let results = data["search_info"]["search_results"].as_array();
+    for result in results.into_iter().flatten() {
+        let Some(url) = result["url"].as_str() else {
+            continue;
+        };
+        if citations.iter().any(|(_, v)| v == url) {
+            continue;
+        }
+        let title = result["title"].as_str().unwrap_or(url);
+        citations.push((title.to_string(), url.to_string()));
+    }
+}
+
+fn format_citations(citations: &[(String, String)]) -> String {
+    if citations.is_empty() {
+        return String::new();
+    }
+    let items: Vec<String> = citations
+        .iter()
+        .enumerate()
+        .map(|(i, (title, url))| format!("{}. [{title}]({url})", i + 1))
+        .collect();
+    format!("\n\nSources:\n{}", items.join("\n"))
+}
+
+/// Fail if the conversation was flagged.
+fn check_result(data: &Value) -> Result<()> {
+    if data["need_clear_history"].as_bool() == Some(true) {
+        let ban_round = data["ban_round"].as_i64().unwrap_or(-1);
+        return Err(NeedClearHistory(ban_round).into());
+    }
     Ok(())
 }
 
@@ -231,15 +658,56 @@ fn build_body(data: SendData, model: &Model) -> Value {
         mut messages,
         temperature,
         top_p,
+        functions,
         stream,
     } = data;
 
-    patch_system_message(&mut messages);
+    let system_message = if NO_SYSTEM_MODELS.contains(&model.name.as_str()) {
+        patch_system_message(&mut messages);
+        None
+    } else {
+        extract_sytem_message(&mut messages)
+    };
+
+    // The conversation has to start with a user turn
+    if let (true, Some(system)) = (messages.is_empty(), &system_message) {
+        messages.push(Message::user(system));
+    }
+
+    let mut list = vec![];
+    for message in messages {
+        let MessageContent::ToolResults((results, text)) = message.content else {
+            list.push(json!(message));
+            continue;
+        };
+        // One call each turn, answered by a `function` message
+        for (i, ToolResult { call, output }) in results.into_iter().enumerate() {
+            let content = if i == 0 { text.as_str() } else { "" };
+            let output = match output {
+                Value::String(output) => output,
+                output => output.to_string(),
+            };
+            list.push(json!({
+                "role": "assistant",
+                "content": content,
+                "function_call": { "name": call.name, "arguments": call.arguments.to_string() },
+            }));
+            list.push(json!({ "role": "function", "name": call.name, "content": output }));
+        }
+    }
 
     let mut body = json!({
-        "messages": messages,
+        "messages": normalize_messages(list),
     });
 
+    if let Some(system) = system_message {
+        body["system"] = system.into();
+    }
+
+    if let Some(functions) = functions.filter(|v| !v.is_empty()) {
+        body["functions"] = json!(functions);
+    }
+
     if let Some(temperature) = temperature {
         body["temperature"] = temperature.into();
     }
@@ -264,21 +732,52 @@ fn catch_error(data: &Value) -> Result<()> {
     {
         debug!("Invalid response: {}", data);
         let error_code = error_code.as_i64().unwrap_or_default();
-        if error_code == 110 {
-            *ACCESS_TOKEN.lock().unwrap() = None;
+        let text = format!("{error_msg} (error_code: {error_code})");
+        // Invalid or expired access token
+        if matches!(error_code, 110 | 111) {
+            return Err(InvalidAccessToken(text).into());
         }
-        bail!("{error_msg} (error_code: {error_code})");
+        bail!("{text}");
     }
     Ok(())
 }
 
-async fn fetch_access_token(
-    client: &reqwest::Client,
-    api_key: &str,
-    secret_key: &str,
-) -> Result<String> {
-    let url = format!("{ACCESS_TOKEN_URL}?grant_type=client_credentials&client_id={api_key}&client_secret={secret_key}");
-    let value: Value = client.get(&url).send().await?.json().await?;
+/// The content filter flagged a round of the conversation, it has to be removed to go on.
+#[derive(Debug)]
+struct NeedClearHistory(i64);
+
+impl fmt::Display for NeedClearHistory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            -1 => write!(
+                f,
+                "The last message was flagged by the content filter, rephrase it (ban_round: -1)"
+            ),
+            ban_round => write!(
+                f,
+                "Round {ban_round} of the conversation was flagged by the content filter, remove it from the history or start a new session (ban_round: {ban_round})"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NeedClearHistory {}
+
+/// A request rejected for its access token, the stored one is dropped on it.
+#[derive(Debug)]
+struct InvalidAccessToken(String);
+
+impl fmt::Display for InvalidAccessToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidAccessToken {}
+
+/// The access token and how many seconds it lasts.
+async fn fetch_access_token(builder: RequestBuilder) -> Result<(String, i64)> {
+    let value: Value = send_token_request(builder).await?.json().await?;
     let result = value["access_token"].as_str().ok_or_else(|| {
         if let Some(err_msg) = value["error_description"].as_str() {
             anyhow!("{err_msg}")
@@ -286,5 +785,486 @@ async fn fetch_access_token(
             anyhow!("Invalid response data")
         }
     })?;
-    Ok(result.to_string())
+    let expires_in = value["expires_in"].as_i64().unwrap_or(30 * 24 * 60 * 60);
+    Ok((result.to_string(), expires_in))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{custom_headers, fixture, with_headers, ClientConfig, FunctionDeclaration};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    fn send_data(messages: Vec<Message>) -> SendData {
+        SendData {
+            messages,
+            temperature: None,
+            top_p: None,
+            functions: None,
+            stream: false,
+        }
+    }
+
+    #[test]
+    fn test_build_body_system() {
+        let model = ErnieClient::list_models(&ErnieConfig::default()).remove(0);
+        let data = send_data(vec![Message::system("Be brief"), Message::user("Hi")]);
+        assert_eq!(
+            build_body(data, &model),
+            json!({
+                "messages": [{ "role": "user", "content": "Hi" }],
+                "system": "Be brief",
+                "max_output_tokens": 2048,
+            })
+        );
+
+        let data = send_data(vec![Message::system("Be brief")]);
+        let body = build_body(data, &model);
+        assert_eq!(body["messages"][0]["role"], "user");
+        assert_eq!(body["system"], "Be brief");
+
+        let model = Model::new("ernie", "ernie-tiny-8k");
+        let data = send_data(vec![Message::system("Be brief"), Message::user("Hi")]);
+        assert_eq!(
+            build_body(data, &model),
+            json!({
+                "messages": [{ "role": "user", "content": "Be brief\n\nHi" }],
+            })
+        );
+    }
+
+    #[test]
+    fn test_build_body_with_functions() {
+        let model = ErnieClient::list_models(&ErnieConfig::default()).remove(0);
+        let call = ToolCall::new("get_weather", json!({ "location": "Boston" }), None);
+        let data = SendData {
+            messages: vec![
+                Message::user("What's the weather in Boston?"),
+                Message {
+                    role: MessageRole::Assistant,
+                    content: MessageContent::ToolResults((
+                        vec![ToolResult {
+                            call,
+                            output: json!({ "weather": "Sunny, 22°C" }),
+                        }],
+                        String::new(),
+                    )),
+                    pinned: false,
+                },
+            ],
+            temperature: None,
+            top_p: None,
+            functions: Some(vec![FunctionDeclaration {
+                name: "get_weather".into(),
+                description: "Get the current weather of a location".into(),
+                parameters: json!({ "type": "object" }),
+            }]),
+            stream: false,
+        };
+        let body = build_body(data, &model);
+        assert_eq!(
+            body["messages"],
+            json!([
+                { "role": "user", "content": "What's the weather in Boston?" },
+                {
+                    "role": "assistant",
+                    "content": "",
+                    "function_call": { "name": "get_weather", "arguments": "{\"location\":\"Boston\"}" },
+                },
+                { "role": "function", "name": "get_weather", "content": "{\"weather\":\"Sunny, 22°C\"}" },
+            ])
+        );
+        assert_eq!(body["functions"][0]["name"], "get_weather");
+    }
+
+    #[test]
+    fn test_search_options() -> Result<()> {
+        let new_client = |model: &str| {
+            let config = ErnieConfig {
+                disable_search: Some(true),
+                enable_citation: Some(true),
+                ..Default::default()
+            };
+            ErnieClient {
+                global_config: Default::default(),
+                config,
+                model: Model::new("ernie", model),
+            }
+        };
+        let body = |client: ErnieClient| -> Result<Value> {
+            let request = client
+                .request_builder(&ReqwestClient::new(), fixture::send_data(false), "xxx")?
+                .build()?;
+            Ok(serde_json::from_slice(
+                request.body().and_then(|v| v.as_bytes()).unwrap(),
+            )?)
+        };
+
+        let mut client = new_client("ernie-4.0-8k");
+        client.model.extra_fields = json!({ "disable_search": false }).as_object().cloned();
+        let value = body(client)?;
+        assert_eq!(value["disable_search"], false);
+        assert_eq!(value["enable_citation"], true);
+
+        let value = body(new_client("ernie-lite-8k"))?;
+        assert!(value.get("disable_search").is_none());
+        assert!(value.get("enable_citation").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalize_messages() {
+        let message = |role: &str, content: &str| json!({ "role": role, "content": content });
+
+        let list = vec![message("user", "Hi"), message("user", "Are you there?")];
+        assert_eq!(
+            normalize_messages(list),
+            [message("user", "Hi\n\nAre you there?")]
+        );
+
+        let list = vec![
+            message("user", "Hi"),
+            message("assistant", "Hello"),
+            message("user", "Bye"),
+            message("assistant", "Good"),
+        ];
+        assert_eq!(
+            normalize_messages(list),
+            [
+                message("user", "Hi"),
+                message("assistant", "Hello"),
+                message("user", "Bye")
+            ]
+        );
+
+        let list = vec![message("assistant", "Hello"), message("user", "Hi")];
+        assert_eq!(normalize_messages(list), [message("user", "Hi")]);
+
+        let list = vec![
+            message("user", "Hi"),
+            message("assistant", "Hello"),
+            message("assistant", "How can I help?"),
+            message("user", "Bye"),
+        ];
+        assert_eq!(
+            normalize_messages(list)[1],
+            message("assistant", "Hello\n\nHow can I help?")
+        );
+    }
+
+    #[test]
+    fn test_check_params() {
+        let body = json!({
+            "messages": [],
+            "penalty_score": 1.2,
+            "max_output_tokens": 1024,
+            "stop": ["\n\n"],
+            "disable_search": true,
+        });
+        assert!(check_params(&body).is_ok());
+
+        let err = check_params(&json!({ "penalty_score": 2.5 })).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid `penalty_score` 2.5, expected a number from 1.0 to 2.0"
+        );
+        assert!(check_params(&json!({ "max_output_tokens": 1 })).is_err());
+        assert!(check_params(&json!({ "stop": ["a", "b", "c", "d", "e"] })).is_err());
+        assert!(check_params(&json!({ "disable_search": "yes" })).is_err());
+    }
+
+    #[test]
+    fn test_chat_endpoint() -> Result<()> {
+        let clients: Vec<ClientConfig> = serde_yaml::from_str(
+            r#"
+- type: ernie
+  models:
+    - name: ernie-4.0-turbo-8k
+      real_name: ernie-4.0-turbo-8k
+      max_input_tokens: 6144
+      max_output_tokens: 2048
+    - name: my-model
+      real_name: /wenxinworkshop/chat/abcdefgh
+"#,
+        )?;
+        let config = match &clients[0] {
+            ClientConfig::ErnieConfig(c) => c.clone(),
+            _ => unreachable!(),
+        };
+        let models = ErnieClient::list_models(&config);
+        assert_eq!(models[0].max_input_tokens, Some(6144));
+        assert_eq!(models[0].max_output_tokens, Some(2048));
+        assert_eq!(
+            chat_endpoint(&models[0])?,
+            "/wenxinworkshop/chat/ernie-4.0-turbo-8k"
+        );
+        assert_eq!(chat_endpoint(&models[1])?, "/wenxinworkshop/chat/abcdefgh");
+
+        let model = ErnieClient::list_models(&ErnieConfig::default()).remove(0);
+        assert_eq!(
+            chat_endpoint(&model)?,
+            "/wenxinworkshop/chat/completions_pro"
+        );
+        assert!(chat_endpoint(&Model::new("ernie", "ernie-x")).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let client = ErnieClient::for_test("{ api_key: xxx, secret_key: yyy }")?;
+        let http = ReqwestClient::new();
+        let request = |data| client.request_builder(&http, data, "token");
+        let ask = |text: &str, stream| SendData {
+            messages: vec![Message::user(text)],
+            ..fixture::send_data(stream)
+        };
+
+        let output = send_message(
+            fixture::replay("ernie/success", request(fixture::send_data(false))?).await?,
+        )
+        .await?;
+        assert_eq!(output.text, "Hello! How can I help you today?");
+        assert_eq!(
+            (output.input_tokens, output.output_tokens),
+            (Some(1), Some(9))
+        );
+
+        let (mut handler, _rx) = fixture::handler();
+        send_message_streaming(
+            fixture::replay("ernie/stream", request(fixture::send_data(true))?).await?,
+            &mut handler,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "Hello! How can I help you today?");
+        assert_eq!(handler.get_usage(), (Some(1), Some(9)));
+
+        let (mut handler, _rx) = fixture::handler();
+        let truncated = json!({ "result": "Hello", "finish_reason": "length" });
+        handle_event(&truncated.to_string(), &mut handler, &mut vec![])?;
+        assert_eq!(handler.get_buffer(), "Hello");
+        assert_eq!(handler.get_stop_reason(), Some("length"));
+
+        let err = send_message(
+            fixture::replay(
+                "ernie/need_clear_history",
+                request(fixture::send_data(false))?,
+            )
+            .await?,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.is::<NeedClearHistory>());
+        assert!(err.to_string().ends_with("(ban_round: 2)"));
+
+        let texts = ["Hello".to_string(), "How are you?".to_string()];
+        let (_, endpoint, _, _) = EMBEDDING_MODELS[0];
+        let builder = client.embeddings_request_builder(&http, endpoint, &texts, "token");
+        let output = embeddings(fixture::replay("ernie/embeddings", builder).await?).await?;
+        assert_eq!(output.vectors.len(), 2);
+        assert_eq!(output.vectors[0], [0.0183, -0.0527, 0.0346, 0.0071]);
+        assert_eq!(output.input_tokens, Some(6));
+
+        let citation_client =
+            ErnieClient::for_test("{ api_key: xxx, secret_key: yyy, enable_citation: true }")?;
+        let builder =
+            citation_client.request_builder(&http, ask("What is AIChat?", true), "token")?;
+        let (mut handler, _rx) = fixture::handler();
+        send_message_streaming(
+            fixture::replay("ernie/citation_stream", builder).await?,
+            &mut handler,
+        )
+        .await?;
+        assert_eq!(
+            handler.get_buffer(),
+            "AIChat is an all-in-one LLM CLI tool.^1^\n\nSources:\n1. [sigoden/aichat - GitHub](https://github.com/sigoden/aichat)"
+        );
+
+        let data = SendData {
+            functions: Some(vec![FunctionDeclaration {
+                name: "get_weather".into(),
+                description: "Get the current weather of a location".into(),
+                parameters: json!({
+                    "type": "object",
+                    "properties": { "location": { "type": "string" } },
+                    "required": ["location"],
+                }),
+            }]),
+            ..ask("What's the weather in Boston?", false)
+        };
+        let output =
+            send_message(fixture::replay("ernie/function_call", request(data)?).await?).await?;
+        assert_eq!(output.text, "");
+        assert_eq!(
+            output.tool_calls,
+            [ToolCall::new(
+                "get_weather",
+                json!({ "location": "Boston" }),
+                None
+            )]
+        );
+
+        let err = send_message(
+            fixture::replay("ernie/auth_failure", request(fixture::send_data(false))?).await?,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Access token invalid or no longer valid (error_code: 110)"
+        );
+        assert!(err.is::<InvalidAccessToken>());
+
+        let token = fetch_access_token(
+            fixture::replay("ernie/token", client.token_request_builder(&http)?).await?,
+        )
+        .await?;
+        assert_eq!(
+            token,
+            (
+                "24.6c5e1ff107f0e8bcef8c46d3424a0e78.2592000.1720000000.282335-12345678".into(),
+                2592000
+            )
+        );
+
+        let (mut handler, _rx) = fixture::handler();
+        let err = send_message_streaming(
+            fixture::replay("ernie/rate_limit", request(fixture::send_data(true))?).await?,
+            &mut handler,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Open api qps request limit reached (error_code: 18)"
+        );
+
+        let (mut handler, _rx) = fixture::handler();
+        let err = send_message_streaming(
+            fixture::replay("ernie/stream_error", request(fixture::send_data(true))?).await?,
+            &mut handler,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "system is busy, please try again later (error_code: 336100)"
+        );
+        assert_eq!(handler.get_buffer(), "Hello!");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_on_invalid_access_token() -> Result<()> {
+        fixture::use_temp_config_dir();
+        let new_client = |name: &str, api_base: String, max_retries| {
+            let config = ErnieConfig {
+                name: Some(name.into()),
+                api_key: Some("xxx".into()),
+                secret_key: Some("yyy".into()),
+                api_base: Some(api_base),
+                extra: Some(ExtraConfig {
+                    max_retries: Some(max_retries),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            let model = ErnieClient::list_models(&config).remove(0);
+            let client = ErnieClient {
+                global_config: Default::default(),
+                config,
+                model,
+            };
+            let key = client.access_token_key().unwrap();
+            let expires_at = Utc::now().timestamp() + 7 * 24 * 60 * 60;
+            ACCESS_TOKENS
+                .lock()
+                .get_or_insert_with(HashMap::new)
+                .insert(key, ("stale".into(), expires_at));
+            client
+        };
+
+        let api_base =
+            fixture::serve(&["ernie/auth_failure", "ernie/success", "ernie/token"]).await?;
+        let client = new_client("ernie-retry", api_base, 0);
+        let output = client.send_data(fixture::send_data(false)).await?;
+        assert_eq!(output.text, "Hello! How can I help you today?");
+        assert!(client.prepare_access_token().await?.starts_with("24."));
+
+        // Gives up after one retry, however many retries are left
+        let api_base = fixture::serve(&["ernie/auth_failure", "ernie/token"]).await?;
+        let client = new_client("ernie-retry-fail", api_base, 2);
+        let err = client
+            .send_data(fixture::send_data(false))
+            .await
+            .unwrap_err();
+        assert!(err.root_cause().is::<InvalidAccessToken>());
+        assert!(!err.chain().any(|v| v.to_string().starts_with("Gave up")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_token_request_without_custom_headers() -> Result<()> {
+        fixture::use_temp_config_dir();
+        // A token endpoint that keeps the head of the request
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let head = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await?;
+            let mut buf = vec![0u8; 4096];
+            let n = stream.read(&mut buf).await?;
+            let body = r#"{"access_token":"24.fresh","expires_in":2592000}"#;
+            let res = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(res.as_bytes()).await?;
+            anyhow::Ok(String::from_utf8_lossy(&buf[..n]).to_lowercase())
+        });
+        let config = ErnieConfig {
+            name: Some("ernie-headers".into()),
+            api_key: Some("xxx".into()),
+            secret_key: Some("yyy".into()),
+            api_base: Some(format!("http://{addr}")),
+            extra: Some(ExtraConfig {
+                headers: Some(HashMap::from([("x-gateway-key".into(), "secret".into())])),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let model = ErnieClient::list_models(&config).remove(0);
+        let client = ErnieClient {
+            global_config: Default::default(),
+            config,
+            model,
+        };
+        let headers = custom_headers(client.config().1)?;
+        assert_eq!(headers.len(), 1);
+        let token = with_headers(headers, client.prepare_access_token()).await?;
+        assert_eq!(token, "24.fresh");
+        let head = head.await??;
+        assert!(head.starts_with("get /oauth/2.0/token?"), "{head}");
+        assert!(!head.contains("x-gateway-key"), "{head}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn record_fixtures() -> Result<()> {
+        if !fixture::recording() {
+            return Ok(());
+        }
+        let client = ErnieClient::for_recording()?;
+        let http = client.build_client()?;
+        let access_token = client.prepare_access_token().await?;
+        let builder = client.request_builder(&http, fixture::send_data(false), &access_token)?;
+        fixture::record("ernie/success", builder).await?;
+        let builder = client.request_builder(&http, fixture::send_data(true), &access_token)?;
+        fixture::record("ernie/stream", builder).await?;
+        let builder = client.request_builder(&http, fixture::send_data(false), "invalid")?;
+        fixture::record("ernie/auth_failure", builder).await?;
+        Ok(())
+    }
 }
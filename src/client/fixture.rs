@@ -0,0 +1,323 @@
+//! Record and replay the HTTP exchanges of the clients.
+//!
+//! Fixtures live in `tests/fixtures/<client>/<case>.json`. A test builds the request with the
+//! client under test and replays the fixture with a local server, which checks the request against
+//! the recorded one and sends the response chunk by chunk, so the stream parsers see the recorded
+//! event sequence.
+//!
+//! Run `AICHAT_RECORD_FIXTURES=1 cargo test claude::tests::record_fixtures` to record fresh
+//! fixtures with the claude client of your config. Only the request method, path and body and the
+//! response status, content type and body are kept; headers and query strings, which carry
+//! credentials, are dropped. Cases that can't be provoked on demand, such as rate limits and
+//! mid-stream errors, are written by hand.
+
+use super::{ClientConfig, Message, ReplyEvent, ReplyHandler, SendData};
+
+use crate::config::{Config, GlobalConfig, WorkingMode};
+use crate::utils::{create_abort_signal, get_env_name};
+
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use http::{header::CONTENT_TYPE, Request as HttpRequest, Response};
+use http_body_util::{BodyExt, StreamBody};
+use hyper::{
+    body::{Frame, Incoming},
+    server::conn::http1,
+    service::service_fn,
+};
+use hyper_util::rt::TokioIo;
+use parking_lot::{Mutex, RwLock};
+use reqwest::{Request, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    env, fs,
+    path::PathBuf,
+    sync::{Arc, Once},
+    time::Duration,
+};
+use tokio::{
+    net::TcpListener,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver},
+    time::sleep,
+};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Fixture {
+    pub request: FixtureRequest,
+    pub response: FixtureResponse,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FixtureRequest {
+    pub method: String,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub body: Value,
+}
+
+/// A JSON `body`, or the raw `chunks` of a stream as they came off the wire.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FixtureResponse {
+    pub status: u16,
+    pub content_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunks: Option<Vec<String>>,
+}
+
+/// Serve the fixture `name` and return `builder`, the request of the client under test, pointed
+/// at it.
+///
+/// Fails unless the method, path and body of the request are the fixture's.
+pub async fn replay(name: &str, builder: RequestBuilder) -> Result<RequestBuilder> {
+    let Fixture { request, response } = load(name)?;
+    let (client, actual) = builder.build_split();
+    let mut actual = actual?;
+    check_request(name, &request, &actual)?;
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let response = Arc::new(response);
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let response = response.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |_| respond(response.clone()));
+                let _ = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    let url = actual.url_mut();
+    url.set_scheme("http")
+        .and_then(|_| url.set_host(Some(&addr.ip().to_string())).map_err(|_| ()))
+        .and_then(|_| url.set_port(Some(addr.port())))
+        .map_err(|_| anyhow!("Cannot point '{url}' at the fixture server"))?;
+    Ok(RequestBuilder::from_parts(client, actual))
+}
+
+/// Serve the fixtures `names` as a mock server, by the path of their requests, and return its url.
+///
+/// Fixtures of the same path are served in turn, the last one from then on.
+pub async fn serve(names: &[&str]) -> Result<String> {
+    let mut routes: HashMap<String, Vec<Arc<FixtureResponse>>> = HashMap::new();
+    for name in names {
+        let Fixture { request, response } = load(name)?;
+        routes
+            .entry(request.path)
+            .or_default()
+            .push(Arc::new(response));
+    }
+    let routes = Arc::new(Mutex::new(routes));
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let routes = routes.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req: HttpRequest<Incoming>| {
+                    let response = {
+                        let mut routes = routes.lock();
+                        match routes.get_mut(req.uri().path()) {
+                            Some(list) if list.len() > 1 => Some(list.remove(0)),
+                            Some(list) => list.first().cloned(),
+                            None => None,
+                        }
+                    };
+                    let response = response.unwrap_or_else(|| {
+                        Arc::new(FixtureResponse {
+                            status: 404,
+                            content_type: "text/plain".into(),
+                            body: None,
+                            chunks: Some(vec![format!("No fixture for {}", req.uri().path())]),
+                        })
+                    });
+                    respond(response)
+                });
+                let _ = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    Ok(format!("http://{addr}"))
+}
+
+/// Send the request and save the exchange as the fixture `name`.
+pub async fn record(name: &str, builder: RequestBuilder) -> Result<()> {
+    let (client, request) = builder.build_split();
+    let request = request?;
+    let method = request.method().to_string();
+    let path = sanitize_path(request.url().path());
+    let body = request_body(&request);
+    let res = client.execute(request).await?;
+    let status = res.status().as_u16();
+    let content_type = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let mut chunks = vec![];
+    let mut stream = res.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        chunks.push(String::from_utf8_lossy(&chunk?).to_string());
+    }
+    let (body_value, chunks) = match chunks.as_slice() {
+        [chunk] => match serde_json::from_str(chunk) {
+            Ok(value) => (Some(value), None),
+            Err(_) => (None, Some(chunks)),
+        },
+        _ => (None, Some(chunks)),
+    };
+    let fixture = Fixture {
+        request: FixtureRequest { method, path, body },
+        response: FixtureResponse {
+            status,
+            content_type,
+            body: body_value,
+            chunks,
+        },
+    };
+    let path = fixture_path(name);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, serde_json::to_string_pretty(&fixture)? + "\n")
+        .with_context(|| format!("Failed to write '{}'", path.display()))?;
+    Ok(())
+}
+
+fn check_request(name: &str, expect: &FixtureRequest, actual: &Request) -> Result<()> {
+    let method = actual.method().as_str();
+    let path = sanitize_path(actual.url().path());
+    let body = request_body(actual);
+    if method != expect.method || path != expect.path || body != expect.body {
+        bail!(
+            "The request doesn't match the fixture '{name}'\n  expected: {} {} {}\n  actual: {method} {path} {body}",
+            expect.method,
+            expect.path,
+            expect.body
+        );
+    }
+    Ok(())
+}
+
+pub fn recording() -> bool {
+    env::var("AICHAT_RECORD_FIXTURES").is_ok()
+}
+
+/// Point the config dir at a temp dir, keeping the caches written by a test out of the user's.
+pub fn use_temp_config_dir() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        let dir = env::temp_dir().join(format!("{}-test", env!("CARGO_CRATE_NAME")));
+        env::set_var(get_env_name("config_dir"), dir);
+    });
+}
+
+/// Find a client in the config of the user for recording.
+pub fn load_client_config<T>(
+    find: impl Fn(&ClientConfig) -> Option<T>,
+) -> Result<(GlobalConfig, T)> {
+    let config = Config::init(WorkingMode::Command)?;
+    let client_config = config
+        .clients
+        .iter()
+        .find_map(find)
+        .ok_or_else(|| anyhow!("No such client in the config"))?;
+    Ok((Arc::new(RwLock::new(config)), client_config))
+}
+
+pub fn send_data(stream: bool) -> SendData {
+    SendData {
+        messages: vec![Message::user("Hello")],
+        temperature: None,
+        top_p: None,
+        functions: None,
+        stream,
+    }
+}
+
+/// A reply handler whose receiver must be kept alive while streaming.
+pub fn handler() -> (ReplyHandler, UnboundedReceiver<ReplyEvent>) {
+    let (tx, rx) = unbounded_channel();
+    (ReplyHandler::new(tx, create_abort_signal()), rx)
+}
+
+fn request_body(request: &Request) -> Value {
+    request
+        .body()
+        .and_then(|v| v.as_bytes())
+        .map(|v| serde_json::from_slice(v).unwrap_or_else(|_| String::from_utf8_lossy(v).into()))
+        .unwrap_or_default()
+}
+
+fn load(name: &str) -> Result<Fixture> {
+    let path = fixture_path(name);
+    let data = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read '{}'", path.display()))?;
+    let fixture = serde_json::from_str(&data)
+        .with_context(|| format!("Invalid fixture '{}'", path.display()))?;
+    Ok(fixture)
+}
+
+fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(format!("{name}.json"))
+}
+
+/// Hide the project of VertexAI.
+fn sanitize_path(path: &str) -> String {
+    let mut segments: Vec<&str> = path.split('/').collect();
+    for i in 1..segments.len() {
+        if segments[i - 1] == "projects" {
+            segments[i] = "PROJECT_ID";
+        }
+    }
+    segments.join("/")
+}
+
+async fn respond(
+    response: Arc<FixtureResponse>,
+) -> Result<Response<http_body_util::combinators::BoxBody<Bytes, Infallible>>, http::Error> {
+    let chunks = match (&response.body, &response.chunks) {
+        (Some(body), _) => vec![body.to_string()],
+        (None, Some(chunks)) => chunks.clone(),
+        (None, None) => vec![],
+    };
+    let (tx, rx) = unbounded_channel();
+    tokio::spawn(async move {
+        for chunk in chunks {
+            if tx.send(Ok(Frame::data(Bytes::from(chunk)))).is_err() {
+                break;
+            }
+            sleep(Duration::from_millis(5)).await;
+        }
+    });
+    let body = StreamBody::new(UnboundedReceiverStream::new(rx));
+    Response::builder()
+        .status(response.status)
+        .header(CONTENT_TYPE, &response.content_type)
+        .body(BodyExt::boxed(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_path() {
+        assert_eq!(
+            sanitize_path("/v1/projects/my-project/locations/us-central1/publishers/google/models/gemini-1.0-pro:generateContent"),
+            "/v1/projects/PROJECT_ID/locations/us-central1/publishers/google/models/gemini-1.0-pro:generateContent"
+        );
+    }
+}
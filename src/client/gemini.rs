@@ -1,6 +1,10 @@
-use super::vertexai::{build_body, send_message, send_message_streaming};
+use super::vertexai::{
+    build_body, send_message_relaxing_safety, send_message_streaming_relaxing_safety,
+    BlockThreshold, SafetySettings,
+};
 use super::{
-    Client, ExtraConfig, GeminiClient, Model, ModelConfig, PromptType, ReplyHandler, SendData,
+    probe_listing, Client, CompletionOutput, ExtraConfig, GeminiClient, Model, ModelConfig,
+    PromptType, ReplyHandler, SendData,
 };
 
 use crate::utils::PromptKind;
@@ -23,7 +27,11 @@ const MODELS: [(&str, usize, &str); 3] = [
 pub struct GeminiConfig {
     pub name: Option<String>,
     pub api_key: Option<String>,
-    pub block_threshold: Option<String>,
+    #[serde(alias = "safety_settings")]
+    pub block_threshold: Option<BlockThreshold>,
+    #[serde(default)]
+    pub auto_relax_safety: bool,
+    pub safety_floor: Option<String>,
     #[serde(default)]
     pub models: Vec<ModelConfig>,
     pub extra: Option<ExtraConfig>,
@@ -33,9 +41,16 @@ pub struct GeminiConfig {
 impl Client for GeminiClient {
     client_common_fns!();
 
-    async fn send_message_inner(&self, client: &ReqwestClient, data: SendData) -> Result<String> {
-        let builder = self.request_builder(client, data)?;
-        send_message(builder).await
+    async fn send_message_inner(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+    ) -> Result<CompletionOutput> {
+        let safety = self.safety_settings()?;
+        send_message_relaxing_safety(safety, data, |data, safety| {
+            self.request_builder(client, data, safety)
+        })
+        .await
     }
 
     async fn send_message_streaming_inner(
@@ -44,8 +59,19 @@ impl Client for GeminiClient {
         handler: &mut ReplyHandler,
         data: SendData,
     ) -> Result<()> {
-        let builder = self.request_builder(client, data)?;
-        send_message_streaming(builder, handler).await
+        let safety = self.safety_settings()?;
+        send_message_streaming_relaxing_safety(safety, data, handler, |data, safety| {
+            self.request_builder(client, data, safety)
+        })
+        .await
+    }
+
+    /// Probe the free models listing.
+    async fn health_check_inner(&self, client: &ReqwestClient) -> Result<()> {
+        let api_key = self.get_api_key()?;
+        let url = format!("{}?key={}", API_BASE.trim_end_matches('/'), api_key);
+        probe_listing(client.get(url)).await?;
+        Ok(())
     }
 }
 
@@ -56,7 +82,20 @@ impl GeminiClient {
     pub const PROMPTS: [PromptType<'static>; 1] =
         [("api_key", "API Key:", true, PromptKind::String)];
 
-    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+    fn safety_settings(&self) -> Result<SafetySettings> {
+        SafetySettings::new(
+            &self.config.block_threshold,
+            self.config.auto_relax_safety,
+            &self.config.safety_floor,
+        )
+    }
+
+    fn request_builder(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+        safety: &SafetySettings,
+    ) -> Result<RequestBuilder> {
         let api_key = self.get_api_key()?;
 
         let func = match data.stream {
@@ -64,9 +103,7 @@ impl GeminiClient {
             false => "generateContent",
         };
 
-        let block_threshold = self.config.block_threshold.clone();
-
-        let body = build_body(data, &self.model, block_threshold)?;
+        let body = build_body(data, &self.model, safety, None)?;
 
         let model = &self.model.name;
 
@@ -0,0 +1,83 @@
+use super::openai::openai_build_body;
+use super::{ExtraConfig, GithubClient, Model, ModelConfig, PromptType, SendData};
+
+use crate::utils::PromptKind;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use serde::Deserialize;
+
+const API_BASE: &str = "https://models.inference.ai.azure.com";
+
+const MODELS: [(&str, usize, &str); 4] = [
+    // https://github.com/marketplace/models
+    ("gpt-4o", 128000, "text,vision"),
+    ("gpt-4o-mini", 128000, "text,vision"),
+    ("Meta-Llama-3-70B-Instruct", 8192, "text"),
+    ("Phi-3-medium-128k-instruct", 128000, "text"),
+];
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct GithubConfig {
+    pub name: Option<String>,
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+openai_compatible_client!(GithubClient);
+
+impl GithubClient {
+    list_models_fn!(GithubConfig, &MODELS);
+    config_get_fn!(api_key, get_api_key);
+
+    pub const PROMPTS: [PromptType<'static>; 1] =
+        [("api_key", "GitHub Token:", true, PromptKind::String)];
+
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let api_key = self.get_api_key()?;
+
+        let body = openai_build_body(data, &self.model);
+
+        let url = format!("{API_BASE}/chat/completions");
+
+        debug!("Github Request: {url} {body}");
+
+        let builder = client.post(url).bearer_auth(api_key).json(&body);
+
+        Ok(builder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{
+        fixture,
+        openai::{openai_send_message, openai_send_message_streaming},
+    };
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let client = GithubClient::for_test("api_key: xxx")?;
+        let http = ReqwestClient::new();
+        let request = |stream| client.request_builder(&http, fixture::send_data(stream));
+
+        let (mut handler, _rx) = fixture::handler();
+        openai_send_message_streaming(
+            fixture::replay("github/stream", request(true)?).await?,
+            &mut handler,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "Hello! How can I help you today?");
+
+        let err =
+            openai_send_message(fixture::replay("github/auth_failure", request(false)?).await?)
+                .await
+                .unwrap_err();
+        assert_eq!(err.to_string(), "Bad credentials (code: unauthorized)");
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use super::openai::openai_build_body;
+use super::{ExtraConfig, GroqClient, Model, ModelConfig, PromptType, SendData};
+
+use crate::utils::PromptKind;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use serde::Deserialize;
+
+const API_BASE: &str = "https://api.groq.com/openai/v1";
+
+const MODELS: [(&str, usize, &str); 4] = [
+    // https://console.groq.com/docs/models
+    ("llama3-8b-8192", 8192, "text"),
+    ("llama3-70b-8192", 8192, "text"),
+    ("mixtral-8x7b-32768", 32768, "text"),
+    ("gemma-7b-it", 8192, "text"),
+];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GroqConfig {
+    pub name: Option<String>,
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+openai_compatible_client!(GroqClient);
+
+impl GroqClient {
+    list_models_fn!(GroqConfig, &MODELS);
+    config_get_fn!(api_key, get_api_key);
+
+    pub const PROMPTS: [PromptType<'static>; 1] =
+        [("api_key", "API Key:", true, PromptKind::String)];
+
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let api_key = self.get_api_key()?;
+
+        let body = openai_build_body(data, &self.model);
+
+        let url = format!("{API_BASE}/chat/completions");
+
+        debug!("Groq Request: {url} {body}");
+
+        let builder = client.post(url).bearer_auth(api_key).json(&body);
+
+        Ok(builder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{
+        fixture,
+        openai::{openai_send_message, openai_send_message_streaming},
+        Client,
+    };
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let client = GroqClient::for_test("api_key: xxx")?;
+        let http = ReqwestClient::new();
+        let request = |stream| client.request_builder(&http, fixture::send_data(stream));
+
+        let output =
+            openai_send_message(fixture::replay("groq/success", request(false)?).await?).await?;
+        assert_eq!(output.text, "Hello! How can I help you today?");
+        assert_eq!(
+            (output.input_tokens, output.output_tokens),
+            (Some(11), Some(10))
+        );
+
+        let (mut handler, _rx) = fixture::handler();
+        let builder = fixture::replay("groq/stream", request(true)?).await?;
+        openai_send_message_streaming(builder, &mut handler).await?;
+        assert_eq!(handler.get_buffer(), "Hello! How can I help you today?");
+        assert_eq!(handler.get_usage(), (Some(11), Some(10)));
+
+        let (mut handler, _rx) = fixture::handler();
+        let builder = fixture::replay("groq/rate_limit", request(true)?).await?;
+        let err = openai_send_message_streaming(builder, &mut handler)
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("(type: tokens, code: rate_limit_exceeded)"));
+
+        let (mut handler, _rx) = fixture::handler();
+        let builder = fixture::replay("groq/stream_error", request(true)?).await?;
+        let err = openai_send_message_streaming(builder, &mut handler)
+            .await
+            .unwrap_err();
+        assert_eq!(handler.get_buffer(), "Hello!");
+        assert_eq!(
+            err.to_string(),
+            "Service Unavailable (type: internal_server_error)"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn record_fixtures() -> Result<()> {
+        if !fixture::recording() {
+            return Ok(());
+        }
+        let client = GroqClient::for_recording()?;
+        let http = client.build_client()?;
+        let builder = client.request_builder(&http, fixture::send_data(false))?;
+        fixture::record("groq/success", builder).await?;
+        let builder = client.request_builder(&http, fixture::send_data(true))?;
+        fixture::record("groq/stream", builder).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+//! Probe the configured clients for `--list-models --live`.
+//!
+//! Each client is checked once, with its first model, and all probes run at the same time. A probe
+//! gives up after `PROBE_TIMEOUT`, and the listing never waits longer than `PROBE_DEADLINE`.
+//!
+//! A client probes a free model listing endpoint where the platform has one, else it sends the
+//! smallest completion possible.
+
+use super::{init_client_with_model, list_models, send_request, Message, Model, SendData};
+
+use crate::config::GlobalConfig;
+
+use anyhow::{bail, Error, Result};
+use futures_util::future::join_all;
+use reqwest::RequestBuilder;
+use std::{
+    fmt,
+    future::Future,
+    time::{Duration, Instant},
+};
+use tokio::time::timeout_at;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+const PROBE_DEADLINE: Duration = Duration::from_secs(8);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reachability {
+    Reachable(Duration),
+    Unreachable(FailureKind, String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailureKind {
+    Auth,
+    Connection,
+    Timeout,
+    Other,
+}
+
+impl fmt::Display for Reachability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reachability::Reachable(latency) => write!(f, "ok {}ms", latency.as_millis()),
+            Reachability::Unreachable(kind, reason) => {
+                let kind = match kind {
+                    FailureKind::Auth => "auth",
+                    FailureKind::Connection => "connection",
+                    FailureKind::Timeout => "timeout",
+                    FailureKind::Other => "error",
+                };
+                write!(f, "unreachable ({kind}): {reason}")
+            }
+        }
+    }
+}
+
+/// List all models, each with the reachability of its client.
+pub async fn list_models_live(config: &GlobalConfig) -> Vec<(Model, Reachability)> {
+    let models = list_models(&config.read());
+    let mut clients: Vec<&Model> = vec![];
+    for model in &models {
+        if !clients.iter().any(|v| v.client_name == model.client_name) {
+            clients.push(model);
+        }
+    }
+    let probes = clients.iter().map(|model| async move {
+        let client = init_client_with_model(config, model)?;
+        client.health_check().await
+    });
+    let results = probe_all(probes, PROBE_TIMEOUT, PROBE_DEADLINE).await;
+    models
+        .iter()
+        .map(|model| {
+            let index = clients
+                .iter()
+                .position(|v| v.client_name == model.client_name)
+                .unwrap_or_default();
+            (model.clone(), results[index].clone())
+        })
+        .collect()
+}
+
+/// The smallest request possible, to probe a client without a free endpoint.
+pub fn probe_data() -> SendData {
+    SendData {
+        messages: vec![Message::user("hi")],
+        temperature: None,
+        top_p: None,
+        functions: None,
+        stream: false,
+    }
+}
+
+/// Probe with a request of the model listing.
+///
+/// Returns false if the platform doesn't have the endpoint, to fall back on a completion.
+pub async fn probe_listing(builder: RequestBuilder) -> Result<bool> {
+    let res = send_request(builder).await?;
+    let status = res.status().as_u16();
+    match status {
+        200..=299 => Ok(true),
+        404 | 405 => Ok(false),
+        _ => {
+            let text = res.text().await.unwrap_or_default();
+            bail!("{} (status: {status})", text.trim())
+        }
+    }
+}
+
+/// Run the probes concurrently, each bounded by `timeout` and all by `deadline`.
+async fn probe_all<F>(
+    probes: impl IntoIterator<Item = F>,
+    timeout: Duration,
+    deadline: Duration,
+) -> Vec<Reachability>
+where
+    F: Future<Output = Result<()>>,
+{
+    let deadline = Instant::now() + deadline;
+    let probes = probes.into_iter().map(|probe| async move {
+        let started = Instant::now();
+        let probe_deadline = deadline.min(started + timeout);
+        match timeout_at(probe_deadline.into(), probe).await {
+            Ok(Ok(())) => Reachability::Reachable(started.elapsed()),
+            Ok(Err(err)) => Reachability::Unreachable(failure_kind(&err), failure_reason(&err)),
+            Err(_) => Reachability::Unreachable(
+                FailureKind::Timeout,
+                format!("no response after {}ms", started.elapsed().as_millis()),
+            ),
+        }
+    });
+    join_all(probes).await
+}
+
+fn failure_kind(err: &Error) -> FailureKind {
+    for cause in err.chain() {
+        if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+            if err.is_timeout() {
+                return FailureKind::Timeout;
+            }
+            if err.is_connect() {
+                return FailureKind::Connection;
+            }
+            if let Some(401 | 403) = err.status().map(|v| v.as_u16()) {
+                return FailureKind::Auth;
+            }
+        }
+    }
+    let message = format!("{err:#}").to_lowercase();
+    let auth_markers = [
+        "401",
+        "403",
+        "unauthenticated",
+        "unauthorized",
+        "authentication",
+        "permission",
+        "api key",
+        "api_key",
+        "access token",
+        "miss ",
+    ];
+    if auth_markers.iter().any(|v| message.contains(v)) {
+        FailureKind::Auth
+    } else {
+        FailureKind::Other
+    }
+}
+
+fn failure_reason(err: &Error) -> String {
+    let reason = err.root_cause().to_string();
+    match reason.lines().next() {
+        Some(line) if line.chars().count() > 80 => {
+            format!("{}…", line.chars().take(80).collect::<String>())
+        }
+        Some(line) => line.to_string(),
+        None => reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use futures_util::future::BoxFuture;
+    use futures_util::FutureExt;
+
+    #[tokio::test]
+    async fn test_probe_all() {
+        let probes: Vec<BoxFuture<Result<()>>> = vec![
+            async { Ok(()) }.boxed(),
+            async { Err(anyhow!("invalid x-api-key (type: authentication_error)")) }.boxed(),
+            std::future::pending().boxed(),
+        ];
+        let started = Instant::now();
+        let results = probe_all(probes, Duration::from_secs(60), Duration::from_millis(200)).await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(matches!(results[0], Reachability::Reachable(_)));
+        assert_eq!(
+            results[1].to_string(),
+            "unreachable (auth): invalid x-api-key (type: authentication_error)"
+        );
+        assert!(matches!(
+            results[2],
+            Reachability::Unreachable(FailureKind::Timeout, _)
+        ));
+    }
+}
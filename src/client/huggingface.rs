@@ -0,0 +1,404 @@
+use super::openai::openai_build_body;
+use super::{
+    send_request, Client, CompletionOutput, ExtraConfig, HuggingFaceClient, Model, ModelConfig,
+    PromptType, ReplyHandler, SendData,
+};
+
+use crate::utils::{PromptKind, SseParser};
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use reqwest::{Client as ReqwestClient, RequestBuilder, Response};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::time::sleep;
+
+const API_BASE: &str = "https://api-inference.huggingface.co/models";
+
+// The hosted API stops after about 20 tokens by default
+const DEFAULT_MAX_NEW_TOKENS: isize = 1024;
+
+// How long to wait out the "model is loading" responses in total
+const MAX_LOADING_WAIT: Duration = Duration::from_secs(300);
+
+const MODELS: [(&str, usize, &str); 3] = [
+    // https://huggingface.co/docs/api-inference/index
+    ("meta-llama/Meta-Llama-3-8B-Instruct", 8192, "text"),
+    ("mistralai/Mistral-7B-Instruct-v0.2", 32768, "text"),
+    ("microsoft/Phi-3-mini-4k-instruct", 4096, "text"),
+];
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct HuggingFaceConfig {
+    pub name: Option<String>,
+    pub api_base: Option<String>,
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub mode: HuggingFaceMode,
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+/// `chat` uses the `/v1/chat/completions` route of TGI, `generate` the raw text-generation one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HuggingFaceMode {
+    #[default]
+    Chat,
+    Generate,
+}
+
+#[async_trait]
+impl Client for HuggingFaceClient {
+    client_common_fns!();
+
+    async fn send_message_inner(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+    ) -> Result<CompletionOutput> {
+        let builder = self.request_builder(client, data)?;
+        send_message(builder, self.config.mode).await
+    }
+
+    async fn send_message_streaming_inner(
+        &self,
+        client: &ReqwestClient,
+        handler: &mut ReplyHandler,
+        data: SendData,
+    ) -> Result<()> {
+        let builder = self.request_builder(client, data)?;
+        send_message_streaming(builder, handler, self.config.mode).await
+    }
+}
+
+impl HuggingFaceClient {
+    list_models_fn!(HuggingFaceConfig, &MODELS);
+    config_get_fn!(api_base, get_api_base);
+    config_get_fn!(api_key, get_api_key);
+
+    pub const PROMPTS: [PromptType<'static>; 1] =
+        [("api_key", "API Key:", false, PromptKind::String)];
+
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let api_key = self.get_api_key().ok();
+        let api_base = self.get_api_base().unwrap_or_else(|_| API_BASE.to_string());
+        let api_base = api_base.trim_end_matches('/');
+
+        // The Inference API serves each model under its own path, a TGI server one model at its root
+        let model_url = match api_base.ends_with("/models") {
+            true => format!("{api_base}/{}", self.model.name),
+            false => api_base.to_string(),
+        };
+
+        let (body, url) = match self.config.mode {
+            HuggingFaceMode::Chat => (
+                build_chat_body(data, &self.model),
+                format!("{model_url}/v1/chat/completions"),
+            ),
+            HuggingFaceMode::Generate => (build_generate_body(data, &self.model), model_url),
+        };
+
+        debug!("HuggingFace Request: {url} {body}");
+
+        let mut builder = client.post(url).json(&body);
+        if let Some(api_key) = api_key {
+            builder = builder.bearer_auth(api_key);
+        }
+
+        Ok(builder)
+    }
+}
+
+async fn send_message(builder: RequestBuilder, mode: HuggingFaceMode) -> Result<CompletionOutput> {
+    let res = send_waiting_for_model(builder).await?;
+    let status = res.status();
+    let data: Value = res.json().await?;
+    if status != 200 {
+        catch_error(&data, status.as_u16())?;
+    }
+
+    let output = match mode {
+        HuggingFaceMode::Chat => {
+            let text = data["choices"][0]["message"]["content"]
+                .as_str()
+                .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+            CompletionOutput {
+                text: text.to_string(),
+                input_tokens: data["usage"]["prompt_tokens"].as_u64(),
+                output_tokens: data["usage"]["completion_tokens"].as_u64(),
+                tool_calls: vec![],
+                request_id: None,
+                stop_sequence: None,
+                stop_reason: None,
+                stats: None,
+                cache_usage: None,
+            }
+        }
+        HuggingFaceMode::Generate => {
+            // The Inference API wraps the result in an array, TGI's `/generate` doesn't
+            let item = if data.is_array() { &data[0] } else { &data };
+            let text = item["generated_text"]
+                .as_str()
+                .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+            CompletionOutput {
+                text: text.to_string(),
+                input_tokens: None,
+                output_tokens: item["details"]["generated_tokens"].as_u64(),
+                tool_calls: vec![],
+                request_id: None,
+                stop_sequence: None,
+                stop_reason: None,
+                stats: None,
+                cache_usage: None,
+            }
+        }
+    };
+    Ok(output)
+}
+
+async fn send_message_streaming(
+    builder: RequestBuilder,
+    handler: &mut ReplyHandler,
+    mode: HuggingFaceMode,
+) -> Result<()> {
+    let res = send_waiting_for_model(builder).await?;
+    let status = res.status();
+    if status != 200 {
+        let data: Value = res.json().await?;
+        catch_error(&data, status.as_u16())?;
+    } else {
+        let mut parser = SseParser::new();
+        let mut stream = res.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            for event in parser.push(&chunk?)? {
+                if handle_event(&event.data, handler, mode)? {
+                    return Ok(());
+                }
+            }
+        }
+        for event in parser.finish()? {
+            handle_event(&event.data, handler, mode)?;
+        }
+    }
+    Ok(())
+}
+
+/// Handle one SSE frame, returning whether the stream is done.
+fn handle_event(data: &str, handler: &mut ReplyHandler, mode: HuggingFaceMode) -> Result<bool> {
+    if data == "[DONE]" {
+        return Ok(true);
+    }
+    let data: Value = serde_json::from_str(data)?;
+    if data["error"].is_string() {
+        catch_error(&data, 200)?;
+    }
+    match mode {
+        HuggingFaceMode::Chat => {
+            if let Some(text) = data["choices"][0]["delta"]["content"].as_str() {
+                handler.text(text)?;
+            }
+            Ok(false)
+        }
+        HuggingFaceMode::Generate => {
+            if data["token"]["special"].as_bool() != Some(true) {
+                if let Some(text) = data["token"]["text"].as_str() {
+                    handler.text(text)?;
+                }
+            }
+            // The last frame carries the whole `generated_text`
+            if data["generated_text"].is_string() {
+                handler.set_usage(None, data["details"]["generated_tokens"].as_u64());
+                return Ok(true);
+            }
+            Ok(false)
+        }
+    }
+}
+
+/// Send the request, retrying while the model is loading with the `estimated_time` hint.
+async fn send_waiting_for_model(builder: RequestBuilder) -> Result<Response> {
+    let mut waited = Duration::ZERO;
+    loop {
+        let builder = builder
+            .try_clone()
+            .ok_or_else(|| anyhow!("Failed to clone the request"))?;
+        let res = send_request(builder).await?;
+        if res.status() != 503 {
+            return Ok(res);
+        }
+        let text = res.text().await?;
+        let data: Value = text.parse().unwrap_or_else(|_| json!({ "error": text }));
+        match data["estimated_time"].as_f64() {
+            Some(estimated_time) if waited < MAX_LOADING_WAIT => {
+                let delay = Duration::from_secs_f64(estimated_time.clamp(1.0, 60.0))
+                    .min(MAX_LOADING_WAIT - waited);
+                debug!("HuggingFace model is loading, retrying in {delay:?}");
+                sleep(delay).await;
+                waited += delay;
+            }
+            _ => catch_error(&data, 503)?,
+        }
+    }
+}
+
+fn build_chat_body(data: SendData, model: &Model) -> Value {
+    let mut body = openai_build_body(data, model);
+    if body["max_tokens"].is_null() {
+        body["max_tokens"] = DEFAULT_MAX_NEW_TOKENS.into();
+    }
+    body
+}
+
+fn build_generate_body(data: SendData, model: &Model) -> Value {
+    let SendData {
+        messages,
+        temperature,
+        top_p,
+        functions: _,
+        stream,
+    } = data;
+
+    let completion = model.completion.clone().unwrap_or_default();
+
+    let mut parameters = json!({
+        "max_new_tokens": model.max_output_tokens.unwrap_or(DEFAULT_MAX_NEW_TOKENS),
+        "return_full_text": false,
+        "details": true,
+    });
+    let stop = completion.stop();
+    if !stop.is_empty() {
+        parameters["stop"] = stop.into();
+    }
+    if let Some(temperature) = temperature {
+        parameters["temperature"] = temperature.into();
+    }
+    if let Some(top_p) = top_p {
+        parameters["top_p"] = top_p.into();
+    }
+
+    let mut body = json!({
+        "inputs": completion.build_prompt(&messages),
+        "parameters": parameters,
+    });
+    if stream {
+        body["stream"] = true.into();
+    }
+    body
+}
+
+fn catch_error(data: &Value, status: u16) -> Result<()> {
+    debug!("Invalid response, status: {status}, data: {data}");
+    let error = &data["error"];
+    match (error.as_str(), data["error_type"].as_str()) {
+        (Some(message), Some(type_)) => bail!("{message} (type: {type_})"),
+        (Some(message), None) => bail!("{message}"),
+        _ => {}
+    }
+    if let Some(message) = error["message"].as_str() {
+        bail!("{message}");
+    }
+    bail!("Invalid response, status: {status}, data: {data}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{fixture, Message};
+
+    #[test]
+    fn test_build_generate_body() {
+        let model = HuggingFaceClient::list_models(&HuggingFaceConfig::default()).remove(1);
+        let data = SendData {
+            messages: vec![Message::user("Hi")],
+            temperature: Some(0.5),
+            top_p: None,
+            functions: None,
+            stream: true,
+        };
+        assert_eq!(
+            build_generate_body(data, &model),
+            json!({
+                "inputs": "User: Hi\nAssistant:",
+                "parameters": {
+                    "max_new_tokens": 1024,
+                    "return_full_text": false,
+                    "details": true,
+                    "stop": ["\nUser:"],
+                    "temperature": 0.5,
+                },
+                "stream": true,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let http = ReqwestClient::new();
+        let mistral = |config: &str| -> Result<HuggingFaceClient> {
+            let mut client = HuggingFaceClient::for_test(config)?;
+            client.model = HuggingFaceClient::list_models(&client.config).remove(1);
+            Ok(client)
+        };
+        let generate = mistral("{ api_key: xxx, mode: generate }")?;
+        let chat = mistral("api_key: xxx")?;
+
+        let output = send_message(
+            fixture::replay(
+                "huggingface/generate",
+                generate.request_builder(&http, fixture::send_data(false))?,
+            )
+            .await?,
+            HuggingFaceMode::Generate,
+        )
+        .await?;
+        assert_eq!(output.text, " Hello! How can I help you today?");
+        assert_eq!(output.output_tokens, Some(10));
+
+        let (mut handler, _rx) = fixture::handler();
+        send_message_streaming(
+            fixture::replay(
+                "huggingface/generate_stream",
+                generate.request_builder(&http, fixture::send_data(true))?,
+            )
+            .await?,
+            &mut handler,
+            HuggingFaceMode::Generate,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), " Hello! How can I help you today?");
+        assert_eq!(handler.get_usage(), (None, Some(10)));
+
+        let (mut handler, _rx) = fixture::handler();
+        send_message_streaming(
+            fixture::replay(
+                "huggingface/chat_stream",
+                chat.request_builder(&http, fixture::send_data(true))?,
+            )
+            .await?,
+            &mut handler,
+            HuggingFaceMode::Chat,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "Hello! How can I help you today?");
+
+        let err = send_message(
+            fixture::replay(
+                "huggingface/auth_failure",
+                chat.request_builder(&http, fixture::send_data(false))?,
+            )
+            .await?,
+            HuggingFaceMode::Chat,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Authorization header is correct, but the token seems invalid"
+        );
+        Ok(())
+    }
+}
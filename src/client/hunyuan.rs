@@ -0,0 +1,369 @@
+use super::{
+    event_source, note_response, send_request, Client, CompletionOutput, ExtraConfig,
+    HunyuanClient, Model, ModelConfig, PromptType, ReplyHandler, SendData,
+};
+
+use crate::utils::{hex_encode, hmac_sha256, sha256sum, PromptKind};
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use reqwest_eventsource::{Error as EventSourceError, Event};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+const HOST: &str = "hunyuan.tencentcloudapi.com";
+const SERVICE: &str = "hunyuan";
+const ACTION: &str = "ChatCompletions";
+const VERSION: &str = "2023-09-01";
+const CONTENT_TYPE: &str = "application/json; charset=utf-8";
+
+const MODELS: [(&str, usize, &str); 3] = [
+    // https://cloud.tencent.com/document/product/1729/97731
+    ("hunyuan-pro", 32768, "text"),
+    ("hunyuan-standard", 32768, "text"),
+    ("hunyuan-lite", 256000, "text"),
+];
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct HunyuanConfig {
+    pub name: Option<String>,
+    pub secret_id: Option<String>,
+    pub secret_key: Option<String>,
+    pub region: Option<String>,
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+#[async_trait]
+impl Client for HunyuanClient {
+    client_common_fns!();
+
+    async fn send_message_inner(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+    ) -> Result<CompletionOutput> {
+        let builder = self.request_builder(client, data)?;
+        send_message(builder).await
+    }
+
+    async fn send_message_streaming_inner(
+        &self,
+        client: &ReqwestClient,
+        handler: &mut ReplyHandler,
+        data: SendData,
+    ) -> Result<()> {
+        let builder = self.request_builder(client, data)?;
+        send_message_streaming(builder, handler).await
+    }
+}
+
+impl HunyuanClient {
+    list_models_fn!(HunyuanConfig, &MODELS);
+    config_get_fn!(secret_id, get_secret_id);
+    config_get_fn!(secret_key, get_secret_key);
+    config_get_fn!(region, get_region);
+
+    pub const PROMPTS: [PromptType<'static>; 2] = [
+        ("secret_id", "Secret ID:", true, PromptKind::String),
+        ("secret_key", "Secret Key:", true, PromptKind::String),
+    ];
+
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let secret_id = self.get_secret_id()?;
+        let secret_key = self.get_secret_key()?;
+
+        let body = build_body(data, &self.model);
+
+        let url = format!("https://{HOST}");
+
+        debug!("Hunyuan Request: {url} {body}");
+
+        let body = body.to_string();
+        let timestamp = Utc::now().timestamp();
+        let authorization = sign_request(
+            &secret_id,
+            &secret_key,
+            SERVICE,
+            HOST,
+            ACTION,
+            &body,
+            timestamp,
+        );
+
+        let mut builder = client
+            .post(url)
+            .header("Authorization", authorization)
+            .header("Content-Type", CONTENT_TYPE)
+            .header("X-TC-Action", ACTION)
+            .header("X-TC-Timestamp", timestamp.to_string())
+            .header("X-TC-Version", VERSION)
+            .body(body);
+        if let Ok(region) = self.get_region() {
+            builder = builder.header("X-TC-Region", region);
+        }
+
+        Ok(builder)
+    }
+}
+
+async fn send_message(builder: RequestBuilder) -> Result<CompletionOutput> {
+    let res = send_request(builder).await?;
+    let status = res.status();
+    let data: Value = res.json().await?;
+    // Failures come back with a 200 status and an `Error` in the envelope
+    let data = &data["Response"];
+    if status != 200 || data["Error"].is_object() {
+        catch_error(data, status.as_u16())?;
+    }
+
+    let text = data["Choices"][0]["Message"]["Content"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+
+    Ok(CompletionOutput {
+        text: text.to_string(),
+        input_tokens: data["Usage"]["PromptTokens"].as_u64(),
+        output_tokens: data["Usage"]["CompletionTokens"].as_u64(),
+        tool_calls: vec![],
+        request_id: None,
+        stop_sequence: None,
+        stop_reason: None,
+        stats: None,
+        cache_usage: None,
+    })
+}
+
+async fn send_message_streaming(builder: RequestBuilder, handler: &mut ReplyHandler) -> Result<()> {
+    let mut es = event_source(builder)?;
+    while let Some(event) = es.next().await {
+        match event {
+            Ok(Event::Open) => {}
+            Ok(Event::Message(message)) => {
+                let data: Value = serde_json::from_str(&message.data)?;
+                if let Some(text) = data["Choices"][0]["Delta"]["Content"].as_str() {
+                    handler.text(text)?;
+                }
+                let usage = &data["Usage"];
+                if usage.is_object() {
+                    handler.set_usage(
+                        usage["PromptTokens"].as_u64(),
+                        usage["CompletionTokens"].as_u64(),
+                    );
+                }
+            }
+            Err(err) => {
+                match err {
+                    EventSourceError::StreamEnded => {}
+                    EventSourceError::InvalidStatusCode(status, res) => {
+                        note_response(&res);
+                        let text = res.text().await?;
+                        let data: Value = match text.parse() {
+                            Ok(data) => data,
+                            Err(_) => {
+                                bail!("Invalid respoinse, status: {status}, text: {text}");
+                            }
+                        };
+                        catch_error(&data["Response"], status.as_u16())?;
+                    }
+                    // Errors are sent as plain JSON instead of an event stream
+                    EventSourceError::InvalidContentType(_, res) => {
+                        let text = res.text().await?;
+                        let data: Value = match text.parse() {
+                            Ok(data) => data,
+                            Err(_) => {
+                                bail!("The API server should return data as 'text/event-stream', but it isn't. Check the client config. {text}");
+                            }
+                        };
+                        catch_error(&data["Response"], 200)?;
+                    }
+                    _ => {
+                        bail!("{}", err);
+                    }
+                }
+                es.close();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn build_body(data: SendData, model: &Model) -> Value {
+    let SendData {
+        messages,
+        temperature,
+        top_p,
+        functions: _,
+        stream,
+    } = data;
+
+    let messages: Vec<Value> = messages
+        .into_iter()
+        .map(|message| {
+            json!({
+                "Role": message.role,
+                "Content": message.content.to_text(),
+            })
+        })
+        .collect();
+
+    let mut body = json!({
+        "Model": &model.name,
+        "Messages": messages,
+    });
+
+    if let Some(temperature) = temperature {
+        body["Temperature"] = temperature.into();
+    }
+    if let Some(top_p) = top_p {
+        body["TopP"] = top_p.into();
+    }
+    if stream {
+        body["Stream"] = true.into();
+    }
+    body
+}
+
+fn catch_error(data: &Value, status: u16) -> Result<()> {
+    debug!("Invalid response, status: {status}, data: {data}");
+    let error = &data["Error"];
+    if let (Some(code), Some(message)) = (error["Code"].as_str(), error["Message"].as_str()) {
+        bail!("{message} (code: {code})");
+    }
+    bail!("Invalid response, status: {status}, data: {data}");
+}
+
+/// Sign a request with TC3-HMAC-SHA256, returning the `Authorization` header.
+fn sign_request(
+    secret_id: &str,
+    secret_key: &str,
+    service: &str,
+    host: &str,
+    action: &str,
+    payload: &str,
+    timestamp: i64,
+) -> String {
+    let date = DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .format("%Y-%m-%d")
+        .to_string();
+
+    let (canonical_request, signed_headers) = canonical_request(host, action, payload);
+
+    let scope = format!("{date}/{service}/tc3_request");
+    let string_to_sign = format!(
+        "TC3-HMAC-SHA256\n{timestamp}\n{scope}\n{}",
+        sha256sum(&canonical_request)
+    );
+
+    let key = format!("TC3{secret_key}");
+    let key = hmac_sha256(key.as_bytes(), &date);
+    let key = hmac_sha256(&key, service);
+    let key = hmac_sha256(&key, "tc3_request");
+    let signature = hex_encode(&hmac_sha256(&key, &string_to_sign));
+
+    format!("TC3-HMAC-SHA256 Credential={secret_id}/{scope}, SignedHeaders={signed_headers}, Signature={signature}")
+}
+
+/// Return the canonical request of a POST to `/` and its signed headers.
+fn canonical_request(host: &str, action: &str, payload: &str) -> (String, String) {
+    let headers = [
+        ("content-type", CONTENT_TYPE.to_string()),
+        ("host", host.to_string()),
+        ("x-tc-action", action.to_lowercase()),
+    ];
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(key, value)| format!("{key}:{value}\n"))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(key, _)| *key)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "POST\n/\n\n{canonical_headers}\n{signed_headers}\n{}",
+        sha256sum(payload)
+    );
+    (canonical_request, signed_headers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::fixture;
+
+    // The DescribeInstances example of the Tencent Cloud signature v3 docs
+    const EXAMPLE_PAYLOAD: &str =
+        r#"{"Limit": 1, "Filters": [{"Values": ["\u672a\u547d\u540d"], "Name": "instance-name"}]}"#;
+
+    #[test]
+    fn test_sign_request() {
+        let (canonical_request, signed_headers) = canonical_request(
+            "cvm.tencentcloudapi.com",
+            "DescribeInstances",
+            EXAMPLE_PAYLOAD,
+        );
+        assert_eq!(signed_headers, "content-type;host;x-tc-action");
+        assert_eq!(
+            sha256sum(&canonical_request),
+            "7019a55be8395899b900fb5564e4200d984910f34794a27cb3fb7d10ff6a1e84"
+        );
+
+        // The docs mask their secret key, so this signature uses the placeholder one
+        let authorization = sign_request(
+            "AKIDz8krbsJ5yKBZQpn74WFkmLPx3EXAMPLE",
+            "Gu5t9xGARNpq86cd98joQYCN3EXAMPLE",
+            "cvm",
+            "cvm.tencentcloudapi.com",
+            "DescribeInstances",
+            EXAMPLE_PAYLOAD,
+            1551113065,
+        );
+        assert_eq!(
+            authorization,
+            "TC3-HMAC-SHA256 Credential=AKIDz8krbsJ5yKBZQpn74WFkmLPx3EXAMPLE/2019-02-25/cvm/tc3_request, SignedHeaders=content-type;host;x-tc-action, Signature=644be983de9a8a3f00db8eadaba61467c3b429e2215758ba897b738ca469fd26"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let client = HunyuanClient::for_test("{ secret_id: xxx, secret_key: xxx }")?;
+        let http = ReqwestClient::new();
+        let request = |stream| client.request_builder(&http, fixture::send_data(stream));
+
+        let (mut handler, _rx) = fixture::handler();
+        send_message_streaming(
+            fixture::replay("hunyuan/stream", request(true)?).await?,
+            &mut handler,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "Hello! How can I help you today?");
+        assert_eq!(handler.get_usage(), (Some(11), Some(10)));
+
+        let err = send_message(fixture::replay("hunyuan/auth_failure", request(false)?).await?)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The SecretId is not found, please ensure that your SecretId is correct. (code: AuthFailure.SecretIdNotFound)"
+        );
+
+        let (mut handler, _rx) = fixture::handler();
+        let err = send_message_streaming(
+            fixture::replay("hunyuan/stream_auth_failure", request(true)?).await?,
+            &mut handler,
+        )
+        .await
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("(code: AuthFailure.SecretIdNotFound)"));
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+use super::{
+    event_source, note_response, send_request, Client, CompletionOutput, ExtraConfig,
+    KoboldCppClient, Model, ModelConfig, PromptType, ReplyHandler, SendData,
+};
+
+use crate::utils::PromptKind;
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use reqwest_eventsource::{Error as EventSourceError, Event};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+// KoboldCpp generates 100 tokens by default
+const DEFAULT_MAX_LENGTH: isize = 1024;
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct KoboldCppConfig {
+    pub name: Option<String>,
+    pub api_base: String,
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+#[async_trait]
+impl Client for KoboldCppClient {
+    client_common_fns!();
+
+    async fn send_message_inner(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+    ) -> Result<CompletionOutput> {
+        let builder = self.request_builder(client, data)?;
+        send_message(builder).await
+    }
+
+    async fn send_message_streaming_inner(
+        &self,
+        client: &ReqwestClient,
+        handler: &mut ReplyHandler,
+        data: SendData,
+    ) -> Result<()> {
+        let builder = self.request_builder(client, data)?;
+        send_message_streaming(builder, handler).await
+    }
+}
+
+impl KoboldCppClient {
+    list_models_fn!(KoboldCppConfig);
+
+    pub const PROMPTS: [PromptType<'static>; 3] = [
+        ("api_base", "API Base:", true, PromptKind::String),
+        ("models[].name", "Model Name:", true, PromptKind::String),
+        (
+            "models[].max_input_tokens",
+            "Max Input Tokens:",
+            false,
+            PromptKind::Integer,
+        ),
+    ];
+
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let endpoint = match data.stream {
+            true => "/api/extra/generate/stream",
+            false => "/api/v1/generate",
+        };
+
+        let mut body = build_body(data, &self.model);
+        self.model.merge_extra_fields(&mut body);
+
+        let url = format!("{}{endpoint}", self.config.api_base.trim_end_matches('/'));
+
+        debug!("KoboldCpp Request: {url} {body}");
+
+        let builder = client.post(url).json(&body);
+
+        Ok(builder)
+    }
+}
+
+async fn send_message(builder: RequestBuilder) -> Result<CompletionOutput> {
+    let res = send_request(builder).await?;
+    let status = res.status();
+    let data: Value = res.json().await?;
+    if status != 200 {
+        catch_error(&data, status.as_u16())?;
+    }
+    let output = data["results"][0]["text"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+    Ok(CompletionOutput::new(output))
+}
+
+async fn send_message_streaming(builder: RequestBuilder, handler: &mut ReplyHandler) -> Result<()> {
+    let mut es = event_source(builder)?;
+    while let Some(event) = es.next().await {
+        match event {
+            Ok(Event::Open) => {}
+            Ok(Event::Message(message)) => {
+                let data: Value = serde_json::from_str(&message.data)?;
+                if let Some(text) = data["token"].as_str() {
+                    handler.text(text)?;
+                }
+                if data["finish_reason"].is_string() {
+                    es.close();
+                    break;
+                }
+            }
+            Err(err) => {
+                match err {
+                    EventSourceError::StreamEnded => {}
+                    EventSourceError::InvalidStatusCode(status, res) => {
+                        note_response(&res);
+                        let text = res.text().await?;
+                        let data: Value = match text.parse() {
+                            Ok(data) => data,
+                            Err(_) => {
+                                bail!("Invalid respoinse, status: {status}, text: {text}");
+                            }
+                        };
+                        catch_error(&data, status.as_u16())?;
+                    }
+                    EventSourceError::InvalidContentType(_, res) => {
+                        let text = res.text().await?;
+                        bail!("The API server should return data as 'text/event-stream', but it isn't. Check the client config. {text}");
+                    }
+                    _ => {
+                        bail!("{}", err);
+                    }
+                }
+                es.close();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Flatten the messages with the model's `completion.prompt_template` for the native generate API.
+fn build_body(data: SendData, model: &Model) -> Value {
+    let SendData {
+        messages,
+        temperature,
+        top_p,
+        functions: _,
+        stream: _,
+    } = data;
+
+    let completion = model.completion.clone().unwrap_or_default();
+
+    let mut body = json!({
+        "prompt": completion.build_prompt(&messages),
+        "max_length": model.max_output_tokens.unwrap_or(DEFAULT_MAX_LENGTH),
+    });
+
+    if let Some(max_input_tokens) = model.max_input_tokens {
+        body["max_context_length"] = max_input_tokens.into();
+    }
+    let stop = completion.stop();
+    if !stop.is_empty() {
+        body["stop_sequence"] = stop.into();
+    }
+    if let Some(temperature) = temperature {
+        body["temperature"] = temperature.into();
+    }
+    if let Some(top_p) = top_p {
+        body["top_p"] = top_p.into();
+    }
+    body
+}
+
+fn catch_error(data: &Value, status: u16) -> Result<()> {
+    debug!("Invalid response, status: {status}, data: {data}");
+    let detail = &data["detail"];
+    match (detail["msg"].as_str(), detail["type"].as_str()) {
+        (Some(message), Some(type_)) => bail!("{message} (type: {type_})"),
+        (Some(message), None) => bail!("{message}"),
+        _ => {}
+    }
+    if let Some(message) = detail.as_str() {
+        bail!("{message}");
+    }
+    bail!("Invalid response, status: {status}, data: {data}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{fixture, CompletionConfig, Message};
+
+    #[test]
+    fn test_build_body() {
+        let model = Model::new("koboldcpp", "llama3")
+            .set_max_input_tokens(Some(8192))
+            .set_completion(Some(CompletionConfig {
+                prompt_template: Some("### Instruction:\n{input}\n\n### Response:\n".into()),
+                stop: Some(vec!["### Instruction:".into()]),
+            }));
+        let data = SendData {
+            messages: vec![Message::user("Hi")],
+            temperature: Some(0.7),
+            top_p: None,
+            functions: None,
+            stream: true,
+        };
+        assert_eq!(
+            build_body(data, &model),
+            json!({
+                "prompt": "### Instruction:\nHi\n\n### Response:\n",
+                "max_length": 1024,
+                "max_context_length": 8192,
+                "stop_sequence": ["### Instruction:"],
+                "temperature": 0.7,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let client = KoboldCppClient::for_test("{ api_base: http://localhost:5001, models: [] }")?;
+        let http = ReqwestClient::new();
+        let request = |stream| client.request_builder(&http, fixture::send_data(stream));
+
+        let output =
+            send_message(fixture::replay("koboldcpp/success", request(false)?).await?).await?;
+        assert_eq!(output.text, " Hello! How can I help you today?");
+
+        let (mut handler, _rx) = fixture::handler();
+        send_message_streaming(
+            fixture::replay("koboldcpp/stream", request(true)?).await?,
+            &mut handler,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), " Hello! How can I help you today?");
+
+        let err = send_message(fixture::replay("koboldcpp/busy", request(false)?).await?)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Server is busy; please try again later. (type: service_unavailable)"
+        );
+        Ok(())
+    }
+}
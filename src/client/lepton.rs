@@ -0,0 +1,108 @@
+use super::openai::openai_build_body;
+use super::{ExtraConfig, LeptonClient, Model, ModelConfig, PromptType, SendData};
+
+use crate::utils::PromptKind;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use serde::Deserialize;
+
+// Each model is served on its own subdomain
+const API_BASE_TEMPLATE: &str = "https://{model}.lepton.run/api/v1";
+
+const MODELS: [(&str, usize, &str); 4] = [
+    // https://www.lepton.ai/playground
+    ("llama3-70b", 8192, "text"),
+    ("llama3-8b", 8192, "text"),
+    ("mixtral-8x7b", 32768, "text"),
+    ("wizardlm-2-8x22b", 65536, "text"),
+];
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct LeptonConfig {
+    pub name: Option<String>,
+    pub api_key: Option<String>,
+    pub api_base_template: Option<String>,
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+openai_compatible_client!(LeptonClient);
+
+impl LeptonClient {
+    list_models_fn!(LeptonConfig, &MODELS);
+    config_get_fn!(api_key, get_api_key);
+
+    pub const PROMPTS: [PromptType<'static>; 1] =
+        [("api_key", "API Key:", true, PromptKind::String)];
+
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let api_key = self.get_api_key()?;
+
+        let body = openai_build_body(data, &self.model);
+
+        let url = format!("{}/chat/completions", self.api_base());
+
+        debug!("Lepton Request: {url} {body}");
+
+        let builder = client.post(url).bearer_auth(api_key).json(&body);
+
+        Ok(builder)
+    }
+
+    /// Fill `{model}` of the template with the model name.
+    fn api_base(&self) -> String {
+        let template = self
+            .config
+            .api_base_template
+            .as_deref()
+            .unwrap_or(API_BASE_TEMPLATE);
+        template
+            .replace("{model}", &self.model.name)
+            .trim_end_matches('/')
+            .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{
+        fixture,
+        openai::{openai_send_message, openai_send_message_streaming},
+    };
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let client = LeptonClient::for_test("api_key: xxx")?;
+        let http = ReqwestClient::new();
+        let request = |stream| client.request_builder(&http, fixture::send_data(stream));
+
+        let (mut handler, _rx) = fixture::handler();
+        openai_send_message_streaming(
+            fixture::replay("lepton/stream", request(true)?).await?,
+            &mut handler,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "Hello! How can I help you today?");
+        assert_eq!(handler.get_usage(), (Some(11), Some(10)));
+
+        let err =
+            openai_send_message(fixture::replay("lepton/auth_failure", request(false)?).await?)
+                .await
+                .unwrap_err();
+        assert_eq!(err.to_string(), "Unauthorized (status: 401)");
+
+        let (mut handler, _rx) = fixture::handler();
+        let err = openai_send_message_streaming(
+            fixture::replay("lepton/stream_auth_failure", request(true)?).await?,
+            &mut handler,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Unauthorized (status: 401)");
+        Ok(())
+    }
+}
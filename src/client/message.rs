@@ -1,11 +1,15 @@
 use crate::config::Input;
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Message {
     pub role: MessageRole,
     pub content: MessageContent,
+    /// Never trimmed or compressed from a session.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
 }
 
 impl Message {
@@ -13,6 +17,29 @@ impl Message {
         Self {
             role: MessageRole::User,
             content: input.to_message_content(),
+            pinned: false,
+        }
+    }
+}
+
+impl Message {
+    pub fn system(text: &str) -> Self {
+        Self::from_text(MessageRole::System, text)
+    }
+
+    pub fn user(text: &str) -> Self {
+        Self::from_text(MessageRole::User, text)
+    }
+
+    pub fn assistant(text: &str) -> Self {
+        Self::from_text(MessageRole::Assistant, text)
+    }
+
+    fn from_text(role: MessageRole, text: &str) -> Self {
+        Self {
+            role,
+            content: MessageContent::Text(text.to_string()),
+            pinned: false,
         }
     }
 }
@@ -40,11 +67,13 @@ impl MessageRole {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum MessageContent {
     Text(String),
     Array(Vec<MessageContentPart>),
+    /// The calls the model made, with their results, and the text that came with the calls.
+    ToolResults((Vec<ToolResult>, String)),
 }
 
 impl MessageContent {
@@ -68,6 +97,7 @@ impl MessageContent {
                 }
                 format!(".file {}{}", files.join(" "), concated_text)
             }
+            MessageContent::ToolResults((_, text)) => text.to_string(),
         }
     }
 
@@ -83,6 +113,7 @@ impl MessageContent {
                     *text = replace_fn(text)
                 }
             }
+            MessageContent::ToolResults(_) => {}
         }
     }
 
@@ -98,22 +129,49 @@ impl MessageContent {
                 }
                 parts.join("\n\n")
             }
+            MessageContent::ToolResults((_, text)) => text.to_string(),
         }
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum MessageContentPart {
     Text { text: String },
     ImageUrl { image_url: ImageUrl },
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct ImageUrl {
     pub url: String,
 }
 
+/// A call of a declared function by the model.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ToolCall {
+    pub name: String,
+    pub arguments: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+}
+
+impl ToolCall {
+    pub fn new(name: &str, arguments: Value, id: Option<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            arguments,
+            id,
+        }
+    }
+}
+
+/// The output of a tool call, sent back to the model in the next turn.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ToolResult {
+    pub call: ToolCall,
+    pub output: Value,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
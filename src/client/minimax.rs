@@ -0,0 +1,269 @@
+use super::{
+    event_source, message::*, note_response, send_request, Client, CompletionOutput, ExtraConfig,
+    MiniMaxClient, Model, ModelConfig, PromptType, ReplyHandler, SendData,
+};
+
+use crate::utils::PromptKind;
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use reqwest_eventsource::{Error as EventSourceError, Event};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+const API_URL: &str = "https://api.minimax.chat/v1/text/chatcompletion_v2";
+
+const MODELS: [(&str, usize, &str); 2] = [
+    // https://platform.minimaxi.com/document/Models
+    ("abab6.5-chat", 8192, "text"),
+    ("abab5.5-chat", 16384, "text"),
+];
+
+/// The api key or the group id is invalid.
+const AUTH_FAILURE_CODE: i64 = 1004;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MiniMaxConfig {
+    pub name: Option<String>,
+    pub group_id: Option<String>,
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+#[async_trait]
+impl Client for MiniMaxClient {
+    client_common_fns!();
+
+    async fn send_message_inner(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+    ) -> Result<CompletionOutput> {
+        let builder = self.request_builder(client, data)?;
+        send_message(builder).await
+    }
+
+    async fn send_message_streaming_inner(
+        &self,
+        client: &ReqwestClient,
+        handler: &mut ReplyHandler,
+        data: SendData,
+    ) -> Result<()> {
+        let builder = self.request_builder(client, data)?;
+        send_message_streaming(builder, handler).await
+    }
+}
+
+impl MiniMaxClient {
+    list_models_fn!(MiniMaxConfig, &MODELS);
+    config_get_fn!(api_key, get_api_key);
+    config_get_fn!(group_id, get_group_id);
+
+    pub const PROMPTS: [PromptType<'static>; 2] = [
+        ("group_id", "Group ID:", true, PromptKind::String),
+        ("api_key", "API Key:", true, PromptKind::String),
+    ];
+
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let api_key = self.get_api_key()?;
+        let group_id = self.get_group_id()?;
+
+        let body = build_body(data, &self.model)?;
+
+        let url = format!("{API_URL}?GroupId={group_id}");
+
+        debug!("MiniMax Request: {url} {body}");
+
+        let builder = client.post(url).bearer_auth(api_key).json(&body);
+
+        Ok(builder)
+    }
+}
+
+async fn send_message(builder: RequestBuilder) -> Result<CompletionOutput> {
+    let res = send_request(builder).await?;
+    let status = res.status();
+    let data: Value = res.json().await?;
+    if status != 200 || has_error(&data) {
+        catch_error(&data, status.as_u16())?;
+    }
+
+    let text = data["choices"][0]["message"]["content"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+
+    Ok(CompletionOutput {
+        text: text.to_string(),
+        input_tokens: None,
+        output_tokens: None,
+        tool_calls: vec![],
+        request_id: None,
+        stop_sequence: None,
+        stop_reason: None,
+        stats: None,
+        cache_usage: None,
+    })
+}
+
+async fn send_message_streaming(builder: RequestBuilder, handler: &mut ReplyHandler) -> Result<()> {
+    let mut es = event_source(builder)?;
+    while let Some(event) = es.next().await {
+        match event {
+            Ok(Event::Open) => {}
+            Ok(Event::Message(message)) => {
+                if message.data == "[DONE]" {
+                    break;
+                }
+                let data: Value = serde_json::from_str(&message.data)?;
+                if has_error(&data) {
+                    es.close();
+                    catch_error(&data, 200)?;
+                }
+                // The last chunk repeats the whole reply in `message`
+                if let Some(text) = data["choices"][0]["delta"]["content"].as_str() {
+                    handler.text(text)?;
+                }
+            }
+            Err(err) => {
+                match err {
+                    EventSourceError::StreamEnded => {}
+                    EventSourceError::InvalidStatusCode(status, res) => {
+                        note_response(&res);
+                        let text = res.text().await?;
+                        let data: Value = match text.parse() {
+                            Ok(data) => data,
+                            Err(_) => {
+                                bail!("Invalid respoinse, status: {status}, text: {text}");
+                            }
+                        };
+                        catch_error(&data, status.as_u16())?;
+                    }
+                    // Errors come back as plain json with status 200
+                    EventSourceError::InvalidContentType(_, res) => {
+                        let text = res.text().await?;
+                        if let Ok(data) = text.parse::<Value>() {
+                            catch_error(&data, 200)?;
+                        }
+                        bail!("The API server should return data as 'text/event-stream', but it isn't. Check the client config. {text}");
+                    }
+                    _ => {
+                        bail!("{}", err);
+                    }
+                }
+                es.close();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn build_body(data: SendData, model: &Model) -> Result<Value> {
+    let SendData {
+        messages,
+        temperature,
+        top_p,
+        functions: _,
+        stream,
+    } = data;
+
+    let messages = messages
+        .into_iter()
+        .map(|message| {
+            if let MessageContent::Array(list) = &message.content {
+                if list
+                    .iter()
+                    .any(|v| matches!(v, MessageContentPart::ImageUrl { .. }))
+                {
+                    bail!("The model does not support images");
+                }
+            }
+            let role = match message.role {
+                MessageRole::System => "system",
+                MessageRole::Assistant => "assistant",
+                MessageRole::User => "user",
+            };
+            Ok(json!({ "role": role, "content": message.content.to_text() }))
+        })
+        .collect::<Result<Vec<Value>>>()?;
+
+    let mut body = json!({
+        "model": &model.name,
+        "messages": messages,
+    });
+
+    if let Some(max_tokens) = model.max_output_tokens {
+        body["max_tokens"] = max_tokens.into();
+    }
+    if let Some(temperature) = temperature {
+        body["temperature"] = temperature.into();
+    }
+    if let Some(top_p) = top_p {
+        body["top_p"] = top_p.into();
+    }
+    if stream {
+        body["stream"] = true.into();
+    }
+
+    Ok(body)
+}
+
+fn has_error(data: &Value) -> bool {
+    data["base_resp"]["status_code"]
+        .as_i64()
+        .map(|v| v != 0)
+        .unwrap_or_default()
+}
+
+fn catch_error(data: &Value, status: u16) -> Result<()> {
+    debug!("Invalid response, status: {status}, data: {data}");
+    let base_resp = &data["base_resp"];
+    if let (Some(code), Some(message)) = (
+        base_resp["status_code"].as_i64(),
+        base_resp["status_msg"].as_str(),
+    ) {
+        if code == AUTH_FAILURE_CODE {
+            bail!("{message} (status_code: {code}), check the api_key and group_id");
+        }
+        bail!("{message} (status_code: {code})");
+    }
+    bail!("Invalid response, status: {status}, data: {data}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::fixture;
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let client = MiniMaxClient::for_test("{ api_key: xxx, group_id: g-1234 }")?;
+        let http = ReqwestClient::new();
+        let request = |stream| client.request_builder(&http, fixture::send_data(stream));
+
+        let (mut handler, _rx) = fixture::handler();
+        send_message_streaming(
+            fixture::replay("minimax/stream", request(true)?).await?,
+            &mut handler,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "Hello! How can I help you today?");
+
+        let (mut handler, _rx) = fixture::handler();
+        let err = send_message_streaming(
+            fixture::replay("minimax/auth_failure", request(true)?).await?,
+            &mut handler,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "login fail: Please carry the API secret key in the 'Authorization' field of the request header (status_code: 1004), check the api_key and group_id"
+        );
+        Ok(())
+    }
+}
@@ -1,13 +1,21 @@
 #[macro_use]
 mod common;
+#[cfg(test)]
+mod fixture;
+mod health;
 mod message;
 mod model;
+mod output_filter;
 mod reply_handler;
+mod request;
 
 pub use common::*;
+pub use health::*;
 pub use message::*;
 pub use model::*;
+pub use output_filter::*;
 pub use reply_handler::*;
+pub use request::*;
 
 register_client!(
     (openai, "openai", OpenAIConfig, OpenAIClient),
@@ -17,6 +25,7 @@ register_client!(
         AzureOpenAIConfig,
         AzureOpenAIClient
     ),
+    (azure_ai, "azure-ai", AzureAIConfig, AzureAIClient),
     (
         openai_compatible,
         "openai-compatible",
@@ -25,10 +34,57 @@ register_client!(
     ),
     (gemini, "gemini", GeminiConfig, GeminiClient),
     (vertexai, "vertexai", VertexAIConfig, VertexAIClient),
+    (
+        vertexai_claude,
+        "vertexai-claude",
+        VertexAIClaudeConfig,
+        VertexAIClaudeClient
+    ),
     (claude, "claude", ClaudeConfig, ClaudeClient),
+    (bedrock, "bedrock", BedrockConfig, BedrockClient),
+    (
+        claude_compatible,
+        "claude-compatible",
+        ClaudeCompatibleConfig,
+        ClaudeCompatibleClient
+    ),
     (mistral, "mistral", MistralConfig, MistralClient),
     (cohere, "cohere", CohereConfig, CohereClient),
+    (groq, "groq", GroqConfig, GroqClient),
+    (github, "github", GithubConfig, GithubClient),
+    (openrouter, "openrouter", OpenRouterConfig, OpenRouterClient),
+    (deepseek, "deepseek", DeepSeekConfig, DeepSeekClient),
+    (deepinfra, "deepinfra", DeepInfraConfig, DeepInfraClient),
+    (sambanova, "sambanova", SambaNovaConfig, SambaNovaClient),
+    (yi, "yi", YiConfig, YiClient),
+    (
+        siliconflow,
+        "siliconflow",
+        SiliconFlowConfig,
+        SiliconFlowClient
+    ),
+    (lepton, "lepton", LeptonConfig, LeptonClient),
     (ollama, "ollama", OllamaConfig, OllamaClient),
+    (koboldcpp, "koboldcpp", KoboldCppConfig, KoboldCppClient),
+    (
+        huggingface,
+        "huggingface",
+        HuggingFaceConfig,
+        HuggingFaceClient
+    ),
+    (replicate, "replicate", ReplicateConfig, ReplicateClient),
     (ernie, "ernie", ErnieConfig, ErnieClient),
+    (alephalpha, "alephalpha", AlephAlphaConfig, AlephAlphaClient),
+    (minimax, "minimax", MiniMaxConfig, MiniMaxClient),
+    (reka, "reka", RekaConfig, RekaClient),
     (qianwen, "qianwen", QianwenConfig, QianwenClient),
+    (hunyuan, "hunyuan", HunyuanConfig, HunyuanClient),
+    (doubao, "doubao", DoubaoConfig, DoubaoClient),
+    (watsonx, "watsonx", WatsonxConfig, WatsonxClient),
+    (command, "command", CommandConfig, CommandClient),
 );
+
+pub use self::deepinfra::sync_deepinfra_models;
+pub use self::ollama::{create_ollama_config, sync_ollama_models};
+pub use self::openrouter::sync_openrouter_models;
+pub use self::vertexai::sync_vertexai_models;
@@ -1,21 +1,33 @@
-use super::message::{Message, MessageContent};
+use super::message::{Message, MessageContent, MessageContentPart, MessageRole};
+use super::output_filter::{OutputFilter, OutputFilterConfig};
 
-use crate::utils::count_tokens;
+use crate::utils::{count_tokens, render_prompt};
 
 use anyhow::{bail, Result};
 use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
 
 const PER_MESSAGES_TOKENS: usize = 5;
 const BASIS_TOKENS: usize = 2;
 
+const DEFAULT_PROMPT_TEMPLATE: &str = "{?system {system}\n\n}{history}User: {input}\nAssistant:";
+const DEFAULT_STOP: [&str; 1] = ["\nUser:"];
+
 #[derive(Debug, Clone)]
 pub struct Model {
     pub client_name: String,
     pub name: String,
+    pub real_name: Option<String>,
     pub max_input_tokens: Option<usize>,
     pub max_output_tokens: Option<isize>,
     pub extra_fields: Option<serde_json::Map<String, serde_json::Value>>,
     pub capabilities: ModelCapabilities,
+    pub completion: Option<CompletionConfig>,
+    pub input_price: Option<f64>,
+    pub output_price: Option<f64>,
+    pub output_filter: Option<OutputFilterConfig>,
+    pub keep_alive: Option<serde_json::Value>,
+    pub format: Option<serde_json::Value>,
 }
 
 impl Default for Model {
@@ -29,10 +41,17 @@ impl Model {
         Self {
             client_name: client_name.into(),
             name: name.into(),
+            real_name: None,
             extra_fields: None,
             max_input_tokens: None,
             max_output_tokens: None,
             capabilities: ModelCapabilities::Text,
+            completion: None,
+            input_price: None,
+            output_price: None,
+            output_filter: None,
+            keep_alive: None,
+            format: None,
         }
     }
 
@@ -41,10 +60,17 @@ impl Model {
             .iter()
             .map(|v| {
                 Model::new(client_name, &v.name)
+                    .set_real_name(v.real_name.clone())
                     .set_capabilities(v.capabilities)
                     .set_max_input_tokens(v.max_input_tokens)
                     .set_max_output_tokens(v.max_output_tokens)
                     .set_extra_fields(v.extra_fields.clone())
+                    .set_completion(v.completion.clone())
+                    .set_input_price(v.input_price)
+                    .set_output_price(v.output_price)
+                    .set_output_filter(v.output_filter.clone())
+                    .set_keep_alive(v.keep_alive.clone())
+                    .set_format(v.format.clone())
             })
             .collect()
     }
@@ -108,6 +134,46 @@ impl Model {
         self
     }
 
+    pub fn set_completion(mut self, completion: Option<CompletionConfig>) -> Self {
+        self.completion = completion;
+        self
+    }
+
+    pub fn set_input_price(mut self, input_price: Option<f64>) -> Self {
+        self.input_price = input_price;
+        self
+    }
+
+    pub fn set_output_price(mut self, output_price: Option<f64>) -> Self {
+        self.output_price = output_price;
+        self
+    }
+
+    pub fn set_real_name(mut self, real_name: Option<String>) -> Self {
+        self.real_name = real_name;
+        self
+    }
+
+    /// The name to send to the API.
+    pub fn real_name(&self) -> &str {
+        self.real_name.as_deref().unwrap_or(&self.name)
+    }
+
+    pub fn set_output_filter(mut self, output_filter: Option<OutputFilterConfig>) -> Self {
+        self.output_filter = output_filter;
+        self
+    }
+
+    pub fn set_keep_alive(mut self, keep_alive: Option<serde_json::Value>) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    pub fn set_format(mut self, format: Option<serde_json::Value>) -> Self {
+        self.format = format;
+        self
+    }
+
     pub fn set_max_input_tokens(mut self, max_input_tokens: Option<usize>) -> Self {
         match max_input_tokens {
             None | Some(0) => self.max_input_tokens = None,
@@ -124,6 +190,17 @@ impl Model {
         self
     }
 
+    /// Describe how `total_tokens` counts for this model.
+    pub fn tokenizer(&self) -> &'static str {
+        let openai_family = self.name.starts_with("gpt-")
+            || ["openai", "azure-openai"].contains(&self.client_name.as_str());
+        if openai_family {
+            "tiktoken (cl100k_base)"
+        } else {
+            "estimated with tiktoken (cl100k_base)"
+        }
+    }
+
     pub fn messages_tokens(&self, messages: &[Message]) -> usize {
         messages
             .iter()
@@ -131,6 +208,7 @@ impl Model {
                 match &v.content {
                     MessageContent::Text(text) => count_tokens(text),
                     MessageContent::Array(_) => 0, // TODO
+                    MessageContent::ToolResults((_, text)) => count_tokens(text),
                 }
             })
             .sum()
@@ -159,6 +237,40 @@ impl Model {
         Ok(())
     }
 
+    /// Fail fast when the messages carry images but the model lacks the `vision` capability.
+    pub fn guard_vision(&self, messages: &[Message]) -> Result<()> {
+        let has_images = messages.iter().any(|message| match &message.content {
+            MessageContent::Array(list) => list
+                .iter()
+                .any(|v| matches!(v, MessageContentPart::ImageUrl { .. })),
+            _ => false,
+        });
+        if has_images && !self.capabilities.contains(ModelCapabilities::Vision) {
+            bail!(
+                "The model '{}' does not support images, set `capabilities: text,vision` on it if it does",
+                self.id()
+            )
+        }
+        Ok(())
+    }
+
+    /// The cost in USD of the tokens, if the model has prices.
+    pub fn cost(&self, input_tokens: usize, output_tokens: usize) -> Option<f64> {
+        if self.input_price.is_none() && self.output_price.is_none() {
+            return None;
+        }
+        let input = input_tokens as f64 * self.input_price.unwrap_or_default();
+        let output = output_tokens as f64 * self.output_price.unwrap_or_default();
+        Some((input + output) / 1_000_000.0)
+    }
+
+    pub fn output_filter(&self) -> Result<Option<OutputFilter>> {
+        self.output_filter
+            .as_ref()
+            .map(OutputFilter::new)
+            .transpose()
+    }
+
     pub fn merge_extra_fields(&self, body: &mut serde_json::Value) {
         if let (Some(body), Some(extra_fields)) = (body.as_object_mut(), &self.extra_fields) {
             for (key, extra_field) in extra_fields {
@@ -183,12 +295,71 @@ impl Model {
 #[derive(Debug, Clone, Deserialize)]
 pub struct ModelConfig {
     pub name: String,
+    /// The name the API knows the model by, if `name` is only a friendly alias
+    pub real_name: Option<String>,
     pub max_input_tokens: Option<usize>,
     pub max_output_tokens: Option<isize>,
     pub extra_fields: Option<serde_json::Map<String, serde_json::Value>>,
     #[serde(deserialize_with = "deserialize_capabilities")]
     #[serde(default = "default_capabilities")]
     pub capabilities: ModelCapabilities,
+    pub completion: Option<CompletionConfig>,
+    /// USD per 1M input tokens
+    pub input_price: Option<f64>,
+    /// USD per 1M output tokens
+    pub output_price: Option<f64>,
+    pub output_filter: Option<OutputFilterConfig>,
+    /// How long ollama keeps the model loaded after a request, e.g. `30m`, or -1 for ever
+    pub keep_alive: Option<serde_json::Value>,
+    /// Constrain ollama output to JSON, with `json` or a JSON schema
+    pub format: Option<serde_json::Value>,
+}
+
+/// Talk to a base model through the legacy text-completions endpoint.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CompletionConfig {
+    pub prompt_template: Option<String>,
+    pub stop: Option<Vec<String>>,
+}
+
+impl CompletionConfig {
+    /// Flatten the messages into a single prompt.
+    ///
+    /// The template supports `{system}`, `{history}` (earlier turns as `User:`/`Assistant:` lines)
+    /// and `{input}` (the last user message).
+    pub fn build_prompt(&self, messages: &[Message]) -> String {
+        let mut system = vec![];
+        let mut history = String::new();
+        let mut input = String::new();
+        let last_user_index = messages.iter().rposition(|v| v.role.is_user());
+        for (i, message) in messages.iter().enumerate() {
+            let text = message.content.to_text().trim().to_string();
+            match message.role {
+                MessageRole::System => system.push(text),
+                MessageRole::User if Some(i) == last_user_index => input = text,
+                MessageRole::User => history.push_str(&format!("User: {text}\n")),
+                MessageRole::Assistant => history.push_str(&format!("Assistant: {text}\n")),
+            }
+        }
+        let variables = HashMap::from([
+            ("system", system.join("\n\n")),
+            ("history", history),
+            ("input", input),
+        ]);
+        let template = self
+            .prompt_template
+            .as_deref()
+            .unwrap_or(DEFAULT_PROMPT_TEMPLATE);
+        render_prompt(template, &variables)
+    }
+
+    pub fn stop(&self) -> Vec<String> {
+        match (&self.stop, &self.prompt_template) {
+            (Some(stop), _) => stop.clone(),
+            (None, None) => DEFAULT_STOP.iter().map(|v| v.to_string()).collect(),
+            (None, Some(_)) => vec![],
+        }
+    }
 }
 
 bitflags::bitflags! {
@@ -196,6 +367,7 @@ bitflags::bitflags! {
     pub struct ModelCapabilities: u32 {
         const Text = 0b00000001;
         const Vision = 0b00000010;
+        const Embedding = 0b00000100;
     }
 }
 
@@ -209,6 +381,9 @@ impl From<&str> for ModelCapabilities {
         if value.contains("vision") {
             output |= ModelCapabilities::Vision;
         }
+        if value.contains("embedding") {
+            output |= ModelCapabilities::Embedding;
+        }
         output
     }
 }
@@ -224,3 +399,72 @@ where
 fn default_capabilities() -> ModelCapabilities {
     ModelCapabilities::Text
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ImageUrl;
+
+    #[test]
+    fn test_build_prompt() {
+        let message = |role, text: &str| Message {
+            role,
+            content: MessageContent::Text(text.into()),
+            pinned: false,
+        };
+        let messages = vec![
+            message(MessageRole::System, "Be brief"),
+            message(MessageRole::User, "Hi"),
+            message(MessageRole::Assistant, "Hello"),
+            message(MessageRole::User, "How are you?"),
+        ];
+        let config = CompletionConfig::default();
+        assert_eq!(
+            config.build_prompt(&messages),
+            "Be brief\n\nUser: Hi\nAssistant: Hello\nUser: How are you?\nAssistant:"
+        );
+        assert_eq!(config.stop(), vec!["\nUser:".to_string()]);
+        let config = CompletionConfig {
+            prompt_template: Some("### Instruction:\n{input}\n\n### Response:\n".into()),
+            stop: None,
+        };
+        assert_eq!(
+            config.build_prompt(&messages[3..]),
+            "### Instruction:\nHow are you?\n\n### Response:\n"
+        );
+        assert!(config.stop().is_empty());
+    }
+
+    #[test]
+    fn test_cost() {
+        let model = Model::new("openai", "gpt-4o");
+        assert_eq!(model.cost(1000, 1000), None);
+        let model = model
+            .set_input_price(Some(5.0))
+            .set_output_price(Some(15.0));
+        assert_eq!(model.cost(1_000_000, 0), Some(5.0));
+        assert_eq!(model.cost(2000, 1000), Some(0.025));
+    }
+
+    #[test]
+    fn test_guard_vision() {
+        let messages = vec![Message {
+            role: MessageRole::User,
+            content: MessageContent::Array(vec![MessageContentPart::ImageUrl {
+                image_url: ImageUrl {
+                    url: "data:image/png;base64,iVBORw0KGgo=".into(),
+                },
+            }]),
+            pinned: false,
+        }];
+        let model = Model::new("ollama", "llava");
+        let err = model.guard_vision(&messages).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The model 'ollama:llava' does not support images, set `capabilities: text,vision` on it if it does"
+        );
+        let model = model.set_capabilities("text,vision".into());
+        assert!(model.guard_vision(&messages).is_ok());
+        assert!(model.guard_vision(&messages[..0]).is_ok());
+    }
+}
@@ -1,23 +1,55 @@
 use super::{
-    message::*, Client, ExtraConfig, Model, ModelConfig, OllamaClient, PromptType, ReplyHandler,
-    SendData,
+    create_config, custom_headers, message::*, ndjson_stream, read_cache, refresh_cache, send_request, Client,
+    ClientConfig, CompletionOutput, EmbeddingsOutput, ExtraConfig, GenerationStats, Model,
+    ModelConfig, OllamaClient, PromptType, ReplyHandler, SendData, with_headers,
 };
 
-use crate::utils::PromptKind;
+use crate::config::{Config, GlobalConfig, WorkingMode};
+use crate::utils::{prompt_input_integer, prompt_input_string, PromptKind};
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Error, Result};
 use async_trait::async_trait;
-use futures_util::StreamExt;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use inquire::{Confirm, Select};
+use is_terminal::IsTerminal;
+use lazy_static::lazy_static;
+use parking_lot::{MappedMutexGuard, Mutex, MutexGuard};
 use reqwest::{Client as ReqwestClient, RequestBuilder};
-use serde::Deserialize;
+use serde::{de::DeserializeOwned, Deserialize};
 use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::write,
+    io::{stderr, stdin},
+    time::Duration,
+};
+
+const MODELS_FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+const PULL_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The `/api/tags` listings, by client name.
+const TAGS_FILE_NAME: &str = "ollama_tags.json";
+const TAGS_TTL: Duration = Duration::from_secs(10 * 60);
+/// The context lengths of the models, by digest.
+const CONTEXT_LENGTHS_FILE_NAME: &str = "ollama_context_lengths.json";
+
+lazy_static! {
+    static ref TAGS: Mutex<Option<HashMap<String, Vec<Value>>>> = Mutex::new(None);
+    static ref CONTEXT_LENGTHS: Mutex<Option<HashMap<String, usize>>> = Mutex::new(None);
+}
 
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct OllamaConfig {
     pub name: Option<String>,
     pub api_base: String,
     pub api_key: Option<String>,
+    pub auth_scheme: Option<String>,
     pub chat_endpoint: Option<String>,
+    pub keep_alive: Option<Value>,
+    pub num_ctx_from_max_input_tokens: Option<bool>,
+    pub auto_pull: Option<bool>,
+    #[serde(default)]
     pub models: Vec<ModelConfig>,
     pub extra: Option<ExtraConfig>,
 }
@@ -26,9 +58,23 @@ pub struct OllamaConfig {
 impl Client for OllamaClient {
     client_common_fns!();
 
-    async fn send_message_inner(&self, client: &ReqwestClient, data: SendData) -> Result<String> {
-        let builder = self.request_builder(client, data)?;
-        send_message(builder).await
+    async fn send_message_inner(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+    ) -> Result<CompletionOutput> {
+        let builder = self.request_builder(client, data.clone())?;
+        let ret = match send_message(builder, self.model.format.is_some()).await {
+            Err(err) if self.pull_missing_model(client, &err).await? => {
+                send_message(
+                    self.request_builder(client, data)?,
+                    self.model.format.is_some(),
+                )
+                .await
+            }
+            ret => ret,
+        };
+        self.explain_not_found(client, ret).await
     }
 
     async fn send_message_streaming_inner(
@@ -37,18 +83,52 @@ impl Client for OllamaClient {
         handler: &mut ReplyHandler,
         data: SendData,
     ) -> Result<()> {
-        let builder = self.request_builder(client, data)?;
-        send_message_streaming(builder, handler).await
+        let builder = self.request_builder(client, data.clone())?;
+        let ret = match send_message_streaming(builder, handler, self.model.format.is_some()).await
+        {
+            Err(err) if self.pull_missing_model(client, &err).await? => {
+                send_message_streaming(
+                    self.request_builder(client, data)?,
+                    handler,
+                    self.model.format.is_some(),
+                )
+                .await
+            }
+            ret => ret,
+        };
+        self.explain_not_found(client, ret).await
+    }
+
+    async fn embeddings_inner(
+        &self,
+        client: &ReqwestClient,
+        texts: Vec<String>,
+    ) -> Result<EmbeddingsOutput> {
+        let ret = match self.embeddings_with(client, texts.clone()).await {
+            Err(err) if self.pull_missing_model(client, &err).await? => {
+                self.embeddings_with(client, texts).await
+            }
+            ret => ret,
+        };
+        self.explain_not_found(client, ret).await
+    }
+
+    async fn health_check_inner(&self, client: &ReqwestClient) -> Result<()> {
+        self.check_model(client).await
     }
 }
 
 impl OllamaClient {
-    list_models_fn!(OllamaConfig);
     config_get_fn!(api_key, get_api_key);
 
     pub const PROMPTS: [PromptType<'static>; 4] = [
         ("api_base", "API Base:", true, PromptKind::String),
-        ("api_key", "API Key:", false, PromptKind::String),
+        (
+            "api_key",
+            "API Key (user:pass for Basic auth, else a Bearer token):",
+            false,
+            PromptKind::String,
+        ),
         ("models[].name", "Model Name:", true, PromptKind::String),
         (
             "models[].max_input_tokens",
@@ -58,29 +138,523 @@ impl OllamaClient {
         ),
     ];
 
-    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+    /// The declared models, or else the installed ones of the `/api/tags` listing.
+    pub fn list_models(local_config: &OllamaConfig) -> Vec<Model> {
+        let client_name = Self::name(local_config);
+        let tags = cached_tags();
+        let tags = tags
+            .get(client_name)
+            .map(|v| v.as_slice())
+            .unwrap_or_default();
+        if !local_config.models.is_empty() {
+            let lengths = cached_context_lengths();
+            return Model::from_config(client_name, &local_config.models)
+                .into_iter()
+                .map(|model| {
+                    let length = find_digest(tags, &model.name).and_then(|v| lengths.get(v));
+                    match (model.max_input_tokens, length) {
+                        (None, Some(length)) => model.set_max_input_tokens(Some(*length)),
+                        _ => model,
+                    }
+                })
+                .collect();
+        }
+        tag_names(tags)
+            .iter()
+            .map(|name| Model::new(client_name, name))
+            .collect()
+    }
+
+    /// Check the model is installed, suggesting the closest installed one if it isn't.
+    async fn check_model(&self, client: &ReqwestClient) -> Result<()> {
+        let names = list_tags(self.tags_request_builder(client)?).await?;
+        check_installed(&self.model.name, &names)
+    }
+
+    /// Suggest an installed model in place of a not found error, if the model isn't installed.
+    async fn explain_not_found<T>(&self, client: &ReqwestClient, ret: Result<T>) -> Result<T> {
+        match ret {
+            Err(err) if err.is::<NotFound>() => {
+                match list_tags(self.tags_request_builder(client)?).await {
+                    Ok(names) => check_installed(&self.model.name, &names).and(Err(err)),
+                    Err(_) => Err(err),
+                }
+            }
+            ret => ret,
+        }
+    }
+
+    /// Pull the model if the error says it is missing and `auto_pull` is on or the user agrees in the REPL.
+    ///
+    /// Returns whether the model was pulled, so the request is worth retrying.
+    async fn pull_missing_model(&self, client: &ReqwestClient, err: &Error) -> Result<bool> {
+        if !err
+            .downcast_ref::<NotFound>()
+            .is_some_and(|v| v.0.contains("try pulling it first"))
+        {
+            return Ok(false);
+        }
+        let name = &self.model.name;
+        if !self.config.auto_pull.unwrap_or_default() {
+            let repl = self.global_config.read().working_mode == WorkingMode::Repl;
+            if !(repl && stdin().is_terminal() && stderr().is_terminal()) {
+                return Ok(false);
+            }
+            let ans = Confirm::new(&format!("{name} isn't installed, pull it?"))
+                .with_default(true)
+                .prompt()?;
+            if !ans {
+                return Ok(false);
+            }
+        }
+
+        let builder = self.pull_request_builder(client)?;
+        let mut last_status = String::new();
+        pull(builder, |status, progress| {
+            if status != last_status && !last_status.is_empty() {
+                eprintln!();
+            }
+            match progress {
+                Some((completed, total)) => {
+                    eprint!("\r{status} {}/{} MB", completed >> 20, total >> 20)
+                }
+                None => eprint!("\r{status}"),
+            }
+            last_status = status.to_string();
+        })
+        .await?;
+        eprintln!();
+        Ok(true)
+    }
+
+    /// Embed the texts in one `/api/embed` request, or one `/api/embeddings` request each on older servers.
+    async fn embeddings_with(
+        &self,
+        client: &ReqwestClient,
+        texts: Vec<String>,
+    ) -> Result<EmbeddingsOutput> {
+        let api_base = &self.config.api_base;
+
+        let url = format!("{api_base}/api/embed");
+        let body = json!({ "model": &self.model.name, "input": texts });
+        debug!("Ollama Request: {url} {body}");
+        let builder = self.authorize(client.post(url).json(&body))?;
+        if let Some(output) = embed(builder).await? {
+            return Ok(output);
+        }
+
+        let url = format!("{api_base}/api/embeddings");
+        let mut output = EmbeddingsOutput::default();
+        for text in texts {
+            let body = json!({ "model": &self.model.name, "prompt": text });
+            debug!("Ollama Request: {url} {body}");
+            let builder = self.authorize(client.post(&url).json(&body))?;
+            output.vectors.push(embeddings(builder).await?);
+        }
+        Ok(output)
+    }
+
+    /// Add the context lengths of the declared models without `max_input_tokens` that `cache`
+    /// misses, by digest, from `/api/show`. Models the server doesn't report are left out.
+    async fn detect_context_lengths(
+        &self,
+        client: &ReqwestClient,
+        tags: &[Value],
+        cache: &mut HashMap<String, usize>,
+    ) {
+        for model in self
+            .config
+            .models
+            .iter()
+            .filter(|v| v.max_input_tokens.is_none())
+        {
+            let Some(digest) = find_digest(tags, &model.name) else {
+                continue;
+            };
+            if cache.contains_key(digest) {
+                continue;
+            }
+            let url = format!("{}/api/show", self.config.api_base);
+            debug!("Ollama Request: {url}");
+            let builder = match self.authorize(client.post(url)) {
+                Ok(builder) => builder.json(&json!({ "model": &model.name })),
+                Err(_) => continue,
+            };
+            match show_context_length(builder.timeout(MODELS_FETCH_TIMEOUT)).await {
+                Ok(Some(length)) => {
+                    cache.insert(digest.to_string(), length);
+                }
+                Ok(None) => {}
+                Err(err) => debug!("Failed to show the ollama model {}: {err}", model.name),
+            }
+        }
+    }
+
+    fn pull_request_builder(&self, client: &ReqwestClient) -> Result<RequestBuilder> {
+        let url = format!("{}/api/pull", self.config.api_base);
+        let body = json!({ "name": &self.model.name, "stream": true });
+        debug!("Ollama Request: {url} {body}");
+        // A download of gigabytes outlasts the `timeout` of chat requests
+        self.authorize(client.post(url).json(&body).timeout(PULL_TIMEOUT))
+    }
+
+    fn tags_request_builder(&self, client: &ReqwestClient) -> Result<RequestBuilder> {
+        let url = format!("{}/api/tags", self.config.api_base);
+        debug!("Ollama Request: {url}");
+        self.authorize(client.get(url))
+    }
+
+    fn authorize(&self, builder: RequestBuilder) -> Result<RequestBuilder> {
         let api_key = self.get_api_key().ok();
+        authorize(
+            builder,
+            api_key.as_deref(),
+            self.config.auth_scheme.as_deref(),
+        )
+    }
 
-        let mut body = build_body(data, &self.model)?;
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let keep_alive = self
+            .model
+            .keep_alive
+            .clone()
+            .or_else(|| self.config.keep_alive.clone());
+        let mut body = build_body(data, &self.model, keep_alive)?;
         self.model.merge_extra_fields(&mut body);
+        if self.config.num_ctx_from_max_input_tokens.unwrap_or(true) {
+            set_num_ctx(&mut body, &self.model);
+        }
 
-        let chat_endpoint = self.config.chat_endpoint.as_deref().unwrap_or("/api/chat");
+        let chat_endpoint = match &self.model.completion {
+            Some(_) => "/api/generate",
+            None => self.config.chat_endpoint.as_deref().unwrap_or("/api/chat"),
+        };
 
         let url = format!("{}{chat_endpoint}", self.config.api_base);
 
         debug!("Ollama Request: {url} {body}");
 
-        let mut builder = client.post(url).json(&body);
-        if let Some(api_key) = api_key {
-            builder = builder.header("Authorization", api_key)
-        }
+        let builder = self.authorize(client.post(url).json(&body))?;
 
         Ok(builder)
     }
 }
 
-async fn send_message(builder: RequestBuilder) -> Result<String> {
-    let res = builder.send().await?;
+/// Create the config of an ollama client, picking the model from the installed ones if the server is up.
+pub fn create_ollama_config() -> Result<(String, Value)> {
+    let (model, mut clients) = create_config(&OllamaClient::PROMPTS[..2], OllamaClient::NAME)?;
+    let config = &mut clients[0];
+    let api_base = config["api_base"].as_str().unwrap_or_default().to_string();
+    let api_key = config["api_key"].as_str().map(|v| v.to_string());
+    let names = tokio::task::block_in_place(|| {
+        tokio::runtime::Handle::current().block_on(async {
+            let client = ReqwestClient::builder()
+                .timeout(MODELS_FETCH_TIMEOUT)
+                .build()?;
+            let builder = authorize(
+                client.get(format!("{api_base}/api/tags")),
+                api_key.as_deref(),
+                None,
+            )?;
+            list_tags(builder).await
+        })
+    })
+    .unwrap_or_else(|err| {
+        debug!("Failed to list ollama models: {err}");
+        vec![]
+    });
+    let name = if names.is_empty() {
+        prompt_input_string("Model Name:", true)?
+    } else {
+        Select::new("Model Name:", names).prompt()?
+    };
+    let mut model_config = json!({ "name": name });
+    if let Ok(max_input_tokens) =
+        prompt_input_integer("Max Input Tokens (empty to detect):", false)?.parse::<u64>()
+    {
+        model_config["max_input_tokens"] = max_input_tokens.into();
+    }
+    if Confirm::new("Does the model support images (e.g. llava)?")
+        .with_default(false)
+        .prompt()?
+    {
+        model_config["capabilities"] = "text,vision".into();
+    }
+    config["models"] = json!([model_config]);
+    Ok((model, clients))
+}
+
+/// Refresh the cached `/api/tags` listings of the ollama clients every few minutes, and detect
+/// the context lengths of the declared models without `max_input_tokens`.
+pub async fn sync_ollama_models(config: &GlobalConfig) -> Result<()> {
+    let list: Vec<OllamaClient> = config
+        .read()
+        .clients
+        .iter()
+        .filter_map(|v| match v {
+            ClientConfig::OllamaConfig(c)
+                if c.models.is_empty() || c.models.iter().any(|v| v.max_input_tokens.is_none()) =>
+            {
+                Some(OllamaClient {
+                    global_config: config.clone(),
+                    config: c.clone(),
+                    model: Model::new(OllamaClient::name(c), ""),
+                })
+            }
+            _ => None,
+        })
+        .collect();
+    if list.is_empty() {
+        return Ok(());
+    }
+    let fetch = async {
+        // Keep the last listing of a server that is down
+        let mut output = cached_tags().clone();
+        for client in &list {
+            let client_name = &client.model.client_name;
+            let tags = async {
+                let http = client.build_client()?;
+                let builder = client
+                    .tags_request_builder(&http)?
+                    .timeout(MODELS_FETCH_TIMEOUT);
+                let headers = custom_headers(&client.config.extra)?;
+                with_headers(headers, fetch_tags(builder)).await
+            };
+            match tags.await {
+                Ok(tags) => {
+                    output.insert(client_name.clone(), tags);
+                }
+                Err(err) => debug!("Failed to list the models of {client_name}: {err}"),
+            }
+        }
+        Ok(serde_json::to_value(output)?)
+    };
+    if let Some(data) = refresh_cache(TAGS_FILE_NAME, TAGS_TTL, fetch).await? {
+        *TAGS.lock() = serde_json::from_value(data).ok();
+    }
+
+    let mut cache = cached_context_lengths().clone();
+    let cache_size = cache.len();
+    for client in list.iter().filter(|v| !v.config.models.is_empty()) {
+        let tags = cached_tags()
+            .get(&client.model.client_name)
+            .cloned()
+            .unwrap_or_default();
+        let http = client.build_client()?;
+        let headers = custom_headers(&client.config.extra)?;
+        with_headers(
+            headers,
+            client.detect_context_lengths(&http, &tags, &mut cache),
+        )
+        .await;
+    }
+    if cache.len() != cache_size {
+        let cache_path = Config::local_path(CONTEXT_LENGTHS_FILE_NAME)?;
+        if let Err(err) = write(&cache_path, serde_json::to_string_pretty(&cache)?) {
+            debug!("Failed to save {}: {err}", cache_path.display());
+        }
+        *CONTEXT_LENGTHS.lock() = Some(cache);
+    }
+    Ok(())
+}
+
+/// The `/api/tags` listings by client name, loaded from the cache on first use.
+fn cached_tags() -> MappedMutexGuard<'static, HashMap<String, Vec<Value>>> {
+    MutexGuard::map(TAGS.lock(), |v| {
+        v.get_or_insert_with(|| load_cache(TAGS_FILE_NAME))
+    })
+}
+
+/// The context lengths by digest, loaded from the cache on first use.
+fn cached_context_lengths() -> MappedMutexGuard<'static, HashMap<String, usize>> {
+    MutexGuard::map(CONTEXT_LENGTHS.lock(), |v| {
+        v.get_or_insert_with(|| load_cache(CONTEXT_LENGTHS_FILE_NAME))
+    })
+}
+
+fn load_cache<T: DeserializeOwned + Default>(file_name: &str) -> T {
+    read_cache(file_name)
+        .and_then(|v| serde_json::from_value(v).ok())
+        .unwrap_or_default()
+}
+
+fn authorize(
+    builder: RequestBuilder,
+    api_key: Option<&str>,
+    auth_scheme: Option<&str>,
+) -> Result<RequestBuilder> {
+    match api_key {
+        Some(api_key) => Ok(builder.header("Authorization", authorization(api_key, auth_scheme)?)),
+        None => Ok(builder),
+    }
+}
+
+/// The Authorization header of the api key.
+///
+/// Without a scheme, a key with its own scheme prefix goes as is, `user:pass` as Basic and any other as Bearer.
+fn authorization(api_key: &str, auth_scheme: Option<&str>) -> Result<String> {
+    let basic = || format!("Basic {}", STANDARD.encode(api_key));
+    let value = match auth_scheme {
+        Some("raw") => api_key.to_string(),
+        Some("basic") => basic(),
+        Some("bearer") => format!("Bearer {api_key}"),
+        Some(scheme) => bail!("Unknown auth_scheme '{scheme}', expected bearer, basic or raw"),
+        None => match api_key.split_once(' ') {
+            Some((scheme, _)) if scheme.chars().all(|v| v.is_ascii_alphabetic()) => {
+                api_key.to_string()
+            }
+            _ if api_key.contains(':') => basic(),
+            _ => format!("Bearer {api_key}"),
+        },
+    };
+    Ok(value)
+}
+
+async fn list_tags(builder: RequestBuilder) -> Result<Vec<String>> {
+    Ok(tag_names(&fetch_tags(builder).await?))
+}
+
+async fn fetch_tags(builder: RequestBuilder) -> Result<Vec<Value>> {
+    let res = send_request(builder).await?;
+    let status = res.status();
+    let data: Value = res.json().await?;
+    if status != 200 {
+        catch_error(&data, status.as_u16())?;
+    }
+    match data["models"].as_array() {
+        Some(list) => Ok(list.clone()),
+        None => bail!("Invalid response data: {data}"),
+    }
+}
+
+fn tag_names(tags: &[Value]) -> Vec<String> {
+    tags.iter()
+        .filter_map(|v| v["name"].as_str().map(|v| v.to_string()))
+        .collect()
+}
+
+/// The digest of the installed model, `llama3` standing for `llama3:latest`.
+fn find_digest<'a>(tags: &'a [Value], name: &str) -> Option<&'a str> {
+    let latest = format!("{name}:latest");
+    tags.iter()
+        .find(|v| v["name"] == name || v["name"] == latest.as_str())
+        .and_then(|v| v["digest"].as_str())
+}
+
+/// The context length of the GGUF metadata, `None` for servers without `model_info`.
+async fn show_context_length(builder: RequestBuilder) -> Result<Option<usize>> {
+    let res = send_request(builder).await?;
+    let status = res.status();
+    let data: Value = res.json().await?;
+    if status != 200 {
+        catch_error(&data, status.as_u16())?;
+    }
+    let length = data["model_info"].as_object().and_then(|info| {
+        info.iter()
+            .find(|(key, _)| key.ends_with(".context_length"))
+            .and_then(|(_, value)| value.as_u64())
+    });
+    Ok(length.map(|v| v as usize))
+}
+
+/// Pull a model, passing each status and its completed and total bytes to `on_progress`.
+async fn pull(
+    builder: RequestBuilder,
+    mut on_progress: impl FnMut(&str, Option<(u64, u64)>),
+) -> Result<()> {
+    let res = send_request(builder).await?;
+    let status = res.status();
+    if status != 200 {
+        let data = res.json().await?;
+        return catch_error(&data, status.as_u16());
+    }
+    let mut success = false;
+    let handle = |line: &str| -> Result<()> {
+        let data: Value = serde_json::from_str(line)?;
+        if let Some(error) = data["error"].as_str() {
+            bail!("Failed to pull the model: {error}");
+        }
+        let status = data["status"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+        let progress = data["completed"].as_u64().zip(data["total"].as_u64());
+        on_progress(status, progress);
+        success = status == "success";
+        Ok(())
+    };
+    ndjson_stream(res.bytes_stream(), handle).await?;
+    if !success {
+        bail!("Failed to pull the model: the stream ended before it succeeded");
+    }
+    Ok(())
+}
+
+/// Embed with the batch `/api/embed` API, or none if the server predates it.
+async fn embed(builder: RequestBuilder) -> Result<Option<EmbeddingsOutput>> {
+    let res = send_request(builder).await?;
+    let status = res.status();
+    let text = res.text().await?;
+    let data: Value = match serde_json::from_str(&text) {
+        Ok(data) => data,
+        // An unknown path gets a plain text 404 page
+        Err(_) if status == 404 => return Ok(None),
+        Err(_) => bail!("Invalid response, status: {status}, text: {text}"),
+    };
+    if status != 200 {
+        catch_error(&data, status.as_u16())?;
+    }
+    let vectors = data["embeddings"]
+        .as_array()
+        .and_then(|list| list.iter().map(parse_vector).collect())
+        .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+    Ok(Some(EmbeddingsOutput {
+        vectors,
+        input_tokens: data["prompt_eval_count"].as_u64(),
+    }))
+}
+
+async fn embeddings(builder: RequestBuilder) -> Result<Vec<f32>> {
+    let res = send_request(builder).await?;
+    let status = res.status();
+    let data: Value = res.json().await?;
+    if status != 200 {
+        catch_error(&data, status.as_u16())?;
+    }
+    parse_vector(&data["embedding"]).ok_or_else(|| anyhow!("Invalid response data: {data}"))
+}
+
+fn parse_vector(data: &Value) -> Option<Vec<f32>> {
+    data.as_array()?
+        .iter()
+        .map(|v| v.as_f64().map(|v| v as f32))
+        .collect()
+}
+
+/// Check the model is installed, a name without a tag meaning the `latest` one.
+fn check_installed(name: &str, names: &[String]) -> Result<()> {
+    if names
+        .iter()
+        .any(|v| v == name || (!name.contains(':') && *v == format!("{name}:latest")))
+    {
+        return Ok(());
+    }
+    match closest_model(name, names) {
+        Some(closest) => bail!("{name} not found, did you mean {closest}?"),
+        None => bail!("{name} not found, no models are installed"),
+    }
+}
+
+/// The installed model closest to the name, preferring the same model with another tag.
+fn closest_model<'a>(name: &str, names: &'a [String]) -> Option<&'a str> {
+    let base = name.split(':').next();
+    names
+        .iter()
+        .min_by_key(|v| (v.split(':').next() != base, strsim::levenshtein(name, v)))
+        .map(|v| v.as_str())
+}
+
+async fn send_message(builder: RequestBuilder, json_format: bool) -> Result<CompletionOutput> {
+    let res = send_request(builder).await?;
     let status = res.status();
     let data = res.json().await?;
     if status != 200 {
@@ -88,51 +662,89 @@ async fn send_message(builder: RequestBuilder) -> Result<String> {
     }
     let output = data["message"]["content"]
         .as_str()
+        .or_else(|| data["response"].as_str())
         .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
-    Ok(output.to_string())
+    let stats = extract_stats(&data);
+    if json_format {
+        check_json(output);
+    }
+    Ok(CompletionOutput {
+        // Missing when the whole prompt was cached
+        input_tokens: data["prompt_eval_count"].as_u64(),
+        output_tokens: stats.map(|v| v.output_tokens),
+        stats,
+        cache_usage: None,
+        ..CompletionOutput::new(output)
+    })
 }
 
-async fn send_message_streaming(builder: RequestBuilder, handler: &mut ReplyHandler) -> Result<()> {
-    let res = builder.send().await?;
+async fn send_message_streaming(
+    builder: RequestBuilder,
+    handler: &mut ReplyHandler,
+    json_format: bool,
+) -> Result<()> {
+    let res = send_request(builder).await?;
     let status = res.status();
     if status != 200 {
         let data = res.json().await?;
         catch_error(&data, status.as_u16())?;
     } else {
-        let mut stream = res.bytes_stream();
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk?;
-            if chunk.is_empty() {
-                continue;
-            }
-            let data: Value = serde_json::from_slice(&chunk)?;
+        let handle = |line: &str| -> Result<()> {
+            let data: Value = serde_json::from_str(line)?;
             if data["done"].is_boolean() {
-                if let Some(text) = data["message"]["content"].as_str() {
+                // `/api/generate` sends `response` in place of `message`
+                if let Some(text) = data["message"]["content"]
+                    .as_str()
+                    .or_else(|| data["response"].as_str())
+                {
                     handler.text(text)?;
                 }
+                if let Some(stats) = extract_stats(&data) {
+                    handler.set_usage(
+                        data["prompt_eval_count"].as_u64(),
+                        Some(stats.output_tokens),
+                    );
+                    handler.set_stats(stats);
+                }
             } else {
                 bail!("Invalid response data: {data}")
             }
+            Ok(())
+        };
+        ndjson_stream(res.bytes_stream(), handle).await?;
+        if json_format {
+            check_json(handler.get_buffer());
         }
     }
     Ok(())
 }
 
-fn build_body(data: SendData, model: &Model) -> Result<Value> {
+/// Warn if the output of the JSON format mode isn't JSON, as some models add prose after it.
+fn check_json(output: &str) {
+    if let Err(err) = serde_json::from_str::<Value>(output) {
+        warn!("The output isn't valid JSON in the JSON format mode: {err}");
+    }
+}
+
+fn build_body(data: SendData, model: &Model, keep_alive: Option<Value>) -> Result<Value> {
     let SendData {
         messages,
         temperature,
         top_p,
+        functions: _,
         stream,
     } = data;
 
+    let completion = model.completion.as_ref();
+    let prompt = completion.map(|v| v.build_prompt(&messages));
+
     let mut network_image_urls = vec![];
     let messages: Vec<Value> = messages
         .into_iter()
         .map(|message| {
             let role = message.role;
             match message.content {
-                MessageContent::Text(text) => json!({
+                MessageContent::Text(text) | MessageContent::ToolResults((_, text)) => json!({
                     "role": role,
                     "content": text,
                 }),
@@ -172,12 +784,36 @@ fn build_body(data: SendData, model: &Model) -> Result<Value> {
         );
     }
 
-    let mut body = json!({
-        "model": &model.name,
-        "messages": messages,
-        "stream": stream,
-        "options": {},
-    });
+    let mut body = match (completion, prompt) {
+        // A raw prompt for `/api/generate`, with the images of all messages
+        (Some(completion), Some(prompt)) => {
+            let images: Vec<Value> = messages
+                .iter()
+                .flat_map(|v| v["images"].as_array().cloned().unwrap_or_default())
+                .collect();
+            let mut body = json!({
+                "model": &model.name,
+                "prompt": prompt,
+                "raw": true,
+                "stream": stream,
+                "options": {},
+            });
+            if !images.is_empty() {
+                body["images"] = images.into();
+            }
+            let stop = completion.stop();
+            if !stop.is_empty() {
+                body["options"]["stop"] = stop.into();
+            }
+            body
+        }
+        _ => json!({
+            "model": &model.name,
+            "messages": messages,
+            "stream": stream,
+            "options": {},
+        }),
+    };
 
     if let Some(num_predict) = model.max_output_tokens {
         body["options"]["num_predict"] = num_predict.into();
@@ -190,13 +826,550 @@ fn build_body(data: SendData, model: &Model) -> Result<Value> {
         body["options"]["top_p"] = top_p.into();
     }
 
+    if let Some(keep_alive) = keep_alive {
+        body["keep_alive"] = keep_alive;
+    }
+
+    if let Some(format) = &model.format {
+        body["format"] = format.clone();
+    }
+
     Ok(body)
 }
 
+/// The statistics of the final message, its durations being in nanoseconds.
+fn extract_stats(data: &Value) -> Option<GenerationStats> {
+    if data["done"] != true {
+        return None;
+    }
+    let seconds = |key: &str| data[key].as_u64().unwrap_or_default() as f64 / 1e9;
+    Some(GenerationStats {
+        prompt_tokens: data["prompt_eval_count"].as_u64().unwrap_or_default(),
+        output_tokens: data["eval_count"].as_u64()?,
+        load_duration: seconds("load_duration"),
+        prompt_duration: seconds("prompt_eval_duration"),
+        output_duration: seconds("eval_duration"),
+    })
+}
+
+/// Size the context window after `max_input_tokens`, unless the extra fields set `num_ctx`.
+///
+/// Ollama runs with a 2048 tokens context by default and silently truncates longer prompts.
+fn set_num_ctx(body: &mut Value, model: &Model) {
+    if body["options"]["num_ctx"].is_null() {
+        if let Some(max_input_tokens) = model.max_input_tokens {
+            body["options"]["num_ctx"] = max_input_tokens.into();
+        }
+    }
+    if let Some(num_ctx) = body["options"]["num_ctx"].as_u64() {
+        debug!("Ollama num_ctx: {num_ctx}");
+    }
+}
+
 fn catch_error(data: &Value, status: u16) -> Result<()> {
     debug!("Invalid response, status: {status}, data: {data}");
     if let Some(error) = data["error"].as_str() {
+        if status == 404 {
+            return Err(NotFound(error.to_string()).into());
+        }
         bail!("{error}");
     }
     bail!("Invalid response, status: {status}, data: {data}");
 }
+
+/// A request for a model that isn't installed.
+#[derive(Debug)]
+struct NotFound(String);
+
+impl fmt::Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{fixture, CompletionConfig};
+    use crate::config::Config;
+    use parking_lot::RwLock;
+    use std::sync::Arc;
+
+    fn new_client(model: &str) -> Result<OllamaClient> {
+        let mut client = OllamaClient::for_test("api_base: http://localhost:11434")?;
+        client.model = Model::new(OllamaClient::name(&client.config), model);
+        Ok(client)
+    }
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        fixture::use_temp_config_dir();
+        let client = new_client("llama3")?;
+        let http = ReqwestClient::new();
+        let request = |stream| client.request_builder(&http, fixture::send_data(stream));
+
+        let output = send_message(
+            fixture::replay("ollama/success", request(false)?).await?,
+            false,
+        )
+        .await?;
+        assert_eq!(output.text, "Hello! How can I help you today?");
+        assert_eq!(
+            (output.input_tokens, output.output_tokens),
+            (Some(11), Some(10))
+        );
+        let stats = output.stats.unwrap();
+        assert_eq!(
+            stats.to_string(),
+            "load 0.00s, prompt 11 tokens in 0.08s, output 10 tokens in 0.40s (24.9 tokens/s)"
+        );
+
+        let (mut handler, _rx) = fixture::handler();
+        send_message_streaming(
+            fixture::replay("ollama/stream", request(true)?).await?,
+            &mut handler,
+            false,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "Hello! How can I help you today?");
+        assert_eq!(handler.get_usage(), (Some(11), Some(10)));
+        assert_eq!(handler.get_stats(), Some(stats));
+
+        let err = send_message(
+            fixture::replay("ollama/auth_failure", request(false)?).await?,
+            false,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "unauthorized");
+
+        let (mut handler, _rx) = fixture::handler();
+        let err = send_message_streaming(
+            fixture::replay("ollama/rate_limit", request(true)?).await?,
+            &mut handler,
+            false,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "server busy, please try again.  maximum pending requests exceeded"
+        );
+
+        let (mut handler, _rx) = fixture::handler();
+        let err = send_message_streaming(
+            fixture::replay("ollama/stream_error", request(true)?).await?,
+            &mut handler,
+            false,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("an unknown error was encountered"));
+        assert_eq!(handler.get_buffer(), "Hello!");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream_chunking() -> Result<()> {
+        fixture::use_temp_config_dir();
+        let client = new_client("llama3")?;
+        let builder = client.request_builder(&ReqwestClient::new(), fixture::send_data(true))?;
+        let (mut handler, _rx) = fixture::handler();
+        send_message_streaming(
+            fixture::replay("ollama/stream_split", builder).await?,
+            &mut handler,
+            false,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "你好! How can I help you today?");
+
+        // Every way of cutting the stream in three, the last line without a newline
+        let input = [
+            r#"{"message":{"role":"assistant","content":"你好!"},"done":false}"#,
+            r#"{"message":{"role":"assistant","content":" How can I help"},"done":false}"#,
+            r#"{"message":{"role":"assistant","content":""},"done":true}"#,
+        ]
+        .join("\n");
+        let input = input.as_bytes();
+        for i in 0..=input.len() {
+            for j in i..=input.len() {
+                let chunks = [&input[..i], &input[i..j], &input[j..]]
+                    .map(|v| Ok::<_, reqwest::Error>(bytes::Bytes::copy_from_slice(v)));
+                let mut output = String::new();
+                ndjson_stream(futures_util::stream::iter(chunks), |line| {
+                    let data: Value = serde_json::from_str(line)?;
+                    output.push_str(data["message"]["content"].as_str().unwrap_or_default());
+                    Ok(())
+                })
+                .await?;
+                assert_eq!(output, "你好! How can I help");
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_authorization() -> Result<()> {
+        assert_eq!(authorization("Basic dTpw", None)?, "Basic dTpw");
+        assert_eq!(authorization("u:p", None)?, "Basic dTpw");
+        assert_eq!(authorization("sk-xxx", None)?, "Bearer sk-xxx");
+        assert_eq!(authorization("u:p", Some("bearer"))?, "Bearer u:p");
+        assert_eq!(authorization("sk-xxx", Some("raw"))?, "sk-xxx");
+        assert!(authorization("sk-xxx", Some("digest")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_installed() {
+        let names = vec!["llama3:latest".to_string(), "llama2:13b".to_string()];
+        assert!(check_installed("llama3", &names).is_ok());
+        assert!(check_installed("llama2:13b", &names).is_ok());
+        assert_eq!(
+            check_installed("llama3:8b", &names)
+                .unwrap_err()
+                .to_string(),
+            "llama3:8b not found, did you mean llama3:latest?"
+        );
+        assert_eq!(
+            check_installed("lama2:13b", &names)
+                .unwrap_err()
+                .to_string(),
+            "lama2:13b not found, did you mean llama2:13b?"
+        );
+        assert_eq!(
+            check_installed("llama3", &[]).unwrap_err().to_string(),
+            "llama3 not found, no models are installed"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_generate() -> Result<()> {
+        fixture::use_temp_config_dir();
+        let model = Model::new("ollama", "llama3-base").set_completion(Some(CompletionConfig {
+            prompt_template: Some("### Input:\n{input}\n\n### Response:\n".into()),
+            stop: Some(vec!["###".into()]),
+        }));
+        let mut data = fixture::send_data(true);
+        data.messages[0].content = MessageContent::Array(vec![
+            MessageContentPart::Text {
+                text: "Describe it".into(),
+            },
+            MessageContentPart::ImageUrl {
+                image_url: ImageUrl {
+                    url: "data:image/png;base64,iVBORw0KGgo=".into(),
+                },
+            },
+        ]);
+        let body = build_body(data.clone(), &model, None)?;
+        assert_eq!(
+            body,
+            json!({
+                "model": "llama3-base",
+                "prompt": "### Input:\nDescribe it\n\n### Response:\n",
+                "raw": true,
+                "stream": true,
+                "options": { "stop": ["###"] },
+                "images": ["iVBORw0KGgo="],
+            })
+        );
+
+        let mut client = new_client("")?;
+        client.model = model;
+        let builder = client.request_builder(&ReqwestClient::new(), data)?;
+        let (mut handler, _rx) = fixture::handler();
+        send_message_streaming(
+            fixture::replay("ollama/generate_stream", builder).await?,
+            &mut handler,
+            false,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "A red square on a white background.");
+        assert_eq!(handler.get_usage(), (Some(14), Some(9)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_body_format() -> Result<()> {
+        let schema = json!({
+            "type": "object",
+            "properties": { "name": { "type": "string" } },
+        });
+        let model = Model::new("ollama", "llama3").set_format(Some(schema.clone()));
+        let body = build_body(fixture::send_data(false), &model, None)?;
+        assert_eq!(body["format"], schema);
+        let model = model.set_format(Some("json".into()));
+        let body = build_body(fixture::send_data(false), &model, None)?;
+        assert_eq!(body["format"], "json");
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_body_keep_alive() -> Result<()> {
+        let model = Model::new("ollama", "llama3");
+        let body = build_body(fixture::send_data(true), &model, Some("30m".into()))?;
+        assert_eq!(body["keep_alive"], "30m");
+        let body = build_body(fixture::send_data(false), &model, Some((-1).into()))?;
+        assert_eq!(body["keep_alive"], -1);
+        let body = build_body(fixture::send_data(false), &model, None)?;
+        assert!(body.get("keep_alive").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_num_ctx() -> Result<()> {
+        let model = Model::new("ollama", "llama3").set_max_input_tokens(Some(32768));
+        let mut body = build_body(fixture::send_data(false), &model, None)?;
+        set_num_ctx(&mut body, &model);
+        assert_eq!(body["options"]["num_ctx"], 32768);
+
+        let mut extra_fields = serde_json::Map::new();
+        extra_fields.insert("options".into(), json!({ "num_ctx": 8192 }));
+        let model = model.set_extra_fields(Some(extra_fields));
+        let mut body = build_body(fixture::send_data(false), &model, None)?;
+        model.merge_extra_fields(&mut body);
+        set_num_ctx(&mut body, &model);
+        assert_eq!(body["options"]["num_ctx"], 8192);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_embeddings() -> Result<()> {
+        let build = |api_base: String| {
+            let config = OllamaConfig {
+                api_base,
+                ..Default::default()
+            };
+            OllamaClient {
+                global_config: Arc::new(RwLock::new(Config::default())),
+                model: Model::new(OllamaClient::name(&config), "nomic-embed-text"),
+                config,
+            }
+        };
+        let http = ReqwestClient::new();
+        let texts = vec!["Hello".to_string(), "How are you?".to_string()];
+
+        let client = build(fixture::serve(&["ollama/embed"]).await?);
+        let output = client.embeddings_inner(&http, texts.clone()).await?;
+        assert_eq!(output.vectors.len(), 2);
+        assert_eq!(output.vectors[1], [-0.0412, 0.0098, 0.0265, -0.0133]);
+        assert_eq!(output.input_tokens, Some(6));
+
+        // Older servers only have the single text API
+        let client = build(
+            fixture::serve(&[
+                "ollama/embed_unsupported",
+                "ollama/embeddings_a",
+                "ollama/embeddings_b",
+            ])
+            .await?,
+        );
+        let output = client.embeddings_inner(&http, texts).await?;
+        assert_eq!(output.vectors[0], [0.0183, -0.0527, 0.0346, 0.0071]);
+        assert_eq!(output.vectors[1], [-0.0412, 0.0098, 0.0265, -0.0133]);
+        assert_eq!(output.input_tokens, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_auto_pull() -> Result<()> {
+        fixture::use_temp_config_dir();
+        let config = OllamaConfig {
+            api_base: fixture::serve(&["ollama/pull_required", "ollama/pull", "ollama/success"])
+                .await?,
+            auto_pull: Some(true),
+            ..Default::default()
+        };
+        let client = OllamaClient {
+            global_config: Arc::new(RwLock::new(Config::default())),
+            model: Model::new(OllamaClient::name(&config), "llama3"),
+            config,
+        };
+        let output = client
+            .send_message_inner(&ReqwestClient::new(), fixture::send_data(false))
+            .await?;
+        assert_eq!(output.text, "Hello! How can I help you today?");
+
+        let mut statuses = vec![];
+        let http = ReqwestClient::new();
+        let builder = new_client("llama3")?.pull_request_builder(&http)?;
+        pull(
+            fixture::replay("ollama/pull", builder).await?,
+            |status, progress| statuses.push((status.to_string(), progress)),
+        )
+        .await?;
+        assert_eq!(
+            statuses[2],
+            ("pulling 6a0746a1ec1a".into(), Some((2097152, 4661211808)))
+        );
+        assert_eq!(statuses.last().unwrap().0, "success");
+
+        let builder = new_client("llama3:70")?.pull_request_builder(&http)?;
+        let err = pull(
+            fixture::replay("ollama/pull_error", builder).await?,
+            |_, _| {},
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to pull the model: pull model manifest: file does not exist"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry() -> Result<()> {
+        let new_client = |api_base, max_retries| {
+            let config = OllamaConfig {
+                api_base,
+                extra: Some(ExtraConfig {
+                    max_retries: Some(max_retries),
+                    max_retry_delay: Some(0),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            OllamaClient {
+                global_config: Arc::new(RwLock::new(Config::default())),
+                model: Model::new(OllamaClient::name(&config), "llama3"),
+                config,
+            }
+        };
+        let api_base = fixture::serve(&["ollama/rate_limit", "ollama/success"]).await?;
+        let output = new_client(api_base, 2)
+            .send_data(fixture::send_data(false))
+            .await?;
+        assert_eq!(output.text, "Hello! How can I help you today?");
+
+        let api_base = fixture::serve(&["ollama/rate_limit", "ollama/success"]).await?;
+        let err = new_client(api_base, 0)
+            .send_data(fixture::send_data(false))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "server busy, please try again.  maximum pending requests exceeded"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_timeout() -> Result<()> {
+        // A server that accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            let mut streams = vec![];
+            while let Ok((stream, _)) = listener.accept().await {
+                streams.push(stream);
+            }
+        });
+        let config = OllamaConfig {
+            api_base: format!("http://{addr}"),
+            extra: Some(ExtraConfig {
+                timeout: Some(1),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let client = OllamaClient {
+            global_config: Arc::new(RwLock::new(Config::default())),
+            model: Model::new(OllamaClient::name(&config), "llama3"),
+            config,
+        };
+        let err = client
+            .send_data(fixture::send_data(false))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.chain().nth(1).map(|v| v.to_string()).as_deref(),
+            Some("The client 'ollama' didn't answer within 1s (timeout)")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_detect_context_lengths() -> Result<()> {
+        fixture::use_temp_config_dir();
+        let builder = new_client("")?.tags_request_builder(&ReqwestClient::new())?;
+        let tags = fetch_tags(fixture::replay("ollama/tags", builder).await?).await?;
+        let model = |name: &str, max_input_tokens| ModelConfig {
+            max_input_tokens,
+            ..serde_json::from_value(json!({ "name": name })).unwrap()
+        };
+        let config = OllamaConfig {
+            name: Some("ollama-show".into()),
+            api_base: fixture::serve(&["ollama/show", "ollama/show_without_info"]).await?,
+            models: vec![
+                model("llama3", None),
+                model("nomic-embed-text", None),
+                model("mistral", None),
+                model("llama3:latest", Some(4096)),
+            ],
+            ..Default::default()
+        };
+        let client = OllamaClient {
+            global_config: Arc::new(RwLock::new(Config::default())),
+            model: Model::new(OllamaClient::name(&config), ""),
+            config,
+        };
+        let mut cache = HashMap::new();
+        client
+            .detect_context_lengths(&ReqwestClient::new(), &tags, &mut cache)
+            .await;
+        assert_eq!(
+            cache,
+            HashMap::from([(tags[0]["digest"].as_str().unwrap().to_string(), 8192)])
+        );
+
+        cached_tags().insert("ollama-show".into(), tags);
+        cached_context_lengths().extend(cache);
+        let models = OllamaClient::list_models(&client.config);
+        let max_input_tokens: Vec<_> = models.iter().map(|v| v.max_input_tokens).collect();
+        assert_eq!(max_input_tokens, [Some(8192), None, None, Some(4096)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_model_not_found() -> Result<()> {
+        fixture::use_temp_config_dir();
+        let builder = new_client("")?.tags_request_builder(&ReqwestClient::new())?;
+        let names = list_tags(fixture::replay("ollama/tags", builder).await?).await?;
+        assert_eq!(names, ["llama3:latest", "nomic-embed-text:latest"]);
+
+        let config = OllamaConfig {
+            api_base: fixture::serve(&["ollama/model_not_found", "ollama/tags"]).await?,
+            ..Default::default()
+        };
+        let client = OllamaClient {
+            global_config: Arc::new(RwLock::new(Config::default())),
+            model: Model::new(OllamaClient::name(&config), "llama3:8b"),
+            config,
+        };
+        let err = client
+            .send_message_inner(&ReqwestClient::new(), fixture::send_data(false))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "llama3:8b not found, did you mean llama3:latest?"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn record_fixtures() -> Result<()> {
+        if !fixture::recording() {
+            return Ok(());
+        }
+        let client = OllamaClient::for_recording()?;
+        let http = client.build_client()?;
+        let builder = client.request_builder(&http, fixture::send_data(false))?;
+        fixture::record("ollama/success", builder).await?;
+        let builder = client.request_builder(&http, fixture::send_data(true))?;
+        fixture::record("ollama/stream", builder).await?;
+        Ok(())
+    }
+}
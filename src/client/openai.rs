@@ -1,17 +1,26 @@
-use super::{ExtraConfig, Model, ModelConfig, OpenAIClient, PromptType, ReplyHandler, SendData};
+use super::{
+    event_source, note_response, send_request, CompletionOutput, ExtraConfig, Model, ModelConfig,
+    OpenAIClient, PromptType, ReplyHandler, SendData,
+};
 
 use crate::utils::PromptKind;
 
 use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
 use futures_util::StreamExt;
-use reqwest::{Client as ReqwestClient, RequestBuilder};
-use reqwest_eventsource::{Error as EventSourceError, Event, RequestBuilderExt};
+use reqwest::{
+    header::{CONTENT_LENGTH, CONTENT_TYPE, RETRY_AFTER},
+    Client as ReqwestClient, RequestBuilder, Response,
+};
+use reqwest_eventsource::{Error as EventSourceError, Event};
 use serde::Deserialize;
 use serde_json::{json, Value};
 
 const API_BASE: &str = "https://api.openai.com/v1";
 
+// Base models stop after 16 tokens by default
+const DEFAULT_COMPLETION_MAX_TOKENS: isize = 1024;
+
 const MODELS: [(&str, usize, &str); 8] = [
     // https://platform.openai.com/docs/models
     ("gpt-3.5-turbo", 16385, "text"),
@@ -49,9 +58,16 @@ impl OpenAIClient {
         let api_key = self.get_api_key()?;
         let api_base = self.get_api_base().unwrap_or_else(|_| API_BASE.to_string());
 
-        let body = openai_build_body(data, &self.model);
-
-        let url = format!("{api_base}/chat/completions");
+        let (body, url) = match &self.model.completion {
+            Some(_) => (
+                openai_build_completion_body(data, &self.model, false),
+                format!("{api_base}/completions"),
+            ),
+            None => (
+                openai_build_body(data, &self.model),
+                format!("{api_base}/chat/completions"),
+            ),
+        };
 
         debug!("OpenAI Request: {url} {body}");
 
@@ -65,26 +81,65 @@ impl OpenAIClient {
     }
 }
 
-pub async fn openai_send_message(builder: RequestBuilder) -> Result<String> {
-    let res = builder.send().await?;
+/// The `/models` listing beside the completion endpoint of `builder`, with the same headers.
+pub fn openai_models_request(builder: RequestBuilder) -> Result<Option<RequestBuilder>> {
+    let (client, request) = builder.build_split();
+    let request = request?;
+    let mut url = request.url().clone();
+    let path = url.path();
+    let base = match path
+        .strip_suffix("/chat/completions")
+        .or_else(|| path.strip_suffix("/completions"))
+    {
+        Some(base) => base.to_string(),
+        None => return Ok(None),
+    };
+    url.set_path(&format!("{base}/models"));
+    let mut headers = request.headers().clone();
+    headers.remove(CONTENT_TYPE);
+    headers.remove(CONTENT_LENGTH);
+    Ok(Some(client.get(url).headers(headers)))
+}
+
+pub async fn openai_send_message(builder: RequestBuilder) -> Result<CompletionOutput> {
+    let res = send_request(builder).await?;
     let status = res.status();
-    let data: Value = res.json().await?;
+    let retry_after = retry_after(&res);
+    let text = res.text().await?;
     if status != 200 {
-        catch_error(&data, status.as_u16())?;
+        catch_error(&text, status.as_u16(), retry_after.as_deref())?;
     }
+    let data: Value =
+        serde_json::from_str(&text).map_err(|_| anyhow!("Invalid response data: {text}"))?;
 
-    let output = data["choices"][0]["message"]["content"]
+    let text = data["choices"][0]["message"]["content"]
         .as_str()
+        .or_else(|| data["choices"][0]["text"].as_str())
+        .or_else(|| data["content"].as_str())
         .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
 
-    Ok(output.to_string())
+    Ok(CompletionOutput {
+        text: text.to_string(),
+        input_tokens: data["usage"]["prompt_tokens"]
+            .as_u64()
+            .or_else(|| data["tokens_evaluated"].as_u64()),
+        output_tokens: data["usage"]["completion_tokens"]
+            .as_u64()
+            .or_else(|| data["tokens_predicted"].as_u64()),
+        tool_calls: vec![],
+        request_id: None,
+        stop_sequence: None,
+        stop_reason: None,
+        stats: None,
+        cache_usage: None,
+    })
 }
 
 pub async fn openai_send_message_streaming(
     builder: RequestBuilder,
     handler: &mut ReplyHandler,
 ) -> Result<()> {
-    let mut es = builder.eventsource()?;
+    let mut es = event_source(builder)?;
     while let Some(event) = es.next().await {
         match event {
             Ok(Event::Open) => {}
@@ -93,21 +148,44 @@ pub async fn openai_send_message_streaming(
                     break;
                 }
                 let data: Value = serde_json::from_str(&message.data)?;
-                if let Some(text) = data["choices"][0]["delta"]["content"].as_str() {
+                // Errors after the stream starts come as a regular event
+                if data["error"].is_object() {
+                    es.close();
+                    catch_error(&message.data, 200, None)?;
+                }
+                // llama.cpp `/completion` sends a bare `content`, which Yi repeats beside `choices`
+                let text = match data["choices"].is_array() {
+                    true => data["choices"][0]["delta"]["content"]
+                        .as_str()
+                        .or_else(|| data["choices"][0]["text"].as_str()),
+                    false => data["content"].as_str(),
+                };
+                if let Some(text) = text {
                     handler.text(text)?;
                 }
+                // The last chunk carries the usage, which Groq nests in `x_groq`
+                let usage = match &data["usage"] {
+                    usage if usage.is_object() => usage,
+                    _ => &data["x_groq"]["usage"],
+                };
+                if usage.is_object() {
+                    handler.set_usage(
+                        usage["prompt_tokens"].as_u64(),
+                        usage["completion_tokens"].as_u64(),
+                    );
+                }
+                // llama.cpp `/completion` ends the stream with `"stop": true`
+                if data["stop"].as_bool() == Some(true) {
+                    break;
+                }
             }
             Err(err) => {
                 match err {
                     EventSourceError::InvalidStatusCode(status, res) => {
+                        note_response(&res);
+                        let retry_after = retry_after(&res);
                         let text = res.text().await?;
-                        let data: Value = match text.parse() {
-                            Ok(data) => data,
-                            Err(_) => {
-                                bail!("Invalid respoinse, status: {status}, text: {text}");
-                            }
-                        };
-                        catch_error(&data, status.as_u16())?;
+                        catch_error(&text, status.as_u16(), retry_after.as_deref())?;
                     }
                     EventSourceError::StreamEnded => {}
                     EventSourceError::InvalidContentType(_, res) => {
@@ -131,6 +209,7 @@ pub fn openai_build_body(data: SendData, model: &Model) -> Value {
         messages,
         temperature,
         top_p,
+        functions: _,
         stream,
     } = data;
 
@@ -157,14 +236,171 @@ pub fn openai_build_body(data: SendData, model: &Model) -> Value {
     body
 }
 
-fn catch_error(data: &Value, status: u16) -> Result<()> {
-    debug!("Invalid response, status: {status}, data: {data}");
-    if let Some(error) = data["error"].as_object() {
-        if let (Some(type_), Some(message)) = (error["type"].as_str(), error["message"].as_str()) {
-            bail!("{message} (type: {type_})");
-        }
+/// Build the body of the legacy text-completions API.
+///
+/// `llama_cpp` targets the `/completion` endpoint of llama.cpp server, which names `max_tokens` as `n_predict`.
+pub fn openai_build_completion_body(data: SendData, model: &Model, llama_cpp: bool) -> Value {
+    let SendData {
+        messages,
+        temperature,
+        top_p,
+        functions: _,
+        stream,
+    } = data;
+
+    let completion = model.completion.clone().unwrap_or_default();
+    let mut body = json!({
+        "model": &model.name,
+        "prompt": completion.build_prompt(&messages),
+    });
+
+    let max_tokens = model
+        .max_output_tokens
+        .unwrap_or(DEFAULT_COMPLETION_MAX_TOKENS);
+    if llama_cpp {
+        body["n_predict"] = max_tokens.into();
+    } else {
+        body["max_tokens"] = max_tokens.into();
+    }
+    let stop = completion.stop();
+    if !stop.is_empty() {
+        body["stop"] = stop.into();
+    }
+    if let Some(temperature) = temperature {
+        body["temperature"] = temperature.into();
+    }
+    if let Some(top_p) = top_p {
+        body["top_p"] = top_p.into();
+    }
+    if stream {
+        body["stream"] = true.into();
+    }
+    body
+}
+
+fn retry_after(res: &Response) -> Option<String> {
+    res.headers()
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
+/// Fail with the error in `text`, in any of the shapes the OpenAI-compatible servers use:
+/// `{"error":{"message":..}}`, SiliconFlow's `{"code":..,"message":..}`, DeepInfra's
+/// `{"detail":{"error":..}}`, a bare `{"error":".."}` or `{"detail":".."}`, or plain text.
+fn catch_error(text: &str, status: u16, retry_after: Option<&str>) -> Result<()> {
+    debug!("Invalid response, status: {status}, text: {text}");
+    let data: Value = match serde_json::from_str(text) {
+        Ok(data) => data,
+        Err(_) => match text.trim() {
+            "" => bail!("Invalid response, status: {status}"),
+            text => bail!("{text} (status: {status})"),
+        },
+    };
+    let error = &data["error"];
+    let (message, error) = if let Some(message) = error["message"].as_str() {
+        (message, error)
     } else if let Some(message) = data["message"].as_str() {
-        bail!("{message}");
+        (message, &data)
+    } else if let Some(message) = error
+        .as_str()
+        .or_else(|| data["detail"]["error"].as_str())
+        .or_else(|| data["detail"].as_str())
+    {
+        (message, &Value::Null)
+    } else {
+        bail!("Invalid response, status: {status}, data: {data}");
+    };
+
+    let mut details = vec![];
+    if let Some(type_) = error["type"].as_str() {
+        details.push(format!("type: {type_}"));
+    }
+    match &error["code"] {
+        Value::String(code) => details.push(format!("code: {code}")),
+        Value::Number(code) => details.push(format!("code: {code}")),
+        _ => {}
+    }
+    // Free tiers are often limited per minute and per day
+    if let (429, Some(retry_after)) = (status, retry_after) {
+        details.push(format!("retry after {retry_after}s"));
+    }
+    let message = match details.is_empty() {
+        true => message.to_string(),
+        false => format!("{message} ({})", details.join(", ")),
+    };
+    if error["code"].as_str() == Some("context_length_exceeded") {
+        bail!("{message}. Reduce your input, e.g. clear the session with `.clear messages`, or lower `max_output_tokens`");
+    }
+    bail!("{message}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catch_error() {
+        let err = |text: &str, status: u16, retry_after: Option<&str>| {
+            catch_error(text, status, retry_after)
+                .unwrap_err()
+                .to_string()
+        };
+        let data = r#"{"error":{"code":"RateLimitReached","message":"Rate limit of 15 per 60s exceeded."}}"#;
+        assert_eq!(
+            err(data, 429, Some("42")),
+            "Rate limit of 15 per 60s exceeded. (code: RateLimitReached, retry after 42s)"
+        );
+        assert_eq!(
+            err(data, 429, None),
+            "Rate limit of 15 per 60s exceeded. (code: RateLimitReached)"
+        );
+        assert_eq!(
+            err(
+                r#"{"error":{"message":"Service Unavailable","type":"internal_server_error"}}"#,
+                503,
+                None
+            ),
+            "Service Unavailable (type: internal_server_error)"
+        );
+        assert_eq!(
+            err(r#"{"code":20015,"message":"Invalid token"}"#, 401, None),
+            "Invalid token (code: 20015)"
+        );
+        assert_eq!(
+            err(
+                r#"{"detail":{"error":"Authentication required"}}"#,
+                401,
+                None
+            ),
+            "Authentication required"
+        );
+        assert_eq!(err("Unauthorized", 401, None), "Unauthorized (status: 401)");
+        assert_eq!(err("", 502, None), "Invalid response, status: 502");
+        assert_eq!(
+            err("{}", 500, None),
+            "Invalid response, status: 500, data: {}"
+        );
+    }
+    #[test]
+    fn test_openai_models_request() {
+        let client = ReqwestClient::new();
+        let listing = |url: &str| {
+            let builder = client.post(url).bearer_auth("sk-1234").json(&json!({}));
+            let request = openai_models_request(builder).unwrap()?.build().unwrap();
+            assert_eq!(request.method(), "GET");
+            assert_eq!(request.headers()["authorization"], "Bearer sk-1234");
+            assert!(request.headers().get(CONTENT_TYPE).is_none());
+            Some(request.url().to_string())
+        };
+        assert_eq!(
+            listing("https://api.openai.com/v1/chat/completions").as_deref(),
+            Some("https://api.openai.com/v1/models")
+        );
+        assert_eq!(
+            listing("http://localhost:8080/v1/completions?version=1").as_deref(),
+            Some("http://localhost:8080/v1/models?version=1")
+        );
+        assert_eq!(listing("https://example.com/v1/generate"), None);
     }
-    bail!("Invalid response, status: {status}, data: {data}");
 }
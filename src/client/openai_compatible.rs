@@ -1,4 +1,4 @@
-use super::openai::openai_build_body;
+use super::openai::{openai_build_body, openai_build_completion_body};
 use super::{ExtraConfig, Model, ModelConfig, OpenAICompatibleClient, PromptType, SendData};
 
 use crate::utils::PromptKind;
@@ -14,6 +14,7 @@ pub struct OpenAICompatibleConfig {
     pub api_base: String,
     pub api_key: Option<String>,
     pub chat_endpoint: Option<String>,
+    pub completion_endpoint: Option<String>,
     pub models: Vec<ModelConfig>,
     pub extra: Option<ExtraConfig>,
 }
@@ -40,16 +41,29 @@ impl OpenAICompatibleClient {
     fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
         let api_key = self.get_api_key().ok();
 
-        let mut body = openai_build_body(data, &self.model);
+        let (mut body, endpoint) = match &self.model.completion {
+            Some(_) => {
+                let endpoint = self
+                    .config
+                    .completion_endpoint
+                    .as_deref()
+                    .unwrap_or("/completions");
+                let llama_cpp = endpoint.ends_with("/completion");
+                let body = openai_build_completion_body(data, &self.model, llama_cpp);
+                (body, endpoint)
+            }
+            None => {
+                let endpoint = self
+                    .config
+                    .chat_endpoint
+                    .as_deref()
+                    .unwrap_or("/chat/completions");
+                (openai_build_body(data, &self.model), endpoint)
+            }
+        };
         self.model.merge_extra_fields(&mut body);
 
-        let chat_endpoint = self
-            .config
-            .chat_endpoint
-            .as_deref()
-            .unwrap_or("/chat/completions");
-
-        let url = format!("{}{chat_endpoint}", self.config.api_base);
+        let url = format!("{}{endpoint}", self.config.api_base);
 
         debug!("OpenAICompatible Request: {url} {body}");
 
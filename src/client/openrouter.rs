@@ -0,0 +1,212 @@
+use super::openai::openai_build_body;
+use super::{
+    custom_headers, read_cache, refresh_cache, send_request, with_headers, Client, ClientConfig, ExtraConfig, Model, ModelConfig,
+    OpenRouterClient, PromptType, SendData,
+};
+
+use crate::config::GlobalConfig;
+use crate::utils::PromptKind;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use serde::Deserialize;
+use serde_json::Value;
+use std::time::Duration;
+
+const API_BASE: &str = "https://openrouter.ai/api/v1";
+
+const DEFAULT_HTTP_REFERER: &str = "https://github.com/sigoden/aichat";
+const DEFAULT_X_TITLE: &str = "AIChat";
+
+const MODELS_CACHE_FILE: &str = "openrouter_models.json";
+const MODELS_CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const MODELS_FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Used until the `/models` listing has been fetched once
+const MODELS: [(&str, usize, &str); 4] = [
+    // https://openrouter.ai/models
+    ("openai/gpt-4o", 128000, "text,vision"),
+    ("anthropic/claude-3-opus", 200000, "text,vision"),
+    ("meta-llama/llama-3-70b-instruct", 8192, "text"),
+    ("mistralai/mixtral-8x22b-instruct", 65536, "text"),
+];
+
+lazy_static! {
+    static ref REMOTE_MODELS: Mutex<Option<Value>> = Mutex::new(None);
+}
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct OpenRouterConfig {
+    pub name: Option<String>,
+    pub api_key: Option<String>,
+    pub http_referer: Option<String>,
+    pub x_title: Option<String>,
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+openai_compatible_client!(OpenRouterClient);
+
+impl OpenRouterClient {
+    config_get_fn!(api_key, get_api_key);
+
+    pub const PROMPTS: [PromptType<'static>; 1] =
+        [("api_key", "API Key:", true, PromptKind::String)];
+
+    /// The declared models, or else the cached `/models` listing of OpenRouter.
+    pub fn list_models(local_config: &OpenRouterConfig) -> Vec<Model> {
+        let client_name = Self::name(local_config);
+        if !local_config.models.is_empty() {
+            return Model::from_config(client_name, &local_config.models);
+        }
+        let mut remote_models = REMOTE_MODELS.lock();
+        if remote_models.is_none() {
+            *remote_models = read_cache(MODELS_CACHE_FILE);
+        }
+        let models = remote_models
+            .as_ref()
+            .map(|data| parse_models(client_name, data))
+            .unwrap_or_default();
+        if models.is_empty() {
+            Model::from_static(client_name, &MODELS)
+        } else {
+            models
+        }
+    }
+
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let api_key = self.get_api_key()?;
+
+        let mut body = openai_build_body(data, &self.model);
+        self.model.merge_extra_fields(&mut body);
+
+        let url = format!("{API_BASE}/chat/completions");
+
+        debug!("OpenRouter Request: {url} {body}");
+
+        let builder = client
+            .post(url)
+            .bearer_auth(api_key)
+            .header(
+                "HTTP-Referer",
+                self.config
+                    .http_referer
+                    .as_deref()
+                    .unwrap_or(DEFAULT_HTTP_REFERER),
+            )
+            .header(
+                "X-Title",
+                self.config.x_title.as_deref().unwrap_or(DEFAULT_X_TITLE),
+            )
+            .json(&body);
+
+        Ok(builder)
+    }
+}
+
+/// Refresh the cached `/models` listing once a day, if an openrouter client doesn't declare models.
+pub async fn sync_openrouter_models(config: &GlobalConfig) -> Result<()> {
+    let Some(local_config) = config.read().clients.iter().find_map(|v| match v {
+        ClientConfig::OpenRouterConfig(c) if c.models.is_empty() => Some(c.clone()),
+        _ => None,
+    }) else {
+        return Ok(());
+    };
+    let headers = custom_headers(&local_config.extra)?;
+    let fetch = async {
+        let model = Model::new(OpenRouterClient::name(&local_config), "");
+        let client = OpenRouterClient {
+            global_config: config.clone(),
+            config: local_config,
+            model,
+        };
+        let builder = client
+            .build_client()?
+            .get(format!("{API_BASE}/models"))
+            .timeout(MODELS_FETCH_TIMEOUT);
+        let res = send_request(builder).await?;
+        let status = res.status();
+        let data: Value = res.json().await?;
+        if status != 200 || !data["data"].is_array() {
+            bail!("Invalid response, status: {status}, data: {data}");
+        }
+        Ok(data)
+    };
+    let fetch = with_headers(headers, fetch);
+    if let Some(data) = refresh_cache(MODELS_CACHE_FILE, MODELS_CACHE_TTL, fetch).await? {
+        *REMOTE_MODELS.lock() = Some(data);
+    }
+    Ok(())
+}
+
+fn parse_models(client_name: &str, data: &Value) -> Vec<Model> {
+    let Some(list) = data["data"].as_array() else {
+        return vec![];
+    };
+    list.iter()
+        .filter_map(|v| {
+            let name = v["id"].as_str()?;
+            // OpenRouter prices are USD per token, ours are per 1M tokens
+            let price = |key: &str| {
+                v["pricing"][key]
+                    .as_str()
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .map(|v| (v * 1e12).round() / 1e6)
+            };
+            let capabilities = match v["architecture"]["modality"].as_str() {
+                Some(modality) if modality.contains("image") => "text,vision",
+                _ => "text",
+            };
+            let model = Model::new(client_name, name)
+                .set_max_input_tokens(v["context_length"].as_u64().map(|v| v as usize))
+                .set_input_price(price("prompt"))
+                .set_output_price(price("completion"))
+                .set_capabilities(capabilities.into());
+            Some(model)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ModelCapabilities;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_models() {
+        let data = json!({
+            "data": [
+                {
+                    "id": "anthropic/claude-3-haiku",
+                    "context_length": 200000,
+                    "architecture": { "modality": "text+image->text" },
+                    "pricing": { "prompt": "0.00000025", "completion": "0.00000125" },
+                },
+                {
+                    "id": "meta-llama/llama-3-8b-instruct:free",
+                    "context_length": 8192,
+                    "architecture": { "modality": "text->text" },
+                    "pricing": { "prompt": "0", "completion": "0" },
+                },
+                { "name": "missing id" },
+            ]
+        });
+        let models = parse_models("openrouter", &data);
+        assert_eq!(models.len(), 2);
+        assert_eq!(models[0].id(), "openrouter:anthropic/claude-3-haiku");
+        assert_eq!(models[0].max_input_tokens, Some(200000));
+        assert_eq!(
+            (models[0].input_price, models[0].output_price),
+            (Some(0.25), Some(1.25))
+        );
+        assert!(models[0].capabilities.contains(ModelCapabilities::Vision));
+        assert_eq!(models[1].name, "meta-llama/llama-3-8b-instruct:free");
+        assert_eq!(models[1].input_price, Some(0.0));
+        assert!(!models[1].capabilities.contains(ModelCapabilities::Vision));
+    }
+}
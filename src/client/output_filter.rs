@@ -0,0 +1,281 @@
+//! Post-process the output of a model.
+//!
+//! `strip` drops the text between two delimiters, e.g. a `<thinking>` block. `replace` rewrites
+//! every line of the output with a regex, the line includes its trailing newline. Both work on a
+//! stream: text that may be the start of a delimiter, and the last incomplete line, are held back
+//! until the next chunk resolves them.
+
+use anyhow::{Context, Result};
+use fancy_regex::Regex;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct OutputFilterConfig {
+    #[serde(default)]
+    pub strip: Vec<StripRule>,
+    #[serde(default)]
+    pub replace: Vec<ReplaceRule>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StripRule {
+    pub start: String,
+    pub end: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReplaceRule {
+    pub pattern: String,
+    #[serde(default)]
+    pub replacement: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputFilter {
+    strip: Vec<StripRule>,
+    replace: Vec<(Regex, String)>,
+    /// The strip rule whose end delimiter is awaited.
+    block: Option<usize>,
+    pending: String,
+    line: String,
+    started: bool,
+    removing: String,
+    removed: Vec<String>,
+}
+
+impl OutputFilter {
+    pub fn new(config: &OutputFilterConfig) -> Result<Self> {
+        let replace = config
+            .replace
+            .iter()
+            .map(|v| {
+                let re = Regex::new(&v.pattern)
+                    .with_context(|| format!("Invalid output filter pattern '{}'", v.pattern))?;
+                Ok((re, v.replacement.clone()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            strip: config
+                .strip
+                .iter()
+                .filter(|v| !v.start.is_empty() && !v.end.is_empty())
+                .cloned()
+                .collect(),
+            replace,
+            block: None,
+            pending: String::new(),
+            line: String::new(),
+            started: false,
+            removing: String::new(),
+            removed: vec![],
+        })
+    }
+
+    /// Filter a whole output.
+    pub fn apply(&mut self, text: &str) -> Result<String> {
+        let mut output = self.push(text)?;
+        output.push_str(&self.finish()?);
+        Ok(output)
+    }
+
+    /// Filter a chunk of a stream, returning the text that can be shown so far.
+    pub fn push(&mut self, text: &str) -> Result<String> {
+        let text = self.strip_blocks(text, false);
+        let text = self.replace_lines(&text, false)?;
+        Ok(self.trim_leading(text))
+    }
+
+    /// Release the text held back at the end of a stream. An unclosed block is dropped.
+    pub fn finish(&mut self) -> Result<String> {
+        let text = self.strip_blocks("", true);
+        let text = self.replace_lines(&text, true)?;
+        if !self.removing.is_empty() {
+            self.removed.push(std::mem::take(&mut self.removing));
+        }
+        Ok(self.trim_leading(text))
+    }
+
+    /// The text removed so far.
+    pub fn take_removed(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.removed)
+    }
+
+    fn strip_blocks(&mut self, text: &str, last: bool) -> String {
+        self.pending.push_str(text);
+        let mut output = String::new();
+        loop {
+            match self.block {
+                None => {
+                    let found = self
+                        .strip
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(i, rule)| self.pending.find(&rule.start).map(|pos| (pos, i)))
+                        .min();
+                    match found {
+                        Some((pos, i)) => {
+                            output.push_str(&self.pending[..pos]);
+                            self.pending.drain(..pos);
+                            let len = self.strip[i].start.len();
+                            self.removing.extend(self.pending.drain(..len));
+                            self.block = Some(i);
+                        }
+                        None => {
+                            let keep = if last {
+                                0
+                            } else {
+                                self.strip
+                                    .iter()
+                                    .map(|rule| partial_suffix(&self.pending, &rule.start))
+                                    .max()
+                                    .unwrap_or_default()
+                            };
+                            let end = self.pending.len() - keep;
+                            output.push_str(&self.pending[..end]);
+                            self.pending.drain(..end);
+                            return output;
+                        }
+                    }
+                }
+                Some(i) => {
+                    let end = &self.strip[i].end;
+                    match self.pending.find(end.as_str()) {
+                        Some(pos) => {
+                            let len = pos + end.len();
+                            self.removing.extend(self.pending.drain(..len));
+                            self.removed.push(std::mem::take(&mut self.removing));
+                            self.block = None;
+                        }
+                        None => {
+                            let keep = if last {
+                                0
+                            } else {
+                                partial_suffix(&self.pending, end)
+                            };
+                            let len = self.pending.len() - keep;
+                            self.removing.extend(self.pending.drain(..len));
+                            if last {
+                                self.block = None;
+                            }
+                            return output;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn replace_lines(&mut self, text: &str, last: bool) -> Result<String> {
+        if self.replace.is_empty() {
+            return Ok(text.to_string());
+        }
+        self.line.push_str(text);
+        let end = match last {
+            true => self.line.len(),
+            false => self.line.rfind('\n').map(|v| v + 1).unwrap_or_default(),
+        };
+        let lines: String = self.line.drain(..end).collect();
+        let mut output = String::new();
+        for line in lines.split_inclusive('\n') {
+            let mut new_line = line.to_string();
+            for (re, replacement) in &self.replace {
+                new_line = re
+                    .try_replacen(&new_line, 0, replacement.as_str())?
+                    .to_string();
+            }
+            if new_line != line {
+                self.removed.push(line.to_string());
+            }
+            output.push_str(&new_line);
+        }
+        Ok(output)
+    }
+
+    /// Drop the blank space left at the start of the output by removed text.
+    fn trim_leading(&mut self, text: String) -> String {
+        if self.started {
+            return text;
+        }
+        let text = text.trim_start();
+        self.started = !text.is_empty();
+        text.to_string()
+    }
+}
+
+/// The length of the longest end of `text` that is a proper prefix of `delimiter`.
+fn partial_suffix(text: &str, delimiter: &str) -> usize {
+    delimiter
+        .char_indices()
+        .map(|(i, _)| i)
+        .skip(1)
+        .filter(|&i| text.ends_with(&delimiter[..i]))
+        .max()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter() -> OutputFilter {
+        let config: OutputFilterConfig = serde_json::from_value(serde_json::json!({
+            "strip": [
+                { "start": "<thinking>", "end": "</thinking>" },
+                { "start": "```scratch", "end": "```" },
+            ],
+            "replace": [
+                { "pattern": "^Sure, here is[^\\n]*\\n" },
+                { "pattern": "^(Assistant: )+" },
+            ],
+        }))
+        .unwrap();
+        OutputFilter::new(&config).unwrap()
+    }
+
+    #[test]
+    fn test_apply() {
+        let input = "<thinking>plan 😊</thinking>\n\nSure, here is the code:\nAssistant: Assistant: a < b\n```scratch\nx\n```done <thinking>unclosed";
+        let mut filter = filter();
+        assert_eq!(filter.apply(input).unwrap(), "a < b\ndone ");
+        assert_eq!(
+            filter.take_removed(),
+            [
+                "<thinking>plan 😊</thinking>",
+                "```scratch\nx\n```",
+                "Sure, here is the code:\n",
+                "Assistant: Assistant: a < b\n",
+                "<thinking>unclosed"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chunking() {
+        let input = "<thinking>plan 😊</thinking>\n\nSure, here is the code:\nAssistant: a < b </thinking>\n```scratch\nx\n```done";
+        let fresh = filter();
+        let expected = fresh.clone().apply(input).unwrap();
+        let boundaries: Vec<usize> = (0..=input.len())
+            .filter(|&i| input.is_char_boundary(i))
+            .collect();
+        for &i in &boundaries {
+            for &j in boundaries.iter().filter(|&&j| j >= i) {
+                let mut filter = fresh.clone();
+                let mut output = String::new();
+                for chunk in [&input[..i], &input[i..j], &input[j..]] {
+                    output.push_str(&filter.push(chunk).unwrap());
+                }
+                output.push_str(&filter.finish().unwrap());
+                assert_eq!(output, expected, "split at {i}, {j}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_held_back_reply_has_started() {
+        let (mut handler, _rx) = crate::client::fixture::handler();
+        handler.set_output_filter(Some(filter()));
+        handler.text("<thinking>plan").unwrap();
+        assert_eq!(handler.get_buffer(), "");
+        assert!(handler.has_started());
+    }
+}
@@ -1,6 +1,6 @@
 use super::{
-    message::*, Client, ExtraConfig, Model, ModelConfig, PromptType, QianwenClient, ReplyHandler,
-    SendData,
+    event_source, message::*, send_request, Client, CompletionOutput, ExtraConfig, Model,
+    ModelConfig, PromptType, QianwenClient, ReplyHandler, SendData,
 };
 
 use crate::utils::{sha256sum, PromptKind};
@@ -13,7 +13,7 @@ use reqwest::{
     multipart::{Form, Part},
     Client as ReqwestClient, RequestBuilder,
 };
-use reqwest_eventsource::{Error as EventSourceError, Event, RequestBuilderExt};
+use reqwest_eventsource::{Error as EventSourceError, Event};
 use serde::Deserialize;
 use serde_json::{json, Value};
 use std::borrow::BorrowMut;
@@ -52,7 +52,7 @@ impl Client for QianwenClient {
         &self,
         client: &ReqwestClient,
         mut data: SendData,
-    ) -> Result<String> {
+    ) -> Result<CompletionOutput> {
         let api_key = self.get_api_key()?;
         patch_messages(&self.model.name, &api_key, &mut data.messages).await?;
         let builder = self.request_builder(client, data)?;
@@ -109,8 +109,8 @@ impl QianwenClient {
     }
 }
 
-async fn send_message(builder: RequestBuilder, is_vl: bool) -> Result<String> {
-    let data: Value = builder.send().await?.json().await?;
+async fn send_message(builder: RequestBuilder, is_vl: bool) -> Result<CompletionOutput> {
+    let data: Value = send_request(builder).await?.json().await?;
     catch_error(&data)?;
 
     let output = if is_vl {
@@ -119,9 +119,19 @@ async fn send_message(builder: RequestBuilder, is_vl: bool) -> Result<String> {
         data["output"]["text"].as_str()
     };
 
-    let output = output.ok_or_else(|| anyhow!("Unexpected response {data}"))?;
-
-    Ok(output.to_string())
+    let text = output.ok_or_else(|| anyhow!("Unexpected response {data}"))?;
+
+    Ok(CompletionOutput {
+        text: text.to_string(),
+        input_tokens: data["usage"]["input_tokens"].as_u64(),
+        output_tokens: data["usage"]["output_tokens"].as_u64(),
+        tool_calls: vec![],
+        request_id: None,
+        stop_sequence: None,
+        stop_reason: None,
+        stats: None,
+        cache_usage: None,
+    })
 }
 
 async fn send_message_streaming(
@@ -129,7 +139,7 @@ async fn send_message_streaming(
     handler: &mut ReplyHandler,
     is_vl: bool,
 ) -> Result<()> {
-    let mut es = builder.eventsource()?;
+    let mut es = event_source(builder)?;
 
     while let Some(event) = es.next().await {
         match event {
@@ -167,6 +177,7 @@ fn build_body(data: SendData, model: &Model, is_vl: bool) -> Result<(Value, bool
         messages,
         temperature,
         top_p,
+        functions: _,
         stream,
     } = data;
 
@@ -177,7 +188,9 @@ fn build_body(data: SendData, model: &Model, is_vl: bool) -> Result<(Value, bool
             .map(|message| {
                 let role = message.role;
                 let content = match message.content {
-                    MessageContent::Text(text) => vec![json!({"text": text})],
+                    MessageContent::Text(text) | MessageContent::ToolResults((_, text)) => {
+                        vec![json!({"text": text})]
+                    }
                     MessageContent::Array(list) => list
                         .into_iter()
                         .map(|item| match item {
@@ -289,6 +302,8 @@ async fn upload(model: &str, api_key: &str, url: &str) -> Result<String> {
     }
     let data = STANDARD.decode(data)?;
 
+    // The upload goes to Aliyun OSS under a signed policy, apart from the model API, so neither
+    // request carries the `headers` of the config
     let client = reqwest::Client::new();
     let policy: Policy = client
         .get(format!(
@@ -0,0 +1,287 @@
+use super::{
+    event_source, message::*, note_response, patch_system_message, send_request, Client,
+    CompletionOutput, ExtraConfig, Model, ModelConfig, PromptType, RekaClient, ReplyHandler,
+    SendData,
+};
+
+use crate::utils::PromptKind;
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use reqwest_eventsource::{Error as EventSourceError, Event};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+const API_URL: &str = "https://api.reka.ai/v1/chat";
+
+const MODELS: [(&str, usize, &str); 3] = [
+    // https://docs.reka.ai/available-models
+    ("reka-core", 128000, "text,vision"),
+    ("reka-flash", 128000, "text,vision"),
+    ("reka-edge", 64000, "text,vision"),
+];
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct RekaConfig {
+    pub name: Option<String>,
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+#[async_trait]
+impl Client for RekaClient {
+    client_common_fns!();
+
+    async fn send_message_inner(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+    ) -> Result<CompletionOutput> {
+        let builder = self.request_builder(client, data)?;
+        send_message(builder).await
+    }
+
+    async fn send_message_streaming_inner(
+        &self,
+        client: &ReqwestClient,
+        handler: &mut ReplyHandler,
+        data: SendData,
+    ) -> Result<()> {
+        let builder = self.request_builder(client, data)?;
+        send_message_streaming(builder, handler).await
+    }
+}
+
+impl RekaClient {
+    list_models_fn!(RekaConfig, &MODELS);
+    config_get_fn!(api_key, get_api_key);
+
+    pub const PROMPTS: [PromptType<'static>; 1] =
+        [("api_key", "API Key:", true, PromptKind::String)];
+
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let api_key = self.get_api_key()?;
+
+        let body = build_body(data, &self.model);
+
+        let url = API_URL;
+
+        debug!("Reka Request: {url} {body}");
+
+        let builder = client.post(url).header("X-Api-Key", api_key).json(&body);
+
+        Ok(builder)
+    }
+}
+
+async fn send_message(builder: RequestBuilder) -> Result<CompletionOutput> {
+    let res = send_request(builder).await?;
+    let status = res.status();
+    let data: Value = res.json().await?;
+    if status != 200 {
+        catch_error(&data, status.as_u16())?;
+    }
+
+    let text = data["responses"][0]["message"]["content"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+
+    Ok(CompletionOutput {
+        text: text.to_string(),
+        input_tokens: data["usage"]["input_tokens"].as_u64(),
+        output_tokens: data["usage"]["output_tokens"].as_u64(),
+        tool_calls: vec![],
+        request_id: None,
+        stop_sequence: None,
+        stop_reason: None,
+        stats: None,
+        cache_usage: None,
+    })
+}
+
+async fn send_message_streaming(builder: RequestBuilder, handler: &mut ReplyHandler) -> Result<()> {
+    let mut es = event_source(builder)?;
+    while let Some(event) = es.next().await {
+        match event {
+            Ok(Event::Open) => {}
+            Ok(Event::Message(message)) => {
+                let data: Value = serde_json::from_str(&message.data)?;
+                if let Some(text) = data["responses"][0]["chunk"]["content"].as_str() {
+                    handler.text(text)?;
+                }
+                let usage = &data["usage"];
+                if usage.is_object() {
+                    handler.set_usage(
+                        usage["input_tokens"].as_u64(),
+                        usage["output_tokens"].as_u64(),
+                    );
+                }
+            }
+            Err(err) => {
+                match err {
+                    EventSourceError::StreamEnded => {}
+                    EventSourceError::InvalidStatusCode(status, res) => {
+                        note_response(&res);
+                        let text = res.text().await?;
+                        let data: Value = match text.parse() {
+                            Ok(data) => data,
+                            Err(_) => {
+                                bail!("Invalid respoinse, status: {status}, text: {text}");
+                            }
+                        };
+                        catch_error(&data, status.as_u16())?;
+                    }
+                    EventSourceError::InvalidContentType(_, res) => {
+                        let text = res.text().await?;
+                        bail!("The API server should return data as 'text/event-stream', but it isn't. Check the client config. {text}");
+                    }
+                    _ => {
+                        bail!("{}", err);
+                    }
+                }
+                es.close();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn build_body(data: SendData, model: &Model) -> Value {
+    let SendData {
+        mut messages,
+        temperature,
+        top_p,
+        functions: _,
+        stream,
+    } = data;
+
+    // Reka has no system role
+    patch_system_message(&mut messages);
+
+    let messages: Vec<Value> = messages
+        .into_iter()
+        .map(|message| {
+            let content = match message.content {
+                MessageContent::Text(text) | MessageContent::ToolResults((_, text)) => {
+                    vec![json!({ "type": "text", "text": text })]
+                }
+                MessageContent::Array(list) => list
+                    .into_iter()
+                    .map(|item| match item {
+                        MessageContentPart::Text { text } => {
+                            json!({ "type": "text", "text": text })
+                        }
+                        MessageContentPart::ImageUrl {
+                            image_url: ImageUrl { url },
+                        } => json!({ "type": "image_url", "image_url": url }),
+                    })
+                    .collect(),
+            };
+            json!({ "role": message.role, "content": content })
+        })
+        .collect();
+
+    let mut body = json!({
+        "model": &model.name,
+        "messages": messages,
+    });
+
+    if let Some(max_tokens) = model.max_output_tokens {
+        body["max_tokens"] = max_tokens.into();
+    }
+    if let Some(temperature) = temperature {
+        body["temperature"] = temperature.into();
+    }
+    if let Some(top_p) = top_p {
+        body["top_p"] = top_p.into();
+    }
+    if stream {
+        body["stream"] = true.into();
+    }
+    body
+}
+
+fn catch_error(data: &Value, status: u16) -> Result<()> {
+    debug!("Invalid response, status: {status}, data: {data}");
+    if let Some(detail) = data["detail"].as_str() {
+        bail!("{detail}");
+    }
+    bail!("Invalid response, status: {status}, data: {data}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::fixture;
+
+    #[test]
+    fn test_build_body() {
+        let model = RekaClient::list_models(&RekaConfig::default()).remove(1);
+        let data = SendData {
+            messages: vec![
+                Message::system("Be brief"),
+                Message {
+                    role: MessageRole::User,
+                    content: MessageContent::Array(vec![
+                        MessageContentPart::Text {
+                            text: "What's in it?".into(),
+                        },
+                        MessageContentPart::ImageUrl {
+                            image_url: ImageUrl {
+                                url: "data:image/png;base64,iVBORw0K".into(),
+                            },
+                        },
+                    ]),
+                    pinned: false,
+                },
+            ],
+            temperature: None,
+            top_p: None,
+            functions: None,
+            stream: false,
+        };
+        assert_eq!(
+            build_body(data, &model),
+            json!({
+                "model": "reka-flash",
+                "messages": [
+                    {
+                        "role": "user",
+                        "content": [
+                            { "type": "text", "text": "Be brief" },
+                            { "type": "text", "text": "What's in it?" },
+                            { "type": "image_url", "image_url": "data:image/png;base64,iVBORw0K" },
+                        ],
+                    },
+                ],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let client = RekaClient::for_test("api_key: xxx")?;
+        let http = ReqwestClient::new();
+        let request = |stream| client.request_builder(&http, fixture::send_data(stream));
+
+        let (mut handler, _rx) = fixture::handler();
+        send_message_streaming(
+            fixture::replay("reka/stream", request(true)?).await?,
+            &mut handler,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "Hello! How can I help you today?");
+        assert_eq!(handler.get_usage(), (Some(11), Some(10)));
+
+        let err = send_message(fixture::replay("reka/auth_failure", request(false)?).await?)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Invalid API key.");
+        Ok(())
+    }
+}
@@ -0,0 +1,353 @@
+use super::{
+    event_source, extract_sytem_message, note_response, send_request, Client, CompletionOutput,
+    ExtraConfig, Model, ModelConfig, PromptType, ReplicateClient, ReplyHandler, SendData,
+};
+
+use crate::utils::PromptKind;
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use reqwest::{
+    header::{ACCEPT, CACHE_CONTROL},
+    Client as ReqwestClient, RequestBuilder,
+};
+use reqwest_eventsource::{Error as EventSourceError, Event};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::time::sleep;
+
+const API_BASE: &str = "https://api.replicate.com/v1";
+
+const POLL_INTERVAL_MIN: Duration = Duration::from_millis(500);
+const POLL_INTERVAL_MAX: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReplicateConfig {
+    pub name: Option<String>,
+    pub api_key: Option<String>,
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+#[async_trait]
+impl Client for ReplicateClient {
+    client_common_fns!();
+
+    async fn send_message_inner(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+    ) -> Result<CompletionOutput> {
+        let api_key = self.get_api_key()?;
+        let builder = self.request_builder(client, data)?;
+        let prediction = create_prediction(builder).await?;
+        send_message(client, prediction, &api_key).await
+    }
+
+    async fn send_message_streaming_inner(
+        &self,
+        client: &ReqwestClient,
+        handler: &mut ReplyHandler,
+        data: SendData,
+    ) -> Result<()> {
+        let builder = self.request_builder(client, data)?;
+        let prediction = create_prediction(builder).await?;
+        let url = prediction["urls"]["stream"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Invalid response data: {prediction}"))?;
+        send_message_streaming(stream_request_builder(client, url), handler).await
+    }
+}
+
+impl ReplicateClient {
+    list_models_fn!(ReplicateConfig);
+    config_get_fn!(api_key, get_api_key);
+
+    pub const PROMPTS: [PromptType<'static>; 3] = [
+        ("api_key", "API Key:", true, PromptKind::String),
+        ("models[].name", "Model Name:", true, PromptKind::String),
+        (
+            "models[].max_input_tokens",
+            "Max Input Tokens:",
+            false,
+            PromptKind::Integer,
+        ),
+    ];
+
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let api_key = self.get_api_key()?;
+
+        let stream = data.stream;
+        let body = build_body(data, &self.model);
+
+        let url = format!("{API_BASE}/models/{}/predictions", self.model.name);
+
+        debug!("Replicate Request: {url} {body}");
+
+        let mut builder = client.post(url).bearer_auth(api_key).json(&body);
+        if !stream {
+            // Hold the connection until the prediction finishes, up to 60 seconds
+            builder = builder.header("Prefer", "wait");
+        }
+
+        Ok(builder)
+    }
+}
+
+fn stream_request_builder(client: &ReqwestClient, url: &str) -> RequestBuilder {
+    client
+        .get(url)
+        .header(ACCEPT, "text/event-stream")
+        .header(CACHE_CONTROL, "no-store")
+}
+
+async fn create_prediction(builder: RequestBuilder) -> Result<Value> {
+    let res = send_request(builder).await?;
+    let status = res.status();
+    let data: Value = res.json().await?;
+    if !status.is_success() {
+        catch_error(&data, status.as_u16())?;
+    }
+    Ok(data)
+}
+
+/// Poll the prediction with backoff until it succeeds, fails or is canceled.
+async fn send_message(
+    client: &ReqwestClient,
+    mut prediction: Value,
+    api_key: &str,
+) -> Result<CompletionOutput> {
+    let mut interval = POLL_INTERVAL_MIN;
+    loop {
+        match prediction["status"].as_str() {
+            Some("succeeded") => break,
+            Some(status @ ("failed" | "canceled")) => match prediction["error"].as_str() {
+                Some(error) => bail!("The prediction {status}: {error}"),
+                None => bail!("The prediction {status}"),
+            },
+            _ => {}
+        }
+        let url = prediction["urls"]["get"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Invalid response data: {prediction}"))?;
+        sleep(interval).await;
+        interval = (interval * 2).min(POLL_INTERVAL_MAX);
+        let res = send_request(client.get(url).bearer_auth(api_key)).await?;
+        let status = res.status();
+        let data: Value = res.json().await?;
+        if status != 200 {
+            catch_error(&data, status.as_u16())?;
+        }
+        prediction = data;
+    }
+
+    let text = match &prediction["output"] {
+        Value::Array(list) => list.iter().filter_map(|v| v.as_str()).collect(),
+        Value::String(text) => text.clone(),
+        _ => bail!("Invalid response data: {prediction}"),
+    };
+
+    Ok(CompletionOutput {
+        text,
+        input_tokens: prediction["metrics"]["input_token_count"].as_u64(),
+        output_tokens: prediction["metrics"]["output_token_count"].as_u64(),
+        tool_calls: vec![],
+        request_id: None,
+        stop_sequence: None,
+        stop_reason: None,
+        stats: None,
+        cache_usage: None,
+    })
+}
+
+async fn send_message_streaming(builder: RequestBuilder, handler: &mut ReplyHandler) -> Result<()> {
+    let mut es = event_source(builder)?;
+    while let Some(event) = es.next().await {
+        match event {
+            Ok(Event::Open) => {}
+            Ok(Event::Message(message)) => match message.event.as_str() {
+                "output" => handler.text(&message.data)?,
+                "error" => {
+                    es.close();
+                    let data: Value = serde_json::from_str(&message.data).unwrap_or_default();
+                    match data["detail"].as_str() {
+                        Some(detail) => bail!("The prediction failed: {detail}"),
+                        None => bail!("The prediction failed: {}", message.data),
+                    }
+                }
+                "done" => {
+                    es.close();
+                    let data: Value = serde_json::from_str(&message.data).unwrap_or_default();
+                    if let Some(reason) = data["reason"].as_str() {
+                        bail!("The prediction ended early ({reason})");
+                    }
+                    break;
+                }
+                _ => {}
+            },
+            Err(err) => {
+                match err {
+                    EventSourceError::StreamEnded => {}
+                    EventSourceError::InvalidStatusCode(status, res) => {
+                        note_response(&res);
+                        let text = res.text().await?;
+                        let data: Value = match text.parse() {
+                            Ok(data) => data,
+                            Err(_) => {
+                                bail!("Invalid respoinse, status: {status}, text: {text}");
+                            }
+                        };
+                        catch_error(&data, status.as_u16())?;
+                    }
+                    EventSourceError::InvalidContentType(_, res) => {
+                        let text = res.text().await?;
+                        bail!("The API server should return data as 'text/event-stream', but it isn't. Check the client config. {text}");
+                    }
+                    _ => {
+                        bail!("{}", err);
+                    }
+                }
+                es.close();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn build_body(data: SendData, model: &Model) -> Value {
+    let SendData {
+        mut messages,
+        temperature,
+        top_p,
+        functions: _,
+        stream,
+    } = data;
+
+    let system_message = extract_sytem_message(&mut messages);
+
+    // A single turn goes as is, so the model's own prompt template applies
+    let prompt = match messages.as_slice() {
+        [message] => message.content.to_text(),
+        _ => model
+            .completion
+            .clone()
+            .unwrap_or_default()
+            .build_prompt(&messages),
+    };
+
+    let mut input = json!({ "prompt": prompt });
+
+    if let Some(system_prompt) = system_message {
+        input["system_prompt"] = system_prompt.into();
+    }
+    if let Some(max_tokens) = model.max_output_tokens {
+        input["max_new_tokens"] = max_tokens.into();
+    }
+    if let Some(temperature) = temperature {
+        input["temperature"] = temperature.into();
+    }
+    if let Some(top_p) = top_p {
+        input["top_p"] = top_p.into();
+    }
+
+    let mut body = json!({ "input": input });
+    if stream {
+        body["stream"] = true.into();
+    }
+    body
+}
+
+fn catch_error(data: &Value, status: u16) -> Result<()> {
+    debug!("Invalid response, status: {status}, data: {data}");
+    match (data["title"].as_str(), data["detail"].as_str()) {
+        (Some(title), Some(detail)) => bail!("{detail} (title: {title})"),
+        (None, Some(detail)) => bail!("{detail}"),
+        _ => bail!("Invalid response, status: {status}, data: {data}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{fixture, Message};
+
+    #[test]
+    fn test_build_body() {
+        let model = Model::new("replicate", "meta/meta-llama-3-70b-instruct");
+        let data = SendData {
+            messages: vec![
+                Message::system("Be brief"),
+                Message::user("Hi"),
+                Message::assistant("Hello"),
+                Message::user("Bye"),
+            ],
+            temperature: Some(0.5),
+            top_p: None,
+            functions: None,
+            stream: true,
+        };
+        assert_eq!(
+            build_body(data, &model),
+            json!({
+                "input": {
+                    "prompt": "User: Hi\nAssistant: Hello\nUser: Bye\nAssistant:",
+                    "system_prompt": "Be brief",
+                    "temperature": 0.5,
+                },
+                "stream": true,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let client = ReplicateClient::for_test(
+            r#"
+api_key: xxx
+models:
+  - name: meta/meta-llama-3-70b-instruct
+"#,
+        )?;
+        let http = ReqwestClient::new();
+        let request = |stream| client.request_builder(&http, fixture::send_data(stream));
+        let prediction =
+            create_prediction(fixture::replay("replicate/success", request(false)?).await?).await?;
+        let output = send_message(&http, prediction, "").await?;
+        assert_eq!(output.text, "Hello! How can I help you today?");
+        assert_eq!(
+            (output.input_tokens, output.output_tokens),
+            (Some(11), Some(10))
+        );
+
+        let prediction =
+            create_prediction(fixture::replay("replicate/failed", request(false)?).await?).await?;
+        let err = send_message(&http, prediction, "").await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The prediction failed: CUDA error: out of memory"
+        );
+
+        let (mut handler, _rx) = fixture::handler();
+        send_message_streaming(fixture::replay(
+                "replicate/stream",
+                stream_request_builder(
+                    &http,
+                    "https://streaming-api.svc.us.c.replicate.net/v1/streams/b2zkkbwbpq4w5goqhzmxqhm7xa",
+                ),
+            ).await?, &mut handler).await?;
+        assert_eq!(handler.get_buffer(), "Hello! How can I help you today?");
+
+        let err =
+            create_prediction(fixture::replay("replicate/auth_failure", request(false)?).await?)
+                .await
+                .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "You did not pass a valid authentication token (title: Unauthenticated)"
+        );
+        Ok(())
+    }
+}
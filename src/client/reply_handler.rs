@@ -1,11 +1,24 @@
+use super::{CacheUsage, GenerationStats, OutputFilter, ToolCall};
+
 use crate::utils::AbortSignal;
 
 use anyhow::{Context, Result};
+use std::time::Instant;
 use tokio::sync::mpsc::UnboundedSender;
 
 pub struct ReplyHandler {
     sender: UnboundedSender<ReplyEvent>,
     buffer: String,
+    tool_calls: Vec<ToolCall>,
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+    first_token_at: Option<Instant>,
+    request_id: Option<String>,
+    stop_sequence: Option<String>,
+    stop_reason: Option<String>,
+    stats: Option<GenerationStats>,
+    cache_usage: Option<CacheUsage>,
+    output_filter: Option<OutputFilter>,
     abort: AbortSignal,
 }
 
@@ -15,6 +28,16 @@ impl ReplyHandler {
             sender,
             abort,
             buffer: String::new(),
+            tool_calls: vec![],
+            input_tokens: None,
+            output_tokens: None,
+            first_token_at: None,
+            request_id: None,
+            stop_sequence: None,
+            stop_reason: None,
+            stats: None,
+            cache_usage: None,
+            output_filter: None,
         }
     }
 
@@ -23,17 +46,19 @@ impl ReplyHandler {
         if text.is_empty() {
             return Ok(());
         }
-        self.buffer.push_str(text);
-        let ret = self
-            .sender
-            .send(ReplyEvent::Text(text.to_string()))
-            .with_context(|| "Failed to send ReplyEvent:Text");
-        self.safe_ret(ret)?;
-        Ok(())
+        if self.first_token_at.is_none() {
+            self.first_token_at = Some(Instant::now());
+        }
+        let text = match self.output_filter.as_mut() {
+            Some(filter) => filter.push(text)?,
+            None => text.to_string(),
+        };
+        self.emit(&text)
     }
 
     pub fn done(&mut self) -> Result<()> {
         // debug!("ReplyDone");
+        self.finish_output_filter()?;
         let ret = self
             .sender
             .send(ReplyEvent::Done)
@@ -42,6 +67,91 @@ impl ReplyHandler {
         Ok(())
     }
 
+    /// Filter the text passed to `text` from now on.
+    pub fn set_output_filter(&mut self, output_filter: Option<OutputFilter>) {
+        self.output_filter = output_filter;
+    }
+
+    /// Send the text held back by the output filter and return what it removed.
+    pub fn finish_output_filter(&mut self) -> Result<Vec<String>> {
+        let Some(mut filter) = self.output_filter.take() else {
+            return Ok(vec![]);
+        };
+        let text = filter.finish()?;
+        self.emit(&text)?;
+        Ok(filter.take_removed())
+    }
+
+    pub fn tool_call(&mut self, call: ToolCall) {
+        self.tool_calls.push(call);
+    }
+
+    pub fn get_tool_calls(&self) -> &[ToolCall] {
+        &self.tool_calls
+    }
+
+    pub fn set_usage(&mut self, input_tokens: Option<u64>, output_tokens: Option<u64>) {
+        if input_tokens.is_some() {
+            self.input_tokens = input_tokens;
+        }
+        if output_tokens.is_some() {
+            self.output_tokens = output_tokens;
+        }
+    }
+
+    pub fn get_usage(&self) -> (Option<u64>, Option<u64>) {
+        (self.input_tokens, self.output_tokens)
+    }
+
+    pub fn get_first_token_at(&self) -> Option<Instant> {
+        self.first_token_at
+    }
+
+    /// Whether any text or tool call was received, even if the output filter holds it back.
+    pub fn has_started(&self) -> bool {
+        self.first_token_at.is_some() || !self.tool_calls.is_empty()
+    }
+
+    pub fn set_request_id(&mut self, request_id: &str) {
+        self.request_id = Some(request_id.to_string());
+    }
+
+    pub fn get_request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
+    pub fn set_stop_sequence(&mut self, stop_sequence: &str) {
+        self.stop_sequence = Some(stop_sequence.to_string());
+    }
+
+    pub fn get_stop_sequence(&self) -> Option<&str> {
+        self.stop_sequence.as_deref()
+    }
+
+    pub fn set_stop_reason(&mut self, stop_reason: &str) {
+        self.stop_reason = Some(stop_reason.to_string());
+    }
+
+    pub fn get_stop_reason(&self) -> Option<&str> {
+        self.stop_reason.as_deref()
+    }
+
+    pub fn set_stats(&mut self, stats: GenerationStats) {
+        self.stats = Some(stats);
+    }
+
+    pub fn get_stats(&self) -> Option<GenerationStats> {
+        self.stats
+    }
+
+    pub fn set_cache_usage(&mut self, cache_usage: CacheUsage) {
+        self.cache_usage = Some(cache_usage);
+    }
+
+    pub fn get_cache_usage(&self) -> Option<CacheUsage> {
+        self.cache_usage
+    }
+
     pub fn get_buffer(&self) -> &str {
         &self.buffer
     }
@@ -50,6 +160,19 @@ impl ReplyHandler {
         self.abort.clone()
     }
 
+    fn emit(&mut self, text: &str) -> Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.buffer.push_str(text);
+        let ret = self
+            .sender
+            .send(ReplyEvent::Text(text.to_string()))
+            .with_context(|| "Failed to send ReplyEvent:Text");
+        self.safe_ret(ret)?;
+        Ok(())
+    }
+
     fn safe_ret(&self, ret: Result<()>) -> Result<()> {
         if ret.is_err() && self.abort.aborted() {
             return Ok(());
@@ -0,0 +1,209 @@
+//! The attempts of the requests of `send_data` and `send_data_streaming`.
+//!
+//! The clients send their requests with [`send_request`] or [`event_source`], which put the
+//! `headers` of the config over their own within [`with_headers`], and pass the response of a
+//! rejected event stream to [`note_response`]. The requests for an access token go through
+//! [`send_token_request`] instead, so the `headers` never reach a third-party token endpoint.
+//!
+//! A transient status of the attempt is noted for the retries, while the error stays the one of
+//! the client, so the last attempt fails the same way a single one would.
+//!
+//! The clients don't retry transient failures on their own. The only retry of theirs is a single
+//! one with a fresh access token once the old one was rejected, apart from `max_retries`.
+
+use super::ExtraConfig;
+
+use anyhow::{Context, Error, Result};
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    RequestBuilder, Response,
+};
+use reqwest_eventsource::{EventSource, RequestBuilderExt};
+use std::{cell::Cell, future::Future, time::Duration};
+
+const DEFAULT_MAX_RETRIES: u32 = 2;
+const DEFAULT_MAX_RETRY_DELAY: u64 = 30;
+const RETRY_DELAY_BASE: Duration = Duration::from_secs(1);
+
+tokio::task_local! {
+    static ATTEMPT: Attempt;
+    /// The `headers` of the config, in place of the ones of the client
+    static HEADERS: HeaderMap;
+}
+
+struct Attempt {
+    /// The transient status response of the attempt
+    transient: Cell<Option<Transient>>,
+}
+
+#[derive(Debug)]
+struct Transient {
+    reason: String,
+    retry_after: Option<Duration>,
+}
+
+/// Send the request, noting a transient status for the retries.
+pub async fn send_request(builder: RequestBuilder) -> reqwest::Result<Response> {
+    let res = override_headers(builder)?.send().await?;
+    note_response(&res);
+    Ok(res)
+}
+
+/// Open the event stream of the request.
+pub fn event_source(builder: RequestBuilder) -> Result<EventSource> {
+    Ok(override_headers(builder)?.eventsource()?)
+}
+
+/// Send the request for an access token, without the `headers` of the config.
+pub async fn send_token_request(builder: RequestBuilder) -> reqwest::Result<Response> {
+    let res = builder.send().await?;
+    note_response(&res);
+    Ok(res)
+}
+
+/// Run `fut` with the `headers` of the config put over the ones of its requests.
+pub async fn with_headers<T>(headers: HeaderMap, fut: impl Future<Output = T>) -> T {
+    HEADERS.scope(headers, fut).await
+}
+
+fn override_headers(builder: RequestBuilder) -> reqwest::Result<RequestBuilder> {
+    let Ok(Some(headers)) = HEADERS.try_with(|v| Some(v.clone()).filter(|v| !v.is_empty())) else {
+        return Ok(builder);
+    };
+    let (client, request) = builder.build_split();
+    let mut request = request?;
+    for (name, value) in &headers {
+        request.headers_mut().insert(name, value.clone());
+    }
+    Ok(RequestBuilder::from_parts(client, request))
+}
+
+/// Note a transient status of the response, with its `Retry-After`, for the retries. Only the
+/// last response of the attempt counts, e.g. after waiting for a model to load.
+pub fn note_response(res: &Response) {
+    let status = res.status().as_u16();
+    let transient = matches!(status, 408 | 429 | 500 | 502 | 503 | 504 | 529).then(|| {
+        let retry_after = res
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse().ok())
+            .map(Duration::from_secs);
+        Transient {
+            reason: format!("status {status}"),
+            retry_after,
+        }
+    });
+    let _ = ATTEMPT.try_with(|v| v.transient.set(transient));
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(extra: &Option<ExtraConfig>) -> Self {
+        let extra = extra.as_ref();
+        Self {
+            max_retries: extra
+                .and_then(|v| v.max_retries)
+                .unwrap_or(DEFAULT_MAX_RETRIES),
+            max_delay: Duration::from_secs(
+                extra
+                    .and_then(|v| v.max_retry_delay)
+                    .unwrap_or(DEFAULT_MAX_RETRY_DELAY),
+            ),
+        }
+    }
+
+    /// Run the attempt `attempt` (from 0), and tell how long to wait before the next one if it
+    /// failed for a transient status or a connection error and retries are left. Once they are
+    /// used up, the error says how many attempts were made.
+    pub async fn attempt<T>(
+        &self,
+        attempt: u32,
+        fut: impl Future<Output = Result<T>>,
+    ) -> (Result<T>, Option<Duration>) {
+        let scope = Attempt {
+            transient: Cell::new(None),
+        };
+        let (ret, transient) = ATTEMPT
+            .scope(scope, async {
+                let ret = fut.await;
+                (ret, ATTEMPT.with(|v| v.transient.take()))
+            })
+            .await;
+        let err = match &ret {
+            Err(err) => err,
+            Ok(_) => return (ret, None),
+        };
+        let (reason, retry_after) = match transient {
+            Some(transient) => (transient.reason, transient.retry_after),
+            None if is_connection_error(err) => ("connection error".to_string(), None),
+            None => return (ret, None),
+        };
+        if attempt >= self.max_retries {
+            if attempt == 0 {
+                return (ret, None);
+            }
+            let ret = ret.with_context(|| format!("Gave up after {} attempts", attempt + 1));
+            return (ret, None);
+        }
+        let delay = retry_after
+            .unwrap_or_else(|| backoff(attempt))
+            .min(self.max_delay);
+        debug!(
+            "Retrying in {delay:?} for {reason} ({}/{}): {err}",
+            attempt + 1,
+            self.max_retries
+        );
+        (ret, Some(delay))
+    }
+}
+
+/// The exponential backoff of the attempt, with up to 50% of jitter.
+fn backoff(attempt: u32) -> Duration {
+    let delay = RETRY_DELAY_BASE * 2u32.saturating_pow(attempt);
+    delay + delay.mul_f64(fastrand::f64() / 2.0)
+}
+
+fn is_connection_error(err: &Error) -> bool {
+    err.chain().any(|cause| {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(|v| v.is_connect())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_override_headers() -> Result<()> {
+        let builder = reqwest::Client::new()
+            .post("http://localhost/v1/messages")
+            .header("anthropic-version", "2023-06-01");
+        let mut headers = HeaderMap::new();
+        headers.insert("anthropic-version", "2024-01-01".parse()?);
+        let request = with_headers(headers, async { override_headers(builder)?.build() }).await?;
+        let values: Vec<_> = request
+            .headers()
+            .get_all("anthropic-version")
+            .iter()
+            .cloned()
+            .collect();
+        assert_eq!(values, ["2024-01-01"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_backoff() {
+        for (attempt, secs) in [(0, 1.0), (1, 2.0), (2, 4.0)] {
+            let delay = backoff(attempt).as_secs_f64();
+            assert!(delay >= secs && delay <= secs * 1.5, "{delay}");
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use super::openai::openai_build_body;
+use super::{ExtraConfig, Model, ModelConfig, PromptType, SambaNovaClient, SendData};
+
+use crate::utils::PromptKind;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use serde::Deserialize;
+
+const API_BASE: &str = "https://api.sambanova.ai/v1";
+
+// Requests asking for more output tokens than this are rejected
+const MAX_OUTPUT_TOKENS: isize = 4096;
+
+const MODELS: [(&str, usize, &str); 3] = [
+    // https://community.sambanova.ai/t/supported-models/193
+    ("Meta-Llama-3.1-8B-Instruct", 16384, "text"),
+    ("Meta-Llama-3.1-70B-Instruct", 65536, "text"),
+    ("Meta-Llama-3.1-405B-Instruct", 16384, "text"),
+];
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct SambaNovaConfig {
+    pub name: Option<String>,
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+openai_compatible_client!(SambaNovaClient);
+
+impl SambaNovaClient {
+    config_get_fn!(api_key, get_api_key);
+
+    pub const PROMPTS: [PromptType<'static>; 1] =
+        [("api_key", "API Key:", true, PromptKind::String)];
+
+    pub fn list_models(local_config: &SambaNovaConfig) -> Vec<Model> {
+        let client_name = Self::name(local_config);
+        if local_config.models.is_empty() {
+            Model::from_static(client_name, &MODELS)
+                .into_iter()
+                .map(|v| v.set_max_output_tokens(Some(MAX_OUTPUT_TOKENS)))
+                .collect()
+        } else {
+            Model::from_config(client_name, &local_config.models)
+        }
+    }
+
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let api_key = self.get_api_key()?;
+
+        let mut body = openai_build_body(data, &self.model);
+        self.model.merge_extra_fields(&mut body);
+
+        let url = format!("{API_BASE}/chat/completions");
+
+        debug!("SambaNova Request: {url} {body}");
+
+        let builder = client.post(url).bearer_auth(api_key).json(&body);
+
+        Ok(builder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{
+        fixture,
+        openai::{openai_send_message, openai_send_message_streaming},
+    };
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let client = SambaNovaClient::for_test("api_key: xxx")?;
+        let http = ReqwestClient::new();
+        let request = |stream| client.request_builder(&http, fixture::send_data(stream));
+
+        let (mut handler, _rx) = fixture::handler();
+        openai_send_message_streaming(
+            fixture::replay("sambanova/stream", request(true)?).await?,
+            &mut handler,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "Hello! How can I help you today?");
+        assert_eq!(handler.get_usage(), (Some(12), Some(10)));
+
+        let err = openai_send_message(
+            fixture::replay("sambanova/context_length", request(false)?).await?,
+        )
+        .await
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Requested generation length 4096 is too long for input length 14000 (type: invalid_request_error, code: context_length_exceeded). Reduce your input"));
+
+        let (mut handler, _rx) = fixture::handler();
+        let err = openai_send_message_streaming(
+            fixture::replay("sambanova/auth_failure", request(true)?).await?,
+            &mut handler,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid API key (type: authentication_error, code: invalid_api_key)"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use super::openai::openai_build_body;
+use super::{ExtraConfig, Model, ModelConfig, PromptType, SendData, SiliconFlowClient};
+
+use crate::utils::PromptKind;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use serde::Deserialize;
+
+const API_BASE: &str = "https://api.siliconflow.cn/v1";
+
+const MODELS: [(&str, usize, &str); 4] = [
+    // https://siliconflow.cn/models
+    ("Qwen/Qwen2-72B-Instruct", 32768, "text"),
+    ("THUDM/glm-4-9b-chat", 32768, "text"),
+    ("deepseek-ai/DeepSeek-V2-Chat", 32768, "text"),
+    ("01-ai/Yi-1.5-34B-Chat-16K", 16384, "text"),
+];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SiliconFlowConfig {
+    pub name: Option<String>,
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+openai_compatible_client!(SiliconFlowClient);
+
+impl SiliconFlowClient {
+    list_models_fn!(SiliconFlowConfig, &MODELS);
+    config_get_fn!(api_key, get_api_key);
+
+    pub const PROMPTS: [PromptType<'static>; 1] =
+        [("api_key", "API Key:", true, PromptKind::String)];
+
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let api_key = self.get_api_key()?;
+
+        let body = openai_build_body(data, &self.model);
+
+        let url = format!("{API_BASE}/chat/completions");
+
+        debug!("SiliconFlow Request: {url} {body}");
+
+        let builder = client.post(url).bearer_auth(api_key).json(&body);
+
+        Ok(builder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{
+        fixture,
+        openai::{openai_send_message, openai_send_message_streaming},
+    };
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let client = SiliconFlowClient::for_test("api_key: xxx")?;
+        let http = ReqwestClient::new();
+        let request = |stream| client.request_builder(&http, fixture::send_data(stream));
+
+        let (mut handler, _rx) = fixture::handler();
+        openai_send_message_streaming(
+            fixture::replay("siliconflow/stream", request(true)?).await?,
+            &mut handler,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "Hello! How can I help you today?");
+        assert_eq!(handler.get_usage(), (Some(9), Some(10)));
+
+        let err = openai_send_message(
+            fixture::replay("siliconflow/auth_failure", request(false)?).await?,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Invalid token (code: 20015)");
+        Ok(())
+    }
+}
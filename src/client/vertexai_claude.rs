@@ -0,0 +1,265 @@
+use super::claude::{build_body, extract_output, handle_event};
+use super::vertexai::{check_unauthenticated, prepare_access_token};
+use super::{
+    event_source, note_response, send_request, Client, CompletionOutput, ExtraConfig, Model,
+    ModelConfig, PromptType, ReplyHandler, SendData, VertexAIClaudeClient,
+};
+
+use crate::utils::PromptKind;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use reqwest_eventsource::{Error as EventSourceError, Event};
+use serde::Deserialize;
+use serde_json::Value;
+
+const ANTHROPIC_VERSION: &str = "vertex-2023-10-16";
+
+const MODELS: [(&str, usize, &str); 3] = [
+    // https://cloud.google.com/vertex-ai/generative-ai/docs/partner-models/use-claude
+    ("claude-3-opus@20240229", 200000, "text,vision"),
+    ("claude-3-sonnet@20240229", 200000, "text,vision"),
+    ("claude-3-haiku@20240307", 200000, "text,vision"),
+];
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct VertexAIClaudeConfig {
+    pub name: Option<String>,
+    pub api_base: Option<String>,
+    pub adc_file: Option<String>,
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+#[async_trait]
+impl Client for VertexAIClaudeClient {
+    client_common_fns!();
+
+    async fn send_message_inner(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+    ) -> Result<CompletionOutput> {
+        let name = &self.model.client_name;
+        let access_token = prepare_access_token(client, name, &self.config.adc_file).await?;
+        let builder = self.request_builder(client, data.clone(), &access_token)?;
+        let ret = send_message(builder).await;
+        if !check_unauthenticated(name, &ret).await {
+            return ret;
+        }
+        debug!("VertexAI access token of '{name}' rejected, retrying with a fresh one");
+        let access_token = prepare_access_token(client, name, &self.config.adc_file).await?;
+        let builder = self.request_builder(client, data, &access_token)?;
+        let ret = send_message(builder).await;
+        check_unauthenticated(name, &ret).await;
+        ret
+    }
+
+    async fn send_message_streaming_inner(
+        &self,
+        client: &ReqwestClient,
+        handler: &mut ReplyHandler,
+        data: SendData,
+    ) -> Result<()> {
+        let name = &self.model.client_name;
+        let access_token = prepare_access_token(client, name, &self.config.adc_file).await?;
+        let builder = self.request_builder(client, data.clone(), &access_token)?;
+        let ret = send_message_streaming(builder, handler).await;
+        if !check_unauthenticated(name, &ret).await || handler.has_started() {
+            return ret;
+        }
+        debug!("VertexAI access token of '{name}' rejected, retrying with a fresh one");
+        let access_token = prepare_access_token(client, name, &self.config.adc_file).await?;
+        let builder = self.request_builder(client, data, &access_token)?;
+        let ret = send_message_streaming(builder, handler).await;
+        check_unauthenticated(name, &ret).await;
+        ret
+    }
+}
+
+impl VertexAIClaudeClient {
+    list_models_fn!(VertexAIClaudeConfig, &MODELS);
+    config_get_fn!(api_base, get_api_base);
+
+    pub const PROMPTS: [PromptType<'static>; 1] =
+        [("api_base", "API Base:", true, PromptKind::String)];
+
+    fn request_builder(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+        access_token: &str,
+    ) -> Result<RequestBuilder> {
+        let api_base = self.get_api_base()?;
+
+        let func = match data.stream {
+            true => "streamRawPredict",
+            false => "rawPredict",
+        };
+
+        let body = build_vertexai_body(data, &self.model)?;
+
+        let url = format!("{api_base}/{}:{}", self.model.name, func);
+
+        debug!("VertexAIClaude Request: {url} {body}");
+
+        let builder = client.post(url).bearer_auth(access_token).json(&body);
+
+        Ok(builder)
+    }
+}
+
+async fn send_message(builder: RequestBuilder) -> Result<CompletionOutput> {
+    let res = send_request(builder).await?;
+    let status = res.status();
+    let data: Value = res.json().await?;
+    if status != 200 {
+        catch_error(&data, status.as_u16())?;
+    }
+    extract_output(&data)
+}
+
+async fn send_message_streaming(builder: RequestBuilder, handler: &mut ReplyHandler) -> Result<()> {
+    let mut es = event_source(builder)?;
+    let mut tool_use = None;
+    while let Some(event) = es.next().await {
+        match event {
+            Ok(Event::Open) => {}
+            Ok(Event::Message(message)) => {
+                let data: Value = serde_json::from_str(&message.data)?;
+                if handle_event(&data, handler, &mut tool_use)? {
+                    es.close();
+                    break;
+                }
+            }
+            Err(err) => {
+                match err {
+                    EventSourceError::StreamEnded => {
+                        bail!(
+                            "The stream ended before `message_stop`, the output may be incomplete"
+                        )
+                    }
+                    EventSourceError::InvalidStatusCode(status, res) => {
+                        note_response(&res);
+                        let text = res.text().await?;
+                        let data: Value = match text.parse() {
+                            Ok(data) => data,
+                            Err(_) => {
+                                bail!("Invalid respoinse, status: {status}, text: {text}");
+                            }
+                        };
+                        catch_error(&data, status.as_u16())?;
+                    }
+                    EventSourceError::InvalidContentType(_, res) => {
+                        let text = res.text().await?;
+                        bail!("The API server should return data as 'text/event-stream', but it isn't. Check the client config. {text}");
+                    }
+                    _ => {
+                        bail!("{}", err);
+                    }
+                }
+                es.close();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The Messages body of Claude, with the model in the url instead.
+fn build_vertexai_body(data: SendData, model: &Model) -> Result<Value> {
+    let mut body = build_body(data, model)?;
+    if let Some(body) = body.as_object_mut() {
+        body.remove("model");
+        body.insert("anthropic_version".into(), ANTHROPIC_VERSION.into());
+    }
+    Ok(body)
+}
+
+/// Vertex AI rejects a request with its own envelope before it reaches Claude.
+fn catch_error(data: &Value, status: u16) -> Result<()> {
+    if data["error"]["status"].is_string() || data[0]["error"]["status"].is_string() {
+        super::vertexai::catch_error(data, status)
+    } else {
+        super::claude::catch_error(data, status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{fixture, Message};
+    use serde_json::json;
+
+    #[test]
+    fn test_build_vertexai_body() -> Result<()> {
+        let model = VertexAIClaudeClient::list_models(&VertexAIClaudeConfig::default()).remove(2);
+        let data = SendData {
+            messages: vec![Message::system("Be brief"), Message::user("Hi")],
+            temperature: None,
+            top_p: None,
+            functions: None,
+            stream: true,
+        };
+        assert_eq!(
+            build_vertexai_body(data, &model)?,
+            json!({
+                "anthropic_version": "vertex-2023-10-16",
+                "max_tokens": 4096,
+                "system": "Be brief",
+                "messages": [{ "role": "user", "content": [{ "type": "text", "text": "Hi" }] }],
+                "stream": true,
+            })
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let mut client = VertexAIClaudeClient::for_test(
+            "api_base: https://us-central1-aiplatform.googleapis.com/v1/projects/my-project/locations/us-central1/publishers/anthropic/models",
+        )?;
+        client.model = VertexAIClaudeClient::list_models(&client.config).remove(2);
+        let http = ReqwestClient::new();
+        let request = |client: &VertexAIClaudeClient, stream| {
+            client.request_builder(&http, fixture::send_data(stream), "token")
+        };
+
+        let output = send_message(
+            fixture::replay("vertexai-claude/success", request(&client, false)?).await?,
+        )
+        .await?;
+        assert_eq!(output.text, "Hello! How can I help you today?");
+
+        let (mut handler, _rx) = fixture::handler();
+        send_message_streaming(
+            fixture::replay("vertexai-claude/stream", request(&client, true)?).await?,
+            &mut handler,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "Hello! How can I help you today?");
+
+        let err = send_message(
+            fixture::replay("vertexai-claude/auth_failure", request(&client, false)?).await?,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().ends_with("(status: UNAUTHENTICATED)"));
+
+        // Over the output limit of haiku
+        client.model.max_output_tokens = Some(8192);
+        let err = send_message(
+            fixture::replay("vertexai-claude/invalid_request", request(&client, false)?).await?,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "max_tokens: 8192 > 4096, which is the maximum allowed number of output tokens for claude-3-haiku-20240307 (type: invalid_request_error)"
+        );
+        Ok(())
+    }
+}
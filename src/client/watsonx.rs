@@ -0,0 +1,321 @@
+use super::openai::openai_build_body;
+use super::{
+    event_source, note_response, send_request, send_token_request, Client, CompletionOutput, ExtraConfig, Model,
+    ModelConfig, PromptType, ReplyHandler, SendData, WatsonxClient,
+};
+
+use crate::utils::PromptKind;
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use futures_util::StreamExt;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use reqwest_eventsource::{Error as EventSourceError, Event};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+const IAM_TOKEN_URL: &str = "https://iam.cloud.ibm.com/identity/token";
+const API_VERSION: &str = "2024-05-01";
+const DEFAULT_REGION: &str = "us-south";
+
+// Refresh the token this long before it expires, so a long session doesn't hit a 401
+const TOKEN_REFRESH_MARGIN: i64 = 300;
+
+const MODELS: [(&str, usize, &str); 3] = [
+    // https://cloud.ibm.com/apidocs/watsonx-ai#list-foundation-model-specs
+    ("ibm/granite-13b-chat-v2", 8192, "text"),
+    ("meta-llama/llama-3-70b-instruct", 8192, "text"),
+    ("meta-llama/llama-3-8b-instruct", 8192, "text"),
+];
+
+lazy_static! {
+    /// The IAM token and its expiry of each watsonx client, by client name.
+    static ref ACCESS_TOKENS: Mutex<HashMap<String, (String, i64)>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct WatsonxConfig {
+    pub name: Option<String>,
+    pub api_key: Option<String>,
+    pub project_id: Option<String>,
+    pub region: Option<String>,
+    pub api_base: Option<String>,
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+#[async_trait]
+impl Client for WatsonxClient {
+    client_common_fns!();
+
+    async fn send_message_inner(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+    ) -> Result<CompletionOutput> {
+        let token = self.prepare_access_token(client).await?;
+        let builder = self.request_builder(client, data, &token)?;
+        send_message(builder)
+            .await
+            .inspect_err(|_| self.check_token(&token))
+    }
+
+    async fn send_message_streaming_inner(
+        &self,
+        client: &ReqwestClient,
+        handler: &mut ReplyHandler,
+        data: SendData,
+    ) -> Result<()> {
+        let token = self.prepare_access_token(client).await?;
+        let builder = self.request_builder(client, data, &token)?;
+        send_message_streaming(builder, handler)
+            .await
+            .inspect_err(|_| self.check_token(&token))
+    }
+}
+
+impl WatsonxClient {
+    list_models_fn!(WatsonxConfig, &MODELS);
+    config_get_fn!(api_key, get_api_key);
+    config_get_fn!(project_id, get_project_id);
+    config_get_fn!(region, get_region);
+    config_get_fn!(api_base, get_api_base);
+
+    pub const PROMPTS: [PromptType<'static>; 2] = [
+        ("api_key", "API Key:", true, PromptKind::String),
+        ("project_id", "Project ID:", true, PromptKind::String),
+    ];
+
+    fn request_builder(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+        token: &str,
+    ) -> Result<RequestBuilder> {
+        let project_id = self.get_project_id()?;
+        let api_base = match self.get_api_base() {
+            Ok(api_base) => api_base.trim_end_matches('/').to_string(),
+            Err(_) => {
+                let region = self
+                    .get_region()
+                    .unwrap_or_else(|_| DEFAULT_REGION.to_string());
+                format!("https://{region}.ml.cloud.ibm.com")
+            }
+        };
+
+        let func = match data.stream {
+            true => "chat_stream",
+            false => "chat",
+        };
+
+        let mut body = openai_build_body(data, &self.model);
+        if let Some(body) = body.as_object_mut() {
+            body.remove("model");
+            body.remove("stream");
+            body.insert("model_id".into(), self.model.name.clone().into());
+            body.insert("project_id".into(), project_id.into());
+        }
+        self.model.merge_extra_fields(&mut body);
+
+        let url = format!("{api_base}/ml/v1/text/{func}?version={API_VERSION}");
+
+        debug!("Watsonx Request: {url} {body}");
+
+        let builder = client.post(url).bearer_auth(token).json(&body);
+
+        Ok(builder)
+    }
+
+    /// The cached IAM token of this client, fetched anew shortly before it expires.
+    async fn prepare_access_token(&self, client: &ReqwestClient) -> Result<String> {
+        let now = Utc::now().timestamp();
+        if let Some((token, expires_at)) = ACCESS_TOKENS.lock().get(&self.model.client_name) {
+            if now + TOKEN_REFRESH_MARGIN < *expires_at {
+                return Ok(token.clone());
+            }
+        }
+        let api_key = self.get_api_key()?;
+        let builder = token_request_builder(client, &api_key);
+        let (token, expires_in) = fetch_access_token(builder)
+            .await
+            .with_context(|| "Failed to fetch access token")?;
+        ACCESS_TOKENS.lock().insert(
+            self.model.client_name.clone(),
+            (token.clone(), now + expires_in),
+        );
+        Ok(token)
+    }
+
+    /// Drop the cached token after a failed request, so the next one starts with a fresh token.
+    fn check_token(&self, token: &str) {
+        let mut tokens = ACCESS_TOKENS.lock();
+        if tokens.get(&self.model.client_name).map(|(v, _)| v == token) == Some(true) {
+            tokens.remove(&self.model.client_name);
+        }
+    }
+}
+
+fn token_request_builder(client: &ReqwestClient, api_key: &str) -> RequestBuilder {
+    client.post(IAM_TOKEN_URL).form(&[
+        ("grant_type", "urn:ibm:params:oauth:grant-type:apikey"),
+        ("apikey", api_key),
+    ])
+}
+
+async fn fetch_access_token(builder: RequestBuilder) -> Result<(String, i64)> {
+    let data: Value = send_token_request(builder).await?.json().await?;
+    if let (Some(access_token), Some(expires_in)) =
+        (data["access_token"].as_str(), data["expires_in"].as_i64())
+    {
+        Ok((access_token.to_string(), expires_in))
+    } else if let Some(message) = data["errorMessage"].as_str() {
+        match data["errorCode"].as_str() {
+            Some(code) => bail!("{message} (code: {code})"),
+            None => bail!("{message}"),
+        }
+    } else {
+        bail!("Invalid response data: {data}")
+    }
+}
+
+async fn send_message(builder: RequestBuilder) -> Result<CompletionOutput> {
+    let res = send_request(builder).await?;
+    let status = res.status();
+    let data: Value = res.json().await?;
+    if status != 200 {
+        catch_error(&data, status.as_u16())?;
+    }
+
+    let text = data["choices"][0]["message"]["content"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+
+    Ok(CompletionOutput {
+        text: text.to_string(),
+        input_tokens: data["usage"]["prompt_tokens"].as_u64(),
+        output_tokens: data["usage"]["completion_tokens"].as_u64(),
+        tool_calls: vec![],
+        request_id: None,
+        stop_sequence: None,
+        stop_reason: None,
+        stats: None,
+        cache_usage: None,
+    })
+}
+
+async fn send_message_streaming(builder: RequestBuilder, handler: &mut ReplyHandler) -> Result<()> {
+    let mut es = event_source(builder)?;
+    while let Some(event) = es.next().await {
+        match event {
+            Ok(Event::Open) => {}
+            Ok(Event::Message(message)) => {
+                let data: Value = serde_json::from_str(&message.data)?;
+                // `chat_stream` sends `choices[].delta`, the generation endpoints `results[]`
+                let text = data["choices"][0]["delta"]["content"]
+                    .as_str()
+                    .or_else(|| data["results"][0]["generated_text"].as_str());
+                if let Some(text) = text {
+                    handler.text(text)?;
+                }
+                let usage = &data["usage"];
+                if usage.is_object() {
+                    handler.set_usage(
+                        usage["prompt_tokens"].as_u64(),
+                        usage["completion_tokens"].as_u64(),
+                    );
+                }
+            }
+            Err(err) => {
+                match err {
+                    EventSourceError::StreamEnded => {}
+                    EventSourceError::InvalidStatusCode(status, res) => {
+                        note_response(&res);
+                        let text = res.text().await?;
+                        let data: Value = match text.parse() {
+                            Ok(data) => data,
+                            Err(_) => {
+                                bail!("Invalid respoinse, status: {status}, text: {text}");
+                            }
+                        };
+                        catch_error(&data, status.as_u16())?;
+                    }
+                    EventSourceError::InvalidContentType(_, res) => {
+                        let text = res.text().await?;
+                        bail!("The API server should return data as 'text/event-stream', but it isn't. Check the client config. {text}");
+                    }
+                    _ => {
+                        bail!("{}", err);
+                    }
+                }
+                es.close();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn catch_error(data: &Value, status: u16) -> Result<()> {
+    debug!("Invalid response, status: {status}, data: {data}");
+    let error = &data["errors"][0];
+    match (error["message"].as_str(), error["code"].as_str()) {
+        (Some(message), Some(code)) => bail!("{message} (code: {code})"),
+        (Some(message), None) => bail!("{message}"),
+        _ => bail!("Invalid response, status: {status}, data: {data}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::fixture;
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let client = WatsonxClient::for_test("{ api_key: xxx, project_id: p-1234 }")?;
+        let http = ReqwestClient::new();
+        let request = |stream| client.request_builder(&http, fixture::send_data(stream), "token");
+
+        let (token, expires_in) = fetch_access_token(
+            fixture::replay("watsonx/token", token_request_builder(&http, "xxx")).await?,
+        )
+        .await?;
+        assert_eq!(
+            (token.as_str(), expires_in),
+            ("eyJraWQiOiIyMDI0MDUwNTA4MzkiLCJhbGciOiJSUzI1NiJ9", 3600)
+        );
+
+        let err = fetch_access_token(
+            fixture::replay("watsonx/token_failure", token_request_builder(&http, "xxx")).await?,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Provided API key could not be found. (code: BXNIM0415E)"
+        );
+
+        let (mut handler, _rx) = fixture::handler();
+        send_message_streaming(
+            fixture::replay("watsonx/stream", request(true)?).await?,
+            &mut handler,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "Hello! How can I help you today?");
+        assert_eq!(handler.get_usage(), (Some(12), Some(10)));
+
+        let err = send_message(fixture::replay("watsonx/auth_failure", request(false)?).await?)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to authenticate the request due to an expired token (code: authentication_token_expired)"
+        );
+        Ok(())
+    }
+}
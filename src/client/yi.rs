@@ -0,0 +1,151 @@
+use super::openai::openai_build_body;
+use super::{message::*, ExtraConfig, Model, ModelConfig, PromptType, SendData, YiClient};
+
+use crate::utils::PromptKind;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use serde::Deserialize;
+use serde_json::Value;
+
+const API_BASE: &str = "https://api.lingyiwanwu.com/v1";
+
+const MODELS: [(&str, usize, &str); 4] = [
+    // https://platform.lingyiwanwu.com/docs#模型与计费
+    ("yi-large", 32768, "text"),
+    ("yi-large-turbo", 16384, "text"),
+    ("yi-medium", 16384, "text"),
+    ("yi-vl-plus", 4096, "text,vision"),
+];
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct YiConfig {
+    pub name: Option<String>,
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+openai_compatible_client!(YiClient);
+
+impl YiClient {
+    list_models_fn!(YiConfig, &MODELS);
+    config_get_fn!(api_key, get_api_key);
+
+    pub const PROMPTS: [PromptType<'static>; 1] =
+        [("api_key", "API Key:", true, PromptKind::String)];
+
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let api_key = self.get_api_key()?;
+
+        let body = build_body(data, &self.model)?;
+
+        let url = format!("{API_BASE}/chat/completions");
+
+        debug!("Yi Request: {url} {body}");
+
+        let builder = client.post(url).bearer_auth(api_key).json(&body);
+
+        Ok(builder)
+    }
+}
+
+fn build_body(data: SendData, model: &Model) -> Result<Value> {
+    let network_image_urls: Vec<&String> = data
+        .messages
+        .iter()
+        .filter_map(|message| match &message.content {
+            MessageContent::Array(list) => Some(list),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|item| match item {
+            MessageContentPart::ImageUrl {
+                image_url: ImageUrl { url },
+            } if !url.starts_with("data:") => Some(url),
+            _ => None,
+        })
+        .collect();
+
+    if !network_image_urls.is_empty() {
+        bail!(
+            "The model does not support network images: {:?}",
+            network_image_urls
+        );
+    }
+
+    Ok(openai_build_body(data, model))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{
+        fixture,
+        openai::{openai_send_message, openai_send_message_streaming},
+    };
+
+    #[test]
+    fn test_build_body() -> Result<()> {
+        let model = YiClient::list_models(&YiConfig::default()).remove(3);
+        let image = |url: &str| Message {
+            role: MessageRole::User,
+            content: MessageContent::Array(vec![
+                MessageContentPart::Text {
+                    text: "What's in it?".into(),
+                },
+                MessageContentPart::ImageUrl {
+                    image_url: ImageUrl { url: url.into() },
+                },
+            ]),
+            pinned: false,
+        };
+        let send_data = |message: Message| SendData {
+            messages: vec![message],
+            temperature: None,
+            top_p: None,
+            functions: None,
+            stream: false,
+        };
+
+        let body = build_body(send_data(image("data:image/png;base64,iVBORw0K")), &model)?;
+        assert_eq!(
+            body["messages"][0]["content"][1]["image_url"]["url"],
+            "data:image/png;base64,iVBORw0K"
+        );
+
+        let err = build_body(send_data(image("https://example.com/a.png")), &model).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"The model does not support network images: ["https://example.com/a.png"]"#
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fixtures() -> Result<()> {
+        let client = YiClient::for_test("api_key: xxx")?;
+        let http = ReqwestClient::new();
+        let request = |stream| client.request_builder(&http, fixture::send_data(stream));
+
+        let (mut handler, _rx) = fixture::handler();
+        openai_send_message_streaming(
+            fixture::replay("yi/stream", request(true)?).await?,
+            &mut handler,
+        )
+        .await?;
+        assert_eq!(handler.get_buffer(), "Hello! How can I help you today?");
+        assert_eq!(handler.get_usage(), (Some(11), Some(10)));
+
+        let err = openai_send_message(fixture::replay("yi/auth_failure", request(false)?).await?)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Incorrect API key provided (type: invalid_request_error)"
+        );
+        Ok(())
+    }
+}
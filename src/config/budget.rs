@@ -0,0 +1,97 @@
+use super::{GlobalConfig, Input};
+
+use crate::client::Model;
+
+use anyhow::{bail, Result};
+use inquire::Confirm;
+use is_terminal::IsTerminal;
+use serde::Deserialize;
+use std::io::{stderr, stdin};
+
+/// Spend limits in USD, for models with `input_price`/`output_price`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BudgetConfig {
+    pub request_soft_limit: Option<f64>,
+    pub request_hard_limit: Option<f64>,
+    pub session_soft_limit: Option<f64>,
+    pub session_hard_limit: Option<f64>,
+}
+
+impl BudgetConfig {
+    /// What is left of the session budget.
+    pub fn remaining(&self, spent: f64) -> Option<f64> {
+        self.session_hard_limit
+            .or(self.session_soft_limit)
+            .map(|limit| (limit - spent).max(0.0))
+    }
+}
+
+/// Estimate the cost of sending `input` to `model`, counting the output as `max_output_tokens`.
+///
+/// Refuse the request above a hard limit, and ask for confirmation above a soft limit.
+pub fn check_budget(config: &GlobalConfig, model: &Model, input: &Input) -> Result<()> {
+    let (budget, spent, estimate) = {
+        let config = config.read();
+        let Some(budget) = config.budget.clone() else {
+            return Ok(());
+        };
+        if config.dry_run {
+            return Ok(());
+        }
+        let messages = config.build_messages(input)?;
+        let input_tokens = model.total_tokens(&messages);
+        let output_tokens = model.max_output_tokens.unwrap_or_default().max(0) as usize;
+        let Some(estimate) = model.cost(input_tokens, output_tokens) else {
+            return Ok(());
+        };
+        (budget, config.spent(), estimate)
+    };
+    let total = spent + estimate;
+    let estimate_text = format!("The request may cost {}", format_cost(estimate));
+    if let Some(limit) = budget.request_hard_limit {
+        if estimate > limit {
+            bail!(
+                "{estimate_text}, over the request limit {}",
+                format_cost(limit)
+            );
+        }
+    }
+    if let Some(limit) = budget.session_hard_limit {
+        if total > limit {
+            bail!(
+                "{estimate_text} on top of {} spent, over the session limit {}",
+                format_cost(spent),
+                format_cost(limit)
+            );
+        }
+    }
+    let warning = match (budget.request_soft_limit, budget.session_soft_limit) {
+        (Some(limit), _) if estimate > limit => Some(format!(
+            "{estimate_text}, over the request soft limit {}",
+            format_cost(limit)
+        )),
+        (_, Some(limit)) if total > limit => Some(format!(
+            "{estimate_text} on top of {} spent, over the session soft limit {}",
+            format_cost(spent),
+            format_cost(limit)
+        )),
+        _ => None,
+    };
+    if let Some(warning) = warning {
+        if stdin().is_terminal() && stderr().is_terminal() {
+            let ans = Confirm::new(&format!("{warning}. Continue?"))
+                .with_default(false)
+                .prompt()?;
+            if !ans {
+                bail!("Cancelled");
+            }
+        } else {
+            eprintln!("⚠️  {warning}");
+        }
+    }
+    Ok(())
+}
+
+pub fn format_cost(cost: f64) -> String {
+    format!("${cost:.4}")
+}
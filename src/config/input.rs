@@ -29,6 +29,7 @@ pub struct Input {
     medias: Vec<String>,
     data_urls: HashMap<String, String>,
     context: InputContext,
+    pinned: bool,
 }
 
 impl Input {
@@ -38,6 +39,7 @@ impl Input {
             medias: Default::default(),
             data_urls: Default::default(),
             context,
+            pinned: false,
         }
     }
 
@@ -83,9 +85,25 @@ impl Input {
             medias,
             data_urls,
             context,
+            pinned: false,
         })
     }
 
+    pub fn attach_media(&mut self, data_url: String, name: &str) {
+        self.data_urls
+            .insert(sha256sum(&data_url), name.to_string());
+        self.medias.push(data_url);
+    }
+
+    /// Pin the question and its answer in the session.
+    pub fn set_pinned(&mut self, pinned: bool) {
+        self.pinned = pinned;
+    }
+
+    pub fn pinned(&self) -> bool {
+        self.pinned
+    }
+
     pub fn is_empty(&self) -> bool {
         self.text.is_empty() && self.medias.is_empty()
     }
@@ -243,6 +261,26 @@ fn is_image_ext(path: &Path) -> bool {
         .unwrap_or_default()
 }
 
+/// Detect the image format from the magic numbers at the head of the data
+pub fn detect_image_mime(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("image/jpeg")
+    } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else {
+        None
+    }
+}
+
+pub fn bytes_to_data_url(mime_type: &str, data: &[u8]) -> String {
+    let encoded_image = STANDARD.encode(data);
+    format!("data:{};base64,{}", mime_type, encoded_image)
+}
+
 fn read_media_to_data_url<P: AsRef<Path>>(image_path: P) -> Result<String> {
     let image_path = image_path.as_ref();
 
@@ -251,10 +289,7 @@ fn read_media_to_data_url<P: AsRef<Path>>(image_path: P) -> Result<String> {
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer)?;
 
-    let encoded_image = STANDARD.encode(buffer);
-    let data_url = format!("data:{};base64,{}", mime_type, encoded_image);
-
-    Ok(data_url)
+    Ok(bytes_to_data_url(&mime_type, &buffer))
 }
 
 fn read_file<P: AsRef<Path>>(file_path: P) -> Result<String> {
@@ -265,3 +300,28 @@ fn read_file<P: AsRef<Path>>(file_path: P) -> Result<String> {
     file.read_to_string(&mut text)?;
     Ok(text)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_image_mime() {
+        assert_eq!(
+            detect_image_mime(b"\x89PNG\r\n\x1a\n\x00\x00"),
+            Some("image/png")
+        );
+        assert_eq!(
+            detect_image_mime(&[0xff, 0xd8, 0xff, 0xe0, 0x00]),
+            Some("image/jpeg")
+        );
+        assert_eq!(
+            detect_image_mime(b"RIFF\x00\x00\x00\x00WEBPVP8 "),
+            Some("image/webp")
+        );
+        assert_eq!(detect_image_mime(b"GIF89a\x01\x00"), Some("image/gif"));
+        assert_eq!(detect_image_mime(b"RIFF\x00\x00\x00\x00WAVE"), None);
+        assert_eq!(detect_image_mime(b"hello world"), None);
+        assert_eq!(detect_image_mime(b""), None);
+    }
+}
@@ -1,18 +1,22 @@
+mod budget;
 mod input;
 mod role;
 mod session;
 
-pub use self::input::{Input, InputContext};
+use self::budget::format_cost;
+pub use self::budget::{check_budget, BudgetConfig};
+pub use self::input::{bytes_to_data_url, detect_image_mime, Input, InputContext};
 use self::role::Role;
 pub use self::role::{CODE_ROLE, EXPLAIN_ROLE, SHELL_ROLE};
-use self::session::{Session, TEMP_SESSION_NAME};
+use self::session::{CompressTurns, Session, TEMP_SESSION_NAME};
 
 use crate::client::{
-    create_client_config, list_client_types, list_models, ClientConfig, ExtraConfig, Message,
-    Model, OpenAIClient, SendData,
+    create_client_config, create_ollama_config, list_chat_models, list_client_types, list_models,
+    ClientConfig, ExtraConfig, Message, Model, OllamaClient, OpenAIClient, SendData,
 };
 use crate::render::{MarkdownRender, RenderOptions};
 use crate::utils::{get_env_name, light_theme_from_colorfgbg, now, render_prompt, set_text};
+use crate::webhook::WebhookConfig;
 
 use anyhow::{anyhow, bail, Context, Result};
 use inquire::{Confirm, Select, Text};
@@ -44,6 +48,8 @@ const CLIENTS_FIELD: &str = "clients";
 const SUMMARIZE_PROMPT: &str =
     "Summarize the discussion briefly in 200 words or less to use as a prompt for future context.";
 const SUMMARY_PROMPT: &str = "This is a summary of the chat history as a recap: ";
+const TITLE_PROMPT: &str =
+    "Write a title of 5 to 8 words for the conversation below. Reply with the title only.\n\n";
 const LEFT_PROMPT: &str = "{color.green}{?session {session}{?role /}}{role}{color.cyan}{?session )}{!session >}{color.reset} ";
 const RIGHT_PROMPT: &str = "{color.purple}{?session {?consume_tokens {consume_tokens}({consume_percent}%)}{!consume_tokens {consume_tokens}}}{color.reset}";
 
@@ -66,10 +72,17 @@ pub struct Config {
     pub prelude: Option<String>,
     pub buffer_editor: Option<String>,
     pub compress_threshold: usize,
+    pub compress_ratio: Option<f64>,
+    pub compress_turns: usize,
+    pub compress_keep_original: bool,
     pub summarize_prompt: Option<String>,
     pub summary_prompt: Option<String>,
+    pub utility_model: Option<String>,
+    pub auto_title: bool,
     pub left_prompt: Option<String>,
     pub right_prompt: Option<String>,
+    pub webhook: Option<WebhookConfig>,
+    pub budget: Option<BudgetConfig>,
     pub clients: Vec<ClientConfig>,
     #[serde(skip)]
     pub roles: Vec<Role>,
@@ -82,7 +95,13 @@ pub struct Config {
     #[serde(skip)]
     pub working_mode: WorkingMode,
     #[serde(skip)]
+    pub tag: Option<String>,
+    #[serde(skip)]
     pub last_message: Option<(Input, String)>,
+    #[serde(skip)]
+    pub spent: f64,
+    #[serde(skip)]
+    pub verbose: bool,
 }
 
 impl Default for Config {
@@ -103,17 +122,27 @@ impl Default for Config {
             prelude: None,
             buffer_editor: None,
             compress_threshold: 2000,
+            compress_ratio: None,
+            compress_turns: 4,
+            compress_keep_original: true,
             summarize_prompt: None,
             summary_prompt: None,
+            utility_model: None,
+            auto_title: false,
             left_prompt: None,
             right_prompt: None,
+            webhook: None,
+            budget: None,
             clients: vec![],
             roles: vec![],
             role: None,
             session: None,
             model: Default::default(),
             working_mode: WorkingMode::Command,
+            tag: None,
             last_message: None,
+            spent: 0.0,
+            verbose: false,
         }
     }
 }
@@ -421,7 +450,7 @@ impl Config {
             .wrap
             .clone()
             .map_or_else(|| String::from("no"), |v| v.to_string());
-        let items = vec![
+        let mut items = vec![
             ("model", self.model.id()),
             ("temperature", format_option(&self.temperature)),
             ("top_p", format_option(&self.top_p)),
@@ -436,11 +465,49 @@ impl Config {
             ("keybindings", self.keybindings.stringify().into()),
             ("prelude", format_option(&self.prelude)),
             ("compress_threshold", self.compress_threshold.to_string()),
+            ("compress_ratio", format_option(&self.compress_ratio)),
+            ("utility_model", format_option(&self.utility_model)),
+            ("auto_title", self.auto_title.to_string()),
             ("config_file", display_path(&Self::config_file()?)),
             ("roles_file", display_path(&Self::roles_file()?)),
             ("messages_file", display_path(&Self::messages_file()?)),
             ("sessions_dir", display_path(&Self::sessions_dir()?)),
         ];
+        if let Some(budget) = &self.budget {
+            let spent = self.spent();
+            items.push(("spent", format_cost(spent)));
+            if let Some(remaining) = budget.remaining(spent) {
+                items.push(("remaining", format_cost(remaining)));
+            }
+        }
+        let output = items
+            .iter()
+            .map(|(name, value)| format!("{name:<20}{value}"))
+            .collect::<Vec<String>>()
+            .join("\n");
+        Ok(output)
+    }
+
+    pub fn budget_info(&self) -> Result<String> {
+        let Some(budget) = &self.budget else {
+            bail!("No budget")
+        };
+        let spent = self.spent();
+        let mut items = vec![("spent", format_cost(spent))];
+        if let Some(remaining) = budget.remaining(spent) {
+            items.push(("remaining", format_cost(remaining)));
+        }
+        let limits = [
+            ("request_soft_limit", budget.request_soft_limit),
+            ("request_hard_limit", budget.request_hard_limit),
+            ("session_soft_limit", budget.session_soft_limit),
+            ("session_hard_limit", budget.session_hard_limit),
+        ];
+        for (name, limit) in limits {
+            if let Some(limit) = limit {
+                items.push((name, format_cost(limit)));
+            }
+        }
         let output = items
             .iter()
             .map(|(name, value)| format!("{name:<20}{value}"))
@@ -449,6 +516,21 @@ impl Config {
         Ok(output)
     }
 
+    /// The spending of the current session, or of this run outside a session.
+    pub fn spent(&self) -> f64 {
+        match &self.session {
+            Some(session) => session.spent(),
+            None => self.spent,
+        }
+    }
+
+    pub fn charge(&mut self, cost: f64) {
+        match self.session.as_mut() {
+            Some(session) => session.charge(cost),
+            None => self.spent += cost,
+        }
+    }
+
     pub fn role_info(&self) -> Result<String> {
         if let Some(role) = &self.role {
             role.export()
@@ -488,7 +570,7 @@ impl Config {
         let (values, filter) = if args.len() == 1 {
             let values = match cmd {
                 ".role" => self.roles.iter().map(|v| v.name.clone()).collect(),
-                ".model" => list_models(self).into_iter().map(|v| v.id()).collect(),
+                ".model" => list_chat_models(self).into_iter().map(|v| v.id()).collect(),
                 ".session" => self.list_sessions(),
                 ".set" => vec![
                     "temperature ",
@@ -636,8 +718,16 @@ impl Config {
                     if !ans {
                         return Ok(());
                     }
+                    let default_name = match &session.title {
+                        Some(title) => Self::session_name_from_title(title)?,
+                        None => String::new(),
+                    };
                     while session.is_temp() || session.name().is_empty() {
-                        session.name = Text::new("Session name:").prompt()?;
+                        let mut prompt = Text::new("Session name:");
+                        if !default_name.is_empty() {
+                            prompt = prompt.with_default(&default_name);
+                        }
+                        session.name = prompt.prompt()?;
                     }
                 }
                 Self::save_session_to_file(&mut session)?;
@@ -650,16 +740,71 @@ impl Config {
         if let Some(session) = self.session.as_mut() {
             if !name.is_empty() {
                 session.name = name.to_string();
+            } else if let (true, Some(title)) = (session.is_temp(), &session.title) {
+                let name = Self::session_name_from_title(title)?;
+                if !name.is_empty() {
+                    session.name = name;
+                }
             }
             Self::save_session_to_file(session)?;
         }
         Ok(())
     }
 
+    /// Give the temp session a title after its first exchange, if `auto_title` is on.
+    ///
+    /// The title falls back to the question; the returned prompt asks the utility model for a
+    /// better one.
+    pub fn start_titling_session(&mut self, input: &Input, output: &str) -> Option<String> {
+        if !self.auto_title {
+            return None;
+        }
+        let session = self.session.as_mut()?;
+        if !session.is_temp() || session.title.is_some() {
+            return None;
+        }
+        session.title = Some(input.summary());
+        Some(format!(
+            "{TITLE_PROMPT}User: {}\nAssistant: {output}",
+            input.render()
+        ))
+    }
+
+    pub fn set_session_title(&mut self, title: &str) {
+        let title = title.lines().next().unwrap_or_default();
+        let title = title
+            .trim()
+            .trim_matches(|c| c == '"' || c == '\'' || c == '*');
+        if title.is_empty() {
+            return;
+        }
+        if let Some(session) = self.session.as_mut() {
+            if session.is_temp() {
+                session.title = Some(title.to_string());
+            }
+        }
+    }
+
+    /// The model for internal tasks such as session titles, defaults to the current model.
+    pub fn get_utility_model(&self) -> Result<Model> {
+        match &self.utility_model {
+            Some(id) => Model::find(&list_models(self), id)
+                .ok_or_else(|| anyhow!("Invalid utility_model '{id}'")),
+            None => Ok(self.model.clone()),
+        }
+    }
+
     pub fn has_session(&self) -> bool {
         self.session.is_some()
     }
 
+    pub fn pin_session_message(&mut self) -> Result<()> {
+        match self.session.as_mut() {
+            Some(session) => session.pin_last_turn(),
+            None => bail!("No session"),
+        }
+    }
+
     pub fn clear_session_messages(&mut self) -> Result<()> {
         if let Some(session) = self.session.as_mut() {
             session.clear_messages();
@@ -667,6 +812,32 @@ impl Config {
         Ok(())
     }
 
+    /// Turn a title into a session name that is not taken yet.
+    fn session_name_from_title(title: &str) -> Result<String> {
+        let mut slug = String::new();
+        for c in title.chars().flat_map(|c| c.to_lowercase()) {
+            if c.is_alphanumeric() {
+                slug.push(c);
+            } else if !slug.is_empty() && !slug.ends_with('-') {
+                slug.push('-');
+            }
+            if slug.chars().count() >= 60 {
+                break;
+            }
+        }
+        let slug = slug.trim_end_matches('-');
+        if slug.is_empty() || slug == TEMP_SESSION_NAME {
+            return Ok(String::new());
+        }
+        let mut name = slug.to_string();
+        let mut i = 1;
+        while Self::session_file(&name)?.exists() {
+            i += 1;
+            name = format!("{slug}-{i}");
+        }
+        Ok(name)
+    }
+
     pub fn list_sessions(&self) -> Vec<String> {
         let sessions_dir = match Self::sessions_dir() {
             Ok(dir) => dir,
@@ -690,7 +861,11 @@ impl Config {
 
     pub fn should_compress_session(&mut self) -> bool {
         if let Some(session) = self.session.as_mut() {
-            if session.need_compress(self.compress_threshold) {
+            let need_compress = match self.compress_ratio {
+                Some(ratio) => session.need_partial_compress(ratio),
+                None => session.need_compress(self.compress_threshold),
+            };
+            if need_compress {
                 session.compressing = true;
                 return true;
             }
@@ -705,6 +880,32 @@ impl Config {
         }
     }
 
+    /// Pick the oldest turns of the session to summarize, with the prompt for the utility model.
+    pub fn prepare_partial_compress(&self) -> Option<(CompressTurns, String)> {
+        let session = self.session.as_ref()?;
+        let turns = session.pick_turns(self.compress_turns)?;
+        let prompt = format!(
+            "{}\n\n{}",
+            self.summarize_prompt(),
+            session.render_turns(&turns)
+        );
+        Some((turns, prompt))
+    }
+
+    pub fn partial_compress_session(&mut self, turns: &CompressTurns, summary: &str) {
+        if let Some(session) = self.session.as_mut() {
+            let summary_prompt = self.summary_prompt.as_deref().unwrap_or(SUMMARY_PROMPT);
+            let compressed = session.compress_turns(
+                turns,
+                format!("{}{}", summary_prompt, summary),
+                self.compress_keep_original,
+            );
+            if !compressed {
+                debug!("The session changed while it was summarized, drop the summary");
+            }
+        }
+    }
+
     pub fn summarize_prompt(&self) -> &str {
         self.summarize_prompt.as_deref().unwrap_or(SUMMARIZE_PROMPT)
     }
@@ -782,11 +983,23 @@ impl Config {
         } else {
             self.top_p
         };
-        self.model.max_input_tokens_limit(&messages)?;
+        if let Some(session) = input.session(&self.session) {
+            session.guard_pinned_tokens()?;
+        }
+        // The client checks the exact count of the API in place of the estimate
+        let api_counted = self
+            .clients
+            .iter()
+            .any(|v| v.counts_input_tokens(&self.model.client_name));
+        if !api_counted {
+            self.model.max_input_tokens_limit(&messages)?;
+        }
+        self.model.guard_vision(&messages)?;
         Ok(SendData {
             messages,
             temperature,
             top_p,
+            functions: None,
             stream,
         })
     }
@@ -933,7 +1146,7 @@ impl Config {
         let model = match &self.model_id {
             Some(v) => v.clone(),
             None => {
-                let models = list_models(self);
+                let models = list_chat_models(self);
                 if models.is_empty() {
                     bail!("No available model");
                 }
@@ -1100,7 +1313,11 @@ fn create_config_file(config_path: &Path) -> Result<()> {
     let client = Select::new("Platform:", list_client_types()).prompt()?;
 
     let mut config = serde_json::json!({});
-    let (model, clients_config) = create_client_config(client)?;
+    let (model, clients_config) = if client == OllamaClient::NAME {
+        create_ollama_config()?
+    } else {
+        create_client_config(client)?
+    };
     config["model"] = model.into();
     config[CLIENTS_FIELD] = clients_config;
 
@@ -144,16 +144,19 @@ If there is a lack of details, provide most logical solution, without requesting
             vec![Message {
                 role: MessageRole::User,
                 content,
+                pinned: false,
             }]
         } else {
             vec![
                 Message {
                     role: MessageRole::System,
                     content: MessageContent::Text(self.prompt.clone()),
+                    pinned: false,
                 },
                 Message {
                     role: MessageRole::User,
                     content,
+                    pinned: false,
                 },
             ]
         }
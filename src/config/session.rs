@@ -1,3 +1,4 @@
+use super::budget::format_cost;
 use super::input::resolve_data_url;
 use super::{Config, Input, Model};
 
@@ -27,6 +28,8 @@ pub struct Session {
     #[serde(default)]
     compressed_messages: Vec<Message>,
     compress_threshold: Option<usize>,
+    #[serde(default)]
+    spent: f64,
     #[serde(skip)]
     pub name: String,
     #[serde(skip)]
@@ -36,9 +39,19 @@ pub struct Session {
     #[serde(skip)]
     pub compressing: bool,
     #[serde(skip)]
+    pub title: Option<String>,
+    #[serde(skip)]
     pub model: Model,
 }
 
+/// The turns of a session picked to compress, with the messages up to them at the time.
+#[derive(Debug, Clone)]
+pub struct CompressTurns {
+    session: String,
+    indices: Vec<usize>,
+    prefix: Vec<Message>,
+}
+
 impl Session {
     pub fn new(config: &Config, name: &str) -> Self {
         Self {
@@ -49,11 +62,13 @@ impl Session {
             messages: vec![],
             compressed_messages: vec![],
             compress_threshold: None,
+            spent: 0.0,
             data_urls: Default::default(),
             name: name.to_string(),
             path: None,
             dirty: false,
             compressing: false,
+            title: None,
             model: config.model.clone(),
         }
     }
@@ -97,10 +112,27 @@ impl Session {
         threshold >= 1000 && self.tokens() > threshold
     }
 
+    /// Whether the messages fill more than `ratio` of the context window.
+    pub fn need_partial_compress(&self, ratio: f64) -> bool {
+        match self.model.max_input_tokens {
+            Some(max_input_tokens) => self.tokens() as f64 > max_input_tokens as f64 * ratio,
+            None => false,
+        }
+    }
+
     pub fn tokens(&self) -> usize {
         self.model.total_tokens(&self.messages)
     }
 
+    pub fn spent(&self) -> f64 {
+        self.spent
+    }
+
+    pub fn charge(&mut self, cost: f64) {
+        self.spent += cost;
+        self.dirty = true;
+    }
+
     pub fn user_messages_len(&self) -> usize {
         self.messages.iter().filter(|v| v.role.is_user()).count()
     }
@@ -131,6 +163,9 @@ impl Session {
             data["total/max"] = format!("{}%", percent).into();
         }
         data["messages"] = json!(self.messages);
+        if !self.compressed_messages.is_empty() {
+            data["compressed_messages"] = json!(self.compressed_messages);
+        }
 
         let output = serde_yaml::to_string(&data)
             .with_context(|| format!("Unable to show info about session {}", &self.name))?;
@@ -165,6 +200,10 @@ impl Session {
             items.push(("max_input_tokens", max_input_tokens.to_string()));
         }
 
+        if self.spent > 0.0 {
+            items.push(("spent", format_cost(self.spent)));
+        }
+
         let mut lines: Vec<String> = items
             .iter()
             .map(|(name, value)| format!("{name:<20}{value}"))
@@ -187,8 +226,9 @@ impl Session {
                     }
                     MessageRole::User => {
                         lines.push(format!(
-                            "{}）{}",
+                            "{}）{}{}",
                             self.name,
+                            if message.pinned { "📌 " } else { "" },
                             message.content.render_input(resolve_url_fn)
                         ));
                     }
@@ -250,13 +290,153 @@ impl Session {
         Ok(())
     }
 
+    /// The indices of the messages in the oldest `turns` turns. System messages, pinned turns and
+    /// the last turn are never included.
+    pub fn oldest_turns(&self, turns: usize) -> Vec<usize> {
+        let turn_starts: Vec<usize> = self
+            .messages
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.role.is_user())
+            .map(|(i, _)| i)
+            .collect();
+        turn_starts
+            .windows(2)
+            .map(|v| (v[0]..v[1]).filter(|&i| !self.messages[i].role.is_system()))
+            .map(|v| v.collect::<Vec<usize>>())
+            .filter(|v| v.iter().all(|&i| !self.messages[i].pinned))
+            .take(turns)
+            .flatten()
+            .collect()
+    }
+
+    /// The oldest `turns` turns to compress, by [`Self::oldest_turns`], if there are any.
+    pub fn pick_turns(&self, turns: usize) -> Option<CompressTurns> {
+        let indices = self.oldest_turns(turns);
+        let last = *indices.last()?;
+        Some(CompressTurns {
+            session: self.name.clone(),
+            prefix: self.messages[..=last].to_vec(),
+            indices,
+        })
+    }
+
+    pub fn render_turns(&self, turns: &CompressTurns) -> String {
+        turns
+            .indices
+            .iter()
+            .map(|&i| {
+                let message = &self.messages[i];
+                let name = match message.role {
+                    MessageRole::User => "User",
+                    _ => "Assistant",
+                };
+                format!("{name}: {}", message.content.to_text())
+            })
+            .collect::<Vec<String>>()
+            .join("\n\n")
+    }
+
+    /// Replace the picked turns with a summary, moving them to the compressed messages if
+    /// `keep_original`.
+    ///
+    /// The summary joins the leading system messages, even past pinned turns, since the clients
+    /// only send the system messages at the start of the conversation as their system prompt.
+    ///
+    /// The summary comes back in the background, so nothing changes, and false is returned, unless
+    /// this is still the session the turns were picked from and it still starts with the same
+    /// messages.
+    pub fn compress_turns(
+        &mut self,
+        turns: &CompressTurns,
+        prompt: String,
+        keep_original: bool,
+    ) -> bool {
+        let CompressTurns {
+            session,
+            indices,
+            prefix,
+        } = turns;
+        if *session != self.name
+            || self.messages.get(..prefix.len()) != Some(prefix.as_slice())
+            || indices.iter().any(|&i| i >= prefix.len())
+        {
+            return false;
+        }
+        if indices.is_empty() {
+            return false;
+        }
+        let mut removed = vec![];
+        for &i in indices.iter().rev() {
+            removed.push(self.messages.remove(i));
+        }
+        if keep_original {
+            removed.reverse();
+            self.compressed_messages.extend(removed);
+        }
+        let position = self
+            .messages
+            .iter()
+            .take_while(|v| v.role.is_system())
+            .count();
+        self.messages.insert(
+            position,
+            Message {
+                role: MessageRole::System,
+                content: MessageContent::Text(prompt),
+                pinned: false,
+            },
+        );
+        self.dirty = true;
+        true
+    }
+
     pub fn compress(&mut self, prompt: String) {
-        self.compressed_messages.append(&mut self.messages);
+        let (pinned, messages): (Vec<Message>, Vec<Message>) =
+            self.messages.drain(..).partition(|v| v.pinned);
+        self.compressed_messages.extend(messages);
         self.messages.push(Message {
             role: MessageRole::System,
             content: MessageContent::Text(prompt),
+            pinned: false,
         });
+        self.messages.extend(pinned);
+        self.dirty = true;
+    }
+
+    /// Pin the last question and its answer.
+    pub fn pin_last_turn(&mut self) -> Result<()> {
+        let Some(start) = self.messages.iter().rposition(|v| v.role.is_user()) else {
+            bail!("No message to pin")
+        };
+        for message in &mut self.messages[start..] {
+            message.pinned = true;
+        }
         self.dirty = true;
+        Ok(())
+    }
+
+    /// Fail if the pinned messages alone don't fit in the context window.
+    pub fn guard_pinned_tokens(&self) -> Result<()> {
+        let Some(max_input_tokens) = self.model.max_input_tokens else {
+            return Ok(());
+        };
+        let pinned: Vec<Message> = self.messages.iter().filter(|v| v.pinned).cloned().collect();
+        let tokens = self.model.total_tokens(&pinned);
+        if tokens < max_input_tokens {
+            return Ok(());
+        }
+        let items = pinned
+            .iter()
+            .map(|message| {
+                let text = message.content.to_text().replace('\n', " ");
+                let text: String = text.chars().take(40).collect();
+                let tokens = self.model.total_tokens(std::slice::from_ref(message));
+                format!("  {:?}: {text} ({tokens} tokens)", message.role)
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+        bail!("The pinned messages take {tokens} tokens, exceeding max_input_tokens {max_input_tokens}:\n{items}")
     }
 
     pub fn save(&mut self, session_path: &Path) -> Result<()> {
@@ -304,12 +484,16 @@ impl Session {
             self.messages.push(Message {
                 role: MessageRole::User,
                 content: input.to_message_content(),
+                pinned: input.pinned(),
             });
+        } else if let Some(message) = self.messages.last_mut() {
+            message.pinned = input.pinned();
         }
         self.data_urls.extend(input.data_urls());
         self.messages.push(Message {
             role: MessageRole::Assistant,
             content: MessageContent::Text(output.to_string()),
+            pinned: input.pinned(),
         });
         self.dirty = true;
         Ok(())
@@ -328,15 +512,18 @@ impl Session {
     }
 
     pub fn build_emssages(&self, input: &Input) -> Vec<Message> {
-        let mut messages = self.messages.clone();
+        let mut messages = merge_system_messages(self.messages.clone());
+        for message in messages.iter_mut() {
+            message.pinned = false;
+        }
         let mut need_add_msg = true;
-        let len = messages.len();
-        if len == 0 {
+        let only_summary = self.messages.iter().all(|v| v.role.is_system() || v.pinned);
+        if messages.is_empty() {
             if let Some(role) = input.role() {
                 messages = role.build_messages(input);
                 need_add_msg = false;
             }
-        } else if len == 1 && self.compressed_messages.len() >= 2 {
+        } else if only_summary && self.compressed_messages.len() >= 2 {
             messages
                 .extend(self.compressed_messages[self.compressed_messages.len() - 2..].to_vec());
         }
@@ -344,8 +531,163 @@ impl Session {
             messages.push(Message {
                 role: MessageRole::User,
                 content: input.to_message_content(),
+                pinned: false,
             });
         }
         messages
     }
 }
+
+/// Join the leading system messages, such as a role prompt and summaries, into one.
+fn merge_system_messages(mut messages: Vec<Message>) -> Vec<Message> {
+    let len = messages.iter().take_while(|v| v.role.is_system()).count();
+    if len < 2 {
+        return messages;
+    }
+    let text = messages
+        .drain(..len)
+        .map(|v| v.content.to_text())
+        .collect::<Vec<String>>()
+        .join("\n\n");
+    messages.insert(
+        0,
+        Message {
+            role: MessageRole::System,
+            content: MessageContent::Text(text),
+            pinned: false,
+        },
+    );
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: MessageRole, text: &str) -> Message {
+        Message {
+            role,
+            content: MessageContent::Text(text.into()),
+            pinned: false,
+        }
+    }
+
+    #[test]
+    fn test_compress_turns() {
+        let mut session = Session {
+            messages: vec![
+                message(MessageRole::System, "Be brief"),
+                message(MessageRole::User, "a"),
+                message(MessageRole::Assistant, "b"),
+                message(MessageRole::User, "c"),
+                message(MessageRole::Assistant, "d"),
+                message(MessageRole::User, "e"),
+                message(MessageRole::Assistant, "f"),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(session.oldest_turns(5), [1, 2, 3, 4]);
+        let turns = session.pick_turns(1).unwrap();
+        assert_eq!(turns.indices, [1, 2]);
+        assert_eq!(session.render_turns(&turns), "User: a\n\nAssistant: b");
+
+        // Stale once the session changed while the summary was generated
+        let mut other = session.clone();
+        other.name = "other".into();
+        assert!(!other.compress_turns(&turns, "Recap: ab".into(), true));
+        let mut cleared = session.clone();
+        cleared.messages.truncate(2);
+        assert!(!cleared.compress_turns(&turns, "Recap: ab".into(), true));
+        assert_eq!(cleared.messages.len(), 2);
+
+        // New messages after the turns don't matter
+        session.messages.push(message(MessageRole::User, "g"));
+        session.messages.push(message(MessageRole::Assistant, "h"));
+        assert!(session.compress_turns(&turns, "Recap: ab".into(), true));
+        assert_eq!(session.compressed_messages.len(), 2);
+        assert_eq!(session.oldest_turns(5), [2, 3, 4, 5]);
+        session.messages.truncate(session.messages.len() - 2);
+
+        let input = Input::from_str("g", Default::default());
+        let messages = session.build_emssages(&input);
+        assert_eq!(messages.len(), 6);
+        assert_eq!(messages[0].content.to_text(), "Be brief\n\nRecap: ab");
+        assert!(messages[1].role.is_user());
+    }
+
+    #[test]
+    fn test_pinned_messages() {
+        let mut session = Session {
+            messages: vec![
+                message(MessageRole::User, "spec"),
+                message(MessageRole::Assistant, "ok"),
+            ],
+            ..Default::default()
+        };
+        session.pin_last_turn().unwrap();
+        session.messages.extend([
+            message(MessageRole::User, "a"),
+            message(MessageRole::Assistant, "b"),
+            message(MessageRole::User, "c"),
+            message(MessageRole::Assistant, "d"),
+        ]);
+        assert_eq!(session.oldest_turns(5), [2, 3]);
+
+        session.compress("Recap".into());
+        let texts: Vec<String> = session
+            .messages
+            .iter()
+            .map(|v| v.content.to_text())
+            .collect();
+        assert_eq!(texts, ["Recap", "spec", "ok"]);
+        let input = Input::from_str("e", Default::default());
+        let messages = session.build_emssages(&input);
+        assert!(messages.iter().all(|v| !v.pinned));
+        assert_eq!(messages.len(), 6);
+
+        session.model.max_input_tokens = Some(5);
+        let err = session.guard_pinned_tokens().unwrap_err().to_string();
+        assert!(err.contains("User: spec"), "{err}");
+    }
+
+    #[test]
+    fn test_compress_turns_after_pinned_turn() {
+        let mut session = Session {
+            messages: vec![
+                message(MessageRole::System, "Be brief"),
+                message(MessageRole::User, "spec"),
+                message(MessageRole::Assistant, "ok"),
+            ],
+            ..Default::default()
+        };
+        session.pin_last_turn().unwrap();
+        session.messages.extend([
+            message(MessageRole::User, "a"),
+            message(MessageRole::Assistant, "b"),
+            message(MessageRole::User, "c"),
+            message(MessageRole::Assistant, "d"),
+            message(MessageRole::User, "e"),
+            message(MessageRole::Assistant, "f"),
+        ]);
+        for (turns, recap) in [(1, "Recap: ab"), (1, "Recap: cd")] {
+            let turns = session.pick_turns(turns).unwrap();
+            assert!(session.compress_turns(&turns, recap.into(), false));
+        }
+
+        let input = Input::from_str("g", Default::default());
+        let messages = session.build_emssages(&input);
+        let texts: Vec<String> = messages.iter().map(|v| v.content.to_text()).collect();
+        assert_eq!(
+            texts,
+            [
+                "Be brief\n\nRecap: ab\n\nRecap: cd",
+                "spec",
+                "ok",
+                "e",
+                "f",
+                "g"
+            ]
+        );
+        assert!(messages[1..].iter().all(|v| !v.role.is_system()));
+    }
+}
@@ -0,0 +1,254 @@
+//! Use the clients of aichat from your own program.
+//!
+//! Build a [`ChatClient`] from the same client config as `config.yaml`, then send messages to it.
+//!
+//! ```no_run
+//! use aichat::{ChatClient, Message};
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let client = ChatClient::from_value(
+//!     serde_json::json!({
+//!         "type": "ollama",
+//!         "api_base": "http://localhost:11434",
+//!         "models": [{ "name": "llama3" }],
+//!     }),
+//!     None,
+//! )?;
+//! let output = client.send(vec![Message::user("Hello")]).await?;
+//! println!("{} ({:?} tokens)", output.text, output.output_tokens);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The modules of the `aichat` binary are private; only the items below are public.
+
+#[macro_use]
+extern crate log;
+
+mod app;
+mod cli;
+mod client;
+mod config;
+mod logger;
+mod metrics;
+mod render;
+mod repl;
+mod replay;
+mod serve;
+mod telemetry;
+#[macro_use]
+mod utils;
+mod webhook;
+
+#[doc(hidden)]
+pub use crate::app::start;
+pub use crate::client::{
+    AlephAlphaConfig, AzureAIConfig, AzureOpenAIConfig, BedrockConfig, ClaudeCompatibleConfig,
+    ClaudeConfig, ClientConfig, CohereConfig, CommandConfig, CompletionConfig, CompletionOutput,
+    DeepInfraConfig, DeepSeekConfig, DoubaoConfig, ErnieConfig, ExtraConfig, FunctionDeclaration,
+    GeminiConfig, GithubConfig, GroqConfig, HuggingFaceConfig, HunyuanConfig, ImageUrl,
+    KoboldCppConfig, LeptonConfig, Message, MessageContent, MessageContentPart, MessageRole,
+    MiniMaxConfig, MistralConfig, ModelCapabilities, ModelConfig, OllamaConfig,
+    OpenAICompatibleConfig, OpenAIConfig, OpenRouterConfig, OutputFilterConfig, QianwenConfig,
+    RekaConfig, ReplaceRule, ReplicateConfig, SambaNovaConfig, SiliconFlowConfig, StripRule,
+    ToolCall, ToolResult, VertexAIClaudeConfig, VertexAIConfig, WatsonxConfig, YiConfig,
+};
+
+use crate::client::{init_client, list_models, Client, Model, ReplyEvent, ReplyHandler, SendData};
+use crate::config::{Config, GlobalConfig};
+use crate::utils::create_abort_signal;
+
+use anyhow::{anyhow, Result};
+use parking_lot::RwLock;
+use std::sync::Arc;
+use tokio::sync::mpsc::unbounded_channel;
+
+/// A client bound to one model.
+pub struct ChatClient {
+    inner: Box<dyn Client>,
+    temperature: Option<f64>,
+    top_p: Option<f64>,
+    functions: Option<Vec<FunctionDeclaration>>,
+}
+
+impl ChatClient {
+    /// Create a client. `model` picks one of the models of the client, defaults to the first one.
+    ///
+    /// ```
+    /// use aichat::{ChatClient, ClientConfig, ModelConfig, OllamaConfig};
+    ///
+    /// let config = ClientConfig::OllamaConfig(OllamaConfig {
+    ///     api_base: "http://localhost:11434".into(),
+    ///     models: vec![serde_json::from_value(serde_json::json!({ "name": "llama3" })).unwrap()],
+    ///     ..Default::default()
+    /// });
+    /// let client = ChatClient::new(config, None).unwrap();
+    /// assert_eq!(client.model(), "ollama:llama3");
+    /// ```
+    pub fn new(config: ClientConfig, model: Option<&str>) -> Result<Self> {
+        let mut config = Config {
+            clients: vec![config],
+            ..Default::default()
+        };
+        let models = list_models(&config);
+        let first = models
+            .first()
+            .ok_or_else(|| anyhow!("No model available"))?;
+        config.model = match model {
+            Some(name) => Model::find(&models, &format!("{}:{name}", first.client_name))
+                .ok_or_else(|| anyhow!("Invalid model '{name}'"))?,
+            None => first.clone(),
+        };
+        let config: GlobalConfig = Arc::new(RwLock::new(config));
+        let inner = init_client(&config)?;
+        Ok(Self {
+            inner,
+            temperature: None,
+            top_p: None,
+            functions: None,
+        })
+    }
+
+    /// Create a client from a serde value in the format of an item of `clients` in `config.yaml`.
+    ///
+    /// ```
+    /// let client = aichat::ChatClient::from_value(
+    ///     serde_json::json!({
+    ///         "type": "openai-compatible",
+    ///         "name": "localai",
+    ///         "api_base": "http://localhost:8080/v1",
+    ///         "models": [{ "name": "llama3" }, { "name": "mistral" }],
+    ///     }),
+    ///     Some("mistral"),
+    /// )
+    /// .unwrap();
+    /// assert_eq!(client.model(), "localai:mistral");
+    /// assert_eq!(client.list_models(), ["localai:llama3", "localai:mistral"]);
+    /// ```
+    pub fn from_value(value: serde_json::Value, model: Option<&str>) -> Result<Self> {
+        let config: ClientConfig = serde_json::from_value(value)?;
+        if let ClientConfig::Unknown = config {
+            anyhow::bail!("Unknown client type");
+        }
+        Self::new(config, model)
+    }
+
+    /// The id of the model in use, e.g. `ollama:llama3`.
+    pub fn model(&self) -> String {
+        self.inner.model().id()
+    }
+
+    pub fn list_models(&self) -> Vec<String> {
+        self.inner.list_models().iter().map(|v| v.id()).collect()
+    }
+
+    pub fn set_temperature(mut self, temperature: Option<f64>) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    pub fn set_top_p(mut self, top_p: Option<f64>) -> Self {
+        self.top_p = top_p;
+        self
+    }
+
+    /// Constrain the output to JSON, with `"json"` or a JSON schema, in place of the model's `format`.
+    ///
+    /// Only ollama supports it.
+    pub fn set_format(mut self, format: Option<serde_json::Value>) -> Self {
+        let model = self.inner.model().clone().set_format(format);
+        self.inner.set_model(model);
+        self
+    }
+
+    /// Declare the functions the model may call, the calls come back in `tool_calls` of the output.
+    pub fn set_functions(mut self, functions: Option<Vec<FunctionDeclaration>>) -> Self {
+        self.functions = functions;
+        self
+    }
+
+    /// Send the messages and wait for the whole answer.
+    pub async fn send(&self, messages: Vec<Message>) -> Result<CompletionOutput> {
+        let data = self.send_data(messages, false)?;
+        self.inner.send_data(data).await
+    }
+
+    /// Send the messages and pass every piece of the answer to `on_text` as it arrives.
+    ///
+    /// ```no_run
+    /// # async fn run(client: aichat::ChatClient) -> anyhow::Result<()> {
+    /// use aichat::Message;
+    /// use std::io::Write;
+    ///
+    /// let output = client
+    ///     .send_streaming(vec![Message::user("Tell me a joke")], |text| {
+    ///         print!("{text}");
+    ///         std::io::stdout().flush().unwrap();
+    ///     })
+    ///     .await?;
+    /// println!("\n{:?} {:?}", output.input_tokens, output.output_tokens);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_streaming(
+        &self,
+        messages: Vec<Message>,
+        mut on_text: impl FnMut(&str),
+    ) -> Result<CompletionOutput> {
+        let data = self.send_data(messages, true)?;
+        let (tx, mut rx) = unbounded_channel();
+        let mut handler = ReplyHandler::new(tx, create_abort_signal());
+        let (ret, _) = tokio::join!(
+            async {
+                let ret = self.inner.send_data_streaming(&mut handler, data).await;
+                handler.done()?;
+                ret
+            },
+            async {
+                while let Some(ReplyEvent::Text(text)) = rx.recv().await {
+                    on_text(&text);
+                }
+            }
+        );
+        ret?;
+        let (input_tokens, output_tokens) = handler.get_usage();
+        Ok(CompletionOutput {
+            text: handler.get_buffer().to_string(),
+            input_tokens,
+            output_tokens,
+            tool_calls: handler.get_tool_calls().to_vec(),
+            request_id: handler.get_request_id().map(|v| v.to_string()),
+            stop_sequence: handler.get_stop_sequence().map(|v| v.to_string()),
+            stop_reason: handler.get_stop_reason().map(|v| v.to_string()),
+            stats: handler.get_stats(),
+            cache_usage: handler.get_cache_usage(),
+        })
+    }
+
+    /// Send every list of messages in one batch and wait until all answers are in.
+    ///
+    /// Only Claude supports batches, at half the price; the answers are in the order
+    /// of the lists and a failed one gets its own error.
+    pub async fn send_batch(
+        &self,
+        list: Vec<Vec<Message>>,
+    ) -> Result<Vec<Result<CompletionOutput>>> {
+        let list = list
+            .into_iter()
+            .map(|messages| self.send_data(messages, false))
+            .collect::<Result<Vec<_>>>()?;
+        self.inner.send_batch(list).await
+    }
+
+    fn send_data(&self, messages: Vec<Message>, stream: bool) -> Result<SendData> {
+        self.inner.model().max_input_tokens_limit(&messages)?;
+        self.inner.model().guard_vision(&messages)?;
+        Ok(SendData {
+            messages,
+            temperature: self.temperature,
+            top_p: self.top_p,
+            functions: self.functions.clone(),
+            stream,
+        })
+    }
+}
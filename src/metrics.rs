@@ -0,0 +1,205 @@
+//! Request metrics gathered by the shared send path, rendered in the
+//! Prometheus text format by the `/metrics` endpoint of serve mode.
+//!
+//! There is no circuit-breaker state series, since no client has a breaker.
+
+use crate::client::Model;
+
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::{collections::BTreeMap, fmt::Write, time::Duration};
+
+const BUCKETS: [f64; 11] = [
+    0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+lazy_static! {
+    static ref METRICS: Mutex<Metrics> = Mutex::new(Metrics::default());
+}
+
+/// Labels are bounded to the client and model names.
+type Labels = (String, String);
+
+#[derive(Debug, Default)]
+struct Metrics {
+    requests: BTreeMap<(Labels, &'static str), u64>,
+    input_tokens: BTreeMap<Labels, u64>,
+    output_tokens: BTreeMap<Labels, u64>,
+    retries: BTreeMap<Labels, u64>,
+    duration: BTreeMap<Labels, Histogram>,
+    first_token: BTreeMap<Labels, Histogram>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (i, bound) in BUCKETS.iter().enumerate() {
+            if value <= *bound {
+                self.buckets[i] += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+pub fn record_request(
+    model: &Model,
+    success: bool,
+    duration: Duration,
+    first_token: Option<Duration>,
+    retries: u32,
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+) {
+    let labels = (model.client_name.clone(), model.name.clone());
+    let status = if success { "success" } else { "error" };
+    let mut metrics = METRICS.lock();
+    *metrics
+        .requests
+        .entry((labels.clone(), status))
+        .or_default() += 1;
+    if let Some(tokens) = input_tokens {
+        *metrics.input_tokens.entry(labels.clone()).or_default() += tokens;
+    }
+    if let Some(tokens) = output_tokens {
+        *metrics.output_tokens.entry(labels.clone()).or_default() += tokens;
+    }
+    if retries > 0 {
+        *metrics.retries.entry(labels.clone()).or_default() += retries as u64;
+    }
+    if let Some(first_token) = first_token {
+        metrics
+            .first_token
+            .entry(labels.clone())
+            .or_default()
+            .observe(first_token.as_secs_f64());
+    }
+    metrics
+        .duration
+        .entry(labels)
+        .or_default()
+        .observe(duration.as_secs_f64());
+}
+
+pub fn render_metrics() -> String {
+    let metrics = METRICS.lock();
+    let mut output = String::new();
+
+    write_header(
+        &mut output,
+        "aichat_requests_total",
+        "counter",
+        "Total number of model requests.",
+    );
+    for ((labels, status), value) in &metrics.requests {
+        let labels = format!("{},status=\"{status}\"", format_labels(labels));
+        let _ = writeln!(output, "aichat_requests_total{{{labels}}} {value}");
+    }
+
+    for (name, help, values) in [
+        (
+            "aichat_input_tokens_total",
+            "Total number of input tokens reported by the API.",
+            &metrics.input_tokens,
+        ),
+        (
+            "aichat_output_tokens_total",
+            "Total number of output tokens reported by the API.",
+            &metrics.output_tokens,
+        ),
+        (
+            "aichat_retries_total",
+            "Total number of retried attempts of model requests.",
+            &metrics.retries,
+        ),
+    ] {
+        write_header(&mut output, name, "counter", help);
+        for (labels, value) in values {
+            let _ = writeln!(output, "{name}{{{}}} {value}", format_labels(labels));
+        }
+    }
+
+    for (name, help, values) in [
+        (
+            "aichat_request_duration_seconds",
+            "Duration of model requests.",
+            &metrics.duration,
+        ),
+        (
+            "aichat_time_to_first_token_seconds",
+            "Time until the first token of a streaming reply.",
+            &metrics.first_token,
+        ),
+    ] {
+        write_header(&mut output, name, "histogram", help);
+        for (labels, histogram) in values {
+            let labels = format_labels(labels);
+            for (bound, value) in BUCKETS.iter().zip(histogram.buckets) {
+                let _ = writeln!(output, "{name}_bucket{{{labels},le=\"{bound}\"}} {value}");
+            }
+            let count = histogram.count;
+            let _ = writeln!(output, "{name}_bucket{{{labels},le=\"+Inf\"}} {count}");
+            let _ = writeln!(output, "{name}_sum{{{labels}}} {}", histogram.sum);
+            let _ = writeln!(output, "{name}_count{{{labels}}} {count}");
+        }
+    }
+
+    output
+}
+
+fn write_header(output: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(output, "# HELP {name} {help}");
+    let _ = writeln!(output, "# TYPE {name} {kind}");
+}
+
+fn format_labels((client, model): &Labels) -> String {
+    format!(
+        "client=\"{}\",model=\"{}\"",
+        escape_label(client),
+        escape_label(model)
+    )
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::default();
+        histogram.observe(0.3);
+        histogram.observe(7.0);
+        assert_eq!(histogram.buckets[0], 0);
+        assert_eq!(histogram.buckets[2], 1);
+        assert_eq!(histogram.buckets[6], 2);
+        assert_eq!(histogram.count, 2);
+    }
+
+    #[test]
+    fn test_retries() {
+        let model = Model::new("metrics-test", "retries");
+        record_request(&model, true, Duration::from_secs(1), None, 2, None, None);
+        record_request(&model, false, Duration::from_secs(1), None, 1, None, None);
+        assert!(render_metrics()
+            .contains("aichat_retries_total{client=\"metrics-test\",model=\"retries\"} 3\n"));
+    }
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(escape_label(r#"a"b\c"#), r#"a\"b\\c"#);
+    }
+}
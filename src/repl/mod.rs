@@ -6,8 +6,8 @@ use self::completer::ReplCompleter;
 use self::highlighter::ReplHighlighter;
 use self::prompt::ReplPrompt;
 
-use crate::client::{ensure_model_capabilities, init_client, send_stream};
-use crate::config::{GlobalConfig, Input, InputContext, State};
+use crate::client::{ensure_model_capabilities, init_client, send_stream, send_utility_message};
+use crate::config::{check_budget, GlobalConfig, Input, InputContext, State};
 use crate::render::render_error;
 use crate::utils::{create_abort_signal, set_text, AbortSignal};
 
@@ -26,9 +26,14 @@ use std::{env, process};
 const MENU_NAME: &str = "completion_menu";
 
 lazy_static! {
-    static ref REPL_COMMANDS: [ReplCommand; 16] = [
+    static ref REPL_COMMANDS: [ReplCommand; 18] = [
         ReplCommand::new(".help", "Show this help message", State::all()),
         ReplCommand::new(".info", "View system info", State::all()),
+        ReplCommand::new(
+            ".info budget",
+            "View spending and remaining budget",
+            State::all()
+        ),
         ReplCommand::new(".model", "Change the current LLM", State::all()),
         ReplCommand::new(
             ".prompt",
@@ -49,6 +54,11 @@ lazy_static! {
             "Save the chat to file",
             State::in_session(),
         ),
+        ReplCommand::new(
+            ".pin",
+            "Keep the last question and answer when compressing",
+            State::in_session(),
+        ),
         ReplCommand::new(
             ".clear messages",
             "Erase messages in the current session",
@@ -149,6 +159,10 @@ impl Repl {
                         let info = self.config.read().session_info()?;
                         println!("{}", info);
                     }
+                    Some("budget") => {
+                        let info = self.config.read().budget_info()?;
+                        println!("{}", info);
+                    }
                     Some(_) => unknown_command()?,
                     None => {
                         let output = self.config.read().system_info()?;
@@ -212,15 +226,21 @@ impl Repl {
                 }
                 ".file" => match args {
                     Some(args) => {
+                        let (args, pinned) = match args.strip_prefix("--pin ") {
+                            Some(args) => (args.trim(), true),
+                            None => (args, false),
+                        };
                         let (files, text) = match args.split_once(" -- ") {
                             Some((files, text)) => (files.trim(), text.trim()),
                             None => (args, ""),
                         };
                         let files = shell_words::split(files).with_context(|| "Invalid args")?;
-                        let input = Input::new(text, files, self.config.read().input_context())?;
+                        let mut input =
+                            Input::new(text, files, self.config.read().input_context())?;
+                        input.set_pinned(pinned);
                         self.ask(input).await?;
                     }
-                    None => println!("Usage: .file <files>... [-- <text>...]"),
+                    None => println!("Usage: .file [--pin] <files>... [-- <text>...]"),
                 },
                 ".exit" => match args {
                     Some("role") => {
@@ -234,6 +254,9 @@ impl Repl {
                         return Ok(true);
                     }
                 },
+                ".pin" => {
+                    self.config.write().pin_session_message()?;
+                }
                 ".clear" => match args {
                     Some("messages") => {
                         self.config.write().clear_session_messages()?;
@@ -263,7 +286,17 @@ impl Repl {
         self.config.read().maybe_print_send_tokens(&input);
         let mut client = init_client(&self.config)?;
         ensure_model_capabilities(client.as_mut(), input.required_capabilities())?;
+        check_budget(&self.config, client.model(), &input)?;
         let output = send_stream(&input, client.as_ref(), &self.config, self.abort.clone()).await?;
+        let title_prompt = self.config.write().start_titling_session(&input, &output);
+        if let Some(prompt) = title_prompt {
+            let config = self.config.clone();
+            tokio::spawn(async move {
+                if let Err(err) = generate_session_title(&config, &prompt).await {
+                    debug!("Failed to generate session title: {err}");
+                }
+            });
+        }
         self.config.write().save_message(input, &output)?;
         self.config.read().maybe_copy(&output);
         if self.config.write().should_compress_session() {
@@ -273,12 +306,17 @@ impl Repl {
             } else {
                 Color::DarkGray
             };
+            let option = if config.read().compress_ratio.is_some() {
+                "compress_ratio"
+            } else {
+                "compress_threshold"
+            };
             print!(
                 "\n📢 {}{}{}\n",
                 color.normal().paint(
                     "Session compression is being activated because the current tokens exceed `"
                 ),
-                color.italic().paint("compress_threshold"),
+                color.italic().paint(option),
                 color.normal().paint("`."),
             );
             tokio::spawn(async move {
@@ -440,7 +478,21 @@ fn parse_command(line: &str) -> Option<(&str, Option<&str>)> {
     }
 }
 
+async fn generate_session_title(config: &GlobalConfig, prompt: &str) -> Result<()> {
+    let title = send_utility_message(config, prompt).await?;
+    config.write().set_session_title(&title);
+    Ok(())
+}
+
 async fn compress_session(config: &GlobalConfig) -> Result<()> {
+    if config.read().compress_ratio.is_some() {
+        let compress = config.read().prepare_partial_compress();
+        if let Some((turns, prompt)) = compress {
+            let summary = send_utility_message(config, &prompt).await?;
+            config.write().partial_compress_session(&turns, &summary);
+        }
+        return Ok(());
+    }
     let input = Input::from_str(
         config.read().summarize_prompt(),
         config.read().input_context(),
@@ -0,0 +1,328 @@
+//! Replay a logged request against the current model.
+//!
+//! The file holds either a request body (as printed in the `Request:` debug log lines)
+//! or an interaction `{"model": .., "request": .., "response": ..}`.
+
+use crate::{
+    client::{
+        init_client, CompletionOutput, ImageUrl, Message, MessageContent, MessageContentPart,
+        MessageRole, SendData,
+    },
+    config::GlobalConfig,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::Value;
+use std::fs::read_to_string;
+use unicode_width::UnicodeWidthStr;
+
+const SEPARATOR: &str = " │ ";
+
+pub async fn run(config: &GlobalConfig, path: &str) -> Result<()> {
+    let content =
+        read_to_string(path).with_context(|| format!("Failed to read replay file '{path}'"))?;
+    let interaction = parse_interaction(&content)?;
+    let client = init_client(config)?;
+    let data = SendData {
+        messages: interaction.messages,
+        temperature: interaction.temperature,
+        top_p: interaction.top_p,
+        functions: None,
+        stream: false,
+    };
+    let output = client
+        .send_data(data)
+        .await
+        .with_context(|| "Failed to replay the request")?;
+
+    let original_title = match &interaction.model {
+        Some(model) => format!("Original ({model})"),
+        None => "Original".into(),
+    };
+    let replay_title = format!("Replay ({})", client.model().id());
+    print_side_by_side(
+        (&original_title, interaction.output.as_ref()),
+        (&replay_title, Some(&output)),
+    );
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct Interaction {
+    model: Option<String>,
+    messages: Vec<Message>,
+    temperature: Option<f64>,
+    top_p: Option<f64>,
+    output: Option<CompletionOutput>,
+}
+
+fn parse_interaction(content: &str) -> Result<Interaction> {
+    let content = content.trim();
+    let value: Value = if content.starts_with('{') {
+        serde_json::from_str(content)?
+    } else {
+        let line = content
+            .lines()
+            .rev()
+            .find(|line| line.contains(" Request: "))
+            .ok_or_else(|| anyhow!("No request found in the replay file"))?;
+        let start = line
+            .find('{')
+            .ok_or_else(|| anyhow!("No request body found in '{line}'"))?;
+        serde_json::from_str(&line[start..])?
+    };
+    let (body, response) = match value.get("request") {
+        Some(request) => (request, value.get("response")),
+        None => (&value, None),
+    };
+    let messages = extract_messages(body);
+    if messages.is_empty() {
+        bail!("No messages found in the request body");
+    }
+    let params = &body["generationConfig"];
+    let params = if params.is_object() {
+        params
+    } else {
+        &body["parameters"]
+    };
+    Ok(Interaction {
+        model: value["model"]
+            .as_str()
+            .or_else(|| body["model"].as_str())
+            .map(|v| v.to_string()),
+        messages,
+        temperature: body["temperature"]
+            .as_f64()
+            .or_else(|| params["temperature"].as_f64()),
+        top_p: body["top_p"]
+            .as_f64()
+            .or_else(|| body["p"].as_f64())
+            .or_else(|| params["topP"].as_f64())
+            .or_else(|| params["top_p"].as_f64()),
+        output: response.and_then(extract_output),
+    })
+}
+
+fn extract_messages(body: &Value) -> Vec<Message> {
+    let mut messages = vec![];
+    let system = body["system"]
+        .as_str()
+        .or_else(|| body["preamble"].as_str())
+        .map(|v| v.to_string())
+        .or_else(|| body["systemInstruction"].get("parts").map(parts_to_text));
+    if let Some(system) = system {
+        messages.push(Message {
+            role: MessageRole::System,
+            content: MessageContent::Text(system),
+            pinned: false,
+        });
+    }
+    if let Some(list) = body["messages"]
+        .as_array()
+        .or_else(|| body["input"]["messages"].as_array())
+    {
+        for item in list {
+            let content = match &item["content"] {
+                Value::String(text) => MessageContent::Text(text.clone()),
+                Value::Array(parts) => MessageContent::Array(convert_parts(parts)),
+                _ => continue,
+            };
+            if let Some(role) = convert_role(&item["role"]) {
+                messages.push(Message {
+                    role,
+                    content,
+                    pinned: false,
+                });
+            }
+        }
+    } else if let Some(list) = body["contents"].as_array() {
+        for item in list {
+            let parts = item["parts"].as_array().cloned().unwrap_or_default();
+            if let Some(role) = convert_role(&item["role"]) {
+                messages.push(Message {
+                    role,
+                    content: MessageContent::Array(convert_parts(&parts)),
+                    pinned: false,
+                });
+            }
+        }
+    } else if let Some(message) = body["message"].as_str() {
+        for item in body["chat_history"].as_array().into_iter().flatten() {
+            if let (Some(role), Some(text)) =
+                (convert_role(&item["role"]), item["message"].as_str())
+            {
+                messages.push(Message {
+                    role,
+                    content: MessageContent::Text(text.to_string()),
+                    pinned: false,
+                });
+            }
+        }
+        messages.push(Message {
+            role: MessageRole::User,
+            content: MessageContent::Text(message.to_string()),
+            pinned: false,
+        });
+    }
+    messages
+}
+
+fn convert_role(role: &Value) -> Option<MessageRole> {
+    match role.as_str()?.to_lowercase().as_str() {
+        "system" => Some(MessageRole::System),
+        "user" => Some(MessageRole::User),
+        "assistant" | "model" | "chatbot" => Some(MessageRole::Assistant),
+        _ => None,
+    }
+}
+
+fn convert_parts(parts: &[Value]) -> Vec<MessageContentPart> {
+    parts
+        .iter()
+        .filter_map(|part| {
+            if let Some(text) = part["text"].as_str() {
+                return Some(MessageContentPart::Text {
+                    text: text.to_string(),
+                });
+            }
+            let url = if let Some(url) = part["image_url"]["url"].as_str() {
+                url.to_string()
+            } else if let Some(url) = part["image"].as_str() {
+                url.to_string()
+            } else if let Some(data) = part["source"]["data"].as_str() {
+                let mime_type = part["source"]["media_type"].as_str()?;
+                format!("data:{mime_type};base64,{data}")
+            } else if let Some(data) = part["inline_data"]["data"].as_str() {
+                let mime_type = part["inline_data"]["mime_type"].as_str()?;
+                format!("data:{mime_type};base64,{data}")
+            } else {
+                return None;
+            };
+            Some(MessageContentPart::ImageUrl {
+                image_url: ImageUrl { url },
+            })
+        })
+        .collect()
+}
+
+fn parts_to_text(parts: &Value) -> String {
+    parts
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|part| part["text"].as_str())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+fn extract_output(data: &Value) -> Option<CompletionOutput> {
+    let text = data["choices"][0]["message"]["content"]
+        .as_str()
+        .or_else(|| data["content"][0]["text"].as_str())
+        .or_else(|| data["candidates"][0]["content"]["parts"][0]["text"].as_str())
+        .or_else(|| data["output"]["text"].as_str())
+        .or_else(|| data["output"]["choices"][0]["message"]["content"][0]["text"].as_str())
+        .or_else(|| data["message"]["content"].as_str())
+        .or_else(|| data["result"].as_str())
+        .or_else(|| data["text"].as_str())?;
+    let find_u64 = |paths: &[&[&str]]| {
+        paths
+            .iter()
+            .find_map(|path| path.iter().fold(data, |value, key| &value[*key]).as_u64())
+    };
+    Some(CompletionOutput {
+        text: text.to_string(),
+        input_tokens: find_u64(&[
+            &["usage", "prompt_tokens"],
+            &["usage", "input_tokens"],
+            &["usageMetadata", "promptTokenCount"],
+            &["meta", "billed_units", "input_tokens"],
+            &["prompt_eval_count"],
+        ]),
+        output_tokens: find_u64(&[
+            &["usage", "completion_tokens"],
+            &["usage", "output_tokens"],
+            &["usageMetadata", "candidatesTokenCount"],
+            &["meta", "billed_units", "output_tokens"],
+            &["eval_count"],
+        ]),
+        tool_calls: vec![],
+        request_id: None,
+        stop_sequence: None,
+        stop_reason: None,
+        stats: None,
+        cache_usage: None,
+    })
+}
+
+fn print_side_by_side(
+    left: (&str, Option<&CompletionOutput>),
+    right: (&str, Option<&CompletionOutput>),
+) {
+    let width = crossterm::terminal::size()
+        .map(|(columns, _)| columns as usize)
+        .unwrap_or(80);
+    let column = (width.saturating_sub(SEPARATOR.width()) / 2).max(20);
+    let render = |(title, output): (&str, Option<&CompletionOutput>)| {
+        let mut lines = vec![title.to_string(), "─".repeat(column)];
+        match output {
+            Some(output) => {
+                let text = textwrap::wrap(&output.text, column);
+                lines.extend(text.into_iter().map(|v| v.to_string()));
+                lines.push(String::new());
+                lines.push(format!(
+                    "tokens: {} in / {} out",
+                    format_tokens(output.input_tokens),
+                    format_tokens(output.output_tokens)
+                ));
+            }
+            None => lines.push("(no response recorded)".into()),
+        }
+        lines
+    };
+    let left = render(left);
+    let right = render(right);
+    for i in 0..left.len().max(right.len()) {
+        let left_line = left.get(i).map(|v| v.as_str()).unwrap_or_default();
+        let right_line = right.get(i).map(|v| v.as_str()).unwrap_or_default();
+        let padding = " ".repeat(column.saturating_sub(left_line.width()));
+        println!("{left_line}{padding}{SEPARATOR}{right_line}");
+    }
+}
+
+fn format_tokens(tokens: Option<u64>) -> String {
+    tokens.map(|v| v.to_string()).unwrap_or_else(|| "-".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_log_line() {
+        let content = r#"2024-04-01T00:00:00.000Z [DEBUG] Claude Request: https://api.anthropic.com/v1/messages {"model":"claude-3-opus-20240229","system":"be brief","messages":[{"role":"user","content":[{"type":"text","text":"hi"}]}],"temperature":0.5}"#;
+        let interaction = parse_interaction(content).unwrap();
+        assert_eq!(interaction.model.as_deref(), Some("claude-3-opus-20240229"));
+        assert_eq!(interaction.temperature, Some(0.5));
+        assert_eq!(interaction.messages.len(), 2);
+        assert!(interaction.messages[0].role.is_system());
+        assert_eq!(interaction.messages[1].content.to_text(), "hi");
+    }
+
+    #[test]
+    fn test_parse_interaction() {
+        let content = r#"{
+            "model": "gemini:gemini-1.0-pro-latest",
+            "request": {"contents":[{"role":"user","parts":[{"text":"hi"}]},{"role":"model","parts":[{"text":"hello"}]},{"role":"user","parts":[{"text":"bye"}]}],"generationConfig":{"topP":0.9}},
+            "response": {"candidates":[{"content":{"parts":[{"text":"goodbye"}]}}],"usageMetadata":{"promptTokenCount":5,"candidatesTokenCount":2}}
+        }"#;
+        let interaction = parse_interaction(content).unwrap();
+        assert_eq!(interaction.top_p, Some(0.9));
+        assert_eq!(interaction.messages.len(), 3);
+        assert!(interaction.messages[1].role.is_assistant());
+        let output = interaction.output.unwrap();
+        assert_eq!(output.text, "goodbye");
+        assert_eq!(output.input_tokens, Some(5));
+        assert_eq!(output.output_tokens, Some(2));
+    }
+}
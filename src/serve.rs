@@ -1,7 +1,9 @@
 use crate::{
     client::{init_client, ClientConfig, Message, Model, ReplyEvent, ReplyHandler, SendData},
     config::{Config, GlobalConfig},
+    metrics::render_metrics,
     utils::create_abort_signal,
+    webhook::WebhookConfig,
 };
 
 use anyhow::{anyhow, bail, Result};
@@ -46,10 +48,16 @@ pub async fn run(config: GlobalConfig, addr: Option<String>) -> Result<()> {
     };
     let clients = config.read().clients.clone();
     let model = config.read().model.clone();
+    let webhook = config.read().webhook.clone();
     let listener = TcpListener::bind(&addr).await?;
-    let server = Arc::new(Server { clients, model });
+    let server = Arc::new(Server {
+        clients,
+        model,
+        webhook,
+    });
     let stop_server = server.run(listener).await?;
     println!("Access the chat completion API at: http://{addr}/v1/chat/completions");
+    println!("Access the Prometheus metrics at: http://{addr}/metrics");
     shutdown_signal().await;
     let _ = stop_server.send(());
     Ok(())
@@ -58,6 +66,7 @@ pub async fn run(config: GlobalConfig, addr: Option<String>) -> Result<()> {
 struct Server {
     clients: Vec<ClientConfig>,
     model: Model,
+    webhook: Option<WebhookConfig>,
 }
 
 impl Server {
@@ -103,6 +112,8 @@ impl Server {
         let mut status = StatusCode::OK;
         let res = if method == Method::POST && uri == "/v1/chat/completions" {
             self.chat_completion(req).await
+        } else if method == Method::GET && uri == "/metrics" {
+            self.metrics()
         } else if method == Method::OPTIONS && uri == "/v1/chat/completions" {
             status = StatusCode::NO_CONTENT;
             Ok(Response::default())
@@ -125,6 +136,13 @@ impl Server {
         Ok(res)
     }
 
+    fn metrics(&self) -> Result<AppResponse> {
+        let res = Response::builder()
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(Full::new(Bytes::from(render_metrics())).boxed())?;
+        Ok(res)
+    }
+
     async fn chat_completion(&self, req: hyper::Request<Incoming>) -> Result<AppResponse> {
         let req_body = req.collect().await?.to_bytes();
         let req_body: ChatCompletionReqBody = serde_json::from_slice(&req_body)
@@ -142,6 +160,7 @@ impl Server {
         let config = Config {
             clients: self.clients.to_vec(),
             model: self.model.clone(),
+            webhook: self.webhook.clone(),
             ..Default::default()
         };
         let config = Arc::new(RwLock::new(config));
@@ -154,7 +173,6 @@ impl Server {
             client.set_model(client.model().clone().set_max_output_tokens(max_tokens));
         }
         let abort = create_abort_signal();
-        let http_client = client.build_client()?;
 
         let completion_id = generate_completion_id();
         let created = Utc::now().timestamp();
@@ -163,6 +181,7 @@ impl Server {
             messages,
             temperature,
             top_p,
+            functions: None,
             stream,
         };
 
@@ -194,7 +213,7 @@ impl Server {
                 }
                 tokio::select! {
                     _ = map_event(rx2, &tx, &mut is_first) => {}
-                    ret = client.send_message_streaming_inner(&http_client, &mut handler, send_data) => {
+                    ret = client.send_data_streaming(&mut handler, send_data) => {
                         if let Err(err) = ret {
                             send_first_event(&tx, Some(format!("{err:?}")), &mut is_first)
                         }
@@ -230,10 +249,13 @@ impl Server {
                 .body(BodyExt::boxed(StreamBody::new(stream)))?;
             Ok(res)
         } else {
-            let content = client.send_message_inner(&http_client, send_data).await?;
-            let res = Response::builder()
-                .header("Content-Type", "application/json")
-                .body(Full::new(ret_non_stream(&completion_id, created, &content)).boxed())?;
+            let output = client.send_data(send_data).await?;
+            let mut res = Response::builder().header("Content-Type", "application/json");
+            if let Some(request_id) = &output.request_id {
+                res = res.header("X-Request-Id", request_id);
+            }
+            let res =
+                res.body(Full::new(ret_non_stream(&completion_id, created, &output.text)).boxed())?;
             Ok(res)
         }
     }
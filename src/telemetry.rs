@@ -0,0 +1,177 @@
+//! OpenTelemetry tracing for model requests.
+//!
+//! Enabled with the `otel` cargo feature. Spans are exported over OTLP/HTTP
+//! when `OTEL_EXPORTER_OTLP_ENDPOINT` (or `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`) is set.
+
+use crate::client::Model;
+
+use anyhow::Result;
+use reqwest::header::HeaderMap;
+
+#[cfg(feature = "otel")]
+pub use self::otel::*;
+
+#[cfg(feature = "otel")]
+mod otel {
+    use super::*;
+
+    use opentelemetry::{
+        propagation::TextMapPropagator,
+        trace::{Span as _, SpanKind, Status, TraceContextExt, Tracer as _, TracerProvider as _},
+        Context, KeyValue,
+    };
+    use opentelemetry_otlp::SpanExporter;
+    use opentelemetry_sdk::{
+        propagation::TraceContextPropagator,
+        runtime,
+        trace::{Span, TracerProvider},
+        Resource,
+    };
+    use reqwest::header::{HeaderName, HeaderValue};
+    use std::{collections::HashMap, env, sync::OnceLock, time::Instant};
+
+    static PROVIDER: OnceLock<TracerProvider> = OnceLock::new();
+
+    pub fn setup_telemetry() -> Result<()> {
+        if env::var("OTEL_EXPORTER_OTLP_ENDPOINT").is_err()
+            && env::var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").is_err()
+        {
+            return Ok(());
+        }
+        let exporter = SpanExporter::builder().with_http().build()?;
+        let provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                env!("CARGO_CRATE_NAME"),
+            )]))
+            .build();
+        let _ = PROVIDER.set(provider);
+        Ok(())
+    }
+
+    pub fn shutdown_telemetry() {
+        if let Some(provider) = PROVIDER.get() {
+            let _ = provider.shutdown();
+        }
+    }
+
+    pub struct RequestSpan {
+        inner: Option<(Span, Instant)>,
+    }
+
+    impl RequestSpan {
+        pub fn start(model: &Model, request_id: &str) -> Self {
+            let inner = PROVIDER.get().map(|provider| {
+                let tracer = provider.tracer(env!("CARGO_CRATE_NAME"));
+                let span = tracer
+                    .span_builder(format!("chat {}", model.name))
+                    .with_kind(SpanKind::Client)
+                    .with_attributes([
+                        KeyValue::new("gen_ai.system", model.client_name.clone()),
+                        KeyValue::new("gen_ai.request.model", model.name.clone()),
+                        KeyValue::new("aichat.request_id", request_id.to_string()),
+                    ])
+                    .start_with_context(&tracer, &parent_context());
+                (span, Instant::now())
+            });
+            Self { inner }
+        }
+
+        /// Headers carrying the W3C trace context of this span.
+        pub fn headers(&self) -> HeaderMap {
+            let mut headers = HeaderMap::new();
+            if let Some((span, _)) = &self.inner {
+                let cx = Context::new().with_remote_span_context(span.span_context().clone());
+                let mut carrier = HashMap::new();
+                TraceContextPropagator::new().inject_context(&cx, &mut carrier);
+                for (key, value) in carrier {
+                    if let (Ok(key), Ok(value)) = (
+                        HeaderName::from_bytes(key.as_bytes()),
+                        HeaderValue::from_str(&value),
+                    ) {
+                        headers.insert(key, value);
+                    }
+                }
+            }
+            headers
+        }
+
+        pub fn finish(
+            self,
+            retries: u32,
+            input_tokens: Option<u64>,
+            output_tokens: Option<u64>,
+            err: Option<&anyhow::Error>,
+        ) {
+            let Some((mut span, started)) = self.inner else {
+                return;
+            };
+            let latency = started.elapsed().as_millis() as i64;
+            span.set_attribute(KeyValue::new("aichat.latency_ms", latency));
+            span.set_attribute(KeyValue::new("aichat.retries", retries as i64));
+            if let Some(tokens) = input_tokens {
+                span.set_attribute(KeyValue::new("gen_ai.usage.input_tokens", tokens as i64));
+            }
+            if let Some(tokens) = output_tokens {
+                span.set_attribute(KeyValue::new("gen_ai.usage.output_tokens", tokens as i64));
+            }
+            if let Some(err) = err {
+                span.set_attribute(KeyValue::new("error.type", error_kind(err)));
+                span.set_status(Status::error(err.to_string()));
+            }
+            span.end();
+        }
+    }
+
+    /// Continue the trace of a parent process that passes `TRACEPARENT`/`TRACESTATE`.
+    fn parent_context() -> Context {
+        let mut carrier = HashMap::new();
+        for (name, key) in [("TRACEPARENT", "traceparent"), ("TRACESTATE", "tracestate")] {
+            if let Ok(value) = env::var(name) {
+                carrier.insert(key.to_string(), value);
+            }
+        }
+        TraceContextPropagator::new().extract(&carrier)
+    }
+
+    fn error_kind(err: &anyhow::Error) -> &'static str {
+        match err.downcast_ref::<reqwest::Error>() {
+            Some(err) if err.is_timeout() => "timeout",
+            Some(err) if err.is_connect() => "connect",
+            Some(_) => "http",
+            None => "api",
+        }
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn setup_telemetry() -> Result<()> {
+    Ok(())
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn shutdown_telemetry() {}
+
+#[cfg(not(feature = "otel"))]
+pub struct RequestSpan;
+
+#[cfg(not(feature = "otel"))]
+impl RequestSpan {
+    pub fn start(_model: &Model, _request_id: &str) -> Self {
+        Self
+    }
+
+    pub fn headers(&self) -> HeaderMap {
+        HeaderMap::new()
+    }
+
+    pub fn finish(
+        self,
+        _retries: u32,
+        _input_tokens: Option<u64>,
+        _output_tokens: Option<u64>,
+        _err: Option<&anyhow::Error>,
+    ) {
+    }
+}
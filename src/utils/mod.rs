@@ -3,6 +3,7 @@ mod clipboard;
 mod prompt_input;
 mod render_prompt;
 mod spinner;
+mod stream_parser;
 mod tiktoken;
 
 pub use self::abort_signal::{create_abort_signal, AbortSignal};
@@ -10,9 +11,11 @@ pub use self::clipboard::set_text;
 pub use self::prompt_input::*;
 pub use self::render_prompt::render_prompt;
 pub use self::spinner::run_spinner;
+pub use self::stream_parser::{JsonStreamParser, LineParser, SseParser};
 pub use self::tiktoken::cl100k_base_singleton;
 
 use fancy_regex::Regex;
+use hmac::{Hmac, Mac};
 use lazy_static::lazy_static;
 use sha2::{Digest, Sha256};
 use std::env;
@@ -89,6 +92,16 @@ pub fn sha256sum(input: &str) -> String {
     format!("{:x}", result)
 }
 
+pub fn hmac_sha256(key: &[u8], msg: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC can take key of any size");
+    mac.update(msg.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+pub fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|v| format!("{:02x}", v)).collect()
+}
+
 pub fn detect_os() -> String {
     let os = env::consts::OS;
     if os == "linux" {
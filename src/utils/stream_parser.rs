@@ -0,0 +1,360 @@
+//! Incremental parsers for streamed responses: push bytes in as they arrive, get complete events out.
+
+use anyhow::{bail, Result};
+
+/// Limit the bytes buffered for a single event.
+const MAX_BUFFER_SIZE: usize = 16 * 1024 * 1024;
+
+/// Split a stream of JSON objects into one string per object.
+///
+/// The objects may be concatenated, separated by whitespace or newlines, or wrapped in a
+/// top-level array as VertexAI does.
+#[derive(Debug, Default)]
+pub struct JsonStreamParser {
+    buffer: Vec<u8>,
+    cursor: usize,
+    depth: usize,
+    quoting: bool,
+    escape: bool,
+    in_array: bool,
+}
+
+impl JsonStreamParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<String>> {
+        self.buffer.extend_from_slice(chunk);
+        let mut output = vec![];
+        let mut start = 0;
+        while self.cursor < self.buffer.len() {
+            let ch = self.buffer[self.cursor];
+            if self.depth == 0 {
+                match ch {
+                    b'{' => {
+                        start = self.cursor;
+                        self.depth = 1;
+                    }
+                    b'[' if !self.in_array => self.in_array = true,
+                    b']' if self.in_array => self.in_array = false,
+                    b',' | b' ' | b'\t' | b'\r' | b'\n' => {}
+                    _ => bail!("Unexpected character '{}' in json stream", ch as char),
+                }
+                if self.depth == 0 {
+                    start = self.cursor + 1;
+                }
+            } else if self.quoting {
+                if self.escape {
+                    self.escape = false;
+                } else if ch == b'\\' {
+                    self.escape = true;
+                } else if ch == b'"' {
+                    self.quoting = false;
+                }
+            } else {
+                match ch {
+                    b'"' => self.quoting = true,
+                    b'{' | b'[' => self.depth += 1,
+                    b'}' | b']' => {
+                        self.depth -= 1;
+                        if self.depth == 0 {
+                            let value = std::str::from_utf8(&self.buffer[start..=self.cursor])?;
+                            output.push(value.to_string());
+                            start = self.cursor + 1;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            self.cursor += 1;
+        }
+        self.buffer.drain(..start);
+        self.cursor -= start;
+        check_buffer_size(&self.buffer)?;
+        Ok(output)
+    }
+
+    /// Fail if the stream ended in the middle of an object.
+    pub fn finish(&self) -> Result<()> {
+        if self.depth > 0 {
+            bail!("Unexpected end of json stream");
+        }
+        Ok(())
+    }
+}
+
+/// Split a stream into lines, for newline-delimited JSON. Empty lines are skipped.
+#[derive(Debug, Default)]
+pub struct LineParser {
+    buffer: Vec<u8>,
+}
+
+impl LineParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<String>> {
+        let lines = split_lines(&mut self.buffer, chunk)?;
+        Ok(lines.into_iter().filter(|v| !v.trim().is_empty()).collect())
+    }
+
+    /// Return the last line if the stream didn't end with a newline.
+    pub fn finish(&mut self) -> Result<Vec<String>> {
+        let line = String::from_utf8(std::mem::take(&mut self.buffer))?;
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(vec![line.to_string()])
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SseEvent {
+    pub event: String,
+    pub data: String,
+}
+
+/// Parse server-sent events. Only the `event` and `data` fields are kept.
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    event: String,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<SseEvent>> {
+        let mut output = vec![];
+        for line in split_lines(&mut self.buffer, chunk)? {
+            self.handle_line(&line, &mut output);
+        }
+        Ok(output)
+    }
+
+    /// Dispatch the last event if the stream didn't end with a blank line.
+    pub fn finish(&mut self) -> Result<Vec<SseEvent>> {
+        let mut output = vec![];
+        let line = String::from_utf8(std::mem::take(&mut self.buffer))?;
+        self.handle_line(line.trim_end_matches('\r'), &mut output);
+        self.handle_line("", &mut output);
+        Ok(output)
+    }
+
+    fn handle_line(&mut self, line: &str, output: &mut Vec<SseEvent>) {
+        if line.is_empty() {
+            if !self.data.is_empty() {
+                output.push(SseEvent {
+                    event: std::mem::take(&mut self.event),
+                    data: std::mem::take(&mut self.data).join("\n"),
+                });
+            }
+            self.event.clear();
+            return;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = value.to_string(),
+            "data" => self.data.push(value.to_string()),
+            _ => {}
+        }
+    }
+}
+
+/// Append `chunk` to `buffer` and take out the complete lines, without line endings.
+fn split_lines(buffer: &mut Vec<u8>, chunk: &[u8]) -> Result<Vec<String>> {
+    let scanned = buffer.len();
+    buffer.extend_from_slice(chunk);
+    let mut output = vec![];
+    let mut start = 0;
+    for i in scanned..buffer.len() {
+        if buffer[i] == b'\n' {
+            let line = std::str::from_utf8(&buffer[start..i])?;
+            output.push(line.strip_suffix('\r').unwrap_or(line).to_string());
+            start = i + 1;
+        }
+    }
+    buffer.drain(..start);
+    check_buffer_size(buffer)?;
+    Ok(output)
+}
+
+fn check_buffer_size(buffer: &[u8]) -> Result<()> {
+    if buffer.len() > MAX_BUFFER_SIZE {
+        bail!("Stream event exceeds {MAX_BUFFER_SIZE} bytes");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Push `input` split at `points`.
+    fn push_chunks<T>(
+        input: &[u8],
+        points: &[usize],
+        mut push: impl FnMut(&[u8]) -> Result<Vec<T>>,
+    ) -> Result<Vec<T>> {
+        let mut points: Vec<usize> = points.iter().map(|v| v % (input.len() + 1)).collect();
+        points.sort();
+        let mut output = vec![];
+        let mut start = 0;
+        for point in points.into_iter().chain([input.len()]) {
+            output.extend(push(&input[start..point])?);
+            start = point;
+        }
+        Ok(output)
+    }
+
+    fn json_stream(input: &[u8], points: &[usize]) -> Result<Vec<String>> {
+        let mut parser = JsonStreamParser::new();
+        let output = push_chunks(input, points, |v| parser.push(v))?;
+        parser.finish()?;
+        Ok(output)
+    }
+
+    fn sse_stream(input: &[u8], points: &[usize]) -> Result<Vec<SseEvent>> {
+        let mut parser = SseParser::new();
+        let mut output = push_chunks(input, points, |v| parser.push(v))?;
+        output.extend(parser.finish()?);
+        Ok(output)
+    }
+
+    fn line_stream(input: &[u8], points: &[usize]) -> Result<Vec<String>> {
+        let mut parser = LineParser::new();
+        let mut output = push_chunks(input, points, |v| parser.push(v))?;
+        output.extend(parser.finish()?);
+        Ok(output)
+    }
+
+    #[test]
+    fn test_json_stream_parser() {
+        let input =
+            "[{\"text\": \"a \\\" } ]\",\n\"list\": [1, {\"b\": []}]},\r\n{\"text\": \"你好\"}\n]";
+        let output = json_stream(input.as_bytes(), &[]).unwrap();
+        assert_eq!(
+            output,
+            [
+                "{\"text\": \"a \\\" } ]\",\n\"list\": [1, {\"b\": []}]}",
+                "{\"text\": \"你好\"}"
+            ]
+        );
+        for i in 0..=input.len() {
+            for j in i..=input.len() {
+                assert_eq!(json_stream(input.as_bytes(), &[i, j]).unwrap(), output);
+            }
+        }
+        assert!(json_stream(b"{\"a\": 1}x", &[]).is_err());
+        assert!(json_stream(b"{\"a\": ", &[]).is_err());
+    }
+
+    #[test]
+    fn test_sse_parser() {
+        let input = ": ping\r\nevent: delta\r\ndata: {\"a\":1}\r\n\r\ndata: line1\ndata:line2\nid: 1\n\ndata: [DONE]";
+        let event = |event: &str, data: &str| SseEvent {
+            event: event.into(),
+            data: data.into(),
+        };
+        assert_eq!(
+            sse_stream(input.as_bytes(), &[]).unwrap(),
+            [
+                event("delta", "{\"a\":1}"),
+                event("", "line1\nline2"),
+                event("", "[DONE]")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_buffer_limit() {
+        let chunk = vec![b'a'; 1024 * 1024];
+        let mut parser = JsonStreamParser::new();
+        parser.push(b"{\"a\": \"").unwrap();
+        assert!((0..17).any(|_| parser.push(&chunk).is_err()));
+        let mut parser = LineParser::new();
+        assert!((0..17).any(|_| parser.push(&chunk).is_err()));
+    }
+
+    fn json_values() -> impl Strategy<Value = Vec<String>> {
+        let leaf = prop_oneof![
+            any::<String>().prop_map(serde_json::Value::from),
+            any::<i64>().prop_map(serde_json::Value::from),
+            Just(serde_json::Value::Null),
+        ];
+        let value = leaf.prop_recursive(3, 16, 4, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..4).prop_map(serde_json::Value::from),
+                prop::collection::hash_map("[a-z{}\\[\\]\"]{0,4}", inner, 0..4)
+                    .prop_map(|v| serde_json::Value::Object(v.into_iter().collect())),
+            ]
+        });
+        let object = prop::collection::hash_map(any::<String>(), value, 0..4)
+            .prop_map(|v| serde_json::Value::Object(v.into_iter().collect()).to_string());
+        prop::collection::vec(object, 0..5)
+    }
+
+    proptest! {
+        #[test]
+        fn prop_json_stream_chunking(
+            values in json_values(),
+            array in any::<bool>(),
+            points in prop::collection::vec(any::<usize>(), 0..8),
+        ) {
+            let input = if array {
+                format!("[{}]", values.join(",\r\n"))
+            } else {
+                values.join("\n")
+            };
+            prop_assert_eq!(json_stream(input.as_bytes(), &points).unwrap(), values);
+        }
+
+        #[test]
+        fn prop_sse_chunking(
+            events in prop::collection::vec(("[a-z_]{0,8}", "[^\r\n]{0,16}"), 0..5),
+            crlf in any::<bool>(),
+            points in prop::collection::vec(any::<usize>(), 0..8),
+        ) {
+            let eol = if crlf { "\r\n" } else { "\n" };
+            let input: String = events
+                .iter()
+                .map(|(event, data)| format!("event: {event}{eol}data: {data}{eol}{eol}"))
+                .collect();
+            let expected: Vec<SseEvent> = events
+                .iter()
+                .map(|(event, data)| SseEvent { event: event.clone(), data: data.clone() })
+                .collect();
+            prop_assert_eq!(sse_stream(input.as_bytes(), &[]).unwrap(), expected.clone());
+            prop_assert_eq!(sse_stream(input.as_bytes(), &points).unwrap(), expected);
+        }
+
+        #[test]
+        fn prop_line_chunking(
+            lines in prop::collection::vec("[^\r\n]*[^\r\n\\s][^\r\n]*", 0..5),
+            points in prop::collection::vec(any::<usize>(), 0..8),
+        ) {
+            let input = lines.join("\n");
+            prop_assert_eq!(line_stream(input.as_bytes(), &points).unwrap(), lines);
+        }
+
+        #[test]
+        fn prop_arbitrary_input(
+            input in prop::collection::vec(any::<u8>(), 0..256),
+            points in prop::collection::vec(any::<usize>(), 0..8),
+        ) {
+            let _ = json_stream(&input, &points);
+            let _ = sse_stream(&input, &points);
+            let _ = line_stream(&input, &points);
+        }
+    }
+}
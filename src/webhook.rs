@@ -0,0 +1,147 @@
+//! Notify a webhook when a model request finishes.
+
+use crate::{
+    client::{Model, SendData},
+    config::GlobalConfig,
+    utils::{hex_encode, hmac_sha256, sha256sum},
+};
+
+use anyhow::{bail, Result};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde_json::json;
+use std::time::{Duration, Instant};
+use tokio::{task::JoinHandle, time::sleep};
+
+const MAX_ATTEMPTS: u64 = 3;
+const TIMEOUT: Duration = Duration::from_secs(10);
+const SIGNATURE_HEADER: &str = "X-Aichat-Signature";
+
+lazy_static! {
+    static ref PENDING: Mutex<Vec<JoinHandle<()>>> = Mutex::new(vec![]);
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    pub secret: Option<String>,
+}
+
+pub struct Webhook {
+    config: WebhookConfig,
+    model_id: String,
+    request_id: String,
+    prompt_hash: String,
+    started: Instant,
+}
+
+impl Webhook {
+    pub fn new(
+        config: &GlobalConfig,
+        model: &Model,
+        data: &SendData,
+        request_id: &str,
+    ) -> Option<Self> {
+        let config = config.read().webhook.clone()?;
+        let prompt = serde_json::to_string(&data.messages).unwrap_or_default();
+        Some(Self {
+            config,
+            model_id: model.id(),
+            request_id: request_id.to_string(),
+            prompt_hash: sha256sum(&prompt),
+            started: Instant::now(),
+        })
+    }
+
+    /// Deliver the result in the background, retrying a couple of times. Failures are only logged.
+    pub fn notify(
+        self,
+        output: &str,
+        input_tokens: Option<u64>,
+        output_tokens: Option<u64>,
+        err: Option<&anyhow::Error>,
+    ) {
+        let body = json!({
+            "model": self.model_id,
+            "request_id": self.request_id,
+            "prompt_hash": self.prompt_hash,
+            "output": output,
+            "usage": {
+                "input_tokens": input_tokens,
+                "output_tokens": output_tokens,
+            },
+            "duration_ms": self.started.elapsed().as_millis() as u64,
+            "success": err.is_none(),
+            "error": err.map(|v| v.to_string()),
+        })
+        .to_string();
+        let handle = tokio::spawn(async move { self.deliver(&body).await });
+        let mut pending = PENDING.lock();
+        pending.retain(|v| !v.is_finished());
+        pending.push(handle);
+    }
+
+    async fn deliver(&self, body: &str) {
+        for attempt in 1..=MAX_ATTEMPTS {
+            match self.send(body).await {
+                Ok(()) => return,
+                Err(err) => {
+                    warn!(
+                        "Failed to deliver webhook to {} (attempt {attempt}/{MAX_ATTEMPTS}): {err}",
+                        self.config.url
+                    );
+                    if attempt < MAX_ATTEMPTS {
+                        sleep(Duration::from_secs(attempt)).await;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn send(&self, body: &str) -> Result<()> {
+        let mut builder = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .build()?
+            .post(&self.config.url)
+            .header("Content-Type", "application/json")
+            .body(body.to_string());
+        if let Some(secret) = &self.config.secret {
+            builder = builder.header(SIGNATURE_HEADER, sign(secret, body));
+        }
+        let res = builder.send().await?;
+        let status = res.status();
+        if !status.is_success() {
+            bail!("Invalid status {status}");
+        }
+        Ok(())
+    }
+}
+
+/// Wait for the deliveries still in flight, so that exiting doesn't drop them.
+pub async fn wait_pending() {
+    let handles = std::mem::take(&mut *PENDING.lock());
+    for handle in handles {
+        let _ = handle.await;
+    }
+}
+
+fn sign(secret: &str, body: &str) -> String {
+    format!(
+        "sha256={}",
+        hex_encode(&hmac_sha256(secret.as_bytes(), body))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        assert_eq!(
+            sign("key", "The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+}
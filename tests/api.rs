@@ -0,0 +1,149 @@
+use aichat::{ChatClient, ClientConfig, CommandConfig, Message, ModelConfig, OllamaConfig};
+
+use serde_json::json;
+use std::{env, sync::Once};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+/// Point the config dir at a temp dir, keeping the caches written by the clients out of the user's.
+fn use_temp_config_dir() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| env::set_var("AICHAT_CONFIG_DIR", env::temp_dir().join("aichat-test")));
+}
+
+fn model_config(name: &str) -> ModelConfig {
+    serde_json::from_value(json!({ "name": name })).unwrap()
+}
+
+/// Answer one HTTP request with `body`, return the request.
+async fn serve_once(listener: TcpListener, body: String) -> String {
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let mut request = vec![];
+    let mut buf = [0; 4096];
+    loop {
+        let n = stream.read(&mut buf).await.unwrap();
+        request.extend_from_slice(&buf[..n]);
+        let text = String::from_utf8_lossy(&request);
+        if let Some((head, content)) = text.split_once("\r\n\r\n") {
+            let length = head
+                .lines()
+                .find_map(|v| {
+                    v.to_ascii_lowercase()
+                        .strip_prefix("content-length: ")
+                        .map(|v| v.parse::<usize>().unwrap())
+                })
+                .unwrap_or_default();
+            if content.len() >= length {
+                break;
+            }
+        }
+    }
+    let response = format!(
+        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await.unwrap();
+    String::from_utf8(request).unwrap()
+}
+
+#[tokio::test]
+async fn test_ollama_client() {
+    use_temp_config_dir();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let api_base = format!("http://{}", listener.local_addr().unwrap());
+    let body = json!({
+        "model": "llama3",
+        "message": { "role": "assistant", "content": "Hi there" },
+        "done": true,
+    });
+    let server = tokio::spawn(serve_once(listener, body.to_string()));
+
+    let client = ChatClient::new(
+        ClientConfig::OllamaConfig(OllamaConfig {
+            api_base,
+            models: vec![model_config("llama2"), model_config("llama3")],
+            ..Default::default()
+        }),
+        Some("llama3"),
+    )
+    .unwrap()
+    .set_temperature(Some(0.5));
+    assert_eq!(client.model(), "ollama:llama3");
+
+    let output = client
+        .send(vec![Message::system("Be brief"), Message::user("Hello")])
+        .await
+        .unwrap();
+    assert_eq!(output.text, "Hi there");
+
+    let request = server.await.unwrap();
+    assert!(request.starts_with("POST /api/chat "));
+    assert!(request.contains(r#""model":"llama3""#));
+    assert!(request.contains(r#""temperature":0.5"#));
+    let request_id = output.request_id.unwrap();
+    assert!(request.contains(&format!("x-request-id: {request_id}")));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_mock_client() {
+    use_temp_config_dir();
+    let script = r#"read input
+case "$input" in
+  *'"stream":true'*) printf '"Hello"\n" world"\n' ;;
+  *) echo '{"text":"Hello world","usage":{"input_tokens":3,"output_tokens":2}}' ;;
+esac"#;
+    let client = ChatClient::new(
+        ClientConfig::CommandConfig(CommandConfig {
+            name: Some("mock".into()),
+            command: "sh".into(),
+            args: vec!["-c".into(), script.into()],
+            models: vec![model_config("mock")],
+            ..Default::default()
+        }),
+        None,
+    )
+    .unwrap();
+    assert_eq!(client.list_models(), ["mock:mock"]);
+
+    let output = client.send(vec![Message::user("Hi")]).await.unwrap();
+    assert_eq!(output.text, "Hello world");
+    assert_eq!(
+        (output.input_tokens, output.output_tokens),
+        (Some(3), Some(2))
+    );
+
+    let mut pieces = vec![];
+    let output = client
+        .send_streaming(vec![Message::user("Hi")], |text| {
+            pieces.push(text.to_string())
+        })
+        .await
+        .unwrap();
+    assert_eq!(pieces, ["Hello", " world"]);
+    assert_eq!(output.text, "Hello world");
+}
+
+#[tokio::test]
+async fn test_send_batch_unsupported() {
+    use_temp_config_dir();
+    let client = ChatClient::new(
+        ClientConfig::OllamaConfig(OllamaConfig {
+            api_base: "http://127.0.0.1:1".into(),
+            models: vec![model_config("llama3")],
+            ..Default::default()
+        }),
+        None,
+    )
+    .unwrap();
+    let err = client
+        .send_batch(vec![vec![Message::user("Hi")]])
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "The client 'ollama' doesn't support batches"
+    );
+}